@@ -0,0 +1,51 @@
+// Collects assembly source line by line. Output follows ca65 syntax.
+pub struct Emitter {
+    lines: Vec<String>,
+    label_count: usize,
+}
+
+impl Emitter {
+    pub fn new() -> Emitter {
+        Emitter {
+            lines: Vec::new(),
+            label_count: 0,
+        }
+    }
+
+    pub fn instruction<S: Into<String>>(&mut self, text: S) {
+        self.lines.push(format!("    {}", text.into()));
+    }
+
+    pub fn label(&mut self, name: &str) {
+        self.lines.push(format!("{}:", name));
+    }
+
+    // Defines `name` as a fixed value rather than a location
+    pub fn equate(&mut self, name: &str, value: &str) {
+        self.lines.push(format!("{} = {}", name, value));
+    }
+
+    pub fn comment(&mut self, text: &str) {
+        self.lines.push(format!("; {}", text));
+    }
+
+    pub fn blank(&mut self) {
+        self.lines.push(String::new());
+    }
+
+    pub fn segment(&mut self, name: &str) {
+        self.instruction(format!(".segment \"{}\"", name));
+    }
+
+    // Creates a label name that is not used anywhere else in the output
+    pub fn unique_label(&mut self, prefix: &str) -> String {
+        self.label_count += 1;
+        format!("__{}_{}", prefix, self.label_count)
+    }
+
+    pub fn finish(self) -> String {
+        let mut result = self.lines.join("\n");
+        result.push('\n');
+        result
+    }
+}
@@ -0,0 +1,2943 @@
+// Lowering of function bodies.
+//
+// Every value lives on a data stack in zero page that grows downward,
+// with the X register pointing at the top slot. A slot is two bytes,
+// low byte at 0,X and high byte at 1,X. One byte values only use the
+// low byte, and the high byte of their slot is left undefined until
+// a cast widens them. Four byte values take two slots, so that their
+// bytes run from 0,X to 3,X while on top. Arguments are pushed left to right before a JSR,
+// and the callee replaces them with its result, so recursive calls
+// need no static storage. Locals are slots pushed on top of the
+// arguments. The hardware stack only holds return addresses.
+//
+// Memory behind a pointer is reached with (zp),Y addressing. Global
+// pointers live in zero page and are used in place; any other pointer
+// is first copied to the `__ptr` scratch pair.
+//
+// Interrupt handlers can fire between any two instructions, including
+// while X holds something else for a foreign routine or an asm block.
+// They save the registers and the scratch bytes on the hardware stack,
+// then run on a part of the data stack set aside below what `main`
+// needs, which the stack measurement sizes.
+//
+// Externs with a fixed address or register arguments are foreign
+// routines. Their arguments are evaluated onto the data stack as usual
+// and then moved into place, with X saved on the hardware stack around
+// the JSR since the routine may use it.
+//
+// Fixed-point numbers are integers counting fractions of one, so they
+// add, subtract and compare like signed integers and only multiply and
+// divide through runtime routines. Floats do all of their arithmetic
+// and conversions in the runtime, which is only linked when used.
+// BCD adds and subtracts in decimal mode, with `CLD` right after, or
+// through the runtime on targets without one. Interrupt handlers clear
+// the flag on entry in case they fire in between.
+//
+// A `match` keeps its value in scratch and jumps to its arms through a
+// chain of compares, a binary search over the ranges of values, or a
+// table of arm addresses for the RTS trick when the values are dense.
+//
+// Nothing here merges, reorders or skips memory accesses, which is
+// what volatile globals and the `peek`/`poke` intrinsics rely on.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::lexer::MulOp;
+use crate::lexer::RelOp;
+use crate::lexer::SumOp;
+
+use crate::parser::asm::AsmBlock;
+use crate::parser::asm::AsmClobber;
+use crate::parser::asm::AsmLocation;
+use crate::parser::asm::AsmOperand;
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::BinOp;
+use crate::parser::bin_op::Factor;
+use crate::parser::matching::MatchStatement;
+use crate::parser::parser::ArgLocation;
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::Statement;
+use crate::parser::types::BcdType;
+use crate::parser::types::FixedType;
+use crate::parser::types::IntType;
+use crate::parser::types::StructLayout;
+use crate::parser::types::StructType;
+use crate::parser::types::Type;
+
+use crate::semantic::constant;
+use crate::semantic::externs;
+use crate::semantic::flow;
+use crate::semantic::interrupts;
+use crate::semantic::intrinsics::Intrinsic;
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::intrinsics::OVERFLOW_TRAP;
+use crate::semantic::matching;
+use crate::semantic::typeck::declared_type;
+
+use crate::codegen::handler_stack;
+use crate::codegen::in_zero_page;
+use crate::codegen::runtime::RuntimeRoutine;
+use crate::codegen::stack::Frame;
+use crate::codegen::symbol_name;
+use crate::codegen::CodegenContext;
+use crate::codegen::TABLE_ROW_LENGTH;
+
+// Where the pointer for an indirect access is found
+enum PointerSource {
+    // A data stack slot, by position
+    Slot(usize),
+    // A global pointer variable in zero page
+    ZeroPage(String),
+    // A constant global pointer in ROM, or one at a fixed address
+    Absolute(String),
+}
+
+// What is added to a pointer before an indirect access
+enum IndirectOffset {
+    // A fixed number of bytes, such as the offset of a field
+    Constant(usize),
+    // An element index in the slot at the given offset from X
+    Index(IntType, usize),
+}
+
+// Where a struct field is found
+enum FieldPlace<'a> {
+    // At `label` in a global, plus `index * stride` when in a table
+    Absolute {
+        label: String,
+        index: Option<&'a AstExprNode>,
+        stride: usize,
+    },
+    // `offset` bytes past a pointer to the struct
+    Indirect {
+        base: &'a AstExprNode,
+        offset: usize,
+    },
+}
+
+// Zero page bytes the generated code and runtime use as scratch, which
+// an interrupt handler must put back
+const SCRATCH_BYTES: [&str; 6] = ["__tmp", "__tmp+1", "__tmp+2", "__tmp+3", "__ptr", "__ptr+1"];
+
+// A `match` with up to this many ranges of values tests them in turn,
+// and one with more splits them by binary search
+const COMPARE_CHAIN_LENGTH: usize = 3;
+
+// A `match` over at least this many ranges jumps through a table, as
+// long as the table has at most `JUMP_TABLE_DENSITY` entries per range
+const JUMP_TABLE_MIN_RANGES: usize = 4;
+const JUMP_TABLE_DENSITY: i64 = 3;
+
+pub struct FunctionLowering<'a, 'b> {
+    context: &'b mut CodegenContext<'a>,
+    decl: &'a FuncDecl,
+    // Ends in RTI rather than RTS
+    is_handler: bool,
+    // Slots the arguments take
+    arg_slots: usize,
+    // Number of slots pushed since the function was entered
+    depth: usize,
+    // Slot position of every visible local, innermost scope last.
+    // Arguments take positions 0 to n - 1, locals count up from n. A
+    // value of two slots is found at the second, which is on top.
+    scopes: Vec<HashMap<String, usize>>,
+    // What the function pushes on either stack
+    frame: Frame,
+}
+
+// Data stack slots a value of `size` bytes takes
+fn slots(size: usize) -> usize {
+    size.div_ceil(2)
+}
+
+// Bits below the point of a value converted to or from a float
+fn fraction_bits(ty: &Type) -> usize {
+    match ty {
+        Type::Fixed(fixed_type) => fixed_type.fraction_bits(),
+        _ => 0,
+    }
+}
+
+impl<'a, 'b> FunctionLowering<'a, 'b> {
+    pub fn new(
+        context: &'b mut CodegenContext<'a>,
+        decl: &'a FuncDecl,
+    ) -> FunctionLowering<'a, 'b> {
+        let mut params: HashMap<String, usize> = HashMap::new();
+        let mut arg_slots = 0;
+        for arg in &decl.args {
+            arg_slots += slots(declared_type(&arg.ty).size());
+            params.insert(arg.name.clone(), arg_slots - 1);
+        }
+
+        FunctionLowering {
+            context,
+            decl,
+            is_handler: interrupts::interrupt_vector(decl).is_some(),
+            arg_slots,
+            depth: 0,
+            scopes: vec![params],
+            frame: Frame::new(decl.location),
+        }
+    }
+
+    fn emit<S: Into<String>>(&mut self, text: S) {
+        let text = text.into();
+        self.frame.note(&text, self.depth);
+        self.context.emitter.instruction(text);
+    }
+
+    fn push(&mut self) {
+        self.emit("DEX");
+        self.emit("DEX");
+        self.depth += 1;
+    }
+
+    fn drop(&mut self) {
+        self.emit("INX");
+        self.emit("INX");
+        self.depth -= 1;
+    }
+
+    fn push_value(&mut self, size: usize) {
+        for _ in 0..slots(size) {
+            self.push();
+        }
+    }
+
+    fn drop_value(&mut self, size: usize) {
+        for _ in 0..slots(size) {
+            self.drop();
+        }
+    }
+
+    // Pushes the slots beyond the top one that a value of `size` bytes
+    // needs, once the top slot has been used to find it
+    fn push_rest(&mut self, size: usize) {
+        for _ in 1..slots(size) {
+            self.push();
+        }
+    }
+
+    // Drops slots until only `depth` remain
+    fn drop_to(&mut self, depth: usize) {
+        let count = self.depth - depth;
+        if count > 2 {
+            self.emit("TXA");
+            self.emit("CLC");
+            self.emit(format!("ADC #{}", 2 * count));
+            self.emit("TAX");
+            self.depth = depth;
+        }
+        while self.depth > depth {
+            self.drop();
+        }
+    }
+
+    fn lookup_slot(&self, name: &str) -> Option<usize> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(position) = scope.get(name) {
+                return Some(*position);
+            }
+        }
+        None
+    }
+
+    // Offset from X of the slot at the given position
+    fn slot_offset(&self, position: usize) -> usize {
+        2 * (self.arg_slots + self.depth - 1 - position)
+    }
+
+    fn int_type(&self, expr: &AstExprNode) -> IntType {
+        self.context.types.int_type(expr)
+    }
+
+    fn value_type(&self, expr: &AstExprNode) -> &'a Type {
+        let types = self.context.types;
+        match types.get(expr) {
+            Some(ty) => ty,
+            None => panic!("Expression at {} has no type", expr.location()),
+        }
+    }
+
+    fn value_size(&self, expr: &AstExprNode) -> usize {
+        self.value_type(expr).size()
+    }
+
+    // Size of what a pointer typed expression points at
+    fn pointee_size(&self, expr: &AstExprNode) -> usize {
+        match self.context.types.get(expr) {
+            Some(Type::Pointer {
+                pointee,
+                mutable: _,
+            }) => pointee.size(),
+            other => panic!("Expression at {} has type {:?}", expr.location(), other),
+        }
+    }
+
+    // The global a name or indexing expression refers to, rather than
+    // an argument or local
+    fn global(&self, expr: &AstExprNode) -> Option<&'a GlobalDecl> {
+        let name = self.context.names.global(expr)?;
+        self.context.globals.get(name).cloned()
+    }
+
+    // Whether an indexing expression reads a global table rather than
+    // through a pointer
+    fn is_table(&self, expr: &AstExprNode) -> bool {
+        self.global(expr).is_some_and(|global| global.ty.is_array())
+    }
+
+    fn pointer_source(&self, id: &str) -> PointerSource {
+        if let Some(position) = self.lookup_slot(id) {
+            return PointerSource::Slot(position);
+        }
+        match self.context.globals.get(id) {
+            Some(global) if in_zero_page(global) => PointerSource::ZeroPage(symbol_name(id)),
+            _ => PointerSource::Absolute(symbol_name(id)),
+        }
+    }
+
+    // The slot position of the value `slots` below the top
+    fn stack_position(&self, slots: usize) -> usize {
+        self.arg_slots + self.depth - 1 - slots
+    }
+
+    // Operands for the low and high bytes of a pointer
+    fn pointer_bytes(&self, source: &PointerSource) -> (String, String) {
+        match source {
+            PointerSource::Slot(position) => {
+                let offset = self.slot_offset(*position);
+                (format!("{},X", offset), format!("{},X", offset + 1))
+            }
+            PointerSource::ZeroPage(label) | PointerSource::Absolute(label) => {
+                (label.clone(), format!("{}+1", label))
+            }
+        }
+    }
+
+    // Turns the index in the slot at `offset` into a two byte offset
+    // in bytes, for elements of `size` bytes. Elements whose size is not
+    // a power of two are multiplied out, which only works on the top slot.
+    fn scale_index(&mut self, index_type: IntType, offset: usize, size: usize) {
+        if index_type.size() == 1 {
+            self.emit(format!("LDA {},X", offset));
+            self.extend(index_type, offset);
+        }
+        if size.is_power_of_two() {
+            for _ in 0..size.trailing_zeros() {
+                self.emit(format!("ASL {},X", offset));
+                self.emit(format!("ROL {},X", offset + 1));
+            }
+        } else {
+            self.push_constant(size as i64, 2);
+            let label = self.context.runtime.require(RuntimeRoutine::Mul16);
+            self.emit(format!("JSR {}", label));
+            self.depth -= 1;
+        }
+    }
+
+    // Loads Y with `index * stride` for the index in the slot at
+    // `offset`. Tables never exceed 256 bytes, so only the low byte of
+    // the index matters.
+    fn table_index(&mut self, offset: usize, stride: usize) {
+        self.emit(format!("LDA {},X", offset));
+        if stride.is_power_of_two() {
+            for _ in 0..stride.trailing_zeros() {
+                self.emit("ASL A");
+            }
+        } else {
+            // Shift and add, starting below the top bit of the stride
+            self.emit("STA __tmp");
+            let bits = usize::BITS - stride.leading_zeros();
+            for bit in (0..bits - 1).rev() {
+                self.emit("ASL A");
+                if stride & (1 << bit) != 0 {
+                    self.emit("CLC");
+                    self.emit("ADC __tmp");
+                }
+            }
+        }
+        self.emit("TAY");
+    }
+
+    // Reads `label`, offset by Y when `indexed`, into the top slot
+    fn load_absolute(&mut self, label: &str, indexed: bool, size: usize) {
+        let suffix = if indexed { ",Y" } else { "" };
+        self.emit(format!("LDA {}{}", label, suffix));
+        self.emit("STA 0,X");
+        for byte in 1..size {
+            self.emit(format!("LDA {}+{}{}", label, byte, suffix));
+            self.emit(format!("STA {},X", byte));
+        }
+    }
+
+    // Writes the top slot to `label`, offset by Y when `indexed`
+    fn store_absolute(&mut self, label: &str, indexed: bool, size: usize) {
+        let suffix = if indexed { ",Y" } else { "" };
+        self.emit("LDA 0,X");
+        self.emit(format!("STA {}{}", label, suffix));
+        for byte in 1..size {
+            self.emit(format!("LDA {},X", byte));
+            self.emit(format!("STA {}+{}{}", label, byte, suffix));
+        }
+    }
+
+    // Loads Y and returns the zero page pointer to use with (zp),Y.
+    // Y holds the low byte of the offset, and any high byte is added
+    // to the copy of the pointer in `__ptr`.
+    fn indirect_base(
+        &mut self,
+        source: &PointerSource,
+        offset: IndirectOffset,
+        size: usize,
+    ) -> String {
+        let (low, high) = self.pointer_bytes(source);
+        let has_high = match offset {
+            IndirectOffset::Constant(bytes) => {
+                self.emit(format!("LDY #{}", bytes));
+                false
+            }
+            IndirectOffset::Index(index_type, offset) => {
+                if index_type.size() == 1 && !index_type.is_signed() && size == 1 {
+                    self.emit(format!("LDY {},X", offset));
+                    false
+                } else {
+                    self.scale_index(index_type, offset, size);
+                    self.emit(format!("LDY {},X", offset));
+                    self.emit(format!("LDA {},X", offset + 1));
+                    true
+                }
+            }
+        };
+
+        if has_high {
+            self.emit("CLC");
+            self.emit(format!("ADC {}", high));
+            self.emit("STA __ptr+1");
+            self.emit(format!("LDA {}", low));
+            self.emit("STA __ptr");
+            return String::from("__ptr");
+        }
+        if let PointerSource::ZeroPage(label) = source {
+            return label.clone();
+        }
+        self.emit(format!("LDA {}", low));
+        self.emit("STA __ptr");
+        self.emit(format!("LDA {}", high));
+        self.emit("STA __ptr+1");
+        String::from("__ptr")
+    }
+
+    // Reads the element at (base),Y into the top slot
+    fn load_indirect(&mut self, base: &str, size: usize) {
+        self.emit(format!("LDA ({}),Y", base));
+        self.emit("STA 0,X");
+        for byte in 1..size {
+            self.emit("INY");
+            self.emit(format!("LDA ({}),Y", base));
+            self.emit(format!("STA {},X", byte));
+        }
+    }
+
+    // Writes the top slot to the element at (base),Y
+    fn store_indirect(&mut self, base: &str, size: usize) {
+        self.emit("LDA 0,X");
+        self.emit(format!("STA ({}),Y", base));
+        for byte in 1..size {
+            self.emit("INY");
+            self.emit(format!("LDA {},X", byte));
+            self.emit(format!("STA ({}),Y", base));
+        }
+    }
+
+    // A named pointer can be read where it is, anything else is
+    // evaluated onto the data stack first
+    fn named_pointer(pointer: &AstExprNode) -> Option<&String> {
+        match pointer {
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location: _,
+            }) => Some(id),
+            _ => None,
+        }
+    }
+
+    // Fills the high byte of the slot at `offset` from its low byte in A
+    fn extend(&mut self, int_type: IntType, offset: usize) {
+        if int_type.is_signed() {
+            self.emit("ASL A");
+            self.emit("LDA #0");
+            self.emit("ADC #$FF");
+            self.emit("EOR #$FF");
+        } else {
+            self.emit("LDA #0");
+        }
+        self.emit(format!("STA {},X", offset + 1));
+    }
+
+    pub fn lower(mut self, statement: &'a Statement) {
+        let label = symbol_name(&self.decl.name);
+        self.context.emitter.blank();
+        self.context.emitter.label(&label);
+        if self.is_handler {
+            self.save_state();
+        }
+        self.frame.enter();
+        self.lower_statement(statement);
+
+        // Only interrupt handlers may reach the end, and their result
+        // is dropped
+        if !flow::always_returns(statement, self.context.types) {
+            let size = declared_type(&self.decl.return_type).size();
+            self.push_constant(0, std::cmp::max(size, 2));
+            self.lower_return();
+        }
+        self.context
+            .frames
+            .insert(self.decl.name.clone(), self.frame);
+    }
+
+    // Handler prologue. The 6502 does not clear decimal mode when it
+    // takes an interrupt, so that is done here too. X may not point at
+    // the data stack when the interrupt fires, so the handler starts
+    // on a stack of its own.
+    fn save_state(&mut self) {
+        self.emit("PHA");
+        self.emit("TXA");
+        self.emit("PHA");
+        self.emit("TYA");
+        self.emit("PHA");
+        self.emit("CLD");
+        for byte in SCRATCH_BYTES.iter() {
+            self.emit(format!("LDA {}", byte));
+            self.emit("PHA");
+        }
+        if let Some(vector) = interrupts::interrupt_vector(self.decl) {
+            self.emit(format!("LDX #<{}", handler_stack(vector)));
+        }
+    }
+
+    // Handler epilogue. Restoring X drops the result and any locals.
+    fn restore_state(&mut self) {
+        for byte in SCRATCH_BYTES.iter().rev() {
+            self.emit("PLA");
+            self.emit(format!("STA {}", byte));
+        }
+        self.emit("PLA");
+        self.emit("TAY");
+        self.emit("PLA");
+        self.emit("TAX");
+        self.emit("PLA");
+        self.emit("RTI");
+    }
+
+    fn lower_return(&mut self) {
+        if self.is_handler {
+            self.restore_state();
+            return;
+        }
+
+        // Move the result over the first argument or local, then drop
+        // everything above it. The last byte goes first in case a two
+        // slot result overlaps where it goes.
+        let below = self.arg_slots + self.depth - self.result_slots();
+        if below > 0 {
+            for byte in (0..2 * self.result_slots()).rev() {
+                self.emit(format!("LDA {},X", byte));
+                self.emit(format!("STA {},X", 2 * below + byte));
+            }
+            self.emit("TXA");
+            self.emit("CLC");
+            self.emit(format!("ADC #{}", 2 * below));
+            self.emit("TAX");
+        }
+        self.emit("RTS");
+    }
+
+    fn result_slots(&self) -> usize {
+        slots(declared_type(&self.decl.return_type).size())
+    }
+
+    // Lowers a statement whose locals go out of scope when it ends
+    fn lower_scoped(&mut self, statement: &'a Statement) {
+        let depth = self.depth;
+        self.scopes.push(HashMap::new());
+        self.lower_statement(statement);
+        self.scopes.pop();
+        if !flow::always_returns(statement, self.context.types) {
+            self.drop_to(depth);
+        }
+        self.depth = depth;
+    }
+
+    fn lower_statement(&mut self, statement: &'a Statement) {
+        match statement {
+            Statement::Select {
+                condition,
+                statement,
+                else_clause,
+            } => {
+                let then_label = self.context.emitter.unique_label("then");
+                let else_label = self.context.emitter.unique_label("else");
+                let end_label = self.context.emitter.unique_label("endif");
+
+                self.lower_expression(condition);
+                let size = self.value_size(condition);
+                if self.value_type(condition).is_float() {
+                    // -0 is as false as 0
+                    self.emit(format!("LDA {},X", size - 1));
+                    self.emit("AND #$7F");
+                    for byte in 0..size - 1 {
+                        self.emit(format!("ORA {},X", byte));
+                    }
+                } else {
+                    self.emit("LDA 0,X");
+                    for byte in 1..size {
+                        self.emit(format!("ORA {},X", byte));
+                    }
+                }
+                self.drop_value(size);
+                self.emit("CMP #0");
+                self.emit(format!("BNE {}", then_label));
+                self.emit(format!("JMP {}", else_label));
+                self.context.emitter.label(&then_label);
+                self.lower_scoped(statement);
+                self.emit(format!("JMP {}", end_label));
+                self.context.emitter.label(&else_label);
+                if let Some(clause) = else_clause {
+                    self.lower_scoped(clause);
+                }
+                self.context.emitter.label(&end_label);
+            }
+            Statement::ReturnExpr(expr) => {
+                self.lower_expression(expr);
+                self.lower_return();
+                self.depth -= self.result_slots();
+            }
+            Statement::Block(statements) => {
+                let depth = self.depth;
+                self.scopes.push(HashMap::new());
+                for statement in statements {
+                    self.lower_statement(statement);
+                }
+                self.scopes.pop();
+                if !flow::always_returns(statement, self.context.types) {
+                    self.drop_to(depth);
+                }
+                self.depth = depth;
+            }
+            Statement::Local {
+                name,
+                ty: _,
+                value,
+                location: _,
+            } => {
+                // The value's slots become the local
+                self.lower_expression(value);
+                let position = self.arg_slots + self.depth - 1;
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert(name.clone(), position);
+            }
+            Statement::Assign {
+                target,
+                value,
+                location: _,
+            } => {
+                self.lower_assign(target, value);
+            }
+            Statement::Expression(expr) => {
+                self.lower_expression(expr);
+                self.drop_value(self.value_size(expr));
+            }
+            Statement::Asm(block) => {
+                self.lower_asm(block);
+            }
+            Statement::Match(matching) => {
+                self.lower_match(matching);
+            }
+        }
+    }
+
+    fn lower_match(&mut self, matching: &'a MatchStatement) {
+        let int_type = self.int_type(&matching.value);
+        let size = int_type.size();
+        // Counted from the smallest value of the type, so that signed
+        // values compare as unsigned ones
+        let ranges: Vec<(i64, i64, usize)> = matching::arm_intervals(matching, int_type)
+            .into_iter()
+            .map(|(low, high, index)| {
+                (
+                    low - int_type.min_value(),
+                    high - int_type.min_value(),
+                    index,
+                )
+            })
+            .collect();
+
+        let arm_labels: Vec<String> = matching
+            .arms
+            .iter()
+            .map(|_| self.context.emitter.unique_label("arm"))
+            .collect();
+        let end_label = self.context.emitter.unique_label("endmatch");
+        let default_label = match matching.default {
+            Some(_) => self.context.emitter.unique_label("default"),
+            None => end_label.clone(),
+        };
+
+        // The value goes to scratch so that its slot is gone before any
+        // arm runs. Flipping the sign bit counts from the smallest value.
+        self.lower_expression(&matching.value);
+        for byte in 0..size {
+            self.emit(format!("LDA {},X", byte));
+            if int_type.is_signed() && byte == size - 1 {
+                self.emit("EOR #$80");
+            }
+            self.emit(format!("STA __tmp+{}", byte));
+        }
+        self.drop();
+
+        let span = match (ranges.first(), ranges.last()) {
+            (Some(first), Some(last)) => last.1 - first.0 + 1,
+            _ => 0,
+        };
+        let targets: Vec<(i64, i64, &str)> = ranges
+            .iter()
+            .map(|(low, high, index)| (*low, *high, arm_labels[*index].as_str()))
+            .collect();
+        if ranges.len() >= JUMP_TABLE_MIN_RANGES
+            && span <= 256
+            && span <= JUMP_TABLE_DENSITY * ranges.len() as i64
+        {
+            self.lower_jump_table(&targets, size, &default_label);
+        } else {
+            self.lower_search(&targets, size, &default_label);
+        }
+
+        for (arm, label) in matching.arms.iter().zip(arm_labels.iter()) {
+            self.context.emitter.label(label);
+            self.lower_scoped(&arm.statement);
+            self.emit(format!("JMP {}", end_label));
+        }
+        if let Some(default) = &matching.default {
+            self.context.emitter.label(&default_label);
+            self.lower_scoped(&default.statement);
+        }
+        self.context.emitter.label(&end_label);
+    }
+
+    // Jumps to `label` if the value in scratch is within `low..=high`
+    fn lower_range_test(&mut self, low: i64, high: i64, size: usize, label: &str) {
+        let next_label = self.context.emitter.unique_label("next");
+        let span = high - low + 1;
+        if span == 1 << (8 * size) {
+            self.emit(format!("JMP {}", label));
+            return;
+        }
+        if span == 1 {
+            for byte in 0..size {
+                self.emit(format!("LDA __tmp+{}", byte));
+                self.emit(format!("CMP #${:02X}", (low >> (8 * byte)) & 0xFF));
+                self.emit(format!("BNE {}", next_label));
+            }
+        } else {
+            // Unsigned `value - low < span`
+            self.emit("SEC");
+            for byte in 0..size {
+                self.emit(format!("LDA __tmp+{}", byte));
+                self.emit(format!("SBC #${:02X}", (low >> (8 * byte)) & 0xFF));
+                self.emit(format!("STA __tmp+{}", size + byte));
+            }
+            self.emit(format!("LDA __tmp+{}", size));
+            self.emit(format!("CMP #${:02X}", span & 0xFF));
+            if size == 2 {
+                self.emit("LDA __tmp+3");
+                self.emit(format!("SBC #${:02X}", (span >> 8) & 0xFF));
+            }
+            self.emit(format!("BCS {}", next_label));
+        }
+        self.emit(format!("JMP {}", label));
+        self.context.emitter.label(&next_label);
+    }
+
+    // Tests the ranges in turn, or splits them in half on the first
+    // value of the middle one
+    fn lower_search(&mut self, ranges: &[(i64, i64, &str)], size: usize, default_label: &str) {
+        if ranges.len() <= COMPARE_CHAIN_LENGTH {
+            for (low, high, label) in ranges {
+                self.lower_range_test(*low, *high, size, label);
+            }
+            self.emit(format!("JMP {}", default_label));
+            return;
+        }
+
+        let middle = ranges.len() / 2;
+        let pivot = ranges[middle].0;
+        let lower_label = self.context.emitter.unique_label("lower");
+        let upper_label = self.context.emitter.unique_label("upper");
+        self.emit("LDA __tmp");
+        self.emit(format!("CMP #${:02X}", pivot & 0xFF));
+        if size == 2 {
+            self.emit("LDA __tmp+1");
+            self.emit(format!("SBC #${:02X}", (pivot >> 8) & 0xFF));
+        }
+        self.emit(format!("BCC {}", lower_label));
+        self.emit(format!("JMP {}", upper_label));
+        self.context.emitter.label(&lower_label);
+        self.lower_search(&ranges[..middle], size, default_label);
+        self.context.emitter.label(&upper_label);
+        self.lower_search(&ranges[middle..], size, default_label);
+    }
+
+    // Pushes the address before the arm for the value and returns to it
+    fn lower_jump_table(&mut self, ranges: &[(i64, i64, &str)], size: usize, default_label: &str) {
+        let base = ranges[0].0;
+        let span = ranges[ranges.len() - 1].1 - base + 1;
+        let low_label = self.context.emitter.unique_label("jumplo");
+        let high_label = self.context.emitter.unique_label("jumphi");
+        let in_range_label = self.context.emitter.unique_label("inrange");
+
+        self.emit("LDA __tmp");
+        self.emit("SEC");
+        self.emit(format!("SBC #${:02X}", base & 0xFF));
+        self.emit("TAY");
+        if size == 2 {
+            let high_byte_label = self.context.emitter.unique_label("inrange");
+            self.emit("LDA __tmp+1");
+            self.emit(format!("SBC #${:02X}", (base >> 8) & 0xFF));
+            self.emit(format!("BEQ {}", high_byte_label));
+            self.emit(format!("JMP {}", default_label));
+            self.context.emitter.label(&high_byte_label);
+        }
+        if span < 256 {
+            self.emit(format!("CPY #${:02X}", span));
+            self.emit(format!("BCC {}", in_range_label));
+            self.emit(format!("JMP {}", default_label));
+        }
+        self.context.emitter.label(&in_range_label);
+        self.emit(format!("LDA {},Y", high_label));
+        self.emit("PHA");
+        self.emit(format!("LDA {},Y", low_label));
+        self.emit("PHA");
+        self.emit("RTS");
+
+        let mut entries: Vec<&str> = Vec::new();
+        for value in base..base + span {
+            match ranges
+                .iter()
+                .find(|(low, high, _)| *low <= value && value <= *high)
+            {
+                Some((_, _, label)) => entries.push(label),
+                None => entries.push(default_label),
+            }
+        }
+        for (table_label, operator) in [(low_label, "<"), (high_label, ">")] {
+            self.context.emitter.label(&table_label);
+            for row in entries.chunks(TABLE_ROW_LENGTH) {
+                let row: Vec<String> = row
+                    .iter()
+                    .map(|label| format!("{}({}-1)", operator, label))
+                    .collect();
+                self.emit(format!(".byte {}", row.join(", ")));
+            }
+        }
+    }
+
+    // Operand for one byte of a variable. Arrays stand for their address.
+    fn variable_byte(&self, name: &str, byte: usize) -> String {
+        if let Some(position) = self.lookup_slot(name) {
+            return format!("{},X", self.slot_offset(position) + byte);
+        }
+        let label = symbol_name(name);
+        match &self.context.globals[name].ty {
+            Type::Array {
+                element: _,
+                length: _,
+            } => {
+                format!("#{}{}", if byte == 0 { "<" } else { ">" }, label)
+            }
+            _ if byte == 0 => label,
+            _ => format!("{}+{}", label, byte),
+        }
+    }
+
+    // Between statements nothing is kept in A, Y or the flags, so only
+    // X needs to be saved around a block. Register operands are loaded
+    // last and stored first, X being loaded after everything that is
+    // reached through it.
+    fn lower_asm(&mut self, block: &'a AsmBlock) {
+        let size = |lowering: &Self, operand: &AsmOperand| lowering.value_size(&operand.value);
+        let reader = |location: AsmLocation| {
+            block
+                .operands
+                .iter()
+                .find(|operand| operand.location == location && operand.direction.reads())
+        };
+        let writer = |location: AsmLocation| {
+            block
+                .operands
+                .iter()
+                .find(|operand| operand.location == location && operand.direction.writes())
+        };
+
+        // Zero page operands take consecutive bytes of `__tmp`
+        let mut zero_page: HashMap<&str, usize> = HashMap::new();
+        let mut next = 0;
+        for operand in block
+            .operands
+            .iter()
+            .filter(|operand| operand.location == AsmLocation::ZeroPage)
+        {
+            zero_page.insert(operand.name(), next);
+            if operand.direction.reads() {
+                for byte in 0..size(self, operand) {
+                    self.emit(format!("LDA {}", self.variable_byte(operand.name(), byte)));
+                    self.emit(format!("STA __tmp+{}", next + byte));
+                }
+            }
+            next += size(self, operand);
+        }
+
+        let saves_x = block.binds(AsmLocation::X) || block.clobbers(AsmClobber::X);
+        if saves_x {
+            self.emit("TXA");
+            self.emit("PHA");
+        }
+        if let Some(operand) = reader(AsmLocation::Y) {
+            self.emit(format!("LDY {}", self.variable_byte(operand.name(), 0)));
+        }
+        let load_a = reader(AsmLocation::A);
+        let load_x = reader(AsmLocation::X);
+        if let Some(operand) = load_a {
+            self.emit(format!("LDA {}", self.variable_byte(operand.name(), 0)));
+        }
+        if let Some(operand) = load_x {
+            if load_a.is_some() {
+                self.emit("PHA");
+            }
+            self.emit(format!("LDA {}", self.variable_byte(operand.name(), 0)));
+            self.emit("TAX");
+            if load_a.is_some() {
+                self.emit("PLA");
+            }
+        }
+
+        // A label of its own scopes any `@` labels in the text
+        let label = self.context.emitter.unique_label("asm");
+        self.context.emitter.label(&label);
+        for line in block.text.lines() {
+            let mut line = self.substitute(line.trim(), &zero_page);
+            if let Some(colon) = line.find(':') {
+                let name = &line[..colon];
+                if !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c == '@' || c == '_' || c.is_alphanumeric())
+                {
+                    self.context.emitter.label(name);
+                    line = line[colon + 1..].trim().to_string();
+                }
+            }
+            if !line.is_empty() {
+                self.emit(line);
+            }
+        }
+
+        let store_a = writer(AsmLocation::A);
+        let store_x = writer(AsmLocation::X);
+        if saves_x {
+            if store_a.is_some() {
+                self.emit("STA __ptr");
+            }
+            if store_x.is_some() {
+                self.emit("STX __ptr+1");
+            }
+            self.emit("PLA");
+            self.emit("TAX");
+            if let Some(operand) = store_a {
+                self.emit("LDA __ptr");
+                self.emit(format!("STA {}", self.variable_byte(operand.name(), 0)));
+            }
+            if let Some(operand) = store_x {
+                self.emit("LDA __ptr+1");
+                self.emit(format!("STA {}", self.variable_byte(operand.name(), 0)));
+            }
+        } else if let Some(operand) = store_a {
+            self.emit(format!("STA {}", self.variable_byte(operand.name(), 0)));
+        }
+        if let Some(operand) = writer(AsmLocation::Y) {
+            self.emit(format!("STY {}", self.variable_byte(operand.name(), 0)));
+        }
+
+        for operand in block.operands.iter().filter(|operand| {
+            operand.location == AsmLocation::ZeroPage && operand.direction.writes()
+        }) {
+            let start = zero_page[operand.name()];
+            for byte in 0..size(self, operand) {
+                self.emit(format!("LDA __tmp+{}", start + byte));
+                self.emit(format!("STA {}", self.variable_byte(operand.name(), byte)));
+            }
+        }
+        if block.clobbers(AsmClobber::Flags) {
+            // Leave decimal mode in case the block set it
+            self.emit("CLD");
+        }
+    }
+
+    // Replaces `{name}` with a zero page operand or a global's symbol
+    fn substitute(&self, line: &str, zero_page: &HashMap<&str, usize>) -> String {
+        let mut result = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}').unwrap();
+            let name = rest[start + 1..end].trim();
+            result.push_str(&rest[..start]);
+            match zero_page.get(name) {
+                Some(offset) => result.push_str(&format!("__tmp+{}", offset)),
+                None => result.push_str(&symbol_name(name)),
+            }
+            rest = &rest[end + 1..];
+        }
+        result.push_str(rest);
+        result
+    }
+
+    fn lower_assign(&mut self, target: &'a AstExprNode, value: &'a AstExprNode) {
+        let size = self.value_size(target);
+        // Offset of whatever was pushed before the value
+        let under = 2 * slots(size);
+        match target {
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location: _,
+            }) => {
+                self.lower_expression(value);
+                if let Some(position) = self.lookup_slot(id) {
+                    let offset = self.slot_offset(position);
+                    for byte in 0..size {
+                        self.emit(format!("LDA {},X", byte));
+                        self.emit(format!("STA {},X", offset + byte));
+                    }
+                } else {
+                    self.store_absolute(&symbol_name(id), false, size);
+                }
+                self.drop_value(size);
+            }
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location: _,
+            }) if !self.is_table(target) => {
+                let source = self.pointer_source(id);
+                self.lower_expression(index);
+                self.lower_expression(value);
+                let index_type = self.int_type(index);
+                let base =
+                    self.indirect_base(&source, IndirectOffset::Index(index_type, under), size);
+                self.store_indirect(&base, size);
+                self.drop_value(size);
+                self.drop();
+            }
+            AstExprNode::Deref {
+                pointer,
+                location: _,
+            } => {
+                if let Some(id) = Self::named_pointer(pointer) {
+                    let source = self.pointer_source(id);
+                    self.lower_expression(value);
+                    let base = self.indirect_base(&source, IndirectOffset::Constant(0), size);
+                    self.store_indirect(&base, size);
+                    self.drop_value(size);
+                } else {
+                    self.lower_expression(pointer);
+                    self.lower_expression(value);
+                    let source = PointerSource::Slot(self.stack_position(slots(size)));
+                    let base = self.indirect_base(&source, IndirectOffset::Constant(0), size);
+                    self.store_indirect(&base, size);
+                    self.drop_value(size);
+                    self.drop();
+                }
+            }
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location: _,
+            }) => {
+                self.lower_expression(index);
+                self.lower_expression(value);
+                self.table_index(under, size);
+                self.store_absolute(&symbol_name(id), true, size);
+                self.drop_value(size);
+                self.drop();
+            }
+            AstExprNode::Field {
+                base,
+                field,
+                location: _,
+            } => match self.field_place(base, field) {
+                FieldPlace::Absolute {
+                    label,
+                    index: None,
+                    stride: _,
+                } => {
+                    self.lower_expression(value);
+                    self.store_absolute(&label, false, size);
+                    self.drop_value(size);
+                }
+                FieldPlace::Absolute {
+                    label,
+                    index: Some(index),
+                    stride,
+                } => {
+                    self.lower_expression(index);
+                    self.lower_expression(value);
+                    self.table_index(under, stride);
+                    self.store_absolute(&label, true, size);
+                    self.drop_value(size);
+                    self.drop();
+                }
+                FieldPlace::Indirect { base, offset } => {
+                    if let Some(id) = Self::named_pointer(base) {
+                        let source = self.pointer_source(id);
+                        self.lower_expression(value);
+                        let pointer =
+                            self.indirect_base(&source, IndirectOffset::Constant(offset), size);
+                        self.store_indirect(&pointer, size);
+                        self.drop_value(size);
+                    } else {
+                        self.lower_struct_pointer(base);
+                        self.lower_expression(value);
+                        let source = PointerSource::Slot(self.stack_position(slots(size)));
+                        let pointer =
+                            self.indirect_base(&source, IndirectOffset::Constant(offset), size);
+                        self.store_indirect(&pointer, size);
+                        self.drop_value(size);
+                        self.drop();
+                    }
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn push_constant(&mut self, value: i64, size: usize) {
+        self.push_value(size);
+        for byte in 0..size {
+            self.emit(format!("LDA #${:02X}", (value >> (8 * byte)) & 0xFF));
+            self.emit(format!("STA {},X", byte));
+        }
+    }
+
+    fn lower_expression(&mut self, expr: &'a AstExprNode) {
+        match expr {
+            AstExprNode::Terminal(factor) => {
+                self.lower_factor(expr, factor, self.value_type(expr));
+            }
+            AstExprNode::SubNode(sub_node) => {
+                self.lower_expression(sub_node);
+            }
+            AstExprNode::Cast {
+                value,
+                ty: _,
+                location: _,
+            } => {
+                self.lower_expression(value);
+                self.lower_cast(self.value_type(value), self.value_type(expr));
+            }
+            AstExprNode::AddressOf {
+                target,
+                location: _,
+            } => {
+                self.lower_address(expr, target);
+            }
+            AstExprNode::Deref {
+                pointer,
+                location: _,
+            } => {
+                let size = self.value_size(expr);
+                if let Some(id) = Self::named_pointer(pointer) {
+                    let source = self.pointer_source(id);
+                    self.push_value(size);
+                    let base = self.indirect_base(&source, IndirectOffset::Constant(0), size);
+                    self.load_indirect(&base, size);
+                } else {
+                    self.lower_expression(pointer);
+                    let source = PointerSource::Slot(self.stack_position(0));
+                    let base = self.indirect_base(&source, IndirectOffset::Constant(0), size);
+                    self.push_rest(size);
+                    self.load_indirect(&base, size);
+                }
+            }
+            AstExprNode::Field {
+                base,
+                field,
+                location: _,
+            } => {
+                let size = self.value_size(expr);
+                match self.field_place(base, field) {
+                    FieldPlace::Absolute {
+                        label,
+                        index: None,
+                        stride: _,
+                    } => {
+                        self.push_value(size);
+                        self.load_absolute(&label, false, size);
+                    }
+                    FieldPlace::Absolute {
+                        label,
+                        index: Some(index),
+                        stride,
+                    } => {
+                        self.lower_expression(index);
+                        self.table_index(0, stride);
+                        self.push_rest(size);
+                        self.load_absolute(&label, true, size);
+                    }
+                    FieldPlace::Indirect { base, offset } => {
+                        let source = match Self::named_pointer(base) {
+                            Some(id) => {
+                                let source = self.pointer_source(id);
+                                self.push();
+                                source
+                            }
+                            None => {
+                                self.lower_struct_pointer(base);
+                                PointerSource::Slot(self.stack_position(0))
+                            }
+                        };
+                        let pointer =
+                            self.indirect_base(&source, IndirectOffset::Constant(offset), size);
+                        self.push_rest(size);
+                        self.load_indirect(&pointer, size);
+                    }
+                }
+            }
+            AstExprNode::Node {
+                left,
+                op_type: op_type @ BinOp::Sum(_),
+                next,
+                location: _,
+            } if self
+                .context
+                .types
+                .get(left)
+                .is_some_and(|ty| ty.is_pointer()) =>
+            {
+                // Pointer arithmetic counts in elements
+                self.lower_expression(left);
+                self.lower_expression(next);
+                let size = self.pointee_size(left);
+                self.scale_index(self.int_type(next), 0, size);
+                self.lower_binary(op_type, IntType::U16, Overflow::Wrapping);
+            }
+            AstExprNode::Node {
+                left,
+                op_type,
+                next,
+                location: _,
+            } => {
+                self.lower_expression(left);
+                self.lower_expression(next);
+                let overflow = self.context.overflow;
+                self.lower_operator(op_type, left, overflow);
+                if let BinOp::Rel(_) = op_type {
+                    if self.int_type(expr).size() == 2 {
+                        self.emit("LDA #0");
+                        self.emit("STA 1,X");
+                    }
+                }
+            }
+        }
+    }
+
+    // Converts the value on top. Integers only change width, while
+    // fixed-point numbers move their bytes by the fraction bits, so a
+    // cast to an integer rounds down. Floats go through a signed 32 bit
+    // integer scaled by the fraction bits and are truncated toward zero.
+    // BCD converts to and from unsigned 16 bit integers.
+    fn lower_cast(&mut self, from: &Type, to: &Type) {
+        match (from, to) {
+            (Type::Float, Type::Float) => {}
+            (Type::Float, _) => {
+                self.emit(format!("LDY #{}", fraction_bits(to)));
+                let label = self.context.runtime.require(RuntimeRoutine::FloatToLong);
+                self.emit(format!("JSR {}", label));
+                if to.size() <= 2 {
+                    self.emit("LDA 0,X");
+                    self.emit("STA 2,X");
+                    self.emit("LDA 1,X");
+                    self.emit("STA 3,X");
+                    self.drop();
+                }
+            }
+            (_, Type::Float) => {
+                if from.size() <= 2 {
+                    self.push();
+                    self.emit("LDA 2,X");
+                    self.emit("STA 0,X");
+                    if from.size() == 1 {
+                        self.extend(self.int_of(from), 0);
+                    } else {
+                        self.emit("LDA 3,X");
+                        self.emit("STA 1,X");
+                    }
+                    if from.is_fixed() || self.int_of(from).is_signed() {
+                        self.emit("LDA 1,X");
+                        self.extend(IntType::I8, 1);
+                        self.emit("STA 3,X");
+                    } else {
+                        self.emit("LDA #0");
+                        self.emit("STA 2,X");
+                        self.emit("STA 3,X");
+                    }
+                }
+                self.emit(format!("LDY #{}", fraction_bits(from)));
+                let label = self.context.runtime.require(RuntimeRoutine::LongToFloat);
+                self.emit(format!("JSR {}", label));
+            }
+            (Type::Bcd(_), Type::Bcd(BcdType::Bcd16)) => {
+                if from.size() == 1 {
+                    self.emit("LDA #0");
+                    self.emit("STA 1,X");
+                }
+            }
+            (Type::Bcd(_), Type::Bcd(_)) => {
+                // The low byte holds the last two digits
+            }
+            (Type::Bcd(_), _) => {
+                if from.size() == 1 {
+                    self.emit("LDA #0");
+                    self.emit("STA 1,X");
+                }
+                let label = self.context.runtime.require(RuntimeRoutine::BcdToBinary);
+                self.emit(format!("JSR {}", label));
+            }
+            (_, Type::Bcd(_)) => {
+                if from.size() == 1 {
+                    self.emit("LDA 0,X");
+                    self.extend(self.int_of(from), 0);
+                }
+                let label = self.context.runtime.require(RuntimeRoutine::BinaryToBcd);
+                self.emit(format!("JSR {}", label));
+            }
+            (Type::Fixed(FixedType::F8x8), Type::Fixed(FixedType::F16x16)) => {
+                self.push();
+                self.emit("LDA 2,X");
+                self.emit("STA 1,X");
+                self.emit("LDA 3,X");
+                self.emit("STA 2,X");
+                self.extend(IntType::I8, 2);
+                self.emit("LDA #0");
+                self.emit("STA 0,X");
+            }
+            (Type::Fixed(FixedType::F16x16), Type::Fixed(FixedType::F8x8)) => {
+                self.emit("LDA 2,X");
+                self.emit("STA 3,X");
+                self.emit("LDA 1,X");
+                self.emit("STA 2,X");
+                self.drop();
+            }
+            (Type::Fixed(FixedType::F8x8), _) => {
+                self.emit("LDA 1,X");
+                self.emit("STA 0,X");
+                if to.size() == 2 {
+                    self.extend(IntType::I8, 0);
+                }
+            }
+            (Type::Fixed(FixedType::F16x16), _) => {
+                // The integer part is in the slot below
+                self.drop();
+            }
+            (_, Type::Fixed(FixedType::F8x8)) => {
+                self.emit("LDA 0,X");
+                self.emit("STA 1,X");
+                self.emit("LDA #0");
+                self.emit("STA 0,X");
+            }
+            (_, Type::Fixed(FixedType::F16x16)) => {
+                self.push();
+                if from.size() == 1 {
+                    self.emit("LDA 2,X");
+                    self.extend(self.int_of(from), 2);
+                }
+                self.emit("LDA #0");
+                self.emit("STA 0,X");
+                self.emit("STA 1,X");
+            }
+            _ => {
+                if from.size() == 1 && to.size() == 2 {
+                    self.emit("LDA 0,X");
+                    self.extend(self.int_of(from), 0);
+                }
+            }
+        }
+    }
+
+    // The integer type of a value that is not a fixed-point number
+    fn int_of(&self, ty: &Type) -> IntType {
+        match ty {
+            Type::Int(int_type) => *int_type,
+            _ => IntType::U16,
+        }
+    }
+
+    // Pushes the address of a global or of an element
+    fn lower_address(&mut self, expr: &'a AstExprNode, target: &'a AstExprNode) {
+        match target {
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location: _,
+            }) => {
+                let label = symbol_name(id);
+                self.push();
+                self.emit(format!("LDA #<{}", label));
+                self.emit("STA 0,X");
+                self.emit(format!("LDA #>{}", label));
+                self.emit("STA 1,X");
+            }
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location: _,
+            }) => {
+                let size = self.pointee_size(expr);
+                self.lower_element_address(target, id, index, size);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Pushes the address of element `index` of a table or pointer
+    fn lower_element_address(
+        &mut self,
+        element: &AstExprNode,
+        id: &str,
+        index: &'a AstExprNode,
+        size: usize,
+    ) {
+        self.lower_expression(index);
+        self.scale_index(self.int_type(index), 0, size);
+        let (low, high) = if self.is_table(element) {
+            let label = symbol_name(id);
+            (format!("#<{}", label), format!("#>{}", label))
+        } else {
+            let source = self.pointer_source(id);
+            self.pointer_bytes(&source)
+        };
+        self.emit("CLC");
+        self.emit("LDA 0,X");
+        self.emit(format!("ADC {}", low));
+        self.emit("STA 0,X");
+        self.emit("LDA 1,X");
+        self.emit(format!("ADC {}", high));
+        self.emit("STA 1,X");
+    }
+
+    // Pushes a pointer to the struct whose field is accessed, which is
+    // either an element through an indexed pointer or a pointer value
+    fn lower_struct_pointer(&mut self, base: &'a AstExprNode) {
+        match base {
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location: _,
+            }) => {
+                let size = self.struct_type(base).size();
+                self.lower_element_address(base, id, index, size);
+            }
+            _ => {
+                self.lower_expression(base);
+            }
+        }
+    }
+
+    // The struct a field access reads from, through a pointer or not
+    fn struct_type(&self, base: &AstExprNode) -> Rc<StructType> {
+        match self.context.types.get(base) {
+            Some(Type::Struct(structure)) => structure.clone(),
+            Some(Type::Pointer {
+                pointee,
+                mutable: _,
+            }) => match pointee.as_ref() {
+                Type::Struct(structure) => structure.clone(),
+                other => panic!("Field access through pointer to {}", other),
+            },
+            other => panic!("Field access on {:?}", other),
+        }
+    }
+
+    fn field_place(&self, base: &'a AstExprNode, field: &str) -> FieldPlace<'a> {
+        let structure = self.struct_type(base);
+        let (offset, field_type) = structure.field(field).unwrap();
+        let is_pointer = self
+            .context
+            .types
+            .get(base)
+            .is_some_and(|ty| ty.is_pointer());
+
+        match base {
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location: _,
+            }) if !is_pointer => FieldPlace::Absolute {
+                label: format!("{}+{}", symbol_name(id), offset),
+                index: None,
+                stride: 0,
+            },
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location: _,
+            }) if self.is_table(base) => {
+                match structure.layout {
+                    StructLayout::ArrayOfStructs => FieldPlace::Absolute {
+                        label: format!("{}+{}", symbol_name(id), offset),
+                        index: Some(index),
+                        stride: structure.size(),
+                    },
+                    StructLayout::StructOfArrays => {
+                        // Each field's table follows those of the fields before it
+                        let length = self.context.globals[id].ty.size() / structure.size();
+                        let start: usize = structure
+                            .fields
+                            .iter()
+                            .take_while(|other| other.name != field)
+                            .map(|other| other.ty.size() * length)
+                            .sum();
+                        FieldPlace::Absolute {
+                            label: format!("{}+{}", symbol_name(id), start),
+                            index: Some(index),
+                            stride: field_type.size(),
+                        }
+                    }
+                }
+            }
+            _ => FieldPlace::Indirect { base, offset },
+        }
+    }
+
+    fn lower_factor(&mut self, expr: &'a AstExprNode, factor: &'a Factor, ty: &Type) {
+        let size = ty.size();
+        match factor {
+            Factor::Numeric { value, location: _ } => {
+                self.push_constant(ty.encode(*value), size);
+            }
+            Factor::Id {
+                id,
+                optional_call: Some(args),
+                location: _,
+            } if Intrinsic::from_name(id).is_some() => {
+                self.lower_intrinsic(Intrinsic::from_name(id).unwrap(), args);
+            }
+            Factor::Id {
+                id,
+                optional_call: Some(args),
+                location: _,
+            } if self.context.externs.contains_key(id) => {
+                let decl = self.context.externs[id];
+                self.lower_foreign_call(decl, args);
+            }
+            Factor::Id {
+                id,
+                optional_call: Some(args),
+                location: _,
+            } => {
+                let mut arg_slots = 0;
+                for arg in args {
+                    self.lower_expression(arg);
+                    arg_slots += slots(self.value_size(arg));
+                }
+                self.emit(format!("JSR {}", symbol_name(id)));
+                self.depth = self.depth + slots(size) - arg_slots;
+            }
+            Factor::Id {
+                id,
+                optional_call: None,
+                location: _,
+            } => {
+                if let Some(position) = self.lookup_slot(id) {
+                    self.push_value(size);
+                    let offset = self.slot_offset(position);
+                    for byte in 0..size {
+                        self.emit(format!("LDA {},X", offset + byte));
+                        self.emit(format!("STA {},X", byte));
+                    }
+                } else if let Some(global) = self.global(expr) {
+                    let label = symbol_name(id);
+                    match &global.ty {
+                        Type::Int(_)
+                        | Type::Fixed(_)
+                        | Type::Float
+                        | Type::Bcd(_)
+                        | Type::Pointer {
+                            pointee: _,
+                            mutable: _,
+                        } => {
+                            self.push_value(size);
+                            self.load_absolute(&label, false, size);
+                        }
+                        Type::Array {
+                            element: _,
+                            length: _,
+                        } => {
+                            // Arrays evaluate to their address
+                            self.push();
+                            self.emit(format!("LDA #<{}", label));
+                            self.emit("STA 0,X");
+                            self.emit(format!("LDA #>{}", label));
+                            self.emit("STA 1,X");
+                        }
+                        Type::Struct(_) => unreachable!(),
+                    }
+                } else {
+                    panic!("Unresolved identifier `{}`", id);
+                }
+            }
+            Factor::Index {
+                id,
+                index,
+                location: _,
+            } if !self.is_table(expr) => {
+                let source = self.pointer_source(id);
+                self.lower_expression(index);
+                let index_type = self.int_type(index);
+                let base = self.indirect_base(&source, IndirectOffset::Index(index_type, 0), size);
+                self.push_rest(size);
+                self.load_indirect(&base, size);
+            }
+            Factor::Index {
+                id,
+                index,
+                location: _,
+            } => {
+                self.lower_expression(index);
+                self.table_index(0, size);
+                self.push_rest(size);
+                self.load_absolute(&symbol_name(id), true, size);
+            }
+        }
+    }
+
+    // A constant address is used directly, anything else goes through
+    // a pointer on the data stack
+    fn lower_intrinsic(&mut self, intrinsic: Intrinsic, args: &'a [Box<AstExprNode>]) {
+        let address =
+            constant::evaluate(&args[0]).map(|value| format!("${:04X}", value as i64 & 0xFFFF));
+        match (intrinsic, address) {
+            (Intrinsic::Peek, Some(address)) => {
+                self.push();
+                self.load_absolute(&address, false, 1);
+            }
+            (Intrinsic::Peek, None) => {
+                self.lower_expression(&args[0]);
+                let source = PointerSource::Slot(self.stack_position(0));
+                let pointer = self.indirect_base(&source, IndirectOffset::Constant(0), 1);
+                self.load_indirect(&pointer, 1);
+            }
+            (Intrinsic::Poke, Some(address)) => {
+                self.lower_expression(&args[1]);
+                self.store_absolute(&address, false, 1);
+            }
+            (Intrinsic::Arithmetic(op, overflow), _) => {
+                self.lower_expression(&args[0]);
+                self.lower_expression(&args[1]);
+                self.lower_operator(&op.bin_op(), &args[0], overflow);
+            }
+            (Intrinsic::Poke, None) => {
+                self.lower_expression(&args[0]);
+                self.lower_expression(&args[1]);
+                let source = PointerSource::Slot(self.stack_position(1));
+                let pointer = self.indirect_base(&source, IndirectOffset::Constant(0), 1);
+                self.store_indirect(&pointer, 1);
+                // The written value is the result
+                self.emit("STA 2,X");
+                self.drop();
+            }
+        }
+    }
+
+    fn lower_foreign_call(&mut self, decl: &'a FuncDecl, args: &'a [Box<AstExprNode>]) {
+        for arg in args {
+            self.lower_expression(arg);
+        }
+        let arg_slots: Vec<usize> = decl
+            .args
+            .iter()
+            .map(|arg| slots(declared_type(&arg.ty).size()))
+            .collect();
+
+        // Register and byte of the slot at the given offset it is loaded from
+        let mut loads: Vec<(&'static str, usize)> = Vec::new();
+        for (index, arg) in decl.args.iter().enumerate() {
+            let offset = 2 * arg_slots[index + 1..].iter().sum::<usize>();
+            match arg.passed_in.as_ref().unwrap() {
+                ArgLocation::Memory(address) => {
+                    let address = externs::memory_address(address);
+                    for byte in 0..declared_type(&arg.ty).size() {
+                        self.emit(format!("LDA {},X", offset + byte));
+                        self.emit(format!("STA ${:04X}", address as usize + byte));
+                    }
+                }
+                passed_in => {
+                    for (byte, register) in passed_in.registers().into_iter().enumerate() {
+                        loads.push((register, offset + byte));
+                    }
+                }
+            }
+        }
+        let load = |register: &str| {
+            loads
+                .iter()
+                .find(|(other, _)| *other == register)
+                .map(|(_, offset)| *offset)
+        };
+
+        // X is not the data stack pointer again until it is pulled back
+        // after the call. Interrupt handlers have stacks of their own.
+        self.emit("TXA");
+        self.emit("PHA");
+        // Y first, while X still points at the arguments
+        if let Some(offset) = load("Y") {
+            self.emit(format!("LDY {},X", offset));
+        }
+        match (load("A"), load("X")) {
+            (Some(a), Some(x)) => {
+                self.emit(format!("LDA {},X", a));
+                self.emit("PHA");
+                self.emit(format!("LDA {},X", x));
+                self.emit("TAX");
+                self.emit("PLA");
+            }
+            (Some(a), None) => {
+                self.emit(format!("LDA {},X", a));
+            }
+            (None, Some(x)) => {
+                self.emit(format!("LDA {},X", x));
+                self.emit("TAX");
+            }
+            (None, None) => {}
+        }
+        self.emit(format!("JSR {}", externs::call_target(decl)));
+
+        // The result is kept in scratch until X is back
+        let returned_in = decl.returned_in.as_ref();
+        if let Some(returned_in) = returned_in {
+            for (byte, register) in returned_in.registers().into_iter().enumerate() {
+                self.emit(format!("ST{} __tmp+{}", register, byte));
+            }
+        }
+        self.emit("PLA");
+        self.emit("TAX");
+
+        // The result takes the place of the arguments
+        let depth = self.depth + slots(declared_type(&decl.return_type).size())
+            - arg_slots.iter().sum::<usize>();
+        while self.depth < depth {
+            self.push();
+        }
+        self.drop_to(depth);
+        match returned_in {
+            Some(ArgLocation::Memory(address)) => {
+                let address = externs::memory_address(address);
+                for byte in 0..decl.return_type.as_ref().unwrap().size() {
+                    self.emit(format!("LDA ${:04X}", address as usize + byte));
+                    self.emit(format!("STA {},X", byte));
+                }
+            }
+            Some(returned_in) => {
+                for byte in 0..returned_in.registers().len() {
+                    self.emit(format!("LDA __tmp+{}", byte));
+                    self.emit(format!("STA {},X", byte));
+                }
+            }
+            None => {
+                self.emit("LDA #0");
+                self.emit("STA 0,X");
+                self.emit("STA 1,X");
+            }
+        }
+    }
+
+    // Widens both operand slots to two bytes before a 16 bit helper
+    fn extend_operands(&mut self, operand_type: IntType) {
+        if operand_type.size() == 1 {
+            self.emit("LDA 0,X");
+            self.extend(operand_type, 0);
+            self.emit("LDA 2,X");
+            self.extend(operand_type, 2);
+        }
+    }
+
+    // Calls the program's overflow trap, or the runtime's if it has none
+    fn call_overflow_trap(&mut self) {
+        if !self.context.has_overflow_trap {
+            let label = self.context.runtime.require(RuntimeRoutine::OverflowTrap);
+            self.emit(format!("JSR {}", label));
+        } else if let Some(decl) = self.context.externs.get(OVERFLOW_TRAP) {
+            let target = externs::call_target(decl);
+            self.emit("TXA");
+            self.emit("PHA");
+            self.emit(format!("JSR {}", target));
+            self.emit("PLA");
+            self.emit("TAX");
+        } else {
+            // Its result is not wanted
+            self.emit(format!("JSR {}", symbol_name(OVERFLOW_TRAP)));
+            self.emit("INX");
+            self.emit("INX");
+        }
+    }
+
+    // Stores the smallest or largest value of a type of `size` bytes in
+    // the slot at `offset`
+    fn store_limit(&mut self, size: usize, signed: bool, offset: usize, largest: bool) {
+        let bits = 8 * size;
+        let value: i64 = match (signed, largest) {
+            (false, false) => 0,
+            (false, true) => (1 << bits) - 1,
+            (true, false) => -(1 << (bits - 1)),
+            (true, true) => (1 << (bits - 1)) - 1,
+        };
+        for byte in 0..size {
+            self.emit(format!("LDA #${:02X}", (value >> (8 * byte)) & 0xFF));
+            self.emit(format!("STA {},X", offset + byte));
+        }
+    }
+
+    // Handles an overflow, which the code before has branched around
+    // when there was none. For saturation the N flag must tell whether
+    // the limit to store is the smallest one.
+    fn on_overflow(
+        &mut self,
+        size: usize,
+        signed: bool,
+        offset: usize,
+        overflow: Overflow,
+        done_label: &str,
+    ) {
+        match overflow {
+            Overflow::Wrapping => {}
+            Overflow::Checked => {
+                self.call_overflow_trap();
+            }
+            Overflow::Saturating => {
+                let smallest_label = self.context.emitter.unique_label("smallest");
+                self.emit(format!("BMI {}", smallest_label));
+                self.store_limit(size, signed, offset, true);
+                self.emit(format!("JMP {}", done_label));
+                self.context.emitter.label(&smallest_label);
+                self.store_limit(size, signed, offset, false);
+            }
+        }
+        self.context.emitter.label(done_label);
+    }
+
+    // Branches to `done_label` if the 16 bit result on top fits in a
+    // byte. For saturation, N is left telling whether it is too small.
+    fn branch_if_byte(&mut self, signed: bool, saturating: bool, done_label: &str) {
+        if signed {
+            self.emit("LDA 0,X");
+            self.emit("ASL A");
+            self.emit("LDA #0");
+            self.emit("ADC #$FF");
+            self.emit("EOR #$FF");
+            self.emit("CMP 1,X");
+            self.emit(format!("BEQ {}", done_label));
+            if saturating {
+                self.emit("LDA 1,X");
+            }
+        } else {
+            self.emit("LDA 1,X");
+            self.emit(format!("BEQ {}", done_label));
+            if saturating {
+                self.emit("LDA #0");
+            }
+        }
+    }
+
+    // Applies an operator to the two values on top, which have the type of `left`
+    fn lower_operator(&mut self, op_type: &BinOp, left: &AstExprNode, overflow: Overflow) {
+        match self.value_type(left) {
+            Type::Fixed(fixed_type) => self.lower_fixed_binary(op_type, *fixed_type, overflow),
+            Type::Float => self.lower_float_binary(op_type),
+            Type::Bcd(bcd_type) => self.lower_bcd_binary(op_type, *bcd_type, overflow),
+            _ => self.lower_binary(op_type, self.int_type(left), overflow),
+        }
+    }
+
+    fn lower_sum(&mut self, sum_op: &SumOp, size: usize, signed: bool, overflow: Overflow) {
+        let left = 2 * slots(size);
+        let (carry, op) = match sum_op {
+            SumOp::ADD => ("CLC", "ADC"),
+            SumOp::SUBTRACT => ("SEC", "SBC"),
+        };
+        self.emit(carry);
+        for byte in 0..size {
+            self.emit(format!("LDA {},X", left + byte));
+            self.emit(format!("{} {},X", op, byte));
+            self.emit(format!("STA {},X", left + byte));
+        }
+        if overflow != Overflow::Wrapping {
+            // The last ADC or SBC leaves V set on signed overflow
+            // and C set on unsigned carry or clear on borrow
+            let done_label = self.context.emitter.unique_label("nooverflow");
+            let saturating = overflow == Overflow::Saturating;
+            if signed {
+                self.emit(format!("BVC {}", done_label));
+                if saturating {
+                    // Overflow flips the sign, so a negative result
+                    // should have been the largest value
+                    self.emit(format!("LDA {},X", left + size - 1));
+                    self.emit("EOR #$80");
+                }
+            } else if *sum_op == SumOp::ADD {
+                self.emit(format!("BCC {}", done_label));
+                if saturating {
+                    self.emit("LDA #0");
+                }
+            } else {
+                self.emit(format!("BCS {}", done_label));
+                if saturating {
+                    self.emit("LDA #$80");
+                }
+            }
+            self.on_overflow(size, signed, left, overflow, &done_label);
+        }
+        self.drop_value(size);
+    }
+
+    // Replaces both operands with 0 or 1
+    fn lower_compare(&mut self, rel_op: &RelOp, size: usize, signed: bool) {
+        let operand_slots = slots(size);
+        let left = 2 * operand_slots;
+        if let RelOp::EQUAL = rel_op {
+            let done_label = self.context.emitter.unique_label("cmp");
+            self.emit("LDY #0");
+            for byte in 0..size {
+                self.emit(format!("LDA {},X", left + byte));
+                self.emit(format!("CMP {},X", byte));
+                self.emit(format!("BNE {}", done_label));
+            }
+            self.emit("INY");
+            self.context.emitter.label(&done_label);
+            self.emit(format!("STY {},X", 2 * (2 * operand_slots - 1)));
+        } else {
+            // Subtracting leaves carry set when the minuend is at least
+            // the subtrahend, so swap operands and invert as needed
+            let (swapped, mut inverted) = match rel_op {
+                RelOp::GREATER_THAN_EQ => (false, false),
+                RelOp::LESS_THAN => (false, true),
+                RelOp::LESS_THAN_EQ => (true, false),
+                RelOp::GREATER_THAN => (true, true),
+                RelOp::EQUAL => unreachable!(),
+            };
+            let (minuend, subtrahend) = if swapped { (0, left) } else { (left, 0) };
+            if size > 1 {
+                self.emit(format!("LDA {},X", minuend));
+                self.emit(format!("CMP {},X", subtrahend));
+                for byte in 1..size {
+                    self.emit(format!("LDA {},X", minuend + byte));
+                    self.emit(format!("SBC {},X", subtrahend + byte));
+                }
+            } else if signed {
+                self.emit("SEC");
+                self.emit(format!("LDA {},X", minuend));
+                self.emit(format!("SBC {},X", subtrahend));
+            } else {
+                self.emit(format!("LDA {},X", minuend));
+                self.emit(format!("CMP {},X", subtrahend));
+            }
+
+            if signed {
+                // The sign of the difference, corrected for overflow,
+                // is set when the minuend is less
+                let label = self.context.emitter.unique_label("signed");
+                self.emit(format!("BVC {}", label));
+                self.emit("EOR #$80");
+                self.context.emitter.label(&label);
+                self.emit("ASL A");
+                inverted = !inverted;
+            }
+            self.emit("LDA #0");
+            self.emit("ROL A");
+            if inverted {
+                self.emit("EOR #1");
+            }
+            self.emit(format!("STA {},X", 2 * (2 * operand_slots - 1)));
+        }
+        let depth = self.depth + 1 - 2 * operand_slots;
+        self.drop_to(depth);
+    }
+
+    fn lower_binary(&mut self, op_type: &BinOp, operand_type: IntType, overflow: Overflow) {
+        let size = operand_type.size();
+        match op_type {
+            BinOp::Sum(sum_op) => {
+                self.lower_sum(sum_op, size, operand_type.is_signed(), overflow);
+            }
+            BinOp::Mult(MulOp::MULTIPLY) if overflow == Overflow::Wrapping => {
+                // The low bytes of a product do not depend on signedness
+                // or on the high bytes of the operands
+                let label = self.context.runtime.require(RuntimeRoutine::Mul16);
+                self.emit(format!("JSR {}", label));
+                self.depth -= 1;
+            }
+            BinOp::Mult(MulOp::MULTIPLY) => {
+                let done_label = self.context.emitter.unique_label("nooverflow");
+                let saturating = overflow == Overflow::Saturating;
+                if size == 1 {
+                    // Widened, the product is exact and fits if its high
+                    // byte only extends the low one
+                    self.extend_operands(operand_type);
+                    let label = self.context.runtime.require(RuntimeRoutine::Mul16);
+                    self.emit(format!("JSR {}", label));
+                    self.depth -= 1;
+                    self.branch_if_byte(operand_type.is_signed(), saturating, &done_label);
+                } else if operand_type.is_signed() {
+                    if saturating {
+                        // The sign the product should have
+                        self.emit("LDA 1,X");
+                        self.emit("EOR 3,X");
+                        self.emit("PHA");
+                    }
+                    let label = self
+                        .context
+                        .runtime
+                        .require(RuntimeRoutine::SignedCheckedMul16);
+                    self.emit(format!("JSR {}", label));
+                    self.depth -= 1;
+                    if saturating {
+                        self.emit("PLA");
+                    }
+                    self.emit(format!("BCC {}", done_label));
+                } else {
+                    let label = self.context.runtime.require(RuntimeRoutine::WideMul16);
+                    self.emit(format!("JSR {}", label));
+                    self.depth -= 1;
+                    self.emit("LDA __tmp+2");
+                    self.emit("ORA __tmp+3");
+                    self.emit(format!("BEQ {}", done_label));
+                    if saturating {
+                        self.emit("LDA #0");
+                    }
+                }
+                self.on_overflow(size, operand_type.is_signed(), 0, overflow, &done_label);
+            }
+            BinOp::Mult(MulOp::DIVIDE) if !operand_type.is_signed() => {
+                self.extend_operands(operand_type);
+                let label = self.context.runtime.require(RuntimeRoutine::Div16);
+                self.emit(format!("JSR {}", label));
+                self.depth -= 1;
+            }
+            BinOp::Mult(MulOp::DIVIDE) => {
+                // Only the smallest value divided by -1 overflows, giving
+                // a quotient one past the largest
+                self.extend_operands(operand_type);
+                if size == 2 && overflow != Overflow::Wrapping {
+                    // The sign the quotient should have
+                    self.emit("LDA 1,X");
+                    self.emit("EOR 3,X");
+                    self.emit("PHA");
+                }
+                let label = self.context.runtime.require(RuntimeRoutine::SignedDiv16);
+                self.emit(format!("JSR {}", label));
+                self.depth -= 1;
+                if overflow != Overflow::Wrapping {
+                    let done_label = self.context.emitter.unique_label("nooverflow");
+                    let saturating = overflow == Overflow::Saturating;
+                    if size == 1 {
+                        self.branch_if_byte(true, saturating, &done_label);
+                    } else {
+                        self.emit("PLA");
+                        self.emit(format!("BMI {}", done_label));
+                        self.emit("LDA 1,X");
+                        self.emit(format!("BPL {}", done_label));
+                        if saturating {
+                            self.emit("LDA #0");
+                        }
+                    }
+                    self.on_overflow(size, true, 0, overflow, &done_label);
+                }
+            }
+            BinOp::Rel(rel_op) => {
+                self.lower_compare(rel_op, size, operand_type.is_signed());
+            }
+        }
+    }
+
+    // Fixed-point sums and comparisons are those of signed integers,
+    // and products and quotients are shifted back into place by the
+    // runtime, which sets carry when they do not fit
+    fn lower_fixed_binary(&mut self, op_type: &BinOp, fixed_type: FixedType, overflow: Overflow) {
+        let size = fixed_type.size();
+        match op_type {
+            BinOp::Sum(sum_op) => {
+                self.lower_sum(sum_op, size, true, overflow);
+            }
+            BinOp::Rel(rel_op) => {
+                self.lower_compare(rel_op, size, true);
+            }
+            BinOp::Mult(mul_op) => {
+                let routine = match (mul_op, fixed_type) {
+                    (MulOp::MULTIPLY, FixedType::F8x8) => RuntimeRoutine::FixedMul8,
+                    (MulOp::MULTIPLY, FixedType::F16x16) => RuntimeRoutine::FixedMul16,
+                    (MulOp::DIVIDE, FixedType::F8x8) => RuntimeRoutine::FixedDiv8,
+                    (MulOp::DIVIDE, FixedType::F16x16) => RuntimeRoutine::FixedDiv16,
+                };
+                let saturating = overflow == Overflow::Saturating;
+                if saturating {
+                    // The sign the result should have
+                    self.emit(format!("LDA {},X", size - 1));
+                    self.emit(format!("EOR {},X", 2 * slots(size) + size - 1));
+                    self.emit("PHA");
+                }
+                let label = self.context.runtime.require(routine);
+                self.emit(format!("JSR {}", label));
+                self.depth -= slots(size);
+                if saturating {
+                    self.emit("PLA");
+                }
+                if overflow != Overflow::Wrapping {
+                    let done_label = self.context.emitter.unique_label("nooverflow");
+                    self.emit(format!("BCC {}", done_label));
+                    self.on_overflow(size, true, 0, overflow, &done_label);
+                }
+            }
+        }
+    }
+
+    // BCD compares like unsigned integers. Decimal carry and borrow are
+    // those of the binary ones, and only add past the largest value or
+    // subtract below zero.
+    fn lower_bcd_binary(&mut self, op_type: &BinOp, bcd_type: BcdType, overflow: Overflow) {
+        let size = bcd_type.size();
+        let sum_op = match op_type {
+            BinOp::Sum(sum_op) => sum_op,
+            BinOp::Rel(rel_op) => {
+                self.lower_compare(rel_op, size, false);
+                return;
+            }
+            BinOp::Mult(_) => unreachable!(),
+        };
+        if self.context.target.has_decimal_mode() {
+            let (carry, op) = match sum_op {
+                SumOp::ADD => ("CLC", "ADC"),
+                SumOp::SUBTRACT => ("SEC", "SBC"),
+            };
+            self.emit("SED");
+            self.emit(carry);
+            for byte in 0..size {
+                self.emit(format!("LDA {},X", 2 + byte));
+                self.emit(format!("{} {},X", op, byte));
+                self.emit(format!("STA {},X", 2 + byte));
+            }
+            self.emit("CLD");
+            self.drop();
+        } else {
+            let routine = match sum_op {
+                SumOp::ADD => RuntimeRoutine::BcdAdd,
+                SumOp::SUBTRACT => RuntimeRoutine::BcdSub,
+            };
+            self.emit(format!("LDY #{}", size));
+            let label = self.context.runtime.require(routine);
+            self.emit(format!("JSR {}", label));
+            self.depth -= 1;
+        }
+        if overflow != Overflow::Wrapping {
+            let done_label = self.context.emitter.unique_label("nooverflow");
+            let limit = match sum_op {
+                SumOp::ADD => {
+                    self.emit(format!("BCC {}", done_label));
+                    0x9999
+                }
+                SumOp::SUBTRACT => {
+                    self.emit(format!("BCS {}", done_label));
+                    0
+                }
+            };
+            if overflow == Overflow::Saturating {
+                for byte in 0..size {
+                    self.emit(format!("LDA #${:02X}", (limit >> (8 * byte)) & 0xFF));
+                    self.emit(format!("STA {},X", byte));
+                }
+            } else {
+                self.call_overflow_trap();
+            }
+            self.context.emitter.label(&done_label);
+        }
+    }
+
+    // Floats are computed entirely by the runtime. Comparisons first turn
+    // both operands into integers that order the same way as unsigned.
+    fn lower_float_binary(&mut self, op_type: &BinOp) {
+        let routine = match op_type {
+            BinOp::Sum(SumOp::ADD) => RuntimeRoutine::FloatAdd,
+            BinOp::Sum(SumOp::SUBTRACT) => RuntimeRoutine::FloatSub,
+            BinOp::Mult(MulOp::MULTIPLY) => RuntimeRoutine::FloatMul,
+            BinOp::Mult(MulOp::DIVIDE) => RuntimeRoutine::FloatDiv,
+            BinOp::Rel(rel_op) => {
+                let label = self.context.runtime.require(RuntimeRoutine::FloatKeys);
+                self.emit(format!("JSR {}", label));
+                self.lower_compare(rel_op, 4, false);
+                return;
+            }
+        };
+        let label = self.context.runtime.require(routine);
+        self.emit(format!("JSR {}", label));
+        self.depth -= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::compile_source;
+    use crate::codegen::sim::{Access, Machine, Stop};
+    use crate::codegen::target::Target;
+    use crate::semantic::intrinsics::Overflow;
+
+    fn compile(source: &str) -> String {
+        compile_source(source, Overflow::Wrapping, Target::Mos6502)
+    }
+
+    // The instructions of the function with label `label`, up to the
+    // blank line after it
+    fn body<'a>(output: &'a str, label: &str) -> Vec<&'a str> {
+        output
+            .lines()
+            .skip_while(|line| *line != format!("{}:", label))
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .map(|line| line.trim())
+            .collect()
+    }
+
+    // Whether `run` appears in `body` as consecutive instructions
+    fn has_run(body: &[&str], run: &[&str]) -> bool {
+        body.windows(run.len()).any(|window| window == run)
+    }
+
+    const SAVE_REGISTERS: [&str; 6] = ["PHA", "TXA", "PHA", "TYA", "PHA", "CLD"];
+
+    const SAVE_SCRATCH: [&str; 12] = [
+        "LDA __tmp",
+        "PHA",
+        "LDA __tmp+1",
+        "PHA",
+        "LDA __tmp+2",
+        "PHA",
+        "LDA __tmp+3",
+        "PHA",
+        "LDA __ptr",
+        "PHA",
+        "LDA __ptr+1",
+        "PHA",
+    ];
+
+    const RESTORE: [&str; 18] = [
+        "PLA",
+        "STA __ptr+1",
+        "PLA",
+        "STA __ptr",
+        "PLA",
+        "STA __tmp+3",
+        "PLA",
+        "STA __tmp+2",
+        "PLA",
+        "STA __tmp+1",
+        "PLA",
+        "STA __tmp",
+        "PLA",
+        "TAY",
+        "PLA",
+        "TAX",
+        "PLA",
+        "RTI",
+    ];
+
+    const HANDLERS: &str = "var ticks: u8\n\
+         def tick interrupt(irq)() { ticks = ticks + 1\n return 0 }\n\
+         def frame interrupt(nmi)() { ticks = 0 }\n\
+         def main() { return 0 }";
+
+    #[test]
+    fn handlers_save_state_then_switch_to_their_own_stack() {
+        let output = compile(HANDLERS);
+        for (label, stack) in [("_tick", "__irq_stack"), ("_frame", "__nmi_stack")] {
+            let body = body(&output, label);
+            assert_eq!(body[..6], SAVE_REGISTERS);
+            assert_eq!(body[6..18], SAVE_SCRATCH);
+            assert_eq!(body[18], format!("LDX #<{}", stack));
+        }
+    }
+
+    #[test]
+    fn handlers_restore_state_and_return_with_rti() {
+        let output = compile(HANDLERS);
+        for label in ["_tick", "_frame"] {
+            let body = body(&output, label);
+            assert!(has_run(&body, &RESTORE), "{:?}", body);
+            assert_eq!(body.last(), Some(&"RTI"));
+            assert!(!body.contains(&"RTS"));
+        }
+    }
+
+    const EXTERNS: &str = "extern addxy(a: u8 in X, b: u8 in Y): u8 in A @ $F010\n\
+         extern swap(v: u16 in AX): u16 in XY @ $F020\n\
+         extern mem(v: u16 in $0300, w: u8 in Y): u16 in $0302 @ $F030\n\
+         extern beep() @ $F040\n\
+         extern chrout(c: u8 in A) @ CHROUT\n";
+
+    // Whether `f` in `source` has `run` in it, with the externs above
+    fn calls_with(source: &str, run: &[&str]) -> bool {
+        let output = compile(&format!("{}{}", EXTERNS, source));
+        has_run(&body(&output, "_f"), run)
+    }
+
+    #[test]
+    fn register_arguments_are_loaded_with_x_saved() {
+        assert!(calls_with(
+            "def f(x: u8): u8 { return addxy(x, 5 as u8) }",
+            &[
+                "TXA",
+                "PHA",
+                "LDY 0,X",
+                "LDA 2,X",
+                "TAX",
+                "JSR $F010",
+                "STA __tmp+0",
+                "PLA",
+                "TAX"
+            ],
+        ));
+    }
+
+    #[test]
+    fn a_is_set_aside_while_x_is_loaded() {
+        assert!(calls_with(
+            "def f(v: u16): u16 { return swap(v) }",
+            &[
+                "TXA",
+                "PHA",
+                "LDA 0,X",
+                "PHA",
+                "LDA 1,X",
+                "TAX",
+                "PLA",
+                "JSR $F020",
+                "STX __tmp+0",
+                "STY __tmp+1",
+                "PLA",
+                "TAX",
+            ],
+        ));
+    }
+
+    #[test]
+    fn memory_arguments_and_results_are_copied() {
+        assert!(calls_with(
+            "def f(v: u16): u16 { return mem(v, 3 as u8) }",
+            &[
+                "LDA 2,X",
+                "STA $0300",
+                "LDA 3,X",
+                "STA $0301",
+                "TXA",
+                "PHA",
+                "LDY 0,X",
+                "JSR $F030",
+                "PLA",
+                "TAX",
+            ],
+        ));
+        assert!(calls_with(
+            "def f(v: u16): u16 { return mem(v, 3 as u8) }",
+            &["LDA $0302", "STA 0,X", "LDA $0303", "STA 1,X"],
+        ));
+    }
+
+    #[test]
+    fn fixed_addresses_are_called_directly_and_symbols_imported() {
+        let output = compile(&format!(
+            "{}def f(c: u8): u8 {{ beep()\n chrout(c)\n return c }}",
+            EXTERNS
+        ));
+        let body = body(&output, "_f");
+        assert!(has_run(&body, &["TXA", "PHA", "JSR $F040", "PLA", "TAX"]));
+        assert!(has_run(
+            &body,
+            &["TXA", "PHA", "LDA 0,X", "JSR CHROUT", "PLA", "TAX"]
+        ));
+        assert!(output.contains(".import CHROUT\n"));
+        assert!(!output.contains(".import $F040"));
+    }
+
+    #[test]
+    fn x_is_restored_before_the_result_is_stored() {
+        let output = compile(&format!(
+            "{}def f(x: u8): u8 {{ return addxy(x, x) }}",
+            EXTERNS
+        ));
+        let body = body(&output, "_f");
+        let jsr = body.iter().position(|line| *line == "JSR $F010").unwrap();
+        let first_store = body[jsr..]
+            .iter()
+            .position(|line| line.ends_with(",X"))
+            .unwrap();
+        let restore = body[jsr..].iter().position(|line| *line == "TAX").unwrap();
+        assert!(restore < first_store);
+    }
+
+    // The instructions either side of the label the asm block in `f`
+    // starts with, and the text of the block
+    fn around_asm(source: &str, text: usize) -> (Vec<String>, Vec<String>) {
+        let output = compile(source);
+        let body = body(&output, "_f");
+        let start = body
+            .iter()
+            .position(|line| line.starts_with("__asm_"))
+            .unwrap();
+        let end = start + 1 + text;
+        let owned = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect();
+        (owned(&body[..start]), owned(&body[end..]))
+    }
+
+    #[test]
+    fn clobbering_x_saves_it_around_the_block() {
+        let (before, after) =
+            around_asm("def f(): u8 { asm clobbers(X) { LDX #0 }\n return 0 }", 1);
+        assert_eq!(before[before.len() - 2..], ["TXA", "PHA"]);
+        assert_eq!(after[..2], ["PLA", "TAX"]);
+    }
+
+    #[test]
+    fn x_bindings_are_loaded_and_stored_with_x_saved() {
+        let (before, after) = around_asm(
+            "def f(n: u8): u8 { var c: u8 = 0\n asm(n: X, out c: Y) { LDY #0\n DEX }\n return c }",
+            2,
+        );
+        assert_eq!(before[before.len() - 4..], ["TXA", "PHA", "LDA 2,X", "TAX"]);
+        assert_eq!(after[..3], ["PLA", "TAX", "STY 0,X"]);
+
+        let (before, after) = around_asm(
+            "def f(n: u8, m: u8): u8 { var r: u8 = 0\n asm(n: A, m: X, out r: X) { STA __tmp }\n return r }",
+            1,
+        );
+        assert_eq!(
+            before[before.len() - 7..],
+            ["TXA", "PHA", "LDA 4,X", "PHA", "LDA 2,X", "TAX", "PLA"]
+        );
+        assert_eq!(
+            after[..5],
+            ["STX __ptr+1", "PLA", "TAX", "LDA __ptr+1", "STA 0,X"]
+        );
+    }
+
+    #[test]
+    fn other_bindings_leave_x_alone() {
+        let (before, after) = around_asm(
+            "def f(a: u8, b: u8): u8 { var r: u8 = 0\n asm(a: A, b: Y, out r: A) { STY __tmp }\n return r }",
+            1,
+        );
+        assert_eq!(before[before.len() - 2..], ["LDY 2,X", "LDA 4,X"]);
+        assert_eq!(after[0], "STA 0,X");
+        assert!(!before.iter().chain(&after).any(|line| line == "PHA"));
+    }
+
+    // The body of `def f(a: T, b: T): T` returning `a op b`
+    fn operator_body(ty: &str, op: &str, overflow: Overflow, target: Target) -> Vec<String> {
+        let source = format!("def f(a: {0}, b: {0}): {0}\n return a {1} b", ty, op);
+        let output = compile_source(&source, overflow, target);
+        body(&output, "_f")
+            .iter()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    // The lines from the first one starting with `first`
+    fn from<'a>(body: &'a [String], first: &str) -> Vec<&'a str> {
+        body.iter()
+            .skip_while(|line| !line.starts_with(first))
+            .map(|line| line.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn fixed_products_and_quotients_call_the_runtime() {
+        let cases = [
+            ("f8x8", "*", "__fxmul8"),
+            ("f8x8", "/", "__fxdiv8"),
+            ("f16x16", "*", "__fxmul16"),
+            ("f16x16", "/", "__fxdiv16"),
+        ];
+        for (ty, op, routine) in cases.iter() {
+            let source = format!("def f(a: {0}, b: {0}): {0}\n return a {1} b", ty, op);
+            let output = compile(&source);
+            assert!(body(&output, "_f").contains(&format!("JSR {}", routine).as_str()));
+            assert!(output.contains(&format!("\n{}:\n", routine)));
+        }
+        for op in ["+", "-"].iter() {
+            let body = operator_body("f8x8", op, Overflow::Checked, Target::Mos6502);
+            assert!(
+                !body.iter().any(|line| line.starts_with("JSR __fx")),
+                "{}",
+                op
+            );
+        }
+        let output = compile("def f(a: f16x16, b: f16x16): u8\n return a < b");
+        assert!(!body(&output, "_f")
+            .iter()
+            .any(|line| line.starts_with("JSR")));
+    }
+
+    #[test]
+    fn fixed_overflow_is_taken_from_carry() {
+        let body = operator_body("f8x8", "*", Overflow::Wrapping, Target::Mos6502);
+        assert_eq!(from(&body, "JSR")[1], "LDA 1,X");
+
+        let body = operator_body("f8x8", "*", Overflow::Checked, Target::Mos6502);
+        let after = from(&body, "JSR");
+        assert!(after[1].starts_with("BCC __nooverflow_"));
+        assert_eq!(after[2], "JSR __overflow_trap");
+        assert_eq!(after[3], format!("{}:", &after[1][4..]));
+
+        // The sign of the result is worked out before the operands are
+        // consumed, and tells which limit to saturate to
+        let body = operator_body("f16x16", "/", Overflow::Saturating, Target::Mos6502);
+        let before = from(&body, "LDA 3,X");
+        assert_eq!(before[..4], ["LDA 3,X", "EOR 7,X", "PHA", "JSR __fxdiv16"]);
+        assert_eq!(before[4], "PLA");
+        assert!(before[5].starts_with("BCC __nooverflow_"));
+        assert!(before[6].starts_with("BMI __smallest_"));
+        assert_eq!(before[7..9], ["LDA #$FF", "STA 0,X"]);
+    }
+
+    #[test]
+    fn float_operators_call_the_runtime() {
+        let cases = [
+            ("+", "__fadd"),
+            ("-", "__fsub"),
+            ("*", "__fmul"),
+            ("/", "__fdiv"),
+        ];
+        for (op, routine) in cases.iter() {
+            let output = compile(&format!("def f(a: f32, b: f32): f32\n return a {} b", op));
+            let call = format!("JSR {}", routine);
+            assert!(body(&output, "_f").contains(&call.as_str()), "{}", op);
+            // Every result is packed back into a float the same way
+            assert!(output.contains("\n__fpack:\n"), "{}", op);
+        }
+        let output = compile("def f(a: f32): f32\n return a - 1");
+        assert!(output.contains("\n__fadd:\n"));
+    }
+
+    #[test]
+    fn float_comparisons_compare_keys() {
+        let output = compile("def f(a: f32, b: f32): u8\n return a < b");
+        let calls: Vec<&str> = body(&output, "_f")
+            .into_iter()
+            .filter(|line| line.starts_with("JSR"))
+            .collect();
+        assert_eq!(calls, ["JSR __fkeys"]);
+        assert!(!output.contains("\n__fpack:\n"));
+    }
+
+    #[test]
+    fn bcd_sums_use_decimal_mode_where_the_target_has_it() {
+        let body = operator_body("bcd16", "+", Overflow::Wrapping, Target::Mos6502);
+        assert_eq!(
+            from(&body, "SED")[..9],
+            [
+                "SED", "CLC", "LDA 2,X", "ADC 0,X", "STA 2,X", "LDA 3,X", "ADC 1,X", "STA 3,X",
+                "CLD"
+            ]
+        );
+        let body = operator_body("bcd8", "-", Overflow::Wrapping, Target::Mos6502);
+        assert_eq!(
+            from(&body, "SED")[..6],
+            ["SED", "SEC", "LDA 2,X", "SBC 0,X", "STA 2,X", "CLD"]
+        );
+
+        let body = operator_body("bcd16", "+", Overflow::Wrapping, Target::Nes2A03);
+        assert!(!body.iter().any(|line| line == "SED"));
+        assert_eq!(from(&body, "LDY")[..2], ["LDY #2", "JSR __bcdadd"]);
+        let body = operator_body("bcd8", "-", Overflow::Wrapping, Target::Nes2A03);
+        assert_eq!(from(&body, "LDY")[..2], ["LDY #1", "JSR __bcdsub"]);
+    }
+
+    #[test]
+    fn bcd_overflow_is_taken_from_carry() {
+        for target in [Target::Mos6502, Target::Nes2A03].iter() {
+            let body = operator_body("bcd8", "+", Overflow::Checked, *target);
+            let after = from(&body, "BCC");
+            assert!(after[0].starts_with("BCC __nooverflow_"));
+            assert_eq!(after[1], "JSR __overflow_trap");
+            assert_eq!(after[2], format!("{}:", &after[0][4..]));
+
+            // Subtracting borrows when carry is clear
+            let body = operator_body("bcd16", "-", Overflow::Saturating, *target);
+            let after = from(&body, "BCS");
+            assert!(after[0].starts_with("BCS __nooverflow_"));
+            assert_eq!(after[1..5], ["LDA #$00", "STA 0,X", "LDA #$00", "STA 1,X"]);
+            assert_eq!(after[5], format!("{}:", &after[0][4..]));
+        }
+    }
+
+    // Runs `a / b` on values of the signed type `ty` in a simulated 6502
+    fn divide(ty: &str, overflow: Overflow, left: i64, right: i64) -> Result<i64, Stop> {
+        let source = format!("def f(a: {0}, b: {0}): {0}\n return a / b", ty);
+        let output = compile_source(&source, overflow, Target::Mos6502);
+        let size = if ty == "i8" { 1 } else { 2 };
+        let mask = (1 << (8 * size)) - 1;
+        let mut machine = Machine::new(&output, Target::Mos6502);
+        let result = machine.call("_f", &[(left & mask, size), (right & mask, size)], size)?;
+        Ok(if result > mask >> 1 {
+            result - mask - 1
+        } else {
+            result
+        })
+    }
+
+    #[test]
+    fn signed_quotients_round_toward_zero() {
+        for ty in ["i8", "i16"].iter() {
+            for overflow in [Overflow::Wrapping, Overflow::Checked, Overflow::Saturating].iter() {
+                for (left, right) in [(-7, 2), (7, -2), (-8, -2), (-1, 2), (0, -5)].iter() {
+                    assert_eq!(
+                        divide(ty, *overflow, *left, *right),
+                        Ok(left / right),
+                        "{} {:?} {} / {}",
+                        ty,
+                        overflow,
+                        left,
+                        right
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn smallest_signed_value_divided_by_minus_one_overflows() {
+        assert_eq!(divide("i8", Overflow::Wrapping, -128, -1), Ok(-128));
+        assert_eq!(divide("i8", Overflow::Saturating, -128, -1), Ok(127));
+        assert_eq!(
+            divide("i8", Overflow::Checked, -128, -1),
+            Err(Stop::Trapped)
+        );
+        assert_eq!(divide("i16", Overflow::Wrapping, -32768, -1), Ok(-32768));
+        assert_eq!(divide("i16", Overflow::Saturating, -32768, -1), Ok(32767));
+        assert_eq!(
+            divide("i16", Overflow::Checked, -32768, -1),
+            Err(Stop::Trapped)
+        );
+        assert_eq!(divide("i16", Overflow::Checked, -32768, 1), Ok(-32768));
+        assert_eq!(divide("i16", Overflow::Checked, -32767, -1), Ok(32767));
+    }
+
+    #[test]
+    fn signed_division_checks_overflow_unless_wrapping() {
+        let body = operator_body("i16", "/", Overflow::Wrapping, Target::Mos6502);
+        assert!(!body.iter().any(|line| line.starts_with("__nooverflow_")));
+        assert!(!body.iter().any(|line| line == "PHA"));
+
+        let body = operator_body("i16", "/", Overflow::Checked, Target::Mos6502);
+        let after = from(&body, "LDA 1,X");
+        assert_eq!(
+            after[..5],
+            ["LDA 1,X", "EOR 3,X", "PHA", "JSR __sdiv16", "PLA"]
+        );
+        // A negative quotient always fits
+        assert!(after[5].starts_with("BMI __nooverflow_"));
+        let done = &after[5][4..];
+        assert_eq!(
+            after[6..10],
+            [
+                "LDA 1,X",
+                &format!("BPL {}", done),
+                "JSR __overflow_trap",
+                &format!("{}:", done)
+            ]
+        );
+
+        let body = operator_body("i8", "/", Overflow::Saturating, Target::Mos6502);
+        let after = from(&body, "JSR __sdiv16");
+        assert!(after[7].starts_with("BEQ __nooverflow_"));
+        assert_eq!(after[8], "LDA 1,X");
+        assert!(after[9].starts_with("BMI __smallest_"));
+        assert_eq!(after[10..12], ["LDA #$7F", "STA 0,X"]);
+
+        // Unsigned quotients always fit
+        let body = operator_body("u16", "/", Overflow::Checked, Target::Mos6502);
+        assert!(!body
+            .iter()
+            .any(|line| line.starts_with("BCC") || line.starts_with("BPL")));
+    }
+
+    #[test]
+    fn signed_division_overflow_calls_the_programs_trap() {
+        let source = "var trapped: u8\n\
+             def overflowTrap() { trapped = trapped + 1\n return 0 }\n\
+             def f(a: i16, b: i16): i16\n return a / b";
+        let output = compile_source(source, Overflow::Checked, Target::Mos6502);
+        let body = body(&output, "_f");
+        assert!(has_run(&body, &["JSR _overflowTrap", "INX", "INX"]));
+        assert!(!output.contains("__overflow_trap"));
+    }
+
+    const POINTERS: &str = "var src: [u8; 4]\n\
+         var dst: [u8; 4]\n\
+         var words: [u16; 3]\n\
+         var cursor: *mut u8\n\
+         const msg: [u8; 4] = [72, 105, 33, 0]\n\
+         def copy(to: *mut u8, from: *u8, n: u8) {\n\
+             if (n == 0) return 0;\n\
+             to[n - 1] = from[n - 1]\n\
+             return copy(to, from, n - 1)\n\
+         }\n\
+         def run() return copy(&dst, &src, 4)\n\
+         def fill(i: u8, v: u8) {\n\
+             cursor = &src\n\
+             cursor[i] = v\n\
+             *(cursor + 3) = v + 1\n\
+             return 0\n\
+         }\n\
+         def strlen(s: *u8): u16 {\n\
+             if (*s == 0) return 0\n\
+             return 1 + strlen(s + 1)\n\
+         }\n\
+         def msglen(): u16 return strlen(&msg)\n\
+         def sum(p: *u16, n: u16): u16 {\n\
+             if (n == 0) return 0;\n\
+             return p[n - 1] + sum(p, n - 1)\n\
+         }\n\
+         def total(): u16 return sum(&words, 3)\n";
+
+    #[test]
+    fn zero_page_pointers_are_used_in_place() {
+        let output = compile(POINTERS);
+        let zero_page = body(&output, "__ptr");
+        assert_eq!(zero_page[..2], [".res 2", "_cursor:"]);
+        assert!(has_run(
+            &body(&output, "_fill"),
+            &["LDY 2,X", "LDA 0,X", "STA (_cursor),Y"]
+        ));
+    }
+
+    #[test]
+    fn other_pointers_are_copied_to_ptr() {
+        let output = compile(POINTERS);
+        assert!(has_run(
+            &body(&output, "_strlen"),
+            &[
+                "LDY #0",
+                "LDA 2,X",
+                "STA __ptr",
+                "LDA 3,X",
+                "STA __ptr+1",
+                "LDA (__ptr),Y"
+            ]
+        ));
+        // Each byte of a wider value is a step of Y
+        assert!(has_run(
+            &body(&output, "_sum"),
+            &[
+                "LDA (__ptr),Y",
+                "STA 0,X",
+                "INY",
+                "LDA (__ptr),Y",
+                "STA 1,X"
+            ]
+        ));
+    }
+
+    #[test]
+    fn pointers_read_and_write_what_they_point_at() {
+        let output = compile(POINTERS);
+        let mut machine = Machine::new(&output, Target::Mos6502);
+        let src = machine.address("_src");
+        let dst = machine.address("_dst");
+        machine.write(src, 4, 0x0403_0201);
+        assert_eq!(machine.call("_run", &[], 2), Ok(0));
+        assert_eq!(machine.read(dst, 4), 0x0403_0201);
+
+        assert_eq!(machine.call("_fill", &[(1, 1), (9, 1)], 2), Ok(0));
+        assert_eq!(machine.read(src, 4), 0x0A03_0901);
+        assert_eq!(machine.read(machine.address("_cursor"), 2), src as i64);
+
+        assert_eq!(machine.call("_msglen", &[], 2), Ok(3));
+        let words = machine.address("_words");
+        machine.write(words, 6, 0x0300_0200_0100);
+        assert_eq!(machine.call("_total", &[], 2), Ok(0x0600));
+    }
+
+    const STRUCTS: &str = "struct Sprite { y: u8, tile: u8, attr: u8, x: u8 }\n\
+         struct Ent soa { x: u8, speed: u16, hp: u8 }\n\
+         struct Pt { a: u16, b: u8, c: u16 }\n\
+         var oam: [Sprite; 4]\n\
+         var ents: [Ent; 5]\n\
+         var pts: [Pt; 3]\n\
+         def setSprite(i: u8, x: u8, y: u8) {\n\
+             oam[i].x = x\n\
+             oam[i].y = y\n\
+             return 0\n\
+         }\n\
+         def spriteX(i: u8): u8 return oam[i].x\n\
+         def setEnt(i: u8, speed: u16, hp: u8) {\n\
+             ents[i].speed = speed\n\
+             ents[i].hp = hp\n\
+             return 0\n\
+         }\n\
+         def entSpeed(i: u8): u16 return ents[i].speed + ents[i].hp as u16\n\
+         def setC(p: *mut Pt, i: u8, v: u16) {\n\
+             p[i].c = v\n\
+             p.b = 7\n\
+             return 0\n\
+         }\n\
+         def run(v: u16) return setC(&pts, 2, v)\n";
+
+    #[test]
+    fn array_of_structs_fields_are_offsets_into_elements() {
+        let output = compile(STRUCTS);
+        assert!(has_run(
+            &body(&output, "_spriteX"),
+            &["ASL A", "ASL A", "TAY", "LDA _oam+3,Y"]
+        ));
+        assert!(body(&output, "_setSprite").contains(&"STA _oam+0,Y"));
+    }
+
+    #[test]
+    fn structure_of_arrays_fields_are_arrays_of_their_own() {
+        let output = compile(STRUCTS);
+        let body = body(&output, "_entSpeed");
+        assert!(has_run(
+            &body,
+            &[
+                "ASL A",
+                "TAY",
+                "LDA _ents+5,Y",
+                "STA 0,X",
+                "LDA _ents+5+1,Y"
+            ]
+        ));
+        assert!(has_run(&body, &["TAY", "LDA _ents+15,Y"]));
+    }
+
+    #[test]
+    fn fields_are_laid_out_as_declared() {
+        let output = compile(STRUCTS);
+        let mut machine = Machine::new(&output, Target::Mos6502);
+        let oam = machine.address("_oam");
+        assert_eq!(
+            machine.call("_setSprite", &[(2, 1), (0x40, 1), (0x80, 1)], 2),
+            Ok(0)
+        );
+        assert_eq!(machine.read(oam + 8, 4), 0x4000_0080);
+        assert_eq!(machine.call("_spriteX", &[(2, 1)], 1), Ok(0x40));
+
+        // Five of each field, one after the other
+        let ents = machine.address("_ents");
+        assert_eq!(
+            machine.call("_setEnt", &[(3, 1), (0x1234, 2), (9, 1)], 2),
+            Ok(0)
+        );
+        assert_eq!(machine.read(ents + 5 + 6, 2), 0x1234);
+        assert_eq!(machine.read(ents + 15 + 3, 1), 9);
+        assert_eq!(machine.call("_entSpeed", &[(3, 1)], 2), Ok(0x123D));
+
+        let pts = machine.address("_pts");
+        assert_eq!(machine.call("_run", &[(0xBEEF, 2)], 2), Ok(0));
+        assert_eq!(machine.read(pts + 2 * 5 + 3, 2), 0xBEEF);
+        assert_eq!(machine.read(pts + 2, 1), 7);
+    }
+
+    const VOLATILE: &str = "var PPUADDR: volatile u8 @ $2006\n\
+         var PPUDATA: volatile u8 @ $2007\n\
+         var TIMER: volatile u16 @ $D004\n\
+         def writeTile(hi: u8, lo: u8, tile: u8) {\n\
+             PPUADDR = hi\n\
+             PPUADDR = lo\n\
+             PPUDATA = tile\n\
+             PPUDATA = tile\n\
+             return 0\n\
+         }\n\
+         def twice(): u8 return PPUDATA + PPUDATA\n\
+         def timer(): u16 return TIMER\n\
+         def pk(a: u16): u8 return peek(a) + peek($C000)\n\
+         def pp(a: u16, v: u8): u8 {\n\
+             poke($C001, v)\n\
+             return poke(a, v + 1)\n\
+         }\n";
+
+    #[test]
+    fn fixed_addresses_are_accessed_absolutely() {
+        let output = compile(VOLATILE);
+        assert!(output.contains("\n_PPUADDR = $2006\n"));
+        assert!(output.contains("\n_TIMER = $D004\n"));
+        let stores: Vec<&str> = body(&output, "_writeTile")
+            .into_iter()
+            .filter(|line| line.starts_with("STA _"))
+            .collect();
+        assert_eq!(
+            stores,
+            [
+                "STA _PPUADDR",
+                "STA _PPUADDR",
+                "STA _PPUDATA",
+                "STA _PPUDATA"
+            ]
+        );
+        assert!(has_run(
+            &body(&output, "_timer"),
+            &["LDA _TIMER", "STA 0,X", "LDA _TIMER+1", "STA 1,X"]
+        ));
+        assert!(body(&output, "_pk").contains(&"LDA $C000"));
+        assert!(body(&output, "_pp").contains(&"STA $C001"));
+    }
+
+    #[test]
+    fn volatile_accesses_all_happen_in_source_order() {
+        let output = compile(VOLATILE);
+        let mut machine = Machine::new(&output, Target::Mos6502);
+        let ppu = [0x2006, 0x2007];
+        machine
+            .call("_writeTile", &[(0x20, 1), (0x40, 1), (7, 1)], 2)
+            .unwrap();
+        assert_eq!(
+            machine.accesses(&ppu),
+            [
+                Access::Write(0x2006, 0x20),
+                Access::Write(0x2006, 0x40),
+                Access::Write(0x2007, 7),
+                Access::Write(0x2007, 7)
+            ]
+        );
+        assert_eq!(machine.call("_twice", &[], 1), Ok(14));
+        assert_eq!(
+            machine.accesses(&ppu),
+            [Access::Read(0x2007), Access::Read(0x2007)]
+        );
+
+        let mapped = [0x0400, 0xC000, 0xC001];
+        machine.write(0xC000, 1, 5);
+        machine.write(0x0400, 1, 3);
+        assert_eq!(machine.call("_pk", &[(0x0400, 2)], 1), Ok(8));
+        assert_eq!(
+            machine.accesses(&mapped),
+            [Access::Read(0x0400), Access::Read(0xC000)]
+        );
+        assert_eq!(machine.call("_pp", &[(0x0400, 2), (9, 1)], 1), Ok(10));
+        assert_eq!(
+            machine.accesses(&mapped),
+            [Access::Write(0xC001, 9), Access::Write(0x0400, 10)]
+        );
+    }
+}
@@ -0,0 +1,376 @@
+pub mod emitter;
+pub mod expr;
+pub mod runtime;
+#[cfg(test)]
+pub mod sim;
+pub mod stack;
+pub mod target;
+
+use std::collections::HashMap;
+
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::types::Type;
+
+use crate::semantic::declarations;
+use crate::semantic::externs;
+use crate::semantic::interrupts;
+use crate::semantic::interrupts::Vector;
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::intrinsics::OVERFLOW_TRAP;
+use crate::semantic::mmio;
+use crate::semantic::names::ResolutionTable;
+use crate::semantic::typeck::TypeTable;
+use crate::semantic::Analysis;
+
+use crate::codegen::emitter::Emitter;
+use crate::codegen::expr::FunctionLowering;
+use crate::codegen::runtime::Runtime;
+use crate::codegen::stack::Frame;
+use crate::codegen::stack::StackUsage;
+use crate::codegen::target::Target;
+
+// Bytes of zero page reserved for the data stack
+pub const DATA_STACK_SIZE: usize = 64;
+
+// Values per line of a data table
+pub const TABLE_ROW_LENGTH: usize = 16;
+
+pub struct CodegenContext<'a> {
+    pub emitter: Emitter,
+    pub runtime: Runtime,
+    pub globals: HashMap<String, &'a GlobalDecl>,
+    // Externs called in place rather than through the data stack
+    pub externs: HashMap<String, &'a FuncDecl>,
+    pub types: &'a TypeTable,
+    pub names: &'a ResolutionTable,
+    // What `+`, `-` and `*` do on overflow
+    pub overflow: Overflow,
+    pub target: Target,
+    // Whether the program has its own overflow trap
+    pub has_overflow_trap: bool,
+    // What each function lowered so far pushes
+    pub frames: HashMap<String, Frame>,
+}
+
+// Kaleidoscope symbols get a leading underscore so they can never
+// collide with mnemonics or the compiler's own `__` labels
+pub fn symbol_name(name: &str) -> String {
+    format!("_{}", name)
+}
+
+// Where the data stack of the handler for `vector` starts
+pub fn handler_stack(vector: Vector) -> String {
+    match vector {
+        Vector::Nmi => String::from("__nmi_stack"),
+        Vector::Irq => String::from("__irq_stack"),
+    }
+}
+
+fn element_type(ty: &Type) -> &Type {
+    match ty {
+        Type::Array { element, length: _ } => element_type(element),
+        _ => ty,
+    }
+}
+
+// Constants go in ROM where tables are read with abs,Y addressing
+fn emit_constant(emitter: &mut Emitter, global: &GlobalDecl, values: &[f64]) {
+    let element = element_type(&global.ty);
+    let directive = match element.size() {
+        4 => ".dword",
+        2 => ".word",
+        _ => ".byte",
+    };
+    let mask = (1i64 << (8 * element.size())) - 1;
+    let values: Vec<i64> = values.iter().map(|value| element.encode(*value)).collect();
+
+    emitter.label(&symbol_name(&global.name));
+    for row in values.chunks(TABLE_ROW_LENGTH) {
+        let row: Vec<String> = row
+            .iter()
+            .map(|value| format!("${:X}", value & mask))
+            .collect();
+        emitter.instruction(format!("{} {}", directive, row.join(", ")));
+    }
+}
+
+// Pointer variables go in zero page so (zp),Y can use them in place
+pub fn in_zero_page(global: &GlobalDecl) -> bool {
+    !global.is_const && global.ty.is_pointer() && global.address.is_none()
+}
+
+// Variables at a fixed address take no space in any segment
+fn in_bss(global: &GlobalDecl) -> bool {
+    !global.is_const && !in_zero_page(global) && global.address.is_none()
+}
+
+fn emit_variable(emitter: &mut Emitter, global: &GlobalDecl) {
+    emitter.label(&symbol_name(&global.name));
+    emitter.instruction(format!(".res {}", global.ty.size()));
+}
+
+// Returns the assembly, and the stack use it was measured to have
+pub fn generate<'a>(
+    program: &'a Program,
+    analysis: &'a Analysis,
+    overflow: Overflow,
+    target: Target,
+) -> (String, StackUsage) {
+    let mut context = CodegenContext {
+        emitter: Emitter::new(),
+        runtime: Runtime::new(),
+        globals: HashMap::new(),
+        externs: HashMap::new(),
+        types: &analysis.types,
+        names: &analysis.names,
+        overflow,
+        target,
+        has_overflow_trap: false,
+        frames: HashMap::new(),
+    };
+
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            }
+            | PrimaryStatement::Extern(decl)
+                if decl.name == OVERFLOW_TRAP =>
+            {
+                context.has_overflow_trap = true;
+                if decl.is_foreign() {
+                    context.externs.insert(decl.name.clone(), decl);
+                }
+            }
+            PrimaryStatement::Global(global) => {
+                context.globals.insert(global.name.clone(), global);
+            }
+            PrimaryStatement::Extern(decl) if decl.is_foreign() => {
+                context.externs.insert(decl.name.clone(), decl);
+            }
+            _ => {}
+        }
+    }
+
+    context.emitter.comment("Generated by comp");
+    for primary in program.primaries() {
+        match primary {
+            // A definition later in the program is exported instead
+            PrimaryStatement::Extern(decl) if declarations::is_defined(program, &decl.name) => {}
+            PrimaryStatement::Extern(decl) => {
+                if let Some(symbol) = externs::imported_symbol(decl) {
+                    context.emitter.instruction(format!(".import {}", symbol));
+                }
+            }
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            } => {
+                context
+                    .emitter
+                    .instruction(format!(".export {}", symbol_name(&decl.name)));
+            }
+            PrimaryStatement::Global(_) | PrimaryStatement::Struct(_) => {}
+        }
+    }
+
+    let globals: Vec<&GlobalDecl> = program
+        .primaries()
+        .iter()
+        .filter_map(|primary| match primary {
+            PrimaryStatement::Global(global) => Some(global),
+            _ => None,
+        })
+        .collect();
+
+    // Fixed addresses come first, like zero page below, so that the
+    // assembler knows their size wherever they are used
+    if globals.iter().any(|global| global.address.is_some()) {
+        context.emitter.blank();
+        for global in &globals {
+            if let Some(address) = mmio::address(global) {
+                context
+                    .emitter
+                    .equate(&symbol_name(&global.name), &format!("${:04X}", address));
+            }
+        }
+    }
+
+    context.emitter.blank();
+    context.emitter.segment("ZEROPAGE");
+    context.emitter.label("__dstack");
+    context
+        .emitter
+        .instruction(format!(".res {}", DATA_STACK_SIZE));
+    context.emitter.label("__tmp");
+    context.emitter.instruction(".res 4");
+    context.emitter.label("__ptr");
+    context.emitter.instruction(".res 2");
+
+    // Declared before any code so that the assembler knows they are
+    // in zero page wherever they are used
+    for global in globals.iter().filter(|global| in_zero_page(global)) {
+        emit_variable(&mut context.emitter, global);
+    }
+
+    context.emitter.blank();
+    context.emitter.segment("CODE");
+
+    let has_main = program.primaries().iter().any(|primary| match primary {
+        PrimaryStatement::Definition {
+            decl,
+            inner_statement: _,
+        } => decl.name == "main" && decl.args.is_empty(),
+        _ => false,
+    });
+    if has_main {
+        context.emitter.instruction(".export __reset");
+        context.emitter.label("__reset");
+        context.emitter.instruction("SEI");
+        context.emitter.instruction("CLD");
+        context.emitter.instruction("LDX #$FF");
+        context.emitter.instruction("TXS");
+        context
+            .emitter
+            .instruction(format!("LDX #<(__dstack + {})", DATA_STACK_SIZE));
+        context
+            .emitter
+            .instruction(format!("JSR {}", symbol_name("main")));
+        context.emitter.label("@halt");
+        context.emitter.instruction("JMP @halt");
+    }
+
+    // Vectors without a handler return straight away
+    let handler = |vector: Vector| {
+        program
+            .primaries()
+            .iter()
+            .find_map(|primary| match primary {
+                PrimaryStatement::Definition {
+                    decl,
+                    inner_statement: _,
+                } if interrupts::interrupt_vector(decl) == Some(vector) => Some(decl.name.clone()),
+                _ => None,
+            })
+    };
+    let nmi_name = handler(Vector::Nmi);
+    let irq_name = handler(Vector::Irq);
+    let nmi = nmi_name.as_deref().map(symbol_name);
+    let irq = irq_name.as_deref().map(symbol_name);
+    if has_main && (nmi.is_none() || irq.is_none()) {
+        context.emitter.label("__rti");
+        context.emitter.instruction("RTI");
+    }
+
+    for primary in program.primaries() {
+        if let PrimaryStatement::Definition {
+            decl,
+            inner_statement,
+        } = primary
+        {
+            FunctionLowering::new(&mut context, decl).lower(inner_statement);
+        }
+    }
+    context.runtime.emit(&mut context.emitter);
+
+    if globals.iter().any(|global| global.is_const) {
+        context.emitter.blank();
+        context.emitter.segment("RODATA");
+        for global in globals.iter().filter(|global| global.is_const) {
+            emit_constant(
+                &mut context.emitter,
+                global,
+                &analysis.initializers[&global.name],
+            );
+        }
+    }
+
+    if globals.iter().any(|global| in_bss(global)) {
+        context.emitter.blank();
+        context.emitter.segment("BSS");
+        for global in globals.iter().filter(|global| in_bss(global)) {
+            emit_variable(&mut context.emitter, global);
+        }
+    }
+
+    if has_main {
+        let default = String::from("__rti");
+        context.emitter.blank();
+        context.emitter.segment("VECTORS");
+        context.emitter.instruction(format!(
+            ".addr {}, __reset, {}",
+            nmi.unwrap_or(default.clone()),
+            irq.unwrap_or(default)
+        ));
+    }
+
+    let usage = StackUsage::measure(program, &context.frames, &context.runtime, &analysis.calls);
+
+    // Handlers cannot rely on X, which foreign calls and asm blocks
+    // may be using. The IRQ handler's stack starts below what `main`
+    // needs, and the NMI handler's below what the IRQ handler needs.
+    let mut base = DATA_STACK_SIZE.saturating_sub(usage.data_need("main"));
+    for (vector, handler) in [(Vector::Irq, &irq_name), (Vector::Nmi, &nmi_name)] {
+        if let Some(name) = handler {
+            context
+                .emitter
+                .equate(&handler_stack(vector), &format!("__dstack + {}", base));
+            base = base.saturating_sub(usage.data_need(name));
+        }
+    }
+    usage.emit(&mut context.emitter);
+    (context.emitter.finish(), usage)
+}
+
+#[cfg(test)]
+pub fn compile_source(source: &str, overflow: Overflow, target: Target) -> String {
+    let (program, analysis) = crate::semantic::check_source(source, overflow);
+    generate(&program, &analysis, overflow, target).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(source: &str) -> String {
+        compile_source(source, Overflow::Wrapping, Target::Mos6502)
+    }
+
+    #[test]
+    fn vectors_default_to_a_bare_rti() {
+        let output = compile("def main() { return 0 }");
+        assert!(output.contains("__rti:\n    RTI\n"));
+        assert!(output.contains(".addr __rti, __reset, __rti\n"));
+    }
+
+    #[test]
+    fn vectors_name_the_handlers() {
+        let output = compile(
+            "def tick interrupt(irq)() { return 0 }\n\
+             def frame interrupt(nmi)() { return 0 }\n\
+             def main() { return 0 }",
+        );
+        assert!(output.contains(".addr _frame, __reset, _tick\n"));
+        assert!(!output.contains("__rti:"));
+    }
+
+    #[test]
+    fn handler_stacks_start_below_what_they_interrupt() {
+        let (program, analysis) = crate::semantic::check_source(
+            "def tick interrupt(irq)() { var a = 1\n return a }\n\
+             def frame interrupt(nmi)() { return 0 }\n\
+             def main() { var a = 1\n var b = 2\n return a + b }",
+            Overflow::Wrapping,
+        );
+        let (output, usage) = generate(&program, &analysis, Overflow::Wrapping, Target::Mos6502);
+        let irq = DATA_STACK_SIZE - usage.data_need("main");
+        let nmi = irq - usage.data_need("tick");
+        assert!(nmi < irq && irq < DATA_STACK_SIZE);
+        assert!(output.contains(&format!("__irq_stack = __dstack + {}\n", irq)));
+        assert!(output.contains(&format!("__nmi_stack = __dstack + {}\n", nmi)));
+    }
+}
@@ -0,0 +1,2005 @@
+use crate::codegen::emitter::Emitter;
+
+// Helper routines that are only linked into the output when the
+// generated code calls them. They all operate on the data stack
+// (see `codegen::expr`), popping their operands and pushing a result,
+// except for the overflow trap which never returns.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RuntimeRoutine {
+    Mul16,
+    Div16,
+    SignedDiv16,
+    // Keeps the high word of the product for overflow checks
+    WideMul16,
+    SignedCheckedMul16,
+    // Signed fixed-point products and quotients, with carry set when
+    // they do not fit
+    FixedMul8,
+    FixedDiv8,
+    FixedMul16,
+    FixedDiv16,
+    // Single precision floats without denormals, infinities that
+    // compute or NaN, with results truncated toward zero
+    FloatPack,
+    FloatAdd,
+    FloatSub,
+    FloatMul,
+    FloatDiv,
+    FloatKeys,
+    LongToFloat,
+    FloatToLong,
+    // Decimal adds and subtracts for targets without decimal mode, of
+    // the number of bytes in Y, leaving carry as `ADC` and `SBC` would
+    BcdAdd,
+    BcdSub,
+    BcdToBinary,
+    BinaryToBcd,
+    OverflowTrap,
+}
+
+const MUL16: &str = "\
+; 2,X * 0,X -> 2,X, shift and add from the high bit down
+__mul16:
+    LDA #0
+    STA __tmp
+    STA __tmp+1
+    LDY #16
+@loop:
+    ASL __tmp
+    ROL __tmp+1
+    ASL 2,X
+    ROL 3,X
+    BCC @skip
+    CLC
+    LDA __tmp
+    ADC 0,X
+    STA __tmp
+    LDA __tmp+1
+    ADC 1,X
+    STA __tmp+1
+@skip:
+    DEY
+    BNE @loop
+    LDA __tmp
+    STA 2,X
+    LDA __tmp+1
+    STA 3,X
+    INX
+    INX
+    RTS";
+
+const DIV16: &str = "\
+; 2,X / 0,X -> 2,X, unsigned restoring division
+__div16:
+    LDA #0
+    STA __tmp
+    STA __tmp+1
+    LDY #16
+@loop:
+    ASL 2,X
+    ROL 3,X
+    ROL __tmp
+    ROL __tmp+1
+    LDA __tmp
+    SEC
+    SBC 0,X
+    STA __tmp+2
+    LDA __tmp+1
+    SBC 1,X
+    BCC @skip
+    STA __tmp+1
+    LDA __tmp+2
+    STA __tmp
+    INC 2,X
+@skip:
+    DEY
+    BNE @loop
+    INX
+    INX
+    RTS";
+
+const SIGNED_DIV16: &str = "\
+; 2,X / 0,X -> 2,X, signed, rounding toward zero
+__sdiv16:
+    LDA 3,X
+    EOR 1,X
+    PHA
+    LDA 3,X
+    BPL @dividend_positive
+    SEC
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@dividend_positive:
+    LDA 1,X
+    BPL @divisor_positive
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+@divisor_positive:
+    JSR __div16
+    PLA
+    BPL @done
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+@done:
+    RTS";
+
+const WIDE_MUL16: &str = "\
+; 2,X * 0,X -> 2,X with the high word in __tmp+2, unsigned,
+; shifting the product right through the multiplier
+__umul16x:
+    LDA #0
+    STA __tmp+2
+    STA __tmp+3
+    LDY #16
+    LSR 3,X
+    ROR 2,X
+@loop:
+    BCC @skip
+    CLC
+    LDA __tmp+2
+    ADC 0,X
+    STA __tmp+2
+    LDA __tmp+3
+    ADC 1,X
+    STA __tmp+3
+@skip:
+    ROR __tmp+3
+    ROR __tmp+2
+    ROR 3,X
+    ROR 2,X
+    DEY
+    BNE @loop
+    INX
+    INX
+    RTS";
+
+const SIGNED_CHECKED_MUL16: &str = "\
+; 2,X * 0,X -> 2,X, signed, with carry set if the product does not fit
+__smul16c:
+    LDA 3,X
+    EOR 1,X
+    PHA
+    LDA 3,X
+    BPL @left_positive
+    SEC
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@left_positive:
+    LDA 1,X
+    BPL @right_positive
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+@right_positive:
+    JSR __umul16x
+    PLA
+    PHA
+    BPL @signed
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+@signed:
+    LDA __tmp+2
+    ORA __tmp+3
+    BNE @overflow
+    LDA 0,X
+    ORA 1,X
+    BEQ @fits
+    PLA
+    EOR 1,X
+    ASL A
+    RTS
+@fits:
+    PLA
+    CLC
+    RTS
+@overflow:
+    PLA
+    SEC
+    RTS";
+
+const FIXED_MUL8: &str = "\
+; 2,X * 0,X -> 2,X in 8.8 fixed point, signed, rounding toward zero,
+; with carry set if the product does not fit
+__fxmul8:
+    LDA 3,X
+    EOR 1,X
+    PHA
+    LDA 3,X
+    BPL @left_positive
+    SEC
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@left_positive:
+    LDA 1,X
+    BPL @right_positive
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+@right_positive:
+    JSR __umul16x
+    LDA 1,X
+    STA 0,X
+    LDA __tmp+2
+    STA 1,X
+    PLA
+    PHA
+    BPL @signed
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+@signed:
+    LDA __tmp+3
+    BNE @overflow
+    LDA 0,X
+    ORA 1,X
+    BEQ @fits
+    PLA
+    EOR 1,X
+    ASL A
+    RTS
+@fits:
+    PLA
+    CLC
+    RTS
+@overflow:
+    PLA
+    SEC
+    RTS";
+
+const FIXED_DIV8: &str = "\
+; 2,X / 0,X -> 2,X in 8.8 fixed point, signed, rounding toward zero,
+; with carry set if the quotient does not fit. The dividend is shifted
+; up a byte, with its low byte in __tmp+3.
+__fxdiv8:
+    LDA 3,X
+    EOR 1,X
+    PHA
+    LDA 3,X
+    BPL @dividend_positive
+    SEC
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@dividend_positive:
+    LDA 1,X
+    BPL @divisor_positive
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+@divisor_positive:
+    LDA #0
+    STA __tmp
+    STA __tmp+1
+    STA __tmp+3
+    LDY #24
+@loop:
+    ASL __tmp+3
+    ROL 2,X
+    ROL 3,X
+    ROL __tmp
+    ROL __tmp+1
+    LDA __tmp
+    SEC
+    SBC 0,X
+    STA __tmp+2
+    LDA __tmp+1
+    SBC 1,X
+    BCC @skip
+    STA __tmp+1
+    LDA __tmp+2
+    STA __tmp
+    INC __tmp+3
+@skip:
+    DEY
+    BNE @loop
+    LDA 3,X
+    STA __tmp
+    LDA 2,X
+    STA 3,X
+    LDA __tmp+3
+    STA 2,X
+    INX
+    INX
+    PLA
+    PHA
+    BPL @signed
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+@signed:
+    LDA __tmp
+    BNE @overflow
+    LDA 0,X
+    ORA 1,X
+    BEQ @fits
+    PLA
+    EOR 1,X
+    ASL A
+    RTS
+@fits:
+    PLA
+    CLC
+    RTS
+@overflow:
+    PLA
+    SEC
+    RTS";
+
+const FIXED_MUL16: &str = "\
+; 4,X * 0,X -> 4,X in 16.16 fixed point, signed, rounding toward
+; zero, with carry set if the product does not fit. The high half of
+; the 64 bit product builds up in __tmp while the low half shifts into
+; the multiplier.
+__fxmul16:
+    LDA 7,X
+    EOR 3,X
+    PHA
+    LDA 7,X
+    BPL @left_positive
+    SEC
+    LDA #0
+    SBC 4,X
+    STA 4,X
+    LDA #0
+    SBC 5,X
+    STA 5,X
+    LDA #0
+    SBC 6,X
+    STA 6,X
+    LDA #0
+    SBC 7,X
+    STA 7,X
+@left_positive:
+    LDA 3,X
+    BPL @right_positive
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@right_positive:
+    LDA #0
+    STA __tmp
+    STA __tmp+1
+    STA __tmp+2
+    STA __tmp+3
+    LDY #32
+    LSR 7,X
+    ROR 6,X
+    ROR 5,X
+    ROR 4,X
+@loop:
+    BCC @skip
+    CLC
+    LDA __tmp
+    ADC 0,X
+    STA __tmp
+    LDA __tmp+1
+    ADC 1,X
+    STA __tmp+1
+    LDA __tmp+2
+    ADC 2,X
+    STA __tmp+2
+    LDA __tmp+3
+    ADC 3,X
+    STA __tmp+3
+@skip:
+    ROR __tmp+3
+    ROR __tmp+2
+    ROR __tmp+1
+    ROR __tmp
+    ROR 7,X
+    ROR 6,X
+    ROR 5,X
+    ROR 4,X
+    DEY
+    BNE @loop
+    LDA 6,X
+    STA 4,X
+    LDA 7,X
+    STA 5,X
+    LDA __tmp
+    STA 6,X
+    LDA __tmp+1
+    STA 7,X
+    INX
+    INX
+    INX
+    INX
+    PLA
+    PHA
+    BPL @signed
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@signed:
+    LDA __tmp+2
+    ORA __tmp+3
+    BNE @overflow
+    LDA 0,X
+    ORA 1,X
+    ORA 2,X
+    ORA 3,X
+    BEQ @fits
+    PLA
+    EOR 3,X
+    ASL A
+    RTS
+@fits:
+    PLA
+    CLC
+    RTS
+@overflow:
+    PLA
+    SEC
+    RTS";
+
+const FIXED_DIV16: &str = "\
+; 4,X / 0,X -> 4,X in 16.16 fixed point, signed, rounding toward
+; zero, with carry set if the quotient does not fit. The dividend is
+; shifted up two bytes, with its low bytes in __ptr, and the remainder
+; builds up in __tmp.
+__fxdiv16:
+    LDA 7,X
+    EOR 3,X
+    PHA
+    LDA 7,X
+    BPL @dividend_positive
+    SEC
+    LDA #0
+    SBC 4,X
+    STA 4,X
+    LDA #0
+    SBC 5,X
+    STA 5,X
+    LDA #0
+    SBC 6,X
+    STA 6,X
+    LDA #0
+    SBC 7,X
+    STA 7,X
+@dividend_positive:
+    LDA 3,X
+    BPL @divisor_positive
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@divisor_positive:
+    LDA #0
+    STA __tmp
+    STA __tmp+1
+    STA __tmp+2
+    STA __tmp+3
+    STA __ptr
+    STA __ptr+1
+    LDY #48
+@loop:
+    ASL __ptr
+    ROL __ptr+1
+    ROL 4,X
+    ROL 5,X
+    ROL 6,X
+    ROL 7,X
+    ROL __tmp
+    ROL __tmp+1
+    ROL __tmp+2
+    ROL __tmp+3
+    SEC
+    LDA __tmp
+    SBC 0,X
+    STA __tmp
+    LDA __tmp+1
+    SBC 1,X
+    STA __tmp+1
+    LDA __tmp+2
+    SBC 2,X
+    STA __tmp+2
+    LDA __tmp+3
+    SBC 3,X
+    STA __tmp+3
+    BCS @subtracted
+    CLC
+    LDA __tmp
+    ADC 0,X
+    STA __tmp
+    LDA __tmp+1
+    ADC 1,X
+    STA __tmp+1
+    LDA __tmp+2
+    ADC 2,X
+    STA __tmp+2
+    LDA __tmp+3
+    ADC 3,X
+    STA __tmp+3
+    JMP @next
+@subtracted:
+    INC __ptr
+@next:
+    DEY
+    BNE @loop
+    LDA 6,X
+    ORA 7,X
+    STA __tmp
+    LDA 4,X
+    STA 6,X
+    LDA 5,X
+    STA 7,X
+    LDA __ptr
+    STA 4,X
+    LDA __ptr+1
+    STA 5,X
+    INX
+    INX
+    INX
+    INX
+    PLA
+    PHA
+    BPL @signed
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@signed:
+    LDA __tmp
+    BNE @overflow
+    LDA 0,X
+    ORA 1,X
+    ORA 2,X
+    ORA 3,X
+    BEQ @fits
+    PLA
+    EOR 3,X
+    ASL A
+    RTS
+@fits:
+    PLA
+    CLC
+    RTS
+@overflow:
+    PLA
+    SEC
+    RTS";
+
+const FLOAT_PACK: &str = "\
+; Packs the mantissa in __tmp, with its top bit set, the signed 16 bit
+; exponent in __ptr and the sign in Y into a float at 4,X, then drops
+; the operand at 0,X. Exponents that are too small give zero and ones
+; that are too large infinity.
+__fpack:
+    LDA __ptr+1
+    BMI @zero
+    BNE @infinity
+    LDA __ptr
+    BEQ @zero
+    CMP #$FF
+    BEQ @infinity
+    LSR A
+    STA 7,X
+    LDA __tmp+2
+    BCS @odd
+    AND #$7F
+@odd:
+    STA 6,X
+    LDA __tmp+1
+    STA 5,X
+    LDA __tmp
+    STA 4,X
+    JMP @sign
+@infinity:
+    LDA #$7F
+    STA 7,X
+    LDA #$80
+    STA 6,X
+    LDA #0
+    STA 5,X
+    STA 4,X
+    JMP @sign
+@zero:
+    LDA #0
+    STA 7,X
+    STA 6,X
+    STA 5,X
+    STA 4,X
+@sign:
+    TYA
+    ORA 7,X
+    STA 7,X
+    INX
+    INX
+    INX
+    INX
+    RTS";
+
+const FLOAT_ADD: &str = "\
+; 4,X + 0,X -> 4,X. The operands are ordered by magnitude and their
+; mantissas widened by a byte below, then the smaller is shifted down
+; to the exponent of the larger.
+__fadd:
+    LDA 2,X
+    ASL A
+    LDA 3,X
+    ROL A
+    BNE @right
+    JMP @drop
+@right:
+    STA __tmp+1
+    LDA 6,X
+    ASL A
+    LDA 7,X
+    ROL A
+    BNE @left
+    LDA 0,X
+    STA 4,X
+    LDA 1,X
+    STA 5,X
+    LDA 2,X
+    STA 6,X
+    LDA 3,X
+    STA 7,X
+    JMP @drop
+@left:
+    STA __tmp
+    LDA 7,X
+    AND #$7F
+    STA __tmp+2
+    LDA 3,X
+    AND #$7F
+    STA __tmp+3
+    LDA 4,X
+    CMP 0,X
+    LDA 5,X
+    SBC 1,X
+    LDA 6,X
+    SBC 2,X
+    LDA __tmp+2
+    SBC __tmp+3
+    BCS @ordered
+    LDA 0,X
+    LDY 4,X
+    STA 4,X
+    STY 0,X
+    LDA 1,X
+    LDY 5,X
+    STA 5,X
+    STY 1,X
+    LDA 2,X
+    LDY 6,X
+    STA 6,X
+    STY 2,X
+    LDA 3,X
+    LDY 7,X
+    STA 7,X
+    STY 3,X
+    LDA __tmp
+    LDY __tmp+1
+    STA __tmp+1
+    STY __tmp
+@ordered:
+    ; The result has the sign of the larger operand
+    LDA 7,X
+    AND #$80
+    PHA
+    LDA 7,X
+    EOR 3,X
+    STA __tmp+3
+    LDA __tmp
+    STA __ptr
+    LDA #0
+    STA __ptr+1
+    SEC
+    LDA __tmp
+    SBC __tmp+1
+    TAY
+    LDA 6,X
+    ORA #$80
+    STA 7,X
+    LDA 5,X
+    STA 6,X
+    LDA 4,X
+    STA 5,X
+    LDA 2,X
+    ORA #$80
+    STA 3,X
+    LDA 1,X
+    STA 2,X
+    LDA 0,X
+    STA 1,X
+    LDA #0
+    STA 4,X
+    STA 0,X
+    CPY #32
+    BCC @align
+    LDY #32
+@align:
+    CPY #0
+    BEQ @aligned
+@shift:
+    LSR 3,X
+    ROR 2,X
+    ROR 1,X
+    ROR 0,X
+    DEY
+    BNE @shift
+@aligned:
+    BIT __tmp+3
+    BMI @subtract
+    CLC
+    LDA 4,X
+    ADC 0,X
+    STA 4,X
+    LDA 5,X
+    ADC 1,X
+    STA 5,X
+    LDA 6,X
+    ADC 2,X
+    STA 6,X
+    LDA 7,X
+    ADC 3,X
+    STA 7,X
+    BCC @normalized
+    ROR 7,X
+    ROR 6,X
+    ROR 5,X
+    ROR 4,X
+    INC __ptr
+    JMP @normalized
+@subtract:
+    SEC
+    LDA 4,X
+    SBC 0,X
+    STA 4,X
+    LDA 5,X
+    SBC 1,X
+    STA 5,X
+    LDA 6,X
+    SBC 2,X
+    STA 6,X
+    LDA 7,X
+    SBC 3,X
+    STA 7,X
+    ORA 6,X
+    ORA 5,X
+    ORA 4,X
+    BNE @normalize
+    ; Equal magnitudes cancel to +0
+    PLA
+    LDA #0
+    STA __ptr
+    PHA
+    JMP @normalized
+@normalize:
+    LDA 7,X
+    BMI @normalized
+    ASL 4,X
+    ROL 5,X
+    ROL 6,X
+    ROL 7,X
+    LDA __ptr
+    BNE @decrement
+    DEC __ptr+1
+@decrement:
+    DEC __ptr
+    JMP @normalize
+@normalized:
+    LDA 5,X
+    STA __tmp
+    LDA 6,X
+    STA __tmp+1
+    LDA 7,X
+    STA __tmp+2
+    PLA
+    TAY
+    JMP __fpack
+@drop:
+    INX
+    INX
+    INX
+    INX
+    RTS";
+
+const FLOAT_SUB: &str = "\
+; 4,X - 0,X -> 4,X, adding the negated right operand
+__fsub:
+    LDA 3,X
+    EOR #$80
+    STA 3,X
+    JMP __fadd";
+
+const FLOAT_MUL: &str = "\
+; 4,X * 0,X -> 4,X. The 48 bit product of the mantissas builds up in
+; __tmp and the left mantissa, which shifts out as it is used.
+__fmul:
+    LDA 7,X
+    EOR 3,X
+    AND #$80
+    PHA
+    LDA 2,X
+    ASL A
+    LDA 3,X
+    ROL A
+    BEQ @zero
+    STA __tmp
+    LDA 6,X
+    ASL A
+    LDA 7,X
+    ROL A
+    BEQ @zero
+    CLC
+    ADC __tmp
+    STA __ptr
+    LDA #0
+    ROL A
+    STA __ptr+1
+    SEC
+    LDA __ptr
+    SBC #127
+    STA __ptr
+    LDA __ptr+1
+    SBC #0
+    STA __ptr+1
+    LDA 6,X
+    ORA #$80
+    STA 6,X
+    LDA 2,X
+    ORA #$80
+    STA 2,X
+    LDA #0
+    STA __tmp
+    STA __tmp+1
+    STA __tmp+2
+    LDY #24
+    LSR 6,X
+    ROR 5,X
+    ROR 4,X
+@loop:
+    BCC @skip
+    CLC
+    LDA __tmp
+    ADC 0,X
+    STA __tmp
+    LDA __tmp+1
+    ADC 1,X
+    STA __tmp+1
+    LDA __tmp+2
+    ADC 2,X
+    STA __tmp+2
+@skip:
+    ROR __tmp+2
+    ROR __tmp+1
+    ROR __tmp
+    ROR 6,X
+    ROR 5,X
+    ROR 4,X
+    DEY
+    BNE @loop
+    LDA __tmp+2
+    BMI @high
+    ASL 6,X
+    ROL __tmp
+    ROL __tmp+1
+    ROL __tmp+2
+    JMP @pack
+@high:
+    INC __ptr
+    BNE @pack
+    INC __ptr+1
+    JMP @pack
+@zero:
+    LDA #0
+    STA __ptr
+    STA __ptr+1
+@pack:
+    PLA
+    TAY
+    JMP __fpack";
+
+const FLOAT_DIV: &str = "\
+; 4,X / 0,X -> 4,X, restoring division of the mantissas for 25
+; quotient bits. Dividing by zero gives infinity.
+__fdiv:
+    LDA 7,X
+    EOR 3,X
+    AND #$80
+    PHA
+    LDA 2,X
+    ASL A
+    LDA 3,X
+    ROL A
+    BNE @divisor
+    JMP @infinity
+@divisor:
+    STA __tmp
+    LDA 6,X
+    ASL A
+    LDA 7,X
+    ROL A
+    BNE @dividend
+    JMP @zero
+@dividend:
+    SEC
+    SBC __tmp
+    STA __ptr
+    LDA #0
+    SBC #0
+    STA __ptr+1
+    CLC
+    LDA __ptr
+    ADC #127
+    STA __ptr
+    LDA __ptr+1
+    ADC #0
+    STA __ptr+1
+    LDA 6,X
+    ORA #$80
+    STA 6,X
+    LDA 2,X
+    ORA #$80
+    STA 2,X
+    LDA #0
+    STA 7,X
+    STA 3,X
+    STA __tmp
+    STA __tmp+1
+    STA __tmp+2
+    LDY #25
+@loop:
+    SEC
+    LDA 4,X
+    SBC 0,X
+    STA 4,X
+    LDA 5,X
+    SBC 1,X
+    STA 5,X
+    LDA 6,X
+    SBC 2,X
+    STA 6,X
+    LDA 7,X
+    SBC 3,X
+    STA 7,X
+    BCS @fits
+    CLC
+    LDA 4,X
+    ADC 0,X
+    STA 4,X
+    LDA 5,X
+    ADC 1,X
+    STA 5,X
+    LDA 6,X
+    ADC 2,X
+    STA 6,X
+    LDA 7,X
+    ADC 3,X
+    STA 7,X
+    CLC
+@fits:
+    ROL __tmp
+    ROL __tmp+1
+    ROL __tmp+2
+    DEY
+    BEQ @done
+    ASL 4,X
+    ROL 5,X
+    ROL 6,X
+    ROL 7,X
+    JMP @loop
+@done:
+    ; Carry holds the first quotient bit, set when the left mantissa
+    ; was at least the right one
+    BCC @low
+    ROR __tmp+2
+    ROR __tmp+1
+    ROR __tmp
+    JMP @pack
+@low:
+    LDA __ptr
+    BNE @decrement
+    DEC __ptr+1
+@decrement:
+    DEC __ptr
+    JMP @pack
+@infinity:
+    LDA #$FF
+    STA __ptr
+    LDA #0
+    STA __ptr+1
+    JMP @pack
+@zero:
+    LDA #0
+    STA __ptr
+    STA __ptr+1
+@pack:
+    PLA
+    TAY
+    JMP __fpack";
+
+const FLOAT_KEYS: &str = "\
+; Turns the floats at 4,X and 0,X into integers that compare the same
+; way as unsigned, with -0 equal to 0
+__fkeys:
+    JSR @key
+    INX
+    INX
+    INX
+    INX
+    JSR @key
+    DEX
+    DEX
+    DEX
+    DEX
+    RTS
+@key:
+    LDA 3,X
+    ASL A
+    ORA 2,X
+    ORA 1,X
+    ORA 0,X
+    BNE @nonzero
+    STA 3,X
+@nonzero:
+    LDA 3,X
+    BMI @negative
+    ORA #$80
+    STA 3,X
+    RTS
+@negative:
+    LDA 0,X
+    EOR #$FF
+    STA 0,X
+    LDA 1,X
+    EOR #$FF
+    STA 1,X
+    LDA 2,X
+    EOR #$FF
+    STA 2,X
+    LDA 3,X
+    EOR #$FF
+    STA 3,X
+    RTS";
+
+const LONG_TO_FLOAT: &str = "\
+; The signed 32 bit integer at 0,X to a float in place, divided by 2
+; to the power of Y
+__ltof:
+    STY __tmp
+    LDA #158
+    SEC
+    SBC __tmp
+    STA __tmp
+    LDA 3,X
+    AND #$80
+    STA __tmp+1
+    BPL @positive
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@positive:
+    LDA 0,X
+    ORA 1,X
+    ORA 2,X
+    ORA 3,X
+    BEQ @done
+@normalize:
+    LDA 3,X
+    BMI @normalized
+    ASL 0,X
+    ROL 1,X
+    ROL 2,X
+    ROL 3,X
+    DEC __tmp
+    JMP @normalize
+@normalized:
+    LDA 1,X
+    STA 0,X
+    LDA 2,X
+    STA 1,X
+    LDA 3,X
+    STA 2,X
+    LDA __tmp
+    LSR A
+    ORA __tmp+1
+    STA 3,X
+    BCS @done
+    LDA 2,X
+    AND #$7F
+    STA 2,X
+@done:
+    RTS";
+
+const FLOAT_TO_LONG: &str = "\
+; The float at 0,X to a signed 32 bit integer in place, multiplied by
+; 2 to the power of Y. Out of range values keep their low bits.
+__ftol:
+    STY __tmp
+    LDA 3,X
+    AND #$80
+    STA __tmp+1
+    LDA 2,X
+    ASL A
+    LDA 3,X
+    ROL A
+    CLC
+    ADC __tmp
+    STA __tmp
+    LDA #0
+    ROL A
+    STA __tmp+2
+    LDA 2,X
+    ORA #$80
+    STA 2,X
+    LDA #0
+    STA 3,X
+    LDA __tmp+2
+    BNE @left
+    LDA __tmp
+    CMP #127
+    BCC @zero
+    CMP #150
+    BCS @left
+    LDA #150
+    SEC
+    SBC __tmp
+    TAY
+@right:
+    LSR 3,X
+    ROR 2,X
+    ROR 1,X
+    ROR 0,X
+    DEY
+    BNE @right
+    JMP @sign
+@left:
+    LDA __tmp
+    SEC
+    SBC #150
+    TAY
+    BEQ @sign
+@shift:
+    ASL 0,X
+    ROL 1,X
+    ROL 2,X
+    ROL 3,X
+    DEY
+    BNE @shift
+@sign:
+    LDA __tmp+1
+    BPL @done
+    SEC
+    LDA #0
+    SBC 0,X
+    STA 0,X
+    LDA #0
+    SBC 1,X
+    STA 1,X
+    LDA #0
+    SBC 2,X
+    STA 2,X
+    LDA #0
+    SBC 3,X
+    STA 3,X
+@done:
+    RTS
+@zero:
+    LDA #0
+    STA 0,X
+    STA 1,X
+    STA 2,X
+    STA 3,X
+    RTS";
+
+const BCD_ADD: &str = "\
+; 2,X + 0,X -> 2,X in BCD without decimal mode. Each digit sum of ten or
+; more is pushed past the next digit by adding six.
+__bcdadd:
+    CLC
+__bcdadc:
+    LDA 0,X
+    STA __tmp
+    LDA 2,X
+    JSR @byte
+    STA 2,X
+    DEY
+    BEQ @done
+    LDA 1,X
+    STA __tmp
+    LDA 3,X
+    JSR @byte
+    STA 3,X
+@done:
+    INX
+    INX
+    RTS
+@byte:
+    ; A + __tmp + carry, with the carry out of the two digits
+    STA __tmp+1
+    AND #$0F
+    STA __tmp+2
+    LDA __tmp
+    AND #$0F
+    ADC __tmp+2
+    CMP #10
+    BCC @low
+    ADC #5
+@low:
+    STA __tmp+2
+    LDA __tmp+1
+    AND #$F0
+    CLC
+    ADC __tmp+2
+    STA __tmp+2
+    LDA __tmp
+    AND #$F0
+    CLC
+    ADC __tmp+2
+    BCS @carry
+    CMP #$A0
+    BCC @high
+@carry:
+    ADC #$5F
+    SEC
+@high:
+    RTS";
+
+const BCD_SUB: &str = "\
+; 2,X - 0,X -> 2,X in BCD without decimal mode, adding the nines'
+; complement of the right operand plus one
+__bcdsub:
+    SEC
+    LDA #$99
+    SBC 0,X
+    STA 0,X
+    LDA #$99
+    SBC 1,X
+    STA 1,X
+    SEC
+    JMP __bcdadc";
+
+const BCD_TO_BINARY: &str = "\
+; The four BCD digits at 0,X to an unsigned integer in place, taking
+; the digits from the top and multiplying by ten before each
+__bcdtobin:
+    LDA #0
+    STA __tmp
+    STA __tmp+1
+    LDY #4
+@digit:
+    ASL __tmp
+    ROL __tmp+1
+    LDA __tmp
+    STA __tmp+2
+    LDA __tmp+1
+    STA __tmp+3
+    ASL __tmp
+    ROL __tmp+1
+    ASL __tmp
+    ROL __tmp+1
+    CLC
+    LDA __tmp
+    ADC __tmp+2
+    STA __tmp
+    LDA __tmp+1
+    ADC __tmp+3
+    STA __tmp+1
+    LDA #0
+    ASL 0,X
+    ROL 1,X
+    ROL A
+    ASL 0,X
+    ROL 1,X
+    ROL A
+    ASL 0,X
+    ROL 1,X
+    ROL A
+    ASL 0,X
+    ROL 1,X
+    ROL A
+    CLC
+    ADC __tmp
+    STA __tmp
+    LDA __tmp+1
+    ADC #0
+    STA __tmp+1
+    DEY
+    BNE @digit
+    LDA __tmp
+    STA 0,X
+    LDA __tmp+1
+    STA 1,X
+    RTS";
+
+const BINARY_TO_BCD: &str = "\
+; The unsigned integer at 0,X to its last four BCD digits in place, by
+; shifting its bits into the digits from the top and adding three to
+; each digit of five or more beforehand, so that it carries when doubled
+__bintobcd:
+    LDA #0
+    STA __tmp
+    STA __tmp+1
+    LDY #16
+@bit:
+    LDA __tmp
+    JSR @adjust
+    STA __tmp
+    LDA __tmp+1
+    JSR @adjust
+    STA __tmp+1
+    ASL 0,X
+    ROL 1,X
+    ROL __tmp
+    ROL __tmp+1
+    DEY
+    BNE @bit
+    LDA __tmp
+    STA 0,X
+    LDA __tmp+1
+    STA 1,X
+    RTS
+@adjust:
+    STA __tmp+2
+    AND #$0F
+    CMP #5
+    LDA __tmp+2
+    BCC @low
+    ADC #2
+@low:
+    CMP #$50
+    BCC @high
+    ADC #$2F
+@high:
+    RTS";
+
+const OVERFLOW_TRAP: &str = "\
+; Checked arithmetic overflowed and the program has no overflowTrap
+__overflow_trap:
+    JMP __overflow_trap";
+
+impl RuntimeRoutine {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RuntimeRoutine::Mul16 => "__mul16",
+            RuntimeRoutine::Div16 => "__div16",
+            RuntimeRoutine::SignedDiv16 => "__sdiv16",
+            RuntimeRoutine::WideMul16 => "__umul16x",
+            RuntimeRoutine::SignedCheckedMul16 => "__smul16c",
+            RuntimeRoutine::FixedMul8 => "__fxmul8",
+            RuntimeRoutine::FixedDiv8 => "__fxdiv8",
+            RuntimeRoutine::FixedMul16 => "__fxmul16",
+            RuntimeRoutine::FixedDiv16 => "__fxdiv16",
+            RuntimeRoutine::FloatPack => "__fpack",
+            RuntimeRoutine::FloatAdd => "__fadd",
+            RuntimeRoutine::FloatSub => "__fsub",
+            RuntimeRoutine::FloatMul => "__fmul",
+            RuntimeRoutine::FloatDiv => "__fdiv",
+            RuntimeRoutine::FloatKeys => "__fkeys",
+            RuntimeRoutine::LongToFloat => "__ltof",
+            RuntimeRoutine::FloatToLong => "__ftol",
+            RuntimeRoutine::BcdAdd => "__bcdadd",
+            RuntimeRoutine::BcdSub => "__bcdsub",
+            RuntimeRoutine::BcdToBinary => "__bcdtobin",
+            RuntimeRoutine::BinaryToBcd => "__bintobcd",
+            RuntimeRoutine::OverflowTrap => "__overflow_trap",
+        }
+    }
+
+    // Other routines this one calls
+    fn dependencies(&self) -> Vec<RuntimeRoutine> {
+        match self {
+            RuntimeRoutine::SignedDiv16 => vec![RuntimeRoutine::Div16],
+            RuntimeRoutine::SignedCheckedMul16 => vec![RuntimeRoutine::WideMul16],
+            RuntimeRoutine::FixedMul8 => vec![RuntimeRoutine::WideMul16],
+            RuntimeRoutine::FloatAdd => vec![RuntimeRoutine::FloatPack],
+            RuntimeRoutine::FloatSub => vec![RuntimeRoutine::FloatAdd],
+            RuntimeRoutine::FloatMul => vec![RuntimeRoutine::FloatPack],
+            RuntimeRoutine::FloatDiv => vec![RuntimeRoutine::FloatPack],
+            RuntimeRoutine::BcdSub => vec![RuntimeRoutine::BcdAdd],
+            _ => vec![],
+        }
+    }
+
+    // Whether `label` starts a line of the routine
+    fn defines(&self, label: &str) -> bool {
+        self.source().lines().any(|line| {
+            line.len() == label.len() + 1 && line.starts_with(label) && line.ends_with(':')
+        })
+    }
+
+    // Bytes pushed on the hardware stack below the return address, from
+    // `label` on and including the routines called or jumped to. Pushes
+    // and pulls are paired in the order they are written, and an RTS or
+    // a jump to another routine ends the path.
+    fn stack_bytes(&self, label: &str) -> usize {
+        let local = label.starts_with('@');
+        let mut pushed: usize = 0;
+        let mut most: usize = 0;
+        for line in self
+            .source()
+            .lines()
+            .skip_while(|line| *line != format!("{}:", label))
+            .skip(1)
+        {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("PHA"), _) | (Some("PHP"), _) => {
+                    pushed += 1;
+                }
+                (Some("PLA"), _) | (Some("PLP"), _) => {
+                    pushed = pushed.saturating_sub(1);
+                }
+                (Some("JSR"), Some(target)) => {
+                    most = std::cmp::max(most, pushed + 2 + self.target_bytes(target));
+                }
+                (Some("JMP"), Some(target)) if !target.starts_with('@') => {
+                    most = std::cmp::max(most, pushed + self.target_bytes(target));
+                    pushed = 0;
+                    if local {
+                        break;
+                    }
+                }
+                (Some("RTS"), _) => {
+                    pushed = 0;
+                    if local {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            most = std::cmp::max(most, pushed);
+        }
+        most
+    }
+
+    // What a JSR or JMP to `label` adds. Jumps back into the routine
+    // itself loop rather than nest.
+    fn target_bytes(&self, label: &str) -> usize {
+        if label.starts_with('@') {
+            return self.stack_bytes(label);
+        }
+        if self.defines(label) {
+            return 0;
+        }
+        match self
+            .dependencies()
+            .iter()
+            .find(|dependency| dependency.defines(label))
+        {
+            Some(dependency) => dependency.stack_bytes(label),
+            None => 0,
+        }
+    }
+
+    fn source(&self) -> &'static str {
+        match self {
+            RuntimeRoutine::Mul16 => MUL16,
+            RuntimeRoutine::Div16 => DIV16,
+            RuntimeRoutine::SignedDiv16 => SIGNED_DIV16,
+            RuntimeRoutine::WideMul16 => WIDE_MUL16,
+            RuntimeRoutine::SignedCheckedMul16 => SIGNED_CHECKED_MUL16,
+            RuntimeRoutine::FixedMul8 => FIXED_MUL8,
+            RuntimeRoutine::FixedDiv8 => FIXED_DIV8,
+            RuntimeRoutine::FixedMul16 => FIXED_MUL16,
+            RuntimeRoutine::FixedDiv16 => FIXED_DIV16,
+            RuntimeRoutine::FloatPack => FLOAT_PACK,
+            RuntimeRoutine::FloatAdd => FLOAT_ADD,
+            RuntimeRoutine::FloatSub => FLOAT_SUB,
+            RuntimeRoutine::FloatMul => FLOAT_MUL,
+            RuntimeRoutine::FloatDiv => FLOAT_DIV,
+            RuntimeRoutine::FloatKeys => FLOAT_KEYS,
+            RuntimeRoutine::LongToFloat => LONG_TO_FLOAT,
+            RuntimeRoutine::FloatToLong => FLOAT_TO_LONG,
+            RuntimeRoutine::BcdAdd => BCD_ADD,
+            RuntimeRoutine::BcdSub => BCD_SUB,
+            RuntimeRoutine::BcdToBinary => BCD_TO_BINARY,
+            RuntimeRoutine::BinaryToBcd => BINARY_TO_BCD,
+            RuntimeRoutine::OverflowTrap => OVERFLOW_TRAP,
+        }
+    }
+}
+
+pub struct Runtime {
+    used: Vec<RuntimeRoutine>,
+}
+
+impl Runtime {
+    pub fn new() -> Runtime {
+        Runtime { used: Vec::new() }
+    }
+
+    // Marks the routine as needed and returns the label to call
+    pub fn require(&mut self, routine: RuntimeRoutine) -> &'static str {
+        if !self.used.contains(&routine) {
+            self.used.push(routine);
+            for dependency in routine.dependencies() {
+                self.require(dependency);
+            }
+        }
+        routine.label()
+    }
+
+    // Hardware stack bytes a JSR to `label` needs past its return
+    // address, or None if no routine in use defines it
+    pub fn stack_bytes(&self, label: &str) -> Option<usize> {
+        self.used
+            .iter()
+            .find(|routine| routine.defines(label))
+            .map(|routine| routine.stack_bytes(label))
+    }
+
+    pub fn emit(&self, emitter: &mut Emitter) {
+        for routine in &self.used {
+            emitter.blank();
+            for line in routine.source().lines() {
+                if line.starts_with(' ') {
+                    emitter.instruction(line.trim());
+                } else if line.starts_with(';') {
+                    emitter.comment(line.trim_start_matches("; "));
+                } else {
+                    emitter.label(line.trim_end_matches(':'));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::compile_source;
+    use crate::codegen::sim::{Machine, Stop};
+    use crate::codegen::target::Target;
+    use crate::parser::types::BcdType;
+    use crate::parser::types::FixedType;
+    use crate::semantic::intrinsics::Overflow;
+
+    fn machine(source: &str, overflow: Overflow, target: Target) -> Machine {
+        Machine::new(&compile_source(source, overflow, target), target)
+    }
+
+    // A fixed-point value as an argument, and as the bytes of a result
+    fn fixed(fixed_type: FixedType, value: f64) -> (i64, usize) {
+        let size = fixed_type.size();
+        let mask = (1i64 << (8 * size)) - 1;
+        (fixed_type.encode(value) & mask, size)
+    }
+
+    const FIXED: &str = "def mul8(a: f8x8, b: f8x8): f8x8\n return a * b\n\
+         def div8(a: f8x8, b: f8x8): f8x8\n return a / b\n\
+         def mul16(a: f16x16, b: f16x16): f16x16\n return a * b\n\
+         def div16(a: f16x16, b: f16x16): f16x16\n return a / b\n";
+
+    // Runs the function `label` of `FIXED` on two values of `fixed_type`
+    fn fixed_result(
+        overflow: Overflow,
+        label: &str,
+        fixed_type: FixedType,
+        left: f64,
+        right: f64,
+    ) -> Result<i64, Stop> {
+        let mut machine = machine(FIXED, overflow, Target::Mos6502);
+        let args = [fixed(fixed_type, left), fixed(fixed_type, right)];
+        machine.call(label, &args, fixed_type.size())
+    }
+
+    #[test]
+    fn fixed_products_round_toward_zero() {
+        let cases = [
+            ("_mul8", FixedType::F8x8, 1.5, 2.25, 3.375),
+            ("_mul8", FixedType::F8x8, -1.5, 2.0, -3.0),
+            ("_mul8", FixedType::F8x8, -0.75, -0.5, 0.375),
+            ("_mul8", FixedType::F8x8, 3.0 / 256.0, 0.5, 1.0 / 256.0),
+            ("_mul8", FixedType::F8x8, -3.0 / 256.0, 0.5, -1.0 / 256.0),
+            ("_mul16", FixedType::F16x16, 100.5, 3.0, 301.5),
+            ("_mul16", FixedType::F16x16, -1.25, -4.0, 5.0),
+            (
+                "_mul16",
+                FixedType::F16x16,
+                3.0 / 65536.0,
+                -0.5,
+                -1.0 / 65536.0,
+            ),
+        ];
+        for (label, fixed_type, left, right, product) in cases.iter() {
+            assert_eq!(
+                fixed_result(Overflow::Wrapping, label, *fixed_type, *left, *right),
+                Ok(fixed(*fixed_type, *product).0),
+                "{} * {}",
+                left,
+                right
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_quotients_round_toward_zero() {
+        let cases = [
+            ("_div8", FixedType::F8x8, 0.5, 0.25, 2.0),
+            ("_div8", FixedType::F8x8, 1.0, 3.0, 85.0 / 256.0),
+            ("_div8", FixedType::F8x8, -1.0, 3.0, -85.0 / 256.0),
+            ("_div8", FixedType::F8x8, -7.5, -2.5, 3.0),
+            ("_div16", FixedType::F16x16, 1.0, 3.0, 21845.0 / 65536.0),
+            ("_div16", FixedType::F16x16, -7.5, 2.5, -3.0),
+            ("_div16", FixedType::F16x16, 1000.0, 0.125, 8000.0),
+        ];
+        for (label, fixed_type, left, right, quotient) in cases.iter() {
+            assert_eq!(
+                fixed_result(Overflow::Wrapping, label, *fixed_type, *left, *right),
+                Ok(fixed(*fixed_type, *quotient).0),
+                "{} / {}",
+                left,
+                right
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_results_that_do_not_fit_overflow() {
+        let cases = [
+            ("_mul8", FixedType::F8x8, 100.0, 2.0),
+            ("_mul8", FixedType::F8x8, -100.0, 2.0),
+            ("_div8", FixedType::F8x8, 100.0, 0.25),
+            ("_mul16", FixedType::F16x16, 30000.0, -3.0),
+            ("_div16", FixedType::F16x16, 30000.0, 0.5),
+        ];
+        for (label, fixed_type, left, right) in cases.iter() {
+            let largest = if (*left < 0.0) == (*right < 0.0) {
+                (1i64 << (8 * fixed_type.size() - 1)) - 1
+            } else {
+                1i64 << (8 * fixed_type.size() - 1)
+            };
+            assert_eq!(
+                fixed_result(Overflow::Checked, label, *fixed_type, *left, *right),
+                Err(Stop::Trapped),
+                "{} {} {}",
+                label,
+                left,
+                right
+            );
+            assert_eq!(
+                fixed_result(Overflow::Saturating, label, *fixed_type, *left, *right),
+                Ok(largest),
+                "{} {} {}",
+                label,
+                left,
+                right
+            );
+        }
+        assert_eq!(
+            fixed_result(Overflow::Checked, "_mul8", FixedType::F8x8, 100.0, 1.25),
+            Ok(fixed(FixedType::F8x8, 125.0).0)
+        );
+    }
+
+    #[test]
+    fn fixed_conversions_round_down() {
+        let source = "def toInt(a: f8x8): i16\n return a as i16\n\
+             def fromInt(a: i8): f8x8\n return a as f8x8\n\
+             def widen(a: f8x8): f16x16\n return a as f16x16\n\
+             def narrow(a: f16x16): f8x8\n return a as f8x8\n";
+        let mut machine = machine(source, Overflow::Wrapping, Target::Mos6502);
+        let f8x8 = |value| fixed(FixedType::F8x8, value);
+        let f16x16 = |value| fixed(FixedType::F16x16, value);
+        assert_eq!(machine.call("_toInt", &[f8x8(2.75)], 2), Ok(2));
+        assert_eq!(machine.call("_toInt", &[f8x8(-2.75)], 2), Ok(0xFFFD));
+        assert_eq!(machine.call("_fromInt", &[(0xFD, 1)], 2), Ok(f8x8(-3.0).0));
+        assert_eq!(machine.call("_widen", &[f8x8(-1.5)], 4), Ok(f16x16(-1.5).0));
+        assert_eq!(
+            machine.call("_narrow", &[f16x16(3.25)], 2),
+            Ok(f8x8(3.25).0)
+        );
+        assert_eq!(
+            machine.call("_narrow", &[f16x16(-1.0 / 65536.0)], 2),
+            Ok(f8x8(-1.0 / 256.0).0)
+        );
+    }
+
+    fn float(value: f32) -> (i64, usize) {
+        (value.to_bits() as i64, 4)
+    }
+
+    const FLOAT: &str = "def add(a: f32, b: f32): f32\n return a + b\n\
+         def sub(a: f32, b: f32): f32\n return a - b\n\
+         def mul(a: f32, b: f32): f32\n return a * b\n\
+         def div(a: f32, b: f32): f32\n return a / b\n\
+         def less(a: f32, b: f32): u8\n return a < b\n\
+         def same(a: f32, b: f32): u8\n return a == b\n";
+
+    // Runs the function `label` of `FLOAT` on two floats
+    fn float_result(label: &str, left: f32, right: f32, size: usize) -> i64 {
+        let mut machine = machine(FLOAT, Overflow::Wrapping, Target::Mos6502);
+        machine
+            .call(label, &[float(left), float(right)], size)
+            .unwrap()
+    }
+
+    #[test]
+    fn float_results_are_truncated_toward_zero() {
+        let exact = [
+            ("_add", 1.5, 2.25, 3.75),
+            ("_add", 1.0, -1.0, 0.0),
+            ("_add", 1e30, 1e30, 2e30),
+            ("_sub", 1.0, 0.25, 0.75),
+            ("_mul", 3.0, -2.5, -7.5),
+            ("_mul", 0.1, 0.3, 0.1 * 0.3),
+            ("_div", 1.0, 4.0, 0.25),
+            ("_div", -9.0, 3.0, -3.0),
+        ];
+        for (label, left, right, result) in exact.iter() {
+            assert_eq!(
+                float_result(label, *left, *right, 4),
+                float(*result).0,
+                "{} {} {}",
+                label,
+                left,
+                right
+            );
+        }
+        // The nearest floats round up
+        let truncated = [
+            ("_div", 1.0, 3.0, 0x3EAA_AAAA),
+            ("_div", 2.0, 3.0, 0x3F2A_AAAA),
+            ("_sub", 0.1, 0.3, 0xBE4C_CCCD),
+            ("_add", 16777216.0, 1.0, 0x4B80_0000),
+        ];
+        for (label, left, right, result) in truncated.iter() {
+            assert_eq!(float_result(label, *left, *right, 4), *result);
+        }
+    }
+
+    #[test]
+    fn float_results_out_of_range_become_infinity_or_zero() {
+        let infinity = float(f32::INFINITY).0;
+        assert_eq!(float_result("_mul", 1e30, 1e30, 4), infinity);
+        assert_eq!(float_result("_mul", 1e-30, 1e-30, 4), 0);
+        assert_eq!(float_result("_div", 1.0, 0.0, 4), infinity);
+        assert_eq!(float_result("_div", -1.0, 0.0, 4), float(-f32::INFINITY).0);
+    }
+
+    #[test]
+    fn floats_compare_by_value() {
+        let cases = [
+            (1.0, 2.0, 1, 0),
+            (2.0, 1.0, 0, 0),
+            (-1.0, 1.0, 1, 0),
+            (-2.0, -1.0, 1, 0),
+            (1.0, 1.0, 0, 1),
+            (0.0, -0.0, 0, 1),
+        ];
+        for (left, right, less, same) in cases.iter() {
+            assert_eq!(
+                float_result("_less", *left, *right, 1),
+                *less,
+                "{} < {}",
+                left,
+                right
+            );
+            assert_eq!(
+                float_result("_same", *left, *right, 1),
+                *same,
+                "{} == {}",
+                left,
+                right
+            );
+        }
+    }
+
+    #[test]
+    fn float_conversions_are_truncated_toward_zero() {
+        let source = "def toInt(a: f32): i16\n return a as i16\n\
+             def toFixed(a: f32): f8x8\n return a as f8x8\n\
+             def fromInt(a: i16): f32\n return a as f32\n\
+             def fromByte(a: u8): f32\n return a as f32\n\
+             def fromFixed(a: f16x16): f32\n return a as f32\n";
+        let mut machine = machine(source, Overflow::Wrapping, Target::Mos6502);
+        assert_eq!(machine.call("_toInt", &[float(2.75)], 2), Ok(2));
+        assert_eq!(machine.call("_toInt", &[float(-2.75)], 2), Ok(0xFFFE));
+        assert_eq!(machine.call("_toInt", &[float(40000.0)], 2), Ok(40000));
+        assert_eq!(
+            machine.call("_toFixed", &[float(-2.75)], 2),
+            Ok(fixed(FixedType::F8x8, -2.75).0)
+        );
+        assert_eq!(
+            machine.call("_toFixed", &[float(1.0 / 3.0)], 2),
+            Ok(fixed(FixedType::F8x8, 85.0 / 256.0).0)
+        );
+        assert_eq!(
+            machine.call("_fromInt", &[(0xFED4, 2)], 4),
+            Ok(float(-300.0).0)
+        );
+        assert_eq!(
+            machine.call("_fromByte", &[(200, 1)], 4),
+            Ok(float(200.0).0)
+        );
+        assert_eq!(
+            machine.call("_fromFixed", &[fixed(FixedType::F16x16, -1.25)], 4),
+            Ok(float(-1.25).0)
+        );
+    }
+
+    const BCD: &str = "def add8(a: bcd8, b: bcd8): bcd8\n return a + b\n\
+         def sub8(a: bcd8, b: bcd8): bcd8\n return a - b\n\
+         def add16(a: bcd16, b: bcd16): bcd16\n return a + b\n\
+         def sub16(a: bcd16, b: bcd16): bcd16\n return a - b\n";
+
+    const TARGETS: [Target; 2] = [Target::Mos6502, Target::Nes2A03];
+
+    // Runs the function `label` of `BCD` on two values of `bcd_type`,
+    // and gives back the value of the result
+    fn bcd_result(
+        overflow: Overflow,
+        target: Target,
+        label: &str,
+        bcd_type: BcdType,
+        left: i64,
+        right: i64,
+    ) -> Result<i64, Stop> {
+        let mut machine = machine(BCD, overflow, target);
+        let size = bcd_type.size();
+        let args = [
+            (bcd_type.encode(left as f64), size),
+            (bcd_type.encode(right as f64), size),
+        ];
+        let result = machine.call(label, &args, size)?;
+        Ok(bcd_type.decode(result))
+    }
+
+    #[test]
+    fn bcd_sums_carry_between_digits() {
+        let cases = [
+            ("_add8", BcdType::Bcd8, 19, 23, 42),
+            ("_add8", BcdType::Bcd8, 5, 5, 10),
+            ("_sub8", BcdType::Bcd8, 42, 17, 25),
+            ("_sub8", BcdType::Bcd8, 30, 30, 0),
+            ("_add16", BcdType::Bcd16, 1234, 8765, 9999),
+            ("_add16", BcdType::Bcd16, 999, 1, 1000),
+            ("_sub16", BcdType::Bcd16, 5000, 1, 4999),
+        ];
+        for target in TARGETS.iter() {
+            for (label, bcd_type, left, right, result) in cases.iter() {
+                assert_eq!(
+                    bcd_result(Overflow::Checked, *target, label, *bcd_type, *left, *right),
+                    Ok(*result),
+                    "{:?} {} {} {}",
+                    target,
+                    label,
+                    left,
+                    right
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bcd_sums_out_of_range_overflow() {
+        // With the wrapped and saturated results
+        let cases = [
+            ("_add8", BcdType::Bcd8, 99, 1, 0, 99),
+            ("_add8", BcdType::Bcd8, 60, 70, 30, 99),
+            ("_sub8", BcdType::Bcd8, 10, 20, 90, 0),
+            ("_add16", BcdType::Bcd16, 1234, 8766, 0, 9999),
+            ("_sub16", BcdType::Bcd16, 0, 1, 9999, 0),
+        ];
+        for target in TARGETS.iter() {
+            for (label, bcd_type, left, right, wrapped, saturated) in cases.iter() {
+                let result =
+                    |overflow| bcd_result(overflow, *target, label, *bcd_type, *left, *right);
+                let case = format!("{:?} {} {} {}", target, label, left, right);
+                assert_eq!(result(Overflow::Wrapping), Ok(*wrapped), "{}", case);
+                assert_eq!(result(Overflow::Saturating), Ok(*saturated), "{}", case);
+                assert_eq!(result(Overflow::Checked), Err(Stop::Trapped), "{}", case);
+            }
+        }
+    }
+
+    #[test]
+    fn bcd_conversions_keep_the_last_four_digits() {
+        let source = "def toInt(a: bcd16): u16\n return a as u16\n\
+             def fromInt(a: u16): bcd16\n return a as bcd16\n\
+             def fromByte(a: i8): bcd16\n return a as bcd16\n\
+             def widen(a: bcd8): bcd16\n return a as bcd16\n\
+             def narrow(a: bcd16): bcd8\n return a as bcd8\n";
+        for target in TARGETS.iter() {
+            let mut machine = machine(source, Overflow::Wrapping, *target);
+            assert_eq!(machine.call("_toInt", &[(0x1234, 2)], 2), Ok(1234));
+            assert_eq!(machine.call("_toInt", &[(0x9999, 2)], 2), Ok(9999));
+            assert_eq!(machine.call("_fromInt", &[(4321, 2)], 2), Ok(0x4321));
+            assert_eq!(machine.call("_fromInt", &[(65535, 2)], 2), Ok(0x5535));
+            assert_eq!(machine.call("_fromByte", &[(0x7F, 1)], 2), Ok(0x0127));
+            assert_eq!(machine.call("_widen", &[(0x42, 1)], 2), Ok(0x0042));
+            assert_eq!(machine.call("_narrow", &[(0x1234, 2)], 1), Ok(0x34));
+        }
+    }
+}
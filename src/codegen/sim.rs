@@ -0,0 +1,734 @@
+// A 6502 that runs the compiler's output, so that tests can check
+// generated code and the runtime against known results.
+//
+// It assembles the subset of ca65 the code generator emits. Segments
+// are laid out at fixed places, and every instruction takes three
+// bytes of address space whatever its addressing mode, which only
+// shows in return addresses. Data is laid out as written.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::codegen::target::Target;
+use crate::codegen::DATA_STACK_SIZE;
+
+// Where each segment starts
+const SEGMENTS: [(&str, u16); 5] = [
+    ("ZEROPAGE", 0x0000),
+    ("BSS", 0x0300),
+    ("CODE", 0x8000),
+    ("RODATA", 0xE000),
+    ("VECTORS", 0xFFFA),
+];
+
+// Where a call made by `Machine::call` returns to
+const RETURN: u16 = 0xFFF0;
+
+// Instructions a call may run before it is taken to be stuck
+const MAX_STEPS: usize = 1_000_000;
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    Low(Box<Expr>),
+    High(Box<Expr>),
+    Negate(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Subtract(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate(u16),
+    Address(u16),
+    IndexedX(u16),
+    IndexedY(u16),
+    IndirectY(u16),
+    Indirect(u16),
+}
+
+#[derive(Debug)]
+struct Instruction {
+    mnemonic: String,
+    mode: Mode,
+}
+
+// A read or write of memory by an instruction
+#[derive(Debug, PartialEq)]
+pub enum Access {
+    Read(u16),
+    Write(u16, u8),
+}
+
+// Why a call did not return
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    // Reached the runtime's overflow trap or the program's own
+    Trapped,
+    Stuck,
+}
+
+// Splits an expression into numbers, names and operators
+fn tokens(text: &str) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        if c.is_whitespace() {
+            index += 1;
+            continue;
+        }
+        let start = index;
+        if "+-<>()".contains(c) {
+            index += 1;
+        } else {
+            while index < chars.len()
+                && (chars[index].is_alphanumeric() || "_@$%".contains(chars[index]))
+            {
+                index += 1;
+            }
+            if index == start {
+                panic!("Cannot assemble `{}`", text);
+            }
+        }
+        result.push(chars[start..index].iter().collect());
+    }
+    result
+}
+
+struct ExprParser<'a> {
+    tokens: Vec<String>,
+    position: usize,
+    // The label `@` names belong to
+    scope: &'a str,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|token| token.as_str())
+    }
+
+    fn next(&mut self) -> String {
+        self.position += 1;
+        self.tokens[self.position - 1].clone()
+    }
+
+    fn expression(&mut self) -> Expr {
+        let mut result = self.unary();
+        while let Some(op) = self.peek() {
+            let op = op.to_string();
+            if op != "+" && op != "-" {
+                break;
+            }
+            self.next();
+            let right = self.unary();
+            result = if op == "+" {
+                Expr::Add(Box::new(result), Box::new(right))
+            } else {
+                Expr::Subtract(Box::new(result), Box::new(right))
+            };
+        }
+        result
+    }
+
+    fn unary(&mut self) -> Expr {
+        let token = self.next();
+        match token.as_str() {
+            "<" => Expr::Low(Box::new(self.unary())),
+            ">" => Expr::High(Box::new(self.unary())),
+            "-" => Expr::Negate(Box::new(self.unary())),
+            "(" => {
+                let inner = self.expression();
+                assert_eq!(self.next(), ")");
+                inner
+            }
+            _ => {
+                if let Some(hex) = token.strip_prefix('$') {
+                    Expr::Number(i64::from_str_radix(hex, 16).unwrap())
+                } else if let Some(binary) = token.strip_prefix('%') {
+                    Expr::Number(i64::from_str_radix(binary, 2).unwrap())
+                } else if token.chars().next().unwrap().is_ascii_digit() {
+                    Expr::Number(token.parse().unwrap())
+                } else if token.starts_with('@') {
+                    Expr::Symbol(format!("{}{}", self.scope, token))
+                } else {
+                    Expr::Symbol(token)
+                }
+            }
+        }
+    }
+}
+
+fn parse_expression(text: &str, scope: &str) -> Expr {
+    let mut parser = ExprParser {
+        tokens: tokens(text),
+        position: 0,
+        scope,
+    };
+    let result = parser.expression();
+    assert!(parser.peek().is_none(), "Cannot assemble `{}`", text);
+    result
+}
+
+struct Assembler {
+    symbols: HashMap<String, Expr>,
+    // Instructions by address, with their operand and its form
+    code: Vec<(u16, String, String, String)>,
+    // Data by address, with its size in bytes
+    data: Vec<(u16, usize, Expr)>,
+}
+
+impl Assembler {
+    fn value(&self, expr: &Expr) -> i64 {
+        match expr {
+            Expr::Number(value) => *value,
+            Expr::Symbol(name) => match self.symbols.get(name) {
+                Some(expr) => self.value(expr),
+                None => panic!("Unknown symbol `{}`", name),
+            },
+            Expr::Low(inner) => self.value(inner) & 0xFF,
+            Expr::High(inner) => (self.value(inner) >> 8) & 0xFF,
+            Expr::Negate(inner) => -self.value(inner),
+            Expr::Add(left, right) => self.value(left) + self.value(right),
+            Expr::Subtract(left, right) => self.value(left) - self.value(right),
+        }
+    }
+
+    fn operand(&self, operand: &str, mnemonic: &str, scope: &str) -> Mode {
+        let value = |text: &str| self.value(&parse_expression(text, scope)) as u16;
+        if operand.is_empty() {
+            Mode::Implied
+        } else if operand == "A" {
+            Mode::Accumulator
+        } else if let Some(rest) = operand.strip_prefix('#') {
+            Mode::Immediate(value(rest) & 0xFF)
+        } else if let Some(inner) = operand
+            .strip_prefix('(')
+            .and_then(|rest| rest.strip_suffix("),Y"))
+        {
+            Mode::IndirectY(value(inner))
+        } else if mnemonic == "JMP" && operand.starts_with('(') {
+            Mode::Indirect(value(&operand[1..operand.len() - 1]))
+        } else if let Some(base) = operand.strip_suffix(",X") {
+            Mode::IndexedX(value(base))
+        } else if let Some(base) = operand.strip_suffix(",Y") {
+            Mode::IndexedY(value(base))
+        } else {
+            Mode::Address(value(operand))
+        }
+    }
+}
+
+pub struct Machine {
+    memory: Vec<u8>,
+    accesses: RefCell<Vec<Access>>,
+    code: HashMap<u16, Instruction>,
+    labels: HashMap<String, u16>,
+    decimal_mode: bool,
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u8,
+    pc: u16,
+    carry: bool,
+    zero: bool,
+    interrupt: bool,
+    decimal: bool,
+    overflow: bool,
+    negative: bool,
+}
+
+impl Machine {
+    // Assembles `output` for a CPU like `target`
+    pub fn new(output: &str, target: Target) -> Machine {
+        let mut assembler = Assembler {
+            symbols: HashMap::new(),
+            code: Vec::new(),
+            data: Vec::new(),
+        };
+        let mut counters: HashMap<&str, u16> = SEGMENTS.iter().cloned().collect();
+        let mut segment = "CODE";
+        let mut scope = String::new();
+        let mut imports: Vec<String> = Vec::new();
+
+        for line in output.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let counter = counters.get_mut(segment).unwrap();
+            if let Some(label) = line.strip_suffix(':') {
+                let name = if label.starts_with('@') {
+                    format!("{}{}", scope, label)
+                } else {
+                    scope = label.to_string();
+                    label.to_string()
+                };
+                assembler
+                    .symbols
+                    .insert(name, Expr::Number(*counter as i64));
+                continue;
+            }
+            if let Some(equals) = line.find(" = ") {
+                let expr = parse_expression(&line[equals + 3..], &scope);
+                assembler.symbols.insert(line[..equals].to_string(), expr);
+                continue;
+            }
+            let (word, rest) = match line.find(' ') {
+                Some(space) => (&line[..space], line[space + 1..].trim()),
+                None => (line, ""),
+            };
+            match word {
+                ".segment" => {
+                    let name = rest.trim_matches('"');
+                    segment = SEGMENTS
+                        .iter()
+                        .find(|(known, _)| *known == name)
+                        .unwrap_or_else(|| panic!("Unknown segment {}", name))
+                        .0;
+                }
+                ".export" => {}
+                ".import" => imports.push(rest.to_string()),
+                ".res" => *counter += rest.parse::<u16>().unwrap(),
+                ".byte" | ".word" | ".addr" | ".dword" => {
+                    let size = match word {
+                        ".byte" => 1,
+                        ".dword" => 4,
+                        _ => 2,
+                    };
+                    for item in rest.split(',') {
+                        let expr = parse_expression(item.trim(), &scope);
+                        assembler.data.push((*counter, size, expr));
+                        *counter = counter.wrapping_add(size as u16);
+                    }
+                }
+                _ => {
+                    assembler.code.push((
+                        *counter,
+                        word.to_string(),
+                        rest.to_string(),
+                        scope.clone(),
+                    ));
+                    *counter += 3;
+                }
+            }
+        }
+        // Foreign routines are never reached in tests
+        for import in imports {
+            assembler.symbols.entry(import).or_insert(Expr::Number(0));
+        }
+
+        let mut memory = vec![0; 0x10000];
+        for (address, size, expr) in &assembler.data {
+            let value = assembler.value(expr);
+            for byte in 0..*size {
+                memory[*address as usize + byte] = (value >> (8 * byte)) as u8;
+            }
+        }
+        let code = assembler
+            .code
+            .iter()
+            .map(|(address, mnemonic, operand, scope)| {
+                let mode = assembler.operand(operand, mnemonic, scope);
+                let instruction = Instruction {
+                    mnemonic: mnemonic.clone(),
+                    mode,
+                };
+                (*address, instruction)
+            })
+            .collect();
+        let labels = assembler
+            .symbols
+            .keys()
+            .map(|name| {
+                (
+                    name.clone(),
+                    assembler.value(&assembler.symbols[name]) as u16,
+                )
+            })
+            .collect();
+
+        Machine {
+            memory,
+            accesses: RefCell::new(Vec::new()),
+            code,
+            labels,
+            decimal_mode: target.has_decimal_mode(),
+            a: 0,
+            x: 0,
+            y: 0,
+            sp: 0xFF,
+            pc: 0,
+            carry: false,
+            zero: false,
+            interrupt: false,
+            decimal: false,
+            overflow: false,
+            negative: false,
+        }
+    }
+
+    pub fn address(&self, label: &str) -> u16 {
+        self.labels[label]
+    }
+
+    pub fn read(&self, address: u16, size: usize) -> i64 {
+        (0..size).fold(0, |value, byte| {
+            value | (self.memory[address as usize + byte] as i64) << (8 * byte)
+        })
+    }
+
+    // Takes the accesses instructions have made to any of `addresses`
+    // since last asked, in the order they were made
+    pub fn accesses(&self, addresses: &[u16]) -> Vec<Access> {
+        self.accesses
+            .borrow_mut()
+            .drain(..)
+            .filter(|access| match access {
+                Access::Read(address) | Access::Write(address, _) => addresses.contains(address),
+            })
+            .collect()
+    }
+
+    pub fn write(&mut self, address: u16, size: usize, value: i64) {
+        for byte in 0..size {
+            self.memory[address as usize + byte] = (value >> (8 * byte)) as u8;
+        }
+    }
+
+    // Calls the function at `label` with arguments given as their
+    // bytes and size, and returns the `size` bytes it leaves on top
+    pub fn call(&mut self, label: &str, args: &[(i64, usize)], size: usize) -> Result<i64, Stop> {
+        let top = self.address("__dstack") as usize + DATA_STACK_SIZE;
+        self.x = top as u8;
+        for (value, arg_size) in args {
+            self.x -= if *arg_size > 2 { 4 } else { 2 };
+            self.write(self.x as u16, *arg_size, *value);
+        }
+        self.sp = 0xFF;
+        self.push_address(RETURN - 1);
+        self.pc = self.address(label);
+        self.decimal = false;
+
+        let traps: Vec<u16> = ["__overflow_trap", "_overflowTrap"]
+            .iter()
+            .filter_map(|trap| self.labels.get(*trap).cloned())
+            .collect();
+        for _ in 0..MAX_STEPS {
+            if self.pc == RETURN {
+                return Ok(self.read(self.x as u16, size));
+            }
+            if traps.contains(&self.pc) {
+                return Err(Stop::Trapped);
+            }
+            self.step();
+        }
+        Err(Stop::Stuck)
+    }
+
+    fn push(&mut self, value: u8) {
+        self.memory[0x100 + self.sp as usize] = value;
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.memory[0x100 + self.sp as usize]
+    }
+
+    fn push_address(&mut self, address: u16) {
+        self.push((address >> 8) as u8);
+        self.push(address as u8);
+    }
+
+    fn pull_address(&mut self) -> u16 {
+        let low = self.pull() as u16;
+        let high = self.pull() as u16;
+        (high << 8) | low
+    }
+
+    fn flags(&self) -> u8 {
+        [
+            self.carry,
+            self.zero,
+            self.interrupt,
+            self.decimal,
+            true,
+            true,
+            self.overflow,
+            self.negative,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |flags, (bit, set)| flags | ((*set as u8) << bit))
+    }
+
+    fn set_flags(&mut self, flags: u8) {
+        let bit = |number: u8| flags & (1 << number) != 0;
+        self.carry = bit(0);
+        self.zero = bit(1);
+        self.interrupt = bit(2);
+        self.decimal = bit(3);
+        self.overflow = bit(6);
+        self.negative = bit(7);
+    }
+
+    fn set_result(&mut self, value: u8) -> u8 {
+        self.zero = value == 0;
+        self.negative = value & 0x80 != 0;
+        value
+    }
+
+    // The address an operand reads or writes
+    fn effective(&self, mode: Mode) -> u16 {
+        match mode {
+            Mode::Address(address) => address,
+            // Zero page indexing wraps within zero page
+            Mode::IndexedX(base) if base < 0x100 => (base + self.x as u16) & 0xFF,
+            Mode::IndexedX(base) => base.wrapping_add(self.x as u16),
+            Mode::IndexedY(base) => base.wrapping_add(self.y as u16),
+            Mode::IndirectY(pointer) => {
+                let low = self.memory[pointer as usize] as u16;
+                let high = self.memory[((pointer + 1) & 0xFF) as usize] as u16;
+                ((high << 8) | low).wrapping_add(self.y as u16)
+            }
+            _ => panic!("No address in {:?}", mode),
+        }
+    }
+
+    fn load(&self, mode: Mode) -> u8 {
+        match mode {
+            Mode::Immediate(value) => value as u8,
+            Mode::Accumulator => self.a,
+            _ => {
+                let address = self.effective(mode);
+                self.accesses.borrow_mut().push(Access::Read(address));
+                self.memory[address as usize]
+            }
+        }
+    }
+
+    fn store(&mut self, mode: Mode, value: u8) {
+        match mode {
+            Mode::Accumulator => self.a = value,
+            _ => {
+                let address = self.effective(mode);
+                self.accesses
+                    .borrow_mut()
+                    .push(Access::Write(address, value));
+                self.memory[address as usize] = value;
+            }
+        }
+    }
+
+    fn add(&mut self, value: u8) {
+        let carry = self.carry as u16;
+        if self.decimal && self.decimal_mode {
+            let mut low = (self.a & 0x0F) as u16 + (value & 0x0F) as u16 + carry;
+            if low > 9 {
+                low += 6;
+            }
+            let mut high = (self.a >> 4) as u16 + (value >> 4) as u16 + (low > 0x0F) as u16;
+            if high > 9 {
+                high += 6;
+            }
+            self.carry = high > 0x0F;
+            self.a = self.set_result(((high << 4) | (low & 0x0F)) as u8);
+        } else {
+            let sum = self.a as u16 + value as u16 + carry;
+            self.overflow = (!(self.a ^ value) & (self.a ^ sum as u8) & 0x80) != 0;
+            self.carry = sum > 0xFF;
+            self.a = self.set_result(sum as u8);
+        }
+    }
+
+    fn subtract(&mut self, value: u8) {
+        if self.decimal && self.decimal_mode {
+            let borrow = !self.carry as i16;
+            let mut low = (self.a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow;
+            let mut high = (self.a >> 4) as i16 - (value >> 4) as i16;
+            if low < 0 {
+                low -= 6;
+                high -= 1;
+            }
+            if high < 0 {
+                high -= 6;
+            }
+            self.carry = self.a as i16 - value as i16 - borrow >= 0;
+            self.a = self.set_result((((high << 4) | (low & 0x0F)) & 0xFF) as u8);
+        } else {
+            let decimal = self.decimal;
+            self.decimal = false;
+            self.add(!value);
+            self.decimal = decimal;
+        }
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.carry = register >= value;
+        self.set_result(register.wrapping_sub(value));
+    }
+
+    fn branch(&mut self, taken: bool, mode: Mode) {
+        if taken {
+            if let Mode::Address(target) = mode {
+                self.pc = target;
+            }
+        }
+    }
+
+    // Shifts or rotates the operand, bringing `carry_in` into the bit
+    // left empty
+    fn shift(&mut self, mode: Mode, left: bool, carry_in: bool) {
+        let value = self.load(mode);
+        let (result, carry) = if left {
+            ((value << 1) | carry_in as u8, value & 0x80 != 0)
+        } else {
+            ((value >> 1) | ((carry_in as u8) << 7), value & 1 != 0)
+        };
+        self.carry = carry;
+        let result = self.set_result(result);
+        self.store(mode, result);
+    }
+
+    fn step(&mut self) {
+        let instruction = match self.code.get(&self.pc) {
+            Some(instruction) => instruction,
+            None => panic!("No instruction at ${:04X}", self.pc),
+        };
+        let mode = instruction.mode;
+        let mnemonic = instruction.mnemonic.to_uppercase();
+        let next = self.pc.wrapping_add(3);
+        self.pc = next;
+        match mnemonic.as_str() {
+            "LDA" => self.a = self.set_result(self.load(mode)),
+            "LDX" => self.x = self.set_result(self.load(mode)),
+            "LDY" => self.y = self.set_result(self.load(mode)),
+            "STA" => self.store(mode, self.a),
+            "STX" => self.store(mode, self.x),
+            "STY" => self.store(mode, self.y),
+            "TAX" => self.x = self.set_result(self.a),
+            "TAY" => self.y = self.set_result(self.a),
+            "TXA" => self.a = self.set_result(self.x),
+            "TYA" => self.a = self.set_result(self.y),
+            "TSX" => self.x = self.set_result(self.sp),
+            "TXS" => self.sp = self.x,
+            "INX" => self.x = self.set_result(self.x.wrapping_add(1)),
+            "INY" => self.y = self.set_result(self.y.wrapping_add(1)),
+            "DEX" => self.x = self.set_result(self.x.wrapping_sub(1)),
+            "DEY" => self.y = self.set_result(self.y.wrapping_sub(1)),
+            "INC" => {
+                let value = self.set_result(self.load(mode).wrapping_add(1));
+                self.store(mode, value);
+            }
+            "DEC" => {
+                let value = self.set_result(self.load(mode).wrapping_sub(1));
+                self.store(mode, value);
+            }
+            "ADC" => self.add(self.load(mode)),
+            "SBC" => self.subtract(self.load(mode)),
+            "AND" => self.a = self.set_result(self.a & self.load(mode)),
+            "ORA" => self.a = self.set_result(self.a | self.load(mode)),
+            "EOR" => self.a = self.set_result(self.a ^ self.load(mode)),
+            "CMP" => self.compare(self.a, self.load(mode)),
+            "CPX" => self.compare(self.x, self.load(mode)),
+            "CPY" => self.compare(self.y, self.load(mode)),
+            "BIT" => {
+                let value = self.load(mode);
+                self.zero = self.a & value == 0;
+                self.overflow = value & 0x40 != 0;
+                self.negative = value & 0x80 != 0;
+            }
+            "ASL" => self.shift(mode, true, false),
+            "LSR" => self.shift(mode, false, false),
+            "ROL" => self.shift(mode, true, self.carry),
+            "ROR" => self.shift(mode, false, self.carry),
+            "PHA" => self.push(self.a),
+            "PLA" => {
+                let value = self.pull();
+                self.a = self.set_result(value);
+            }
+            "PHP" => self.push(self.flags()),
+            "PLP" => {
+                let flags = self.pull();
+                self.set_flags(flags);
+            }
+            "CLC" => self.carry = false,
+            "SEC" => self.carry = true,
+            "CLD" => self.decimal = false,
+            "SED" => self.decimal = true,
+            "CLI" => self.interrupt = false,
+            "SEI" => self.interrupt = true,
+            "CLV" => self.overflow = false,
+            "NOP" => {}
+            "BCC" => self.branch(!self.carry, mode),
+            "BCS" => self.branch(self.carry, mode),
+            "BEQ" => self.branch(self.zero, mode),
+            "BNE" => self.branch(!self.zero, mode),
+            "BMI" => self.branch(self.negative, mode),
+            "BPL" => self.branch(!self.negative, mode),
+            "BVC" => self.branch(!self.overflow, mode),
+            "BVS" => self.branch(self.overflow, mode),
+            "JMP" => {
+                self.pc = match mode {
+                    Mode::Indirect(pointer) => self.read(pointer, 2) as u16,
+                    _ => self.effective(mode),
+                }
+            }
+            "JSR" => {
+                self.push_address(next.wrapping_sub(1));
+                self.pc = self.effective(mode);
+            }
+            "RTS" => self.pc = self.pull_address().wrapping_add(1),
+            "RTI" => {
+                let flags = self.pull();
+                self.set_flags(flags);
+                self.pc = self.pull_address();
+            }
+            _ => panic!("Cannot run `{}`", instruction.mnemonic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_a_routine_with_local_labels() {
+        let output = "    .segment \"ZEROPAGE\"\n\
+             __dstack:\n    .res 64\n\
+             \n    .segment \"CODE\"\n\
+             _count:\n    LDY #0\n\
+             @loop:\n    INY\n    DEC 0,X\n    BNE @loop\n    STY 0,X\n    RTS\n";
+        let mut machine = Machine::new(output, Target::Mos6502);
+        assert_eq!(machine.call("_count", &[(5, 2)], 1), Ok(5));
+    }
+
+    #[test]
+    fn decimal_mode_follows_the_target() {
+        let output = "    .segment \"ZEROPAGE\"\n\
+             __dstack:\n    .res 64\n\
+             \n    .segment \"CODE\"\n\
+             _add:\n    SED\n    CLC\n    LDA #$19\n    ADC #$23\n    CLD\n    STA 0,X\n    RTS\n";
+        let mut machine = Machine::new(output, Target::Mos6502);
+        assert_eq!(machine.call("_add", &[(0, 2)], 1), Ok(0x42));
+        let mut machine = Machine::new(output, Target::Nes2A03);
+        assert_eq!(machine.call("_add", &[(0, 2)], 1), Ok(0x3C));
+    }
+
+    #[test]
+    fn return_addresses_can_be_pushed_for_rts() {
+        let output = "    .segment \"ZEROPAGE\"\n\
+             __dstack:\n    .res 64\n\
+             \n    .segment \"CODE\"\n\
+             _jump:\n    LDA #>(__target-1)\n    PHA\n    LDA #<(__target-1)\n    PHA\n    RTS\n\
+             __target:\n    LDA #7\n    STA 0,X\n    RTS\n";
+        let mut machine = Machine::new(output, Target::Mos6502);
+        assert_eq!(machine.call("_jump", &[(0, 2)], 1), Ok(7));
+    }
+}
@@ -0,0 +1,479 @@
+// Worst-case use of the hardware and data stacks.
+//
+// Each function's own use is counted while it is lowered: the most
+// slots it pushes on the data stack, the most bytes it pushes on the
+// hardware stack, and how much of each was pushed at every JSR. Runtime
+// routines are measured from their source. An entry point then needs
+// its own use at the deepest call, plus what the callee needs, all the
+// way down. Recursive functions are counted once, since how deep they
+// go depends on the values they are called with.
+//
+// `main` is entered by a JSR from the reset code, and an interrupt
+// handler by the three bytes the CPU pushes. An IRQ can arrive while
+// `main` is at its deepest and an NMI while the IRQ handler is at its
+// own, so their uses add up on both stacks.
+
+use std::cmp::max;
+use std::collections::HashMap;
+
+use crate::lexer::SourceLocation;
+
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+
+use crate::semantic::calls::CallGraph;
+use crate::semantic::calls::Recursion;
+use crate::semantic::interrupts;
+use crate::semantic::lints::Lint;
+use crate::semantic::lints::Warning;
+use crate::semantic::SemanticError;
+
+use crate::codegen::emitter::Emitter;
+use crate::codegen::runtime::Runtime;
+
+// Bytes the CPU pushes when it takes an interrupt
+const INTERRUPT_FRAME: usize = 3;
+
+// Bytes a JSR pushes
+const RETURN_ADDRESS: usize = 2;
+
+// A JSR in a function, with what the function had pushed at the time
+struct CallSite {
+    target: String,
+    slots: usize,
+    bytes: usize,
+}
+
+// What one function pushes, counted instruction by instruction
+pub struct Frame {
+    location: SourceLocation,
+    // Most data stack slots pushed at once, past the arguments
+    slots: usize,
+    // Most hardware stack bytes pushed at once, past the return address
+    bytes: usize,
+    calls: Vec<CallSite>,
+    // Hardware stack bytes pushed so far, and after the prologue
+    pushed: usize,
+    entered: usize,
+}
+
+impl Frame {
+    pub fn new(location: SourceLocation) -> Frame {
+        Frame {
+            location,
+            slots: 0,
+            bytes: 0,
+            calls: Vec::new(),
+            pushed: 0,
+            entered: 0,
+        }
+    }
+
+    // Marks the end of the prologue. Every return pulls back to here.
+    pub fn enter(&mut self) {
+        self.entered = self.pushed;
+    }
+
+    // Counts an instruction emitted with `depth` data stack slots pushed
+    pub fn note(&mut self, instruction: &str, depth: usize) {
+        let mut words = instruction.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("PHA"), _) | (Some("PHP"), _) => {
+                self.pushed += 1;
+            }
+            (Some("PLA"), _) | (Some("PLP"), _) => {
+                self.pushed = self.pushed.saturating_sub(1);
+            }
+            (Some("JSR"), Some(target)) => {
+                self.calls.push(CallSite {
+                    target: target.to_string(),
+                    slots: depth,
+                    bytes: self.pushed,
+                });
+            }
+            (Some("RTS"), _) | (Some("RTI"), _) => {
+                self.pushed = self.entered;
+            }
+            _ => {}
+        }
+        self.slots = max(self.slots, depth);
+        self.bytes = max(self.bytes, self.pushed);
+    }
+}
+
+// The most a call needs of each stack, with the calls that need it
+#[derive(Clone)]
+struct Need {
+    data: usize,
+    data_chain: Vec<String>,
+    hardware: usize,
+    hardware_chain: Vec<String>,
+}
+
+impl Need {
+    fn leaf(name: &str, hardware: usize) -> Need {
+        Need {
+            data: 0,
+            data_chain: vec![name.to_string()],
+            hardware,
+            hardware_chain: vec![name.to_string()],
+        }
+    }
+}
+
+// A function the program can be started or interrupted in
+struct EntryPoint {
+    name: String,
+    location: SourceLocation,
+    need: Need,
+}
+
+pub struct StackUsage {
+    entries: Vec<EntryPoint>,
+    // Recursive functions some entry point reaches, with the first such
+    // entry point and how the function recurses
+    recursive: Vec<(String, SourceLocation, String, Recursion)>,
+}
+
+// The function a call target is the label of, if any
+fn function_name(label: &str) -> Option<&str> {
+    if label.starts_with('_') && !label.starts_with("__") {
+        return Some(&label[1..]);
+    }
+    None
+}
+
+fn chain(chain: &[String]) -> String {
+    chain.join(" -> ")
+}
+
+struct Measure<'a> {
+    frames: &'a HashMap<String, Frame>,
+    runtime: &'a Runtime,
+    needs: HashMap<String, Need>,
+    // Functions whose calls are being followed, for cutting cycles
+    open: Vec<String>,
+}
+
+impl<'a> Measure<'a> {
+    // Foreign routines and labels in `asm` blocks are only known by
+    // their return address
+    fn need(&mut self, label: &str) -> Need {
+        let name = match function_name(label) {
+            Some(name) if self.frames.contains_key(name) => name.to_string(),
+            _ => return Need::leaf(label, self.runtime.stack_bytes(label).unwrap_or(0)),
+        };
+        if let Some(need) = self.needs.get(&name) {
+            return need.clone();
+        }
+
+        let frame = &self.frames[&name];
+        let mut need = Need::leaf(&name, frame.bytes);
+        need.data = 2 * frame.slots;
+        self.open.push(name.clone());
+        for call in &frame.calls {
+            if function_name(&call.target)
+                .is_some_and(|callee| self.open.iter().any(|open| open == callee))
+            {
+                continue;
+            }
+            let callee = self.need(&call.target);
+            if 2 * call.slots + callee.data > need.data {
+                need.data = 2 * call.slots + callee.data;
+                need.data_chain = vec![name.clone()];
+                need.data_chain.extend(callee.data_chain.iter().cloned());
+            }
+            if call.bytes + RETURN_ADDRESS + callee.hardware > need.hardware {
+                need.hardware = call.bytes + RETURN_ADDRESS + callee.hardware;
+                need.hardware_chain = vec![name.clone()];
+                need.hardware_chain
+                    .extend(callee.hardware_chain.iter().cloned());
+            }
+        }
+        self.open.pop();
+        self.needs.insert(name, need.clone());
+        need
+    }
+
+    // Every function reachable from `name` through calls, `name` first
+    fn reachable(&self, name: &str) -> Vec<String> {
+        let mut result: Vec<String> = vec![name.to_string()];
+        let mut index = 0;
+        while index < result.len() {
+            if let Some(frame) = self.frames.get(&result[index]) {
+                for callee in frame
+                    .calls
+                    .iter()
+                    .filter_map(|call| function_name(&call.target))
+                {
+                    if self.frames.contains_key(callee)
+                        && !result.iter().any(|found| found == callee)
+                    {
+                        result.push(callee.to_string());
+                    }
+                }
+            }
+            index += 1;
+        }
+        result
+    }
+}
+
+impl StackUsage {
+    // Measures from `main` and the interrupt handlers, once every
+    // function has been lowered and the runtime is known
+    pub fn measure(
+        program: &Program,
+        frames: &HashMap<String, Frame>,
+        runtime: &Runtime,
+        calls: &CallGraph,
+    ) -> StackUsage {
+        let mut measure = Measure {
+            frames,
+            runtime,
+            needs: HashMap::new(),
+            open: Vec::new(),
+        };
+        let mut usage = StackUsage {
+            entries: Vec::new(),
+            recursive: Vec::new(),
+        };
+
+        for primary in program.primaries() {
+            if let PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            } = primary
+            {
+                let is_handler = interrupts::interrupt_vector(decl).is_some();
+                let is_main = decl.name == "main" && decl.args.is_empty();
+                if !(is_handler || is_main) {
+                    continue;
+                }
+                let mut need = measure.need(&format!("_{}", decl.name));
+                need.hardware += if is_handler {
+                    INTERRUPT_FRAME
+                } else {
+                    RETURN_ADDRESS
+                };
+
+                for name in measure.reachable(&decl.name) {
+                    let recursion = calls.recursion(&name);
+                    if recursion != Recursion::None
+                        && !usage
+                            .recursive
+                            .iter()
+                            .any(|(found, _, _, _)| *found == name)
+                    {
+                        let location = frames[&name].location;
+                        usage
+                            .recursive
+                            .push((name, location, decl.name.clone(), recursion));
+                    }
+                }
+                usage.entries.push(EntryPoint {
+                    name: decl.name.clone(),
+                    location: decl.location,
+                    need,
+                });
+            }
+        }
+        usage
+    }
+
+    // Bytes of data stack an entry point needs, none if it is not one
+    pub fn data_need(&self, name: &str) -> usize {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .map_or(0, |entry| entry.need.data)
+    }
+
+    // Recursion leaves the stacks without a bound
+    pub fn warnings(&self) -> Vec<Warning> {
+        self.recursive
+            .iter()
+            .map(|(name, location, entry, recursion)| {
+                let message = format!(
+                    "`{}` is {}, so the stack `{}` needs has no bound",
+                    name, recursion, entry
+                );
+                Warning::new(Lint::UnboundedRecursion, message, *location)
+            })
+            .collect()
+    }
+
+    // Entry points together may use at most `budget` bytes of hardware
+    // stack and `data_size` bytes of data stack
+    pub fn check(&self, budget: usize, data_size: usize, errors: &mut Vec<SemanticError>) {
+        let hardware: usize = self.entries.iter().map(|entry| entry.need.hardware).sum();
+        if hardware > budget {
+            let deepest = self
+                .entries
+                .iter()
+                .max_by_key(|entry| entry.need.hardware)
+                .unwrap();
+            let message = format!(
+                "Up to {} bytes of hardware stack can be in use, over the budget of {}, with {} through {}",
+                hardware, budget, deepest.need.hardware, chain(&deepest.need.hardware_chain)
+            );
+            errors.push(SemanticError::new(message, deepest.location));
+        }
+        let data: usize = self.entries.iter().map(|entry| entry.need.data).sum();
+        if data > data_size {
+            let deepest = self
+                .entries
+                .iter()
+                .max_by_key(|entry| entry.need.data)
+                .unwrap();
+            let message = format!(
+                "Up to {} bytes of data stack can be in use, over the {} there are, with {} through {}",
+                data, data_size, deepest.need.data, chain(&deepest.need.data_chain)
+            );
+            errors.push(SemanticError::new(message, deepest.location));
+        }
+    }
+
+    // Comments at the end of the output on what each entry point needs
+    pub fn emit(&self, emitter: &mut Emitter) {
+        if self.entries.is_empty() {
+            return;
+        }
+        emitter.blank();
+        emitter.comment("Worst-case stack use, in bytes");
+        for entry in &self.entries {
+            emitter.comment(&format!(
+                "{}: {} of hardware stack through {}",
+                entry.name,
+                entry.need.hardware,
+                chain(&entry.need.hardware_chain)
+            ));
+            emitter.comment(&format!(
+                "{}: {} of data stack through {}",
+                entry.name,
+                entry.need.data,
+                chain(&entry.need.data_chain)
+            ));
+        }
+        for (name, _, _, recursion) in &self.recursive {
+            emitter.comment(&format!(
+                "{} is {}, so these are for one call of it",
+                name, recursion
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location() -> SourceLocation {
+        SourceLocation { line: 1, column: 1 }
+    }
+
+    // A frame that emits each instruction with the data stack depth given
+    fn frame(instructions: &[(&str, usize)]) -> Frame {
+        let mut frame = Frame::new(location());
+        frame.enter();
+        for (instruction, depth) in instructions {
+            frame.note(instruction, *depth);
+        }
+        frame
+    }
+
+    fn frames() -> HashMap<String, Frame> {
+        let mut frames = HashMap::new();
+        frames.insert(
+            String::from("main"),
+            frame(&[("DEX", 1), ("JSR _outer", 2), ("RTS", 1)]),
+        );
+        frames.insert(
+            String::from("outer"),
+            frame(&[("PHA", 1), ("JSR _inner", 3), ("PLA", 1), ("RTS", 1)]),
+        );
+        frames.insert(String::from("inner"), frame(&[("DEX", 4), ("RTS", 4)]));
+        frames.insert(
+            String::from("spin"),
+            frame(&[("JSR _spin", 1), ("JSR _inner", 2), ("RTS", 0)]),
+        );
+        frames
+    }
+
+    #[test]
+    fn frames_count_what_they_push() {
+        let frames = frames();
+        let outer = &frames["outer"];
+        assert_eq!((outer.slots, outer.bytes), (3, 1));
+        assert_eq!(outer.calls[0].target, "_inner");
+        assert_eq!((outer.calls[0].slots, outer.calls[0].bytes), (3, 1));
+    }
+
+    #[test]
+    fn needs_add_up_along_the_deepest_chain() {
+        let frames = frames();
+        let runtime = Runtime::new();
+        let mut measure = Measure {
+            frames: &frames,
+            runtime: &runtime,
+            needs: HashMap::new(),
+            open: Vec::new(),
+        };
+        let need = measure.need("_main");
+        // 2 slots at the call, then 3 in `outer` and 4 in `inner`
+        assert_eq!(need.data, 2 * (2 + 3 + 4));
+        assert_eq!(need.data_chain, vec!["main", "outer", "inner"]);
+        // Two return addresses and the byte `outer` pushed
+        assert_eq!(need.hardware, 2 + 1 + 2);
+        assert_eq!(need.hardware_chain, vec!["main", "outer", "inner"]);
+    }
+
+    #[test]
+    fn recursive_calls_are_counted_once() {
+        let frames = frames();
+        let runtime = Runtime::new();
+        let mut measure = Measure {
+            frames: &frames,
+            runtime: &runtime,
+            needs: HashMap::new(),
+            open: Vec::new(),
+        };
+        let need = measure.need("_spin");
+        assert_eq!(need.data, 2 * (2 + 4));
+        assert_eq!(need.data_chain, vec!["spin", "inner"]);
+        assert_eq!(measure.reachable("spin"), vec!["spin", "inner"]);
+    }
+
+    fn entry(name: &str, data: usize, hardware: usize) -> EntryPoint {
+        EntryPoint {
+            name: name.to_string(),
+            location: location(),
+            need: Need {
+                data,
+                data_chain: vec![name.to_string()],
+                hardware,
+                hardware_chain: vec![name.to_string()],
+            },
+        }
+    }
+
+    #[test]
+    fn entry_points_share_the_budget() {
+        let usage = StackUsage {
+            entries: vec![entry("main", 20, 30), entry("irq", 10, 12)],
+            recursive: Vec::new(),
+        };
+        let mut errors = Vec::new();
+        usage.check(42, 30, &mut errors);
+        assert!(errors.is_empty());
+        usage.check(41, 29, &mut errors);
+        assert_eq!(
+            errors[0].message,
+            "Up to 42 bytes of hardware stack can be in use, over the budget of 41, with 30 through main"
+        );
+        assert_eq!(
+            errors[1].message,
+            "Up to 30 bytes of data stack can be in use, over the 29 there are, with 20 through main"
+        );
+    }
+}
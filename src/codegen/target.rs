@@ -0,0 +1,25 @@
+// The CPU the output runs on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Target {
+    Mos6502,
+    // The NES CPU, a 6502 whose decimal mode flag does nothing
+    Nes2A03,
+}
+
+impl Target {
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "6502" => Some(Target::Mos6502),
+            "2a03" => Some(Target::Nes2A03),
+            _ => None,
+        }
+    }
+
+    // Whether `SED` makes `ADC` and `SBC` work on BCD
+    pub fn has_decimal_mode(&self) -> bool {
+        match self {
+            Target::Mos6502 => true,
+            Target::Nes2A03 => false,
+        }
+    }
+}
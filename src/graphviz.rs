@@ -31,6 +31,23 @@ pub struct Graphviz {
 }
 
 impl Graphviz {
+    // An empty graph, to be built node by node where what is drawn is
+    // not a tree of `CreatesGraphviz` items
+    pub fn new() -> Graphviz {
+        Graphviz {
+            labels: HashMap::new(),
+            connections: Vec::new()
+        }
+    }
+
+    pub fn node(&mut self, name: String, label: String) {
+        self.labels.insert(name, label);
+    }
+
+    pub fn connect(&mut self, from: String, to: String) {
+        self.connections.push((from, to));
+    }
+
     pub fn write_file(&self, filename: String) {
         let output_file = File::create(&filename).expect(&format!("Could not open file {}", &filename));
         let mut writer = BufWriter::new(output_file);
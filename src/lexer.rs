@@ -4,12 +4,17 @@ use std::fmt;
 #[derive(Debug)]
 #[derive(PartialEq)]
 #[derive(Copy, Clone)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 enum LexerStateDescriptor {
     START,
     IDENTIFIER,
     NUMERIC,
     NUMERIC_DOT,
     NUMERIC_FLOAT,
+    HEX,
+    DOT,
+    // A number was followed by `..`
+    RANGE,
     GT,
     LT,
     EQ
@@ -18,6 +23,7 @@ enum LexerStateDescriptor {
 #[derive(Debug)]
 #[derive(PartialEq)]
 #[derive(Copy, Clone)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum TokenType {
     IDENTIFIER,
     DEF,
@@ -26,18 +32,39 @@ pub enum TokenType {
     THEN,
     ELSE,
     RETURN,
+    CONST,
+    VAR,
+    AS,
+    MUT,
+    STRUCT,
+    VOLATILE,
+    ASM,
+    MATCH,
     L_PAREN,
     R_PAREN,
+    L_BRACKET,
+    R_BRACKET,
+    L_BRACE,
+    R_BRACE,
     COMMA,
+    COLON,
+    SEMICOLON,
+    AMPERSAND,
+    DOT,
+    DOT_DOT,
+    AT,
     ASSIGN,
     REL_OP,
     MUL_OP,
     SUM_OP,
-    NUMBER
+    NUMBER,
+    // The raw text between the braces of an `asm` block
+    ASM_TEXT
 }
 
 #[derive(Debug)]
 #[derive(Copy, Clone)]
+#[derive(PartialEq)]
 pub enum MulOp {
     MULTIPLY,
     DIVIDE
@@ -108,6 +135,21 @@ impl fmt::Display for RelOp {
     }
 }
 
+// Line and column (both starting at 1) of the first character of a token
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(PartialEq)]
+pub struct SourceLocation {
+    pub line: u32,
+    pub column: u32
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 #[derive(Debug)]
 #[derive(Clone)]
 pub struct LexerToken {
@@ -116,7 +158,8 @@ pub struct LexerToken {
     pub number: Option<f64>,
     pub rel_op: Option<RelOp>,
     pub sum_op: Option<SumOp>,
-    pub mul_op: Option<MulOp>
+    pub mul_op: Option<MulOp>,
+    pub location: SourceLocation
 }
 
 impl LexerToken {
@@ -127,7 +170,8 @@ impl LexerToken {
             number: None,
             rel_op: None,
             mul_op: None,
-            sum_op: None
+            sum_op: None,
+            location: SourceLocation { line: 0, column: 0 }
         }
     }
 
@@ -138,7 +182,8 @@ impl LexerToken {
             number: None,
             rel_op: None,
             mul_op: Some(mul_op),
-            sum_op: None
+            sum_op: None,
+            location: SourceLocation { line: 0, column: 0 }
         }
     }
 
@@ -149,7 +194,8 @@ impl LexerToken {
             number: None,
             rel_op: None,
             mul_op: None,
-            sum_op: Some(sum_op)
+            sum_op: Some(sum_op),
+            location: SourceLocation { line: 0, column: 0 }
         }
     }
 
@@ -160,7 +206,8 @@ impl LexerToken {
             number: None,
             rel_op: Some(rel_op),
             mul_op: None,
-            sum_op: None
+            sum_op: None,
+            location: SourceLocation { line: 0, column: 0 }
         }
     }
 
@@ -171,7 +218,20 @@ impl LexerToken {
             number: None,
             rel_op: None,
             mul_op: None,
-            sum_op: None
+            sum_op: None,
+            location: SourceLocation { line: 0, column: 0 }
+        }
+    }
+
+    fn from_asm_text(text: String) -> LexerToken {
+        LexerToken {
+            token_type: TokenType::ASM_TEXT,
+            label: Some(text),
+            number: None,
+            rel_op: None,
+            mul_op: None,
+            sum_op: None,
+            location: SourceLocation { line: 0, column: 0 }
         }
     }
 
@@ -182,7 +242,8 @@ impl LexerToken {
             number: Some(number),
             rel_op: None,
             mul_op: None,
-            sum_op: None
+            sum_op: None,
+            location: SourceLocation { line: 0, column: 0 }
         }
     }
 }
@@ -191,7 +252,8 @@ struct LexerState <'a>{
     state: LexerStateDescriptor,
     itt: Chars<'a>,
     latest: Option<char>,
-    position: u32,
+    position: SourceLocation,
+    next_position: SourceLocation,
     backtrace: bool
 }
 
@@ -206,12 +268,44 @@ impl<'a> LexerState<'a> {
 
         let next_char: Option<char> = self.itt.next();
         self.latest = next_char;
+
+        // Track where the returned character sits in the source
+        self.position = self.next_position;
+        if next_char == Some('\n') {
+            self.next_position.line += 1;
+            self.next_position.column = 1;
+        }
+        else {
+            self.next_position.column += 1;
+        }
         return next_char;
     }
 
     fn backtrace(&mut self) {
         self.backtrace = true;
     }
+
+    // Takes everything up to the `}` that closes a block, whose `{` was
+    // just read. Braces inside it must balance.
+    fn raw_block(&mut self) -> Result<String, &'static str> {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match self.next() {
+                Some('}') if depth == 0 => return Ok(text),
+                Some(c) => {
+                    if c == '{' {
+                        depth += 1;
+                    }
+                    else if c == '}' {
+                        depth -= 1;
+                    }
+                    text.push(c);
+                }
+                None => return Err("Unexpected EOF in asm block")
+            }
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -232,6 +326,17 @@ fn finish_float(id: &mut Vec<char>) -> Result<LexerToken, &'static str> {
     }
 }
 
+fn finish_hex(id: &mut Vec<char>) -> Result<LexerToken, &'static str> {
+    let digits: String = id.iter().collect();
+    id.clear();
+    if let Ok(new_number) = u32::from_str_radix(&digits, 16) {
+        Ok(LexerToken::from_number(new_number as f64))
+    }
+    else {
+        Err("Could not construct hexadecimal value")
+    }
+}
+
 fn finish_id(id: &mut Vec<char>) -> LexerToken {
     let full_id: String = id.iter().collect();
     id.clear();
@@ -254,6 +359,30 @@ fn finish_id(id: &mut Vec<char>) -> LexerToken {
         "extern" => {
             return LexerToken::from_single(TokenType::EXTERN);
         }
+        "const" => {
+            LexerToken::from_single(TokenType::CONST)
+        }
+        "var" => {
+            LexerToken::from_single(TokenType::VAR)
+        }
+        "as" => {
+            LexerToken::from_single(TokenType::AS)
+        }
+        "mut" => {
+            LexerToken::from_single(TokenType::MUT)
+        }
+        "struct" => {
+            LexerToken::from_single(TokenType::STRUCT)
+        }
+        "volatile" => {
+            LexerToken::from_single(TokenType::VOLATILE)
+        }
+        "asm" => {
+            LexerToken::from_single(TokenType::ASM)
+        }
+        "match" => {
+            LexerToken::from_single(TokenType::MATCH)
+        }
         _ => {
             return LexerToken::from_label(full_id);
         }
@@ -284,6 +413,14 @@ fn process_eof(state: &LexerStateDescriptor, id: &mut Vec<char>) -> Result<Optio
                 return Err("Could not construct an float after EOF")
             }
         }
+        LexerStateDescriptor::HEX => {
+            if let Ok(resp) = finish_hex(id) {
+                Ok(Some(resp))
+            }
+            else {
+                Err("Could not construct a hexadecimal value after EOF")
+            }
+        }
         LexerStateDescriptor::LT => {
             return Ok(Some(LexerToken::from_rel_op(RelOp::LESS_THAN)))
         }
@@ -293,6 +430,12 @@ fn process_eof(state: &LexerStateDescriptor, id: &mut Vec<char>) -> Result<Optio
         LexerStateDescriptor::EQ => {
             return Ok(Some(LexerToken::from_single(TokenType::ASSIGN)))
         }
+        LexerStateDescriptor::DOT => {
+            Ok(Some(LexerToken::from_single(TokenType::DOT)))
+        }
+        LexerStateDescriptor::RANGE => {
+            Ok(Some(LexerToken::from_single(TokenType::DOT_DOT)))
+        }
         _ => {
             return Err("Unexpected EOF")
         }
@@ -331,12 +474,68 @@ fn process_state(state: &LexerStateDescriptor, cur_char: char, id: &mut Vec<char
 
                     return Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
                 }
+                '[' => {
+                    // L_BRACKET token
+                    let resp = LexerToken::from_single(TokenType::L_BRACKET);
+
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
+                }
+                ']' => {
+                    // R_BRACKET token
+                    let resp = LexerToken::from_single(TokenType::R_BRACKET);
+
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
+                }
+                '{' => {
+                    // L_BRACE token
+                    let resp = LexerToken::from_single(TokenType::L_BRACE);
+
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
+                }
+                '}' => {
+                    // R_BRACE token
+                    let resp = LexerToken::from_single(TokenType::R_BRACE);
+
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
+                }
                 ',' => {
                     // COMMA token
                     let resp = LexerToken::from_single(TokenType::COMMA);
 
                     return Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
                 }
+                ':' => {
+                    // COLON token
+                    let resp = LexerToken::from_single(TokenType::COLON);
+
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
+                }
+                ';' => {
+                    // SEMICOLON token
+                    let resp = LexerToken::from_single(TokenType::SEMICOLON);
+
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
+                }
+                '.' => {
+                    // Transition to DOT
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::DOT, None))
+                }
+                '$' => {
+                    // Starts a HEX number
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::HEX, None))
+                }
+                '@' => {
+                    // AT token
+                    let resp = LexerToken::from_single(TokenType::AT);
+
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
+                }
+                '&' => {
+                    // AMPERSAND token
+                    let resp = LexerToken::from_single(TokenType::AMPERSAND);
+
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
+                }
                 '+' => {
                     // PLUS token
                     let resp = LexerToken::from_sum_op(SumOp::ADD);
@@ -410,8 +609,19 @@ fn process_state(state: &LexerStateDescriptor, cur_char: char, id: &mut Vec<char
             // A number literal with a period. Another number must
             // follow. This will be a float.
             if cur_char.is_numeric() {
+                id.push(cur_char);
                 return Ok((StateResponse::CONTINUE, LexerStateDescriptor::NUMERIC_FLOAT, None))
             }
+            else if cur_char == '.' {
+                // Not a float but the start of a range like `1..4`
+                id.pop();
+                if let Ok(resp) = finish_float(id) {
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::RANGE, Some(resp)))
+                }
+                else {
+                    Err("Was unable to create a numeric constant")
+                }
+            }
             else {
                 return Err("Error lexing a floating point constant, expected numbers after '.'");
             }
@@ -431,6 +641,43 @@ fn process_state(state: &LexerStateDescriptor, cur_char: char, id: &mut Vec<char
                 }
             }
         }
+        LexerStateDescriptor::HEX => {
+            // A hexadecimal literal such as `$2007`
+            if cur_char.is_ascii_hexdigit() {
+                id.push(cur_char);
+                Ok((StateResponse::CONTINUE, LexerStateDescriptor::HEX, None))
+            }
+            else {
+                if let Ok(resp) = finish_hex(id) {
+                    Ok((StateResponse::BACKTRACE, LexerStateDescriptor::START, Some(resp)))
+                }
+                else {
+                    Err("Expected hexadecimal digits after '$'")
+                }
+            }
+        }
+        LexerStateDescriptor::DOT => {
+            // A period. Could be field access or a range
+            match cur_char {
+                '.' => {
+                    let resp = LexerToken::from_single(TokenType::DOT_DOT);
+
+                    Ok((StateResponse::CONTINUE, LexerStateDescriptor::START, Some(resp)))
+                }
+                _ => {
+                    // Backtrace and continue
+                    let resp = LexerToken::from_single(TokenType::DOT);
+
+                    Ok((StateResponse::BACKTRACE, LexerStateDescriptor::START, Some(resp)))
+                }
+            }
+        }
+        LexerStateDescriptor::RANGE => {
+            // Both periods after the number are consumed
+            let resp = LexerToken::from_single(TokenType::DOT_DOT);
+
+            Ok((StateResponse::BACKTRACE, LexerStateDescriptor::START, Some(resp)))
+        }
         LexerStateDescriptor::EQ => {
             // An equal sign. Could be assignment or equality
             match cur_char {
@@ -505,17 +752,38 @@ pub fn lex_string(lex_string: String) -> Vec<LexerToken> {
         state: LexerStateDescriptor::START,
         itt: lex_string.chars(),
         latest: None,
-        position: 0,
+        position: SourceLocation { line: 1, column: 1 },
+        next_position: SourceLocation { line: 1, column: 1 },
         backtrace: false
     };
 
     let mut cur_str: Vec<char> = Vec::new();
     let mut tokens: Vec<LexerToken> = Vec::new();
+    let mut in_asm_header = false;
     let mut cur_char = state.next();
+    let mut token_start = state.position;
 
     while cur_char.is_some() {
+        // Any token emitted later starts at the character that left START
+        if state.state == LexerStateDescriptor::START {
+            token_start = state.position;
+        }
+
+        // The first block after `asm` is not Kaleidoscope, so it is
+        // kept as text for the assembler
+        if state.state == LexerStateDescriptor::START && cur_char == Some('{') && in_asm_header {
+            let mut token = LexerToken::from_asm_text(state.raw_block().unwrap());
+            token.location = token_start;
+            tokens.push(token);
+            in_asm_header = false;
+            cur_char = state.next();
+            continue;
+        }
+
         let (response, descriptor, cur_token) = process_state(&state.state, cur_char.unwrap(), &mut cur_str).unwrap();
-        if let Some(token) = cur_token {
+        if let Some(mut token) = cur_token {
+            token.location = token_start;
+            in_asm_header = in_asm_header || token.token_type == TokenType::ASM;
             tokens.push(token);
         }
         state.state = descriptor;
@@ -525,7 +793,8 @@ pub fn lex_string(lex_string: String) -> Vec<LexerToken> {
         cur_char = state.next();
     }
 
-    if let Some(final_token) = process_eof(&state.state, &mut cur_str).unwrap() {
+    if let Some(mut final_token) = process_eof(&state.state, &mut cur_str).unwrap() {
+        final_token.location = token_start;
         tokens.push(final_token)
     }
 
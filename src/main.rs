@@ -2,20 +2,140 @@ mod lexer;
 mod parser;
 mod token_stream;
 mod graphviz;
+mod semantic;
+mod codegen;
+mod optimize;
 
 use std::fs;
 use std::env;
+use std::path::Path;
+use std::process;
+
+use crate::graphviz::CreatesGraphviz;
+use crate::graphviz::Graphviz;
+
+use crate::codegen::target::Target;
+
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::lints;
+use crate::semantic::lints::Level;
+use crate::semantic::lints::LintLevels;
+use crate::semantic::SemanticError;
 
 fn main() {
 
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    // `--overflow=wrapping|saturating|checked` picks what `+`, `-` and `*` do on overflow
+    let mut overflow = Overflow::Wrapping;
+    if let Some(position) = args.iter().position(|arg| arg.starts_with("--overflow=")) {
+        let name = args.remove(position)["--overflow=".len()..].to_string();
+        overflow = match Overflow::from_name(&name) {
+            Some(overflow) => overflow,
+            None => {
+                eprintln!("error: Unknown overflow mode `{}`, expected `wrapping`, `saturating` or `checked`", name);
+                process::exit(1);
+            }
+        };
+    }
+
+    // `--target=6502|2a03` picks the CPU, which decides how BCD is added
+    let mut target = Target::Mos6502;
+    if let Some(position) = args.iter().position(|arg| arg.starts_with("--target=")) {
+        let name = args.remove(position)["--target=".len()..].to_string();
+        target = match Target::from_name(&name) {
+            Some(target) => target,
+            None => {
+                eprintln!("error: Unknown target `{}`, expected `6502` or `2a03`", name);
+                process::exit(1);
+            }
+        };
+    }
+
+    // `--call-graph=file` writes which functions call which, for graphviz
+    let mut call_graph: Option<String> = None;
+    if let Some(position) = args.iter().position(|arg| arg.starts_with("--call-graph=")) {
+        call_graph = Some(args.remove(position)["--call-graph=".len()..].to_string());
+    }
+
+    // `--stack-budget=bytes` caps the hardware stack `main` and the
+    // interrupt handlers may use together
+    let mut stack_budget: usize = 256;
+    if let Some(position) = args.iter().position(|arg| arg.starts_with("--stack-budget=")) {
+        let value = args.remove(position)["--stack-budget=".len()..].to_string();
+        stack_budget = match value.parse::<usize>() {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                eprintln!("error: Invalid stack budget `{}`, expected a number of bytes", value);
+                process::exit(1);
+            }
+        };
+    }
+
+    // `-A<lint>`, `-W<lint>` and `-D<lint>` allow a lint, warn about it or
+    // make it an error. `all` names every lint, and later flags win.
+    let mut lints = LintLevels::new();
+    while let Some(position) = args.iter().skip(1).position(|arg| Level::parse_flag(arg).is_some()) {
+        let flag = args.remove(position + 1);
+        let (level, name) = Level::parse_flag(&flag).unwrap();
+        if !lints.set(name, level) {
+            eprintln!("error: Unknown lint `{}`", name);
+            process::exit(1);
+        }
+    }
+
     if args.len() < 2 {
-        println!("Usage: [invocation] filename")
+        println!("Usage: [invocation] [--overflow=mode] [--target=cpu] [--call-graph=file] [--stack-budget=bytes] [-A|-W|-D<lint>] filename")
     }
     else {
         let contents: String = fs::read_to_string(&args[1]).expect("Could not open file");
         let tokens = lexer::lex_string(contents);
-        let result = parser::parser::parse_stream(&tokens);
-        println!("done! {:?}", result.expect("WHOOPS"));
+        let mut program = match parser::parser::parse_stream(&tokens) {
+            Ok(program) => program,
+            Err(error) => {
+                // Running out of tokens is reported after the last one
+                match (error.location(), tokens.last()) {
+                    (None, Some(last)) => eprintln!("error: {}: {}", last.location, error),
+                    _ => eprintln!("error: {}", error)
+                }
+                process::exit(1);
+            }
+        };
+        Graphviz::from(program.as_ref() as &dyn CreatesGraphviz).write_file(String::from("./a.out"));
+
+        let mut analysis = match semantic::check_program(&program, overflow, &lints) {
+            Ok(analysis) => analysis,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("error: {}", error);
+                }
+                process::exit(1);
+            }
+        };
+
+        for warning in &analysis.warnings {
+            eprintln!("warning: {}", warning);
+        }
+        if let Some(filename) = call_graph {
+            analysis.calls.to_graphviz().write_file(filename);
+        }
+
+        optimize::folding::fold_program(&mut program, &mut analysis, overflow);
+        let (code, usage) = codegen::generate(&program, &analysis, overflow, target);
+        let mut errors: Vec<SemanticError> = Vec::new();
+        for warning in lints::apply_levels(&program, &lints, usage.warnings(), &mut errors) {
+            eprintln!("warning: {}", warning);
+        }
+        usage.check(stack_budget, codegen::DATA_STACK_SIZE, &mut errors);
+        if !errors.is_empty() {
+            for error in errors {
+                eprintln!("error: {}", error);
+            }
+            process::exit(1);
+        }
+
+        let output = Path::new(&args[1]).with_extension("s");
+        fs::write(&output, code).expect("Could not write output file");
+        println!("done! {}", output.display());
     }
 }
@@ -0,0 +1,545 @@
+// Constant folding and algebraic simplification of function bodies.
+//
+// Expressions are rewritten in place, each into one of the same type,
+// and the program is checked again afterwards for the tables keyed by
+// where expressions are. Integer results are brought into range of
+// their type the way the generated code would, with the overflow mode
+// in force, and a checked result that does not fit is left for the
+// trap. Fixed-point and float results can round differently at run
+// time, so only integers are folded.
+//
+// An operand is only dropped when evaluating it can have no effect:
+// no calls to effectful functions, no volatile globals, no memory
+// behind a pointer, which may be a hardware register, and no checked
+// arithmetic that could trap.
+
+use std::collections::HashMap;
+
+use crate::lexer::MulOp;
+use crate::lexer::SourceLocation;
+use crate::lexer::SumOp;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::BinOp;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::Initializer;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::parser::Statement;
+use crate::parser::types::Type;
+
+use crate::semantic::constant;
+use crate::semantic::effects;
+use crate::semantic::effects::Effect;
+use crate::semantic::effects::Effects;
+use crate::semantic::effects::Source;
+use crate::semantic::interpret;
+use crate::semantic::intrinsics::Intrinsic;
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::names;
+use crate::semantic::names::ResolutionTable;
+use crate::semantic::typeck;
+use crate::semantic::typeck::TypeTable;
+use crate::semantic::walk_expression;
+use crate::semantic::Analysis;
+
+struct Folder<'a> {
+    types: &'a TypeTable,
+    names: &'a ResolutionTable,
+    effects: &'a Effects,
+    overflow: Overflow,
+    globals: HashMap<&'a str, &'a GlobalDecl>,
+    // Values of scalar integer constants
+    constants: HashMap<String, f64>,
+}
+
+fn literal(value: f64, location: SourceLocation) -> AstExprNode {
+    AstExprNode::Terminal(Factor::Numeric { value, location })
+}
+
+fn literal_value(expr: &AstExprNode) -> Option<f64> {
+    match expr {
+        AstExprNode::Terminal(Factor::Numeric { value, location: _ }) => Some(*value),
+        _ => None,
+    }
+}
+
+// Moves an expression out of its place, leaving a literal behind
+fn take(expr: &mut AstExprNode) -> AstExprNode {
+    let location = expr.location();
+    std::mem::replace(expr, literal(0.0, location))
+}
+
+impl<'a> Folder<'a> {
+    fn is_int(&self, expr: &AstExprNode) -> bool {
+        matches!(self.types.get(expr), Some(Type::Int(_)))
+    }
+
+    fn has_effects(&self, expr: &AstExprNode) -> bool {
+        let mut found = false;
+        walk_expression(expr, &mut |node| {
+            found |= match node {
+                AstExprNode::Terminal(Factor::Id {
+                    id,
+                    optional_call: Some(_),
+                    location: _,
+                }) => self.effects.of(id) == Effect::Effectful,
+                AstExprNode::Node {
+                    left: _,
+                    op_type: BinOp::Mult(MulOp::DIVIDE),
+                    next: _,
+                    location: _,
+                } => false,
+                AstExprNode::Node {
+                    left: _,
+                    op_type: BinOp::Rel(_),
+                    next: _,
+                    location: _,
+                } => false,
+                AstExprNode::Node {
+                    left: _,
+                    op_type: _,
+                    next: _,
+                    location: _,
+                } => self.overflow == Overflow::Checked,
+                _ => match effects::source(node, self.names, &self.globals) {
+                    Source::Nothing => false,
+                    Source::Global(global) => global.is_volatile,
+                    Source::Pointer => true,
+                },
+            };
+        });
+        found
+    }
+
+    // A literal of type `ty` for `value`, or None if it would trap
+    fn fitted(
+        &self,
+        value: f64,
+        ty: Option<&Type>,
+        location: SourceLocation,
+        overflow: Overflow,
+    ) -> Option<AstExprNode> {
+        Some(literal(interpret::fit(value, ty?, overflow)?, location))
+    }
+
+    fn fold_binary(&self, expr: &mut AstExprNode) -> Option<AstExprNode> {
+        let ty = self.types.get(expr).cloned();
+        let (left, op_type, next, location) = match expr {
+            AstExprNode::Node {
+                left,
+                op_type,
+                next,
+                location,
+            } => (left, op_type, next, *location),
+            _ => return None,
+        };
+        if !self.is_int(left) || !self.is_int(next) {
+            return None;
+        }
+
+        match (&*op_type, literal_value(left), literal_value(next)) {
+            // Left for the runtime to deal with
+            (BinOp::Mult(MulOp::DIVIDE), Some(_), Some(0.0)) => None,
+            (BinOp::Rel(_), Some(left), Some(right)) => {
+                Some(literal(constant::apply(op_type, left, right), location))
+            }
+            (_, Some(left), Some(right)) => self.fitted(
+                constant::apply(op_type, left, right),
+                ty.as_ref(),
+                location,
+                self.overflow,
+            ),
+            (BinOp::Sum(_), _, Some(0.0)) => Some(take(left)),
+            (BinOp::Sum(SumOp::ADD), Some(0.0), _) => Some(take(next)),
+            (BinOp::Mult(_), _, Some(1.0)) => Some(take(left)),
+            (BinOp::Mult(MulOp::MULTIPLY), Some(1.0), _) => Some(take(next)),
+            (BinOp::Mult(MulOp::MULTIPLY), _, Some(0.0)) if !self.has_effects(left) => {
+                Some(take(next))
+            }
+            (BinOp::Mult(MulOp::MULTIPLY), Some(0.0), _) if !self.has_effects(next) => {
+                Some(take(left))
+            }
+            _ => None,
+        }
+    }
+
+    // What an expression becomes once its operands have been folded, if
+    // it changes
+    fn simplify(&self, expr: &mut AstExprNode) -> Option<AstExprNode> {
+        let ty = self.types.get(expr);
+        let is_int = self.is_int(expr);
+        let constant = self
+            .names
+            .global(expr)
+            .and_then(|name| self.constants.get(name));
+        match expr {
+            AstExprNode::SubNode(sub_node) => Some(take(sub_node)),
+            AstExprNode::Node {
+                left: _,
+                op_type: _,
+                next: _,
+                location: _,
+            } => self.fold_binary(expr),
+            AstExprNode::Cast {
+                value,
+                ty: _,
+                location,
+            } if is_int && self.is_int(value) => {
+                self.fitted(literal_value(value)?, ty, *location, Overflow::Wrapping)
+            }
+            AstExprNode::Terminal(Factor::Id {
+                id: _,
+                optional_call: None,
+                location,
+            }) => self.fitted(*constant?, ty, *location, Overflow::Wrapping),
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: Some(args),
+                location,
+            }) if is_int => {
+                let (op, overflow) = match Intrinsic::from_name(id) {
+                    Some(Intrinsic::Arithmetic(op, overflow)) if args.len() == 2 => (op, overflow),
+                    _ => return None,
+                };
+                let value = constant::apply(
+                    &op.bin_op(),
+                    literal_value(&args[0])?,
+                    literal_value(&args[1])?,
+                );
+                self.fitted(value, ty, *location, overflow)
+            }
+            _ => None,
+        }
+    }
+
+    fn fold_expression(&self, expr: &mut AstExprNode) {
+        match expr {
+            AstExprNode::Node {
+                left,
+                op_type: _,
+                next,
+                location: _,
+            } => {
+                self.fold_expression(left);
+                self.fold_expression(next);
+            }
+            AstExprNode::SubNode(sub_node) => {
+                self.fold_expression(sub_node);
+            }
+            AstExprNode::Cast {
+                value,
+                ty: _,
+                location: _,
+            } => {
+                self.fold_expression(value);
+            }
+            AstExprNode::Deref {
+                pointer,
+                location: _,
+            } => {
+                self.fold_expression(pointer);
+            }
+            AstExprNode::Terminal(Factor::Id {
+                id: _,
+                optional_call: Some(args),
+                location: _,
+            }) => {
+                for arg in args {
+                    self.fold_expression(arg);
+                }
+            }
+            AstExprNode::Terminal(Factor::Index {
+                id: _,
+                index,
+                location: _,
+            }) => {
+                self.fold_expression(index);
+            }
+            // Whatever has its address taken, or a field read, has to
+            // stay a place in memory
+            _ => {}
+        }
+        if let Some(simpler) = self.simplify(expr) {
+            *expr = simpler;
+        }
+    }
+
+    fn fold_statement(&self, statement: &mut Statement) {
+        let replacement = match statement {
+            Statement::Select {
+                condition,
+                statement,
+                else_clause,
+            } => {
+                self.fold_expression(condition);
+                self.fold_statement(statement);
+                if let Some(clause) = else_clause {
+                    self.fold_statement(clause);
+                }
+                // Only the branch taken is kept, in a block of its own
+                // so that its locals stay in scope
+                literal_value(condition).map(|value| {
+                    let branch = if value != 0.0 {
+                        Some(std::mem::replace(
+                            statement.as_mut(),
+                            Statement::Block(Vec::new()),
+                        ))
+                    } else {
+                        else_clause.take().map(|clause| *clause)
+                    };
+                    Statement::Block(branch.into_iter().collect())
+                })
+            }
+            Statement::ReturnExpr(expr) | Statement::Expression(expr) => {
+                self.fold_expression(expr);
+                None
+            }
+            Statement::Block(statements) => {
+                for statement in statements {
+                    self.fold_statement(statement);
+                }
+                None
+            }
+            Statement::Local {
+                name: _,
+                ty: _,
+                value,
+                location: _,
+            } => {
+                self.fold_expression(value);
+                None
+            }
+            Statement::Assign {
+                target: _,
+                value,
+                location: _,
+            } => {
+                self.fold_expression(value);
+                None
+            }
+            Statement::Match(matching) => {
+                self.fold_expression(&mut matching.value);
+                for arm in &mut matching.arms {
+                    self.fold_statement(&mut arm.statement);
+                }
+                if let Some(default) = &mut matching.default {
+                    self.fold_statement(&mut default.statement);
+                }
+                None
+            }
+            // Operands of `asm` name variables
+            Statement::Asm(_) => None,
+        };
+        if let Some(replacement) = replacement {
+            *statement = replacement;
+        }
+    }
+}
+
+// Folds every function body of a program that passed analysis, with
+// `overflow` being what `+`, `-` and `*` do in the generated code
+pub fn fold_program(program: &mut Program, analysis: &mut Analysis, overflow: Overflow) {
+    let mut folder = Folder {
+        types: &analysis.types,
+        names: &analysis.names,
+        effects: &analysis.effects,
+        overflow,
+        globals: HashMap::new(),
+        constants: HashMap::new(),
+    };
+    let mut bodies: Vec<&mut Statement> = Vec::new();
+    for primary in program.primaries_mut() {
+        match primary {
+            PrimaryStatement::Global(global) => {
+                if let (true, Type::Int(_), Some(Initializer::Scalar(_))) =
+                    (global.is_const, &global.ty, &global.initializer)
+                {
+                    if let Some(value) = analysis
+                        .initializers
+                        .get(&global.name)
+                        .and_then(|values| values.first())
+                    {
+                        if let Some(value) = interpret::fit(*value, &global.ty, Overflow::Wrapping)
+                        {
+                            folder.constants.insert(global.name.clone(), value);
+                        }
+                    }
+                }
+                let global: &GlobalDecl = global;
+                folder.globals.entry(&global.name).or_insert(global);
+            }
+            PrimaryStatement::Definition {
+                decl: _,
+                inner_statement,
+            } => {
+                bodies.push(inner_statement);
+            }
+            _ => {}
+        }
+    }
+    for body in bodies {
+        folder.fold_statement(body);
+    }
+
+    // Both tables are keyed by the address of each expression, and an
+    // operand kept in place of its parent has moved. Every expression
+    // is replaced by one of the same type, so the program still checks.
+    analysis.names = names::check_names(program, &mut Vec::new(), &mut Vec::new());
+    let mut errors = Vec::new();
+    analysis.types = typeck::check_types(program, &mut errors);
+    if let Some(error) = errors.first() {
+        panic!("Folding left an ill-typed program: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen;
+    use crate::codegen::target::Target;
+    use crate::semantic::check_source;
+
+    fn fold_source(source: &str, overflow: Overflow) -> (Box<Program>, Analysis) {
+        let (mut program, mut analysis) = check_source(source, overflow);
+        fold_program(&mut program, &mut analysis, overflow);
+        (program, analysis)
+    }
+
+    // The body of the last function
+    fn body(program: &Program) -> &Statement {
+        program
+            .primaries()
+            .into_iter()
+            .rev()
+            .find_map(|primary| match primary {
+                PrimaryStatement::Definition {
+                    decl: _,
+                    inner_statement,
+                } => Some(inner_statement.as_ref()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    // What the last function returns, once folded
+    fn folded(source: &str, overflow: Overflow) -> String {
+        let (program, _) = fold_source(source, overflow);
+        let mut returned = None;
+        if let Statement::Block(statements) = body(&program) {
+            for statement in statements {
+                if let Statement::ReturnExpr(expr) = statement {
+                    returned = Some(match expr.as_ref() {
+                        AstExprNode::Terminal(factor) => format!("{}", factor),
+                        _ => String::from("unfolded"),
+                    });
+                }
+            }
+        }
+        returned.unwrap()
+    }
+
+    fn returning(expr: &str) -> String {
+        format!(
+            "extern poll(): u8\n\
+             var v: volatile u8 @ $4000\n\
+             const table: [u8; 2] = [1, 2]\n\
+             def f(x: u8, p: *u8): u8 {{ return {} }}",
+            expr
+        )
+    }
+
+    #[test]
+    fn identities_keep_the_other_operand() {
+        for expr in ["x + 0", "0 + x", "x - 0", "x * 1", "1 * x", "x / 1", "(x)"] {
+            assert_eq!(
+                folded(&returning(expr), Overflow::Wrapping),
+                "Id: x",
+                "{}",
+                expr
+            );
+        }
+        assert_eq!(folded(&returning("0 - x"), Overflow::Wrapping), "unfolded");
+    }
+
+    #[test]
+    fn multiplying_by_zero_keeps_operands_with_effects() {
+        for expr in ["x * 0", "0 * x", "table[1] * 0"] {
+            assert_eq!(
+                folded(&returning(expr), Overflow::Wrapping),
+                "0",
+                "{}",
+                expr
+            );
+        }
+        for expr in ["poll() * 0", "v * 0", "p[0] * 0", "0 * *p"] {
+            assert_eq!(
+                folded(&returning(expr), Overflow::Wrapping),
+                "unfolded",
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn results_are_fitted_to_the_overflow_mode() {
+        // Literal operands out of range are reported, but constants are not
+        let source = format!("const base: u8 = 200\n{}", returning("base + 100"));
+        assert_eq!(folded(&source, Overflow::Wrapping), "44");
+        assert_eq!(folded(&source, Overflow::Saturating), "255");
+        // Left for the overflow trap to report at run time
+        assert_eq!(folded(&source, Overflow::Checked), "unfolded");
+        assert_eq!(folded(&returning("2 * 3 + 4"), Overflow::Checked), "10");
+    }
+
+    #[test]
+    fn constants_are_folded_unless_hidden() {
+        let source = "const limit: u8 = 5\ndef f(x: u8): u8 { return limit + 1 }";
+        assert_eq!(folded(source, Overflow::Wrapping), "6");
+        let source = "const limit: u8 = 5\ndef f(limit: u8): u8 { return limit }";
+        assert_eq!(folded(source, Overflow::Wrapping), "Id: limit");
+    }
+
+    #[test]
+    fn division_by_a_literal_zero_is_left_alone() {
+        assert_eq!(folded(&returning("6 / 0"), Overflow::Wrapping), "unfolded");
+        assert_eq!(folded(&returning("7 / 2"), Overflow::Wrapping), "3");
+    }
+
+    #[test]
+    fn constant_conditions_keep_only_the_branch_taken() {
+        let source = "def f(): u8 {\n\
+                      if (2 > 1) { return 1 } else { return 2 }\n\
+                      }";
+        let (program, _) = fold_source(source, Overflow::Wrapping);
+        match body(&program) {
+            Statement::Block(statements) => match &statements[0] {
+                Statement::Block(branch) => {
+                    assert_eq!(branch.len(), 1);
+                    match &branch[0] {
+                        Statement::Block(taken) => match &taken[0] {
+                            Statement::ReturnExpr(expr) => {
+                                assert_eq!(literal_value(expr), Some(1.0))
+                            }
+                            _ => panic!("Expected the return of the first branch"),
+                        },
+                        _ => panic!("Expected the first branch"),
+                    }
+                }
+                _ => panic!("Expected the select to be replaced"),
+            },
+            _ => panic!("Expected a block"),
+        }
+    }
+
+    #[test]
+    fn folded_programs_can_be_generated() {
+        let source = "def f(x: u8): u8 { return (x * 1) + 0 }\n\
+                      def main(): u8 { return f(3) }";
+        let (program, analysis) = fold_source(source, Overflow::Wrapping);
+        let (code, _) = codegen::generate(&program, &analysis, Overflow::Wrapping, Target::Mos6502);
+        assert!(code.contains("_f:"));
+    }
+}
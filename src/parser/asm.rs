@@ -0,0 +1,254 @@
+use std::fmt;
+
+use crate::lexer::LexerToken;
+use crate::lexer::SourceLocation;
+use crate::lexer::TokenType;
+
+use crate::token_stream::TokenStream;
+use crate::token_stream::UnexpectedTokenError;
+
+use crate::graphviz::CreatesGraphviz;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::Factor;
+
+// Where an operand is placed while the block runs
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AsmLocation {
+    A,
+    X,
+    Y,
+    // A scratch byte or pair, named in the text with `{name}`
+    ZeroPage,
+}
+
+impl fmt::Display for AsmLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AsmLocation::A => {
+                write!(f, "A")
+            }
+            AsmLocation::X => {
+                write!(f, "X")
+            }
+            AsmLocation::Y => {
+                write!(f, "Y")
+            }
+            AsmLocation::ZeroPage => {
+                write!(f, "zp")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AsmDirection {
+    // Loaded before the block
+    In,
+    // Stored back after the block
+    Out,
+    InOut,
+}
+
+impl AsmDirection {
+    pub fn reads(&self) -> bool {
+        *self != AsmDirection::Out
+    }
+
+    pub fn writes(&self) -> bool {
+        *self != AsmDirection::In
+    }
+}
+
+// What a block may change besides its outputs
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AsmClobber {
+    A,
+    X,
+    Y,
+    Flags,
+}
+
+// `[in|out|inout] variable: location`
+pub struct AsmOperand {
+    pub direction: AsmDirection,
+    // Always an `Id`, so it is typed like any other use of the variable
+    pub value: Box<AstExprNode>,
+    pub location: AsmLocation,
+}
+
+impl AsmOperand {
+    pub fn name(&self) -> &str {
+        match self.value.as_ref() {
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: _,
+                location: _,
+            }) => id,
+            _ => unreachable!(),
+        }
+    }
+}
+
+// `asm(operands) clobbers(registers) { text }`. The text is passed to
+// the assembler line by line, with `{name}` replaced by the zero page
+// location of an operand or the symbol of a global or function.
+pub struct AsmBlock {
+    pub operands: Vec<AsmOperand>,
+    pub clobbers: Vec<AsmClobber>,
+    pub text: String,
+    pub location: SourceLocation,
+}
+
+impl AsmBlock {
+    pub fn clobbers(&self, register: AsmClobber) -> bool {
+        self.clobbers.contains(&register)
+    }
+
+    pub fn binds(&self, location: AsmLocation) -> bool {
+        self.operands
+            .iter()
+            .any(|operand| operand.location == location)
+    }
+
+    // The names inside `{}` in the text, in order
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut result: Vec<&str> = Vec::new();
+        let mut rest = self.text.as_str();
+        while let Some(start) = rest.find('{') {
+            let after = &rest[start + 1..];
+            match after.find('}') {
+                Some(end) => {
+                    result.push(after[..end].trim());
+                    rest = &after[end + 1..];
+                }
+                None => break,
+            }
+        }
+        result
+    }
+}
+
+impl CreatesGraphviz for AsmBlock {
+    fn get_name(&self) -> String {
+        String::from("asm")
+    }
+
+    fn get_connections(&self) -> Vec<&dyn CreatesGraphviz> {
+        let mut result: Vec<&dyn CreatesGraphviz> = Vec::new();
+        for operand in &self.operands {
+            result.push(operand.value.as_ref());
+        }
+        result
+    }
+}
+
+fn get_operand<I>(token_stream: &mut TokenStream<I>) -> Result<AsmOperand, UnexpectedTokenError>
+where
+    I: Iterator<Item = LexerToken>,
+{
+    let mut name_token = token_stream.expect(TokenType::IDENTIFIER)?;
+    let mut direction = AsmDirection::In;
+    if !token_stream.next_is(TokenType::COLON) {
+        // The first name was a direction
+        direction = match name_token.label.as_ref().unwrap().as_str() {
+            "in" => AsmDirection::In,
+            "out" => AsmDirection::Out,
+            "inout" => AsmDirection::InOut,
+            _ => {
+                return Err(UnexpectedTokenError::unknown_asm_direction(
+                    name_token.label.unwrap(),
+                    name_token.location,
+                ))
+            }
+        };
+        name_token = token_stream.expect(TokenType::IDENTIFIER)?;
+    }
+    let _ = token_stream.expect(TokenType::COLON)?;
+
+    let location_token = token_stream.expect(TokenType::IDENTIFIER)?;
+    let location = match location_token.label.as_ref().unwrap().as_str() {
+        "A" => AsmLocation::A,
+        "X" => AsmLocation::X,
+        "Y" => AsmLocation::Y,
+        "zp" => AsmLocation::ZeroPage,
+        _ => {
+            return Err(UnexpectedTokenError::unknown_asm_location(
+                location_token.label.unwrap(),
+                location_token.location,
+            ))
+        }
+    };
+
+    let result = AsmOperand {
+        direction,
+        value: Box::new(AstExprNode::Terminal(Factor::Id {
+            id: name_token.label.unwrap(),
+            optional_call: None,
+            location: name_token.location,
+        })),
+        location,
+    };
+    Ok(result)
+}
+
+fn get_clobber<I>(token_stream: &mut TokenStream<I>) -> Result<AsmClobber, UnexpectedTokenError>
+where
+    I: Iterator<Item = LexerToken>,
+{
+    let token = token_stream.expect(TokenType::IDENTIFIER)?;
+    match token.label.as_ref().unwrap().as_str() {
+        "A" => Ok(AsmClobber::A),
+        "X" => Ok(AsmClobber::X),
+        "Y" => Ok(AsmClobber::Y),
+        "flags" => Ok(AsmClobber::Flags),
+        _ => Err(UnexpectedTokenError::unknown_asm_clobber(
+            token.label.unwrap(),
+            token.location,
+        )),
+    }
+}
+
+// Parses the rest of an `asm` statement after its keyword. The lexer
+// has already captured the text between the braces as one token.
+pub fn get_asm_block<I>(
+    token_stream: &mut TokenStream<I>,
+    asm_token: LexerToken,
+) -> Result<AsmBlock, UnexpectedTokenError>
+where
+    I: Iterator<Item = LexerToken>,
+{
+    let mut operands: Vec<AsmOperand> = Vec::new();
+    if token_stream.accept(TokenType::L_PAREN).is_some() {
+        let mut continuing_list: bool = token_stream.accept(TokenType::R_PAREN).is_none();
+        while continuing_list {
+            operands.push(get_operand(token_stream)?);
+            continuing_list = token_stream.accept(TokenType::COMMA).is_some();
+        }
+        if !operands.is_empty() {
+            let _ = token_stream.expect(TokenType::R_PAREN)?;
+        }
+    }
+
+    let mut clobbers: Vec<AsmClobber> = Vec::new();
+    if token_stream.accept_label("clobbers").is_some() {
+        let _ = token_stream.expect(TokenType::L_PAREN)?;
+        let mut continuing_list: bool = token_stream.accept(TokenType::R_PAREN).is_none();
+        while continuing_list {
+            clobbers.push(get_clobber(token_stream)?);
+            continuing_list = token_stream.accept(TokenType::COMMA).is_some();
+        }
+        if !clobbers.is_empty() {
+            let _ = token_stream.expect(TokenType::R_PAREN)?;
+        }
+    }
+
+    let text_token = token_stream.expect(TokenType::ASM_TEXT)?;
+    let result = AsmBlock {
+        operands,
+        clobbers,
+        text: text_token.label.unwrap(),
+        location: asm_token.location,
+    };
+    Ok(result)
+}
@@ -8,11 +8,15 @@ use crate::token_stream::TokenStream;
 
 use crate::lexer::TokenType;
 use crate::lexer::LexerToken;
+use crate::lexer::SourceLocation;
 
 use crate::token_stream::UnexpectedTokenError;
 
 use crate::graphviz::CreatesGraphviz;
 
+use crate::parser::types::Type;
+use crate::parser::types::parse_type;
+
 #[derive(Debug)]
 pub enum BinOp {
     Sum(SumOp),
@@ -40,16 +44,35 @@ impl fmt::Display for BinOp {
 pub enum Factor {
     Id{
         id: String,
-        optional_call: Option<Vec<Box<AstExprNode>>>
+        optional_call: Option<Vec<Box<AstExprNode>>>,
+        location: SourceLocation
+    },
+    Index {
+        id: String,
+        index: Box<AstExprNode>,
+        location: SourceLocation
     },
-    Numeric(f64)
+    Numeric {
+        value: f64,
+        location: SourceLocation
+    }
+}
+
+impl Factor {
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            Factor::Id{id: _, optional_call: _, location} => *location,
+            Factor::Index{id: _, index: _, location} => *location,
+            Factor::Numeric{value: _, location} => *location
+        }
+    }
 }
 
 impl fmt::Display for Factor {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Factor::Id{
-                id, optional_call
+                id, optional_call, location: _
             } => {
                 if optional_call.is_none() {
                     write!(f, "Id: {}", id)
@@ -58,8 +81,13 @@ impl fmt::Display for Factor {
                     write!(f, "Call: {}", id)
                 }
             }
-            Factor::Numeric(numeric) => {
-                write!(f, "{}", numeric)
+            Factor::Index{
+                id, index: _, location: _
+            } => {
+                write!(f, "Index: {}", id)
+            }
+            Factor::Numeric{value, location: _} => {
+                write!(f, "{}", value)
             }
         }
     }
@@ -71,12 +99,47 @@ pub enum AstExprNode {
     Node{
         left: Box<AstExprNode>,
         op_type: BinOp,
-        next: Box<AstExprNode>
+        next: Box<AstExprNode>,
+        location: SourceLocation
     },
     SubNode(Box<AstExprNode>),
+    Cast {
+        value: Box<AstExprNode>,
+        ty: Type,
+        location: SourceLocation
+    },
+    AddressOf {
+        target: Box<AstExprNode>,
+        location: SourceLocation
+    },
+    Deref {
+        pointer: Box<AstExprNode>,
+        location: SourceLocation
+    },
+    Field {
+        base: Box<AstExprNode>,
+        field: String,
+        location: SourceLocation
+    },
     Terminal(Factor)
 }
 
+impl AstExprNode {
+    // Where the expression starts in the source, or the operator for
+    // binary expressions
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            AstExprNode::Node{left: _, op_type: _, next: _, location} => *location,
+            AstExprNode::SubNode(sub_node) => sub_node.location(),
+            AstExprNode::Cast{value: _, ty: _, location} => *location,
+            AstExprNode::AddressOf{target: _, location} => *location,
+            AstExprNode::Deref{pointer: _, location} => *location,
+            AstExprNode::Field{base: _, field: _, location} => *location,
+            AstExprNode::Terminal(factor) => factor.location()
+        }
+    }
+}
+
 impl CreatesGraphviz for AstExprNode {
 
     fn get_name(&self) -> String {
@@ -85,34 +148,59 @@ impl CreatesGraphviz for AstExprNode {
                 format!("{}", terminal)
             }
             AstExprNode::Node {
-                left: _, op_type, next: _
+                left: _, op_type, next: _, location: _
             } => {
                 format!("{}", op_type)
             }
             AstExprNode::SubNode(_) => {
                 format!("( )")
             }
+            AstExprNode::Cast {
+                value: _, ty, location: _
+            } => {
+                format!("as {}", ty)
+            }
+            AstExprNode::AddressOf {
+                target: _, location: _
+            } => {
+                "&".to_string()
+            }
+            AstExprNode::Deref {
+                pointer: _, location: _
+            } => {
+                "*".to_string()
+            }
+            AstExprNode::Field {
+                base: _, field, location: _
+            } => {
+                format!(".{}", field)
+            }
         }
     }
 
     fn get_connections(&self) -> Vec<&CreatesGraphviz> {
         match self {
             AstExprNode::Terminal(terminal) => {
-                if let Factor::Id{id: _, optional_call} = terminal {
-                    let mut ret: Vec<&CreatesGraphviz> = Vec::new();
-                    if let Some(arglist) = optional_call {
-                        for arg in arglist {
-                            ret.push(arg.as_ref())
+                match terminal {
+                    Factor::Id{id: _, optional_call, location: _} => {
+                        let mut ret: Vec<&dyn CreatesGraphviz> = Vec::new();
+                        if let Some(arglist) = optional_call {
+                            for arg in arglist {
+                                ret.push(arg.as_ref())
+                            }
                         }
+                        ret
+                    }
+                    Factor::Index{id: _, index, location: _} => {
+                        vec![index.as_ref()]
+                    }
+                    Factor::Numeric{value: _, location: _} => {
+                        vec![]
                     }
-                    return ret;
-                }
-                else {
-                    return vec![]
                 }
             }
             AstExprNode::Node {
-                left, op_type, next
+                left, op_type, next, location: _
             } => {
                 let _ = op_type;
                 return vec![left.as_ref(), next.as_ref()];
@@ -120,6 +208,26 @@ impl CreatesGraphviz for AstExprNode {
             AstExprNode::SubNode(sub_node) => {
                 return vec![sub_node.as_ref()];
             }
+            AstExprNode::Cast {
+                value, ty: _, location: _
+            } => {
+                vec![value.as_ref()]
+            }
+            AstExprNode::AddressOf {
+                target, location: _
+            } => {
+                vec![target.as_ref()]
+            }
+            AstExprNode::Deref {
+                pointer, location: _
+            } => {
+                vec![pointer.as_ref()]
+            }
+            AstExprNode::Field {
+                base, field: _, location: _
+            } => {
+                vec![base.as_ref()]
+            }
         }
     }
 }
@@ -145,18 +253,39 @@ impl BinOp {
 
 
 fn factor<I>(token_stream: &mut TokenStream<I>) -> Result<Box<AstExprNode>, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
+    let result = primary(token_stream)?;
+    cast(token_stream, result)
+}
+
+// A factor without trailing casts, so that `*p as u16` reads the
+// pointee before casting it. Field accesses bind tightest.
+fn primary<I>(token_stream: &mut TokenStream<I>) -> Result<Box<AstExprNode>, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
     let result: Box<AstExprNode>;
-    let token = token_stream.expect_multi(&vec![TokenType::IDENTIFIER, TokenType::NUMBER, TokenType::L_PAREN])?;
+    let token = token_stream.expect_multi(&vec![TokenType::IDENTIFIER, TokenType::NUMBER, TokenType::L_PAREN, TokenType::AMPERSAND, TokenType::MUL_OP])?;
     match token.token_type {
         TokenType::IDENTIFIER => {
-            let optional_call: Option<Vec<Box<AstExprNode>>> = get_optional_call(token_stream)?;
-            let factor = Factor::Id{
-                id: token.label.unwrap(),
-                optional_call: optional_call};
+            let location = token.location;
+            let factor = if token_stream.accept(TokenType::L_BRACKET).is_some() {
+                let index = expression(token_stream)?;
+                let _ = token_stream.expect(TokenType::R_BRACKET)?;
+                Factor::Index{
+                    id: token.label.unwrap(),
+                    index,
+                    location}
+            }
+            else {
+                let optional_call: Option<Vec<Box<AstExprNode>>> = get_optional_call(token_stream)?;
+                Factor::Id{
+                    id: token.label.unwrap(),
+                    optional_call,
+                    location}
+            };
             result = Box::new(AstExprNode::Terminal(factor))
         }
         TokenType::NUMBER => {
-            let factor = Factor::Numeric(token.number.unwrap());
+            let factor = Factor::Numeric{
+                value: token.number.unwrap(),
+                location: token.location};
             result = Box::new(AstExprNode::Terminal(factor))
         }
         TokenType::L_PAREN => {
@@ -164,10 +293,47 @@ fn factor<I>(token_stream: &mut TokenStream<I>) -> Result<Box<AstExprNode>, Unex
             let _ = token_stream.expect(TokenType::R_PAREN)?;
             result = Box::new(AstExprNode::SubNode(expression));
         }
+        TokenType::AMPERSAND => {
+            result = Box::new(AstExprNode::AddressOf {
+                target: primary(token_stream)?,
+                location: token.location
+            });
+        }
+        TokenType::MUL_OP if token.mul_op == Some(MulOp::MULTIPLY) => {
+            result = Box::new(AstExprNode::Deref {
+                pointer: primary(token_stream)?,
+                location: token.location
+            });
+        }
         _ => {
-            unreachable!()
+            let error = UnexpectedTokenError::unexpected(token.token_type, token.location);
+            return Err(error);
         }
     }
+
+    let mut result = result;
+    while token_stream.accept(TokenType::DOT).is_some() {
+        let field_token = token_stream.expect(TokenType::IDENTIFIER)?;
+        result = Box::new(AstExprNode::Field {
+            base: result,
+            field: field_token.label.unwrap(),
+            location: field_token.location
+        });
+    }
+    Ok(result)
+}
+
+// Applies any trailing `as` casts, which bind tighter than every binary operator
+fn cast<I>(token_stream: &mut TokenStream<I>, value: Box<AstExprNode>) -> Result<Box<AstExprNode>, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
+    let mut result = value;
+    while let Some(as_token) = token_stream.accept(TokenType::AS) {
+        let ty = parse_type(token_stream)?;
+        result = Box::new(AstExprNode::Cast {
+            value: result,
+            ty,
+            location: as_token.location
+        });
+    }
     return Ok(result)
 }
 
@@ -175,11 +341,19 @@ type AstConstructor<I> = fn (&mut TokenStream<I>) -> Result<Box<AstExprNode>, Un
 
 fn construct_ast_inner<I>(token_stream: &mut TokenStream<I>, op_type: TokenType, next_constructor: AstConstructor<I>, current_constructor: AstConstructor<I>) -> Result<Box<AstExprNode>, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
     let left: Box<AstExprNode> = next_constructor(token_stream)?;
+    // A `*` that starts a line dereferences at the start of the next
+    // statement, rather than multiplying what came before
+    let is_deref = token_stream.next_starts_line()
+        && token_stream.peek().is_some_and(|top| top.mul_op == Some(MulOp::MULTIPLY));
+    if is_deref {
+        return Ok(left);
+    }
     if let Some(op) = token_stream.accept(op_type) {
         let ret = AstExprNode::Node {
             left: left,
             op_type: BinOp::from_token_type(op_type, &op).unwrap(), 
-            next: current_constructor(token_stream)?
+            next: current_constructor(token_stream)?,
+            location: op.location
         };
         return Ok(Box::new(ret))
     }
@@ -212,8 +386,8 @@ fn get_optional_call<I>(token_stream: &mut TokenStream<I>) -> Result<Option<Vec<
                 arglist.push(expression(token_stream)?);
                 continue_list = token_stream.accept(TokenType::COMMA).is_some();
             }
+            let _ = token_stream.expect(TokenType::R_PAREN)?;
         }
-        let _ = token_stream.expect(TokenType::R_PAREN)?;
         return Ok(Some(arglist));
     }
     else {
@@ -0,0 +1,151 @@
+use crate::lexer::LexerToken;
+use crate::lexer::SourceLocation;
+use crate::lexer::TokenType;
+
+use crate::token_stream::TokenStream;
+use crate::token_stream::UnexpectedTokenError;
+
+use crate::graphviz::CreatesGraphviz;
+
+use crate::parser::bin_op::expression;
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::parser::statement;
+use crate::parser::parser::Statement;
+
+// One pattern of an arm, made of constant expressions
+pub enum MatchPattern {
+    Value(Box<AstExprNode>),
+    // `low..high`, or `low..=high` when the end is included
+    Range {
+        low: Box<AstExprNode>,
+        high: Box<AstExprNode>,
+        inclusive: bool,
+    },
+}
+
+impl MatchPattern {
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            MatchPattern::Value(value) => value.location(),
+            MatchPattern::Range {
+                low,
+                high: _,
+                inclusive: _,
+            } => low.location(),
+        }
+    }
+}
+
+// `patterns: statement`, or `else: statement` with no patterns
+pub struct MatchArm {
+    pub patterns: Vec<MatchPattern>,
+    pub statement: Box<Statement>,
+    pub location: SourceLocation,
+}
+
+// `match (value) { arms }`. The first arm with a matching pattern runs,
+// or the `else` arm when none does.
+pub struct MatchStatement {
+    pub value: Box<AstExprNode>,
+    pub arms: Vec<MatchArm>,
+    pub default: Option<MatchArm>,
+    pub location: SourceLocation,
+}
+
+impl MatchStatement {
+    // Every arm body, the `else` arm last
+    pub fn statements(&self) -> Vec<&Statement> {
+        let mut result: Vec<&Statement> =
+            self.arms.iter().map(|arm| arm.statement.as_ref()).collect();
+        if let Some(default) = &self.default {
+            result.push(default.statement.as_ref());
+        }
+        result
+    }
+}
+
+impl CreatesGraphviz for MatchStatement {
+    fn get_name(&self) -> String {
+        String::from("match")
+    }
+
+    fn get_connections(&self) -> Vec<&dyn CreatesGraphviz> {
+        let mut result: Vec<&dyn CreatesGraphviz> = vec![self.value.as_ref()];
+        for statement in self.statements() {
+            result.push(statement);
+        }
+        result
+    }
+}
+
+fn get_pattern<I>(token_stream: &mut TokenStream<I>) -> Result<MatchPattern, UnexpectedTokenError>
+where
+    I: Iterator<Item = LexerToken>,
+{
+    let low = expression(token_stream)?;
+    if token_stream.accept(TokenType::DOT_DOT).is_none() {
+        return Ok(MatchPattern::Value(low));
+    }
+    let inclusive = token_stream.accept(TokenType::ASSIGN).is_some();
+    let result = MatchPattern::Range {
+        low,
+        high: expression(token_stream)?,
+        inclusive,
+    };
+    Ok(result)
+}
+
+// Parses the rest of a `match` statement after its keyword
+pub fn get_match_statement<I>(
+    token_stream: &mut TokenStream<I>,
+    match_token: LexerToken,
+) -> Result<MatchStatement, UnexpectedTokenError>
+where
+    I: Iterator<Item = LexerToken>,
+{
+    let _ = token_stream.expect(TokenType::L_PAREN)?;
+    let value = expression(token_stream)?;
+    let _ = token_stream.expect(TokenType::R_PAREN)?;
+    let _ = token_stream.expect(TokenType::L_BRACE)?;
+
+    let mut arms: Vec<MatchArm> = Vec::new();
+    let mut default: Option<MatchArm> = None;
+    while token_stream.accept(TokenType::R_BRACE).is_none() {
+        if token_stream.accept(TokenType::SEMICOLON).is_some() {
+            continue;
+        }
+
+        if let Some(else_token) = token_stream.accept(TokenType::ELSE) {
+            if default.is_some() {
+                return Err(UnexpectedTokenError::duplicate_default(else_token.location));
+            }
+            let _ = token_stream.expect(TokenType::COLON)?;
+            default = Some(MatchArm {
+                patterns: Vec::new(),
+                statement: statement(token_stream)?,
+                location: else_token.location,
+            });
+            continue;
+        }
+
+        let mut patterns: Vec<MatchPattern> = vec![get_pattern(token_stream)?];
+        while token_stream.accept(TokenType::COMMA).is_some() {
+            patterns.push(get_pattern(token_stream)?);
+        }
+        let location = patterns[0].location();
+        let _ = token_stream.expect(TokenType::COLON)?;
+        arms.push(MatchArm {
+            patterns,
+            statement: statement(token_stream)?,
+            location,
+        });
+    }
+
+    let result = MatchStatement {
+        value,
+        arms,
+        default,
+        location: match_token.location,
+    };
+    Ok(result)
+}
@@ -1,2 +1,5 @@
 pub mod parser;
-pub mod bin_op;
\ No newline at end of file
+pub mod asm;
+pub mod matching;
+pub mod bin_op;
+pub mod types;
\ No newline at end of file
@@ -1,22 +1,89 @@
 
+use std::fmt;
+use std::rc::Rc;
+
 use crate::lexer::LexerToken;
 use crate::lexer::TokenType;
+use crate::lexer::SourceLocation;
 
 use crate::token_stream::TokenStream;
 use crate::token_stream::UnexpectedTokenError;
 
 use crate::graphviz::CreatesGraphviz;
-use crate::graphviz::Graphviz;
 
+use crate::parser::asm::AsmBlock;
+use crate::parser::asm::get_asm_block;
+use crate::parser::matching::MatchStatement;
+use crate::parser::matching::get_match_statement;
 use crate::parser::bin_op::AstExprNode;
 use crate::parser::bin_op::expression;
 
+use crate::parser::types::StructField;
+use crate::parser::types::StructLayout;
+use crate::parser::types::StructType;
+use crate::parser::types::Type;
+use crate::parser::types::parse_type;
+
+
+// Where a foreign routine takes an argument or leaves its result.
+// Pairs hold the low byte in the first register.
+pub enum ArgLocation {
+    A,
+    X,
+    Y,
+    AX,
+    AY,
+    XY,
+    Memory(Box<AstExprNode>)
+}
+
+impl ArgLocation {
+    // The registers holding the low and high bytes
+    pub fn registers(&self) -> Vec<&'static str> {
+        match self {
+            ArgLocation::A => vec!["A"],
+            ArgLocation::X => vec!["X"],
+            ArgLocation::Y => vec!["Y"],
+            ArgLocation::AX => vec!["A", "X"],
+            ArgLocation::AY => vec!["A", "Y"],
+            ArgLocation::XY => vec!["X", "Y"],
+            ArgLocation::Memory(_) => vec![]
+        }
+    }
+}
 
-type FuncArg = String;
+impl fmt::Display for ArgLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgLocation::Memory(_) => {
+                write!(f, "memory")
+            }
+            _ => {
+                write!(f, "{}", self.registers().join(""))
+            }
+        }
+    }
+}
+
+
+pub struct FuncArg {
+    pub name: String,
+    pub ty: Option<Type>,
+    // Only for externs that are called in place
+    pub passed_in: Option<ArgLocation>,
+    pub location: SourceLocation
+}
 
 impl CreatesGraphviz for FuncArg {
     fn get_name(&self) -> String {
-        return self.clone();
+        let name = match &self.ty {
+            Some(ty) => format!("{}: {}", self.name, ty),
+            None => self.name.clone()
+        };
+        match &self.passed_in {
+            Some(passed_in) => format!("{} in {}", name, passed_in),
+            None => name
+        }
     }
 
     fn get_connections(&self) -> Vec<&CreatesGraphviz> {
@@ -25,16 +92,61 @@ impl CreatesGraphviz for FuncArg {
 }
 
 
-struct FuncDecl {
-    name: String,
-    args: Vec<FuncArg>
+// An attribute between a function's name and its parameters, such as
+// `interrupt(nmi)`
+pub struct FuncAttribute {
+    pub name: String,
+    pub args: Vec<String>,
+    pub location: SourceLocation
+}
+
+impl fmt::Display for FuncAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.args.is_empty() {
+            write!(f, "{}", self.name)
+        }
+        else {
+            write!(f, "{}({})", self.name, self.args.join(", "))
+        }
+    }
+}
+
+
+pub struct FuncDecl {
+    pub name: String,
+    pub attributes: Vec<FuncAttribute>,
+    pub args: Vec<FuncArg>,
+    pub return_type: Option<Type>,
+    pub returned_in: Option<ArgLocation>,
+    // `@ address` or `@ symbol` of an extern, such as a ROM routine
+    pub address: Option<Box<AstExprNode>>,
+    pub location: SourceLocation
+}
+
+impl FuncDecl {
+    pub fn attribute(&self, name: &str) -> Option<&FuncAttribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    // Foreign routines take their arguments in registers and memory
+    // instead of on the data stack
+    pub fn is_foreign(&self) -> bool {
+        self.address.is_some() || self.returned_in.is_some() || self.args.iter().any(|arg| arg.passed_in.is_some())
+    }
 }
 
 
 impl CreatesGraphviz for FuncDecl {
 
     fn get_name(&self) -> String {
-        return self.name.clone();
+        let mut name = self.name.clone();
+        for attribute in &self.attributes {
+            name = format!("{} {}", name, attribute);
+        }
+        match &self.return_type {
+            Some(ty) => format!("{}: {}", name, ty),
+            None => name
+        }
     }
 
     fn get_connections(&self) -> Vec<&CreatesGraphviz> {
@@ -47,18 +159,21 @@ impl CreatesGraphviz for FuncDecl {
 }
 
 
-fn get_func_decl<I>(token_stream: &mut TokenStream<I>) -> Result<FuncDecl, UnexpectedTokenError> where I: Iterator<Item = LexerToken>{
-    let name_token = token_stream.expect(TokenType::IDENTIFIER)?;
-    let name: String = name_token.label.unwrap();
-    let _: LexerToken = token_stream.expect(TokenType::L_PAREN)?;
-    let mut args: Vec<String> = Vec::new();
+// A parenthesised list of `name` or `name: type`, after its `(`
+fn get_arg_list<I>(token_stream: &mut TokenStream<I>) -> Result<Vec<FuncArg>, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
+    let mut args: Vec<FuncArg> = Vec::new();
 
     let mut continuing_list: bool = token_stream.accept(TokenType::R_PAREN).is_none();
 
     while continuing_list {
         let arg_token: LexerToken = token_stream.expect(TokenType::IDENTIFIER)?;
-        let arg_name = arg_token.label.unwrap();
-        args.push(arg_name);
+        let arg = FuncArg {
+            name: arg_token.label.unwrap(),
+            ty: optional_type(token_stream)?,
+            passed_in: optional_location(token_stream)?,
+            location: arg_token.location
+        };
+        args.push(arg);
 
         continuing_list = token_stream.accept(TokenType::COMMA).is_some();
     }
@@ -66,22 +181,138 @@ fn get_func_decl<I>(token_stream: &mut TokenStream<I>) -> Result<FuncDecl, Unexp
     if args.len() > 0 {
         let _: LexerToken = token_stream.expect(TokenType::R_PAREN)?;
     }
+    Ok(args)
+}
+
+
+// Attributes such as `interrupt(nmi)` sit between the name and the
+// parameters. The last parenthesised list is always the parameters,
+// and any list before it belongs to the attribute it follows.
+fn get_func_decl<I>(token_stream: &mut TokenStream<I>) -> Result<FuncDecl, UnexpectedTokenError> where I: Iterator<Item = LexerToken>{
+    let name_token = token_stream.expect(TokenType::IDENTIFIER)?;
+    let name: String = name_token.label.unwrap();
+
+    let mut attributes: Vec<FuncAttribute> = Vec::new();
+    let mut pending: Option<(LexerToken, Vec<FuncArg>)> = None;
+    loop {
+        if let Some(paren_token) = token_stream.accept(TokenType::L_PAREN) {
+            let list = get_arg_list(token_stream)?;
+            if let Some((earlier_token, earlier)) = pending.take() {
+                attach_attribute_args(&mut attributes, earlier_token, earlier)?;
+            }
+            pending = Some((paren_token, list));
+        }
+        else if let Some(attribute_token) = token_stream.accept(TokenType::IDENTIFIER) {
+            if let Some((earlier_token, earlier)) = pending.take() {
+                attach_attribute_args(&mut attributes, earlier_token, earlier)?;
+            }
+            attributes.push(FuncAttribute {
+                name: attribute_token.label.unwrap(),
+                args: Vec::new(),
+                location: attribute_token.location
+            });
+        }
+        else {
+            break;
+        }
+    }
 
+    let args = match pending {
+        Some((_, args)) => args,
+        None => {
+            // Always fails, reporting the missing parameter list
+            let _: LexerToken = token_stream.expect(TokenType::L_PAREN)?;
+            unreachable!()
+        }
+    };
+
+    let return_type = optional_type(token_stream)?;
     let result = FuncDecl {
-        name: name,
-        args: args
+        name,
+        attributes,
+        args,
+        return_type,
+        returned_in: optional_location(token_stream)?,
+        address: None,
+        location: name_token.location
     };
     return Ok(result);
 }
 
 
-enum Statement {
+// Gives a parenthesised list to the attribute just before it, as long
+// as the attribute has none yet and the list is only names
+fn attach_attribute_args(attributes: &mut [FuncAttribute], paren_token: LexerToken, list: Vec<FuncArg>) -> Result<(), UnexpectedTokenError> {
+    match attributes.last_mut() {
+        Some(attribute) if attribute.args.is_empty() => {
+            for arg in list {
+                if arg.ty.is_some() {
+                    return Err(UnexpectedTokenError::unexpected(TokenType::COLON, arg.location));
+                }
+                attribute.args.push(arg.name);
+            }
+            Ok(())
+        }
+        _ => {
+            Err(UnexpectedTokenError::unexpected(TokenType::L_PAREN, paren_token.location))
+        }
+    }
+}
+
+
+// Parses an optional `in A`, `in XY` or `in address`
+fn optional_location<I>(token_stream: &mut TokenStream<I>) -> Result<Option<ArgLocation>, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
+    if token_stream.accept_label("in").is_none() {
+        return Ok(None);
+    }
+    if let Some(register_token) = token_stream.accept(TokenType::IDENTIFIER) {
+        let location = match register_token.label.as_ref().unwrap().as_str() {
+            "A" => ArgLocation::A,
+            "X" => ArgLocation::X,
+            "Y" => ArgLocation::Y,
+            "AX" => ArgLocation::AX,
+            "AY" => ArgLocation::AY,
+            "XY" => ArgLocation::XY,
+            _ => return Err(UnexpectedTokenError::unknown_arg_location(register_token.label.unwrap(), register_token.location))
+        };
+        return Ok(Some(location));
+    }
+    Ok(Some(ArgLocation::Memory(expression(token_stream)?)))
+}
+
+
+// Parses an optional `: type` annotation
+fn optional_type<I>(token_stream: &mut TokenStream<I>) -> Result<Option<Type>, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
+    if token_stream.accept(TokenType::COLON).is_some() {
+        return Ok(Some(parse_type(token_stream)?));
+    }
+    Ok(None)
+}
+
+
+pub enum Statement {
     Select {
         condition: Box<AstExprNode>,
         statement: Box<Statement>,
         else_clause: Option<Box<Statement>>
     },
-    ReturnExpr(Box<AstExprNode>)
+    ReturnExpr(Box<AstExprNode>),
+    Block(Vec<Statement>),
+    // A local variable, visible until the end of the enclosing block
+    Local {
+        name: String,
+        ty: Option<Type>,
+        value: Box<AstExprNode>,
+        location: SourceLocation
+    },
+    Assign {
+        target: Box<AstExprNode>,
+        value: Box<AstExprNode>,
+        location: SourceLocation
+    },
+    Expression(Box<AstExprNode>),
+    Asm(AsmBlock),
+    Match(MatchStatement)
 }
 
 
@@ -96,6 +327,31 @@ impl CreatesGraphviz for Statement {
             Statement::ReturnExpr(_)=> {
                 String::from("return")
             }
+            Statement::Block(_) => {
+                String::from("{ }")
+            }
+            Statement::Local {
+                name, ty, value: _, location: _
+            } => {
+                match ty {
+                    Some(ty) => format!("var {}: {}", name, ty),
+                    None => format!("var {}", name)
+                }
+            }
+            Statement::Assign {
+                target: _, value: _, location: _
+            } => {
+                String::from("=")
+            }
+            Statement::Expression(_) => {
+                String::from("expr")
+            }
+            Statement::Asm(block) => {
+                block.get_name()
+            }
+            Statement::Match(matching) => {
+                matching.get_name()
+            }
         }
     }
 
@@ -113,13 +369,53 @@ impl CreatesGraphviz for Statement {
             Statement::ReturnExpr(expr) => {
                 return vec![expr.as_ref()];
             }
+            Statement::Block(statements) => {
+                let mut result: Vec<&dyn CreatesGraphviz> = Vec::new();
+                for statement in statements {
+                    result.push(statement);
+                }
+                result
+            }
+            Statement::Local {
+                name: _, ty: _, value, location: _
+            } => {
+                vec![value.as_ref()]
+            }
+            Statement::Assign {
+                target, value, location: _
+            } => {
+                vec![target.as_ref(), value.as_ref()]
+            }
+            Statement::Expression(expr) => {
+                vec![expr.as_ref()]
+            }
+            Statement::Asm(block) => {
+                block.get_connections()
+            }
+            Statement::Match(matching) => {
+                matching.get_connections()
+            }
         }
     }
 }
 
 
-fn statement<I>(token_stream: &mut TokenStream<I>) -> Result<Box<Statement>, UnexpectedTokenError> where I: Iterator<Item = LexerToken>{
-    let token = token_stream.expect_multi(&vec![TokenType::IF, TokenType::RETURN])?;
+pub fn statement<I>(token_stream: &mut TokenStream<I>) -> Result<Box<Statement>, UnexpectedTokenError> where I: Iterator<Item = LexerToken>{
+    if token_stream.next_is(TokenType::IDENTIFIER) || token_stream.next_is(TokenType::MUL_OP) {
+        // Either an assignment or an expression evaluated for its side effects
+        let target = expression(token_stream)?;
+        if let Some(assign_token) = token_stream.accept(TokenType::ASSIGN) {
+            let result = Statement::Assign {
+                target,
+                value: expression(token_stream)?,
+                location: assign_token.location
+            };
+            return Ok(Box::new(result));
+        }
+        return Ok(Box::new(Statement::Expression(target)));
+    }
+
+    let token = token_stream.expect_multi(&vec![TokenType::IF, TokenType::RETURN, TokenType::L_BRACE, TokenType::VAR, TokenType::ASM, TokenType::MATCH])?;
     match token.token_type {
         TokenType::IF => {
             let _ = token_stream.expect(TokenType::L_PAREN)?;
@@ -147,6 +443,33 @@ fn statement<I>(token_stream: &mut TokenStream<I>) -> Result<Box<Statement>, Une
         TokenType::RETURN => {
             return Ok(Box::new(Statement::ReturnExpr(expression(token_stream)?)));
         }
+        TokenType::L_BRACE => {
+            let mut statements: Vec<Statement> = Vec::new();
+            while token_stream.accept(TokenType::R_BRACE).is_none() {
+                if token_stream.accept(TokenType::SEMICOLON).is_none() {
+                    statements.push(*statement(token_stream)?);
+                }
+            }
+            Ok(Box::new(Statement::Block(statements)))
+        }
+        TokenType::VAR => {
+            let name_token = token_stream.expect(TokenType::IDENTIFIER)?;
+            let ty = optional_type(token_stream)?;
+            let _ = token_stream.expect(TokenType::ASSIGN)?;
+            let result = Statement::Local {
+                name: name_token.label.unwrap(),
+                ty,
+                value: expression(token_stream)?,
+                location: name_token.location
+            };
+            Ok(Box::new(result))
+        }
+        TokenType::ASM => {
+            Ok(Box::new(Statement::Asm(get_asm_block(token_stream, token)?)))
+        }
+        TokenType::MATCH => {
+            Ok(Box::new(Statement::Match(get_match_statement(token_stream, token)?)))
+        }
         _ => {
             unreachable!()
         }
@@ -154,12 +477,173 @@ fn statement<I>(token_stream: &mut TokenStream<I>) -> Result<Box<Statement>, Une
 }
 
 
-enum PrimaryStatement {
+pub enum Initializer {
+    Scalar(Box<AstExprNode>),
+    List(Vec<AstExprNode>)
+}
+
+
+impl CreatesGraphviz for Initializer {
+    fn get_name(&self) -> String {
+        match self {
+            Initializer::Scalar(_) => {
+                String::from("=")
+            }
+            Initializer::List(_) => {
+                String::from("[ ]")
+            }
+        }
+    }
+
+    fn get_connections(&self) -> Vec<&dyn CreatesGraphviz> {
+        match self {
+            Initializer::Scalar(expr) => {
+                vec![expr.as_ref()]
+            }
+            Initializer::List(elements) => {
+                let mut result: Vec<&dyn CreatesGraphviz> = Vec::new();
+                for element in elements {
+                    result.push(element);
+                }
+                result
+            }
+        }
+    }
+}
+
+
+// A global `var`, which lives in RAM, or a `const`, which is placed in ROM
+// and must be initialized
+pub struct GlobalDecl {
+    pub name: String,
+    pub ty: Type,
+    pub is_const: bool,
+    // Every read and write is emitted as written and in order. Passes
+    // that rewrite expressions must never merge, move or drop them.
+    pub is_volatile: bool,
+    // Fixed location, such as a hardware register, instead of storage
+    pub address: Option<Box<AstExprNode>>,
+    pub initializer: Option<Initializer>,
+    pub location: SourceLocation
+}
+
+
+impl CreatesGraphviz for GlobalDecl {
+    fn get_name(&self) -> String {
+        let keyword = if self.is_const { "const" } else { "var" };
+        let qualifier = if self.is_volatile { "volatile " } else { "" };
+        let placement = if self.address.is_some() { " @" } else { "" };
+        format!("{} {}: {}{}{}", keyword, self.name, qualifier, self.ty, placement)
+    }
+
+    fn get_connections(&self) -> Vec<&dyn CreatesGraphviz> {
+        let mut result: Vec<&dyn CreatesGraphviz> = Vec::new();
+        if let Some(address) = &self.address {
+            result.push(address.as_ref());
+        }
+        if let Some(initializer) = &self.initializer {
+            result.push(initializer);
+        }
+        result
+    }
+}
+
+
+fn get_global_decl<I>(token_stream: &mut TokenStream<I>, is_const: bool) -> Result<GlobalDecl, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
+    let name_token = token_stream.expect(TokenType::IDENTIFIER)?;
+    let _ = token_stream.expect(TokenType::COLON)?;
+    let is_volatile = token_stream.accept(TokenType::VOLATILE).is_some();
+    let ty = parse_type(token_stream)?;
+
+    let mut address: Option<Box<AstExprNode>> = None;
+    if token_stream.accept(TokenType::AT).is_some() {
+        address = Some(expression(token_stream)?);
+    }
+
+    let mut initializer: Option<Initializer> = None;
+    if is_const {
+        let _ = token_stream.expect(TokenType::ASSIGN)?;
+        if token_stream.accept(TokenType::L_BRACKET).is_some() {
+            let mut elements: Vec<AstExprNode> = Vec::new();
+            let mut continuing_list: bool = token_stream.accept(TokenType::R_BRACKET).is_none();
+            while continuing_list {
+                elements.push(*expression(token_stream)?);
+                continuing_list = token_stream.accept(TokenType::COMMA).is_some();
+            }
+
+            if !elements.is_empty() {
+                let _ = token_stream.expect(TokenType::R_BRACKET)?;
+            }
+            initializer = Some(Initializer::List(elements));
+        }
+        else {
+            initializer = Some(Initializer::Scalar(expression(token_stream)?));
+        }
+    }
+
+    let result = GlobalDecl {
+        name: name_token.label.unwrap(),
+        ty,
+        is_const,
+        is_volatile,
+        address,
+        initializer,
+        location: name_token.location
+    };
+    Ok(result)
+}
+
+
+// `struct Name { field: type, ... }`. An `soa` or `aos` after the name
+// picks how arrays of the struct are laid out, `aos` being the default.
+fn get_struct_decl<I>(token_stream: &mut TokenStream<I>) -> Result<Rc<StructType>, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
+    let name_token = token_stream.expect(TokenType::IDENTIFIER)?;
+    let mut layout = StructLayout::ArrayOfStructs;
+    if let Some(layout_token) = token_stream.accept(TokenType::IDENTIFIER) {
+        let layout_name = layout_token.label.unwrap();
+        layout = match layout_name.as_str() {
+            "aos" => StructLayout::ArrayOfStructs,
+            "soa" => StructLayout::StructOfArrays,
+            _ => return Err(UnexpectedTokenError::unknown_layout(layout_name, layout_token.location))
+        };
+    }
+
+    let _ = token_stream.expect(TokenType::L_BRACE)?;
+    let mut fields: Vec<StructField> = Vec::new();
+    while token_stream.accept(TokenType::R_BRACE).is_none() {
+        let field_token = token_stream.expect(TokenType::IDENTIFIER)?;
+        let _ = token_stream.expect(TokenType::COLON)?;
+        fields.push(StructField {
+            name: field_token.label.unwrap(),
+            ty: parse_type(token_stream)?,
+            location: field_token.location
+        });
+        if token_stream.accept(TokenType::COMMA).is_none() {
+            let _ = token_stream.expect(TokenType::R_BRACE)?;
+            break;
+        }
+    }
+
+    let name = name_token.label.unwrap();
+    let result = Rc::new(StructType {
+        name: name.clone(),
+        fields,
+        layout,
+        location: name_token.location
+    });
+    token_stream.structs.insert(name, result.clone());
+    Ok(result)
+}
+
+
+pub enum PrimaryStatement {
     Definition {
         decl: FuncDecl,
         inner_statement: Box<Statement>
     },
-    Extern(FuncDecl)
+    Extern(FuncDecl),
+    Global(GlobalDecl),
+    Struct(Rc<StructType>)
 }
 
 
@@ -174,6 +658,12 @@ impl CreatesGraphviz for PrimaryStatement {
             } => {
                 String::from("Def")
             }
+            PrimaryStatement::Global(global) => {
+                global.get_name()
+            }
+            PrimaryStatement::Struct(structure) => {
+                format!("struct {}", structure.name)
+            }
         }
     }
 
@@ -187,18 +677,27 @@ impl CreatesGraphviz for PrimaryStatement {
             PrimaryStatement::Extern(decl) => {
                 return vec![decl]
             }
+            PrimaryStatement::Global(global) => {
+                global.get_connections()
+            }
+            PrimaryStatement::Struct(_) => {
+                vec![]
+            }
         }
     }
 }
 
 
 fn primary<I>(token_stream: &mut TokenStream<I>) -> Result<PrimaryStatement, UnexpectedTokenError> where I: Iterator<Item = LexerToken> {
-    let token = token_stream.expect_multi(&vec![TokenType::EXTERN, TokenType::DEF])?;
+    let token = token_stream.expect_multi(&vec![TokenType::EXTERN, TokenType::DEF, TokenType::CONST, TokenType::VAR, TokenType::STRUCT])?;
     let result: PrimaryStatement;
 
     match token.token_type {
         TokenType::EXTERN => {
-            let decl = get_func_decl(token_stream)?;
+            let mut decl = get_func_decl(token_stream)?;
+            if token_stream.accept(TokenType::AT).is_some() {
+                decl.address = Some(expression(token_stream)?);
+            }
             result = PrimaryStatement::Extern(decl);
         }
         TokenType::DEF => {
@@ -209,6 +708,15 @@ fn primary<I>(token_stream: &mut TokenStream<I>) -> Result<PrimaryStatement, Une
                 inner_statement: statement
             }
         }
+        TokenType::CONST => {
+            result = PrimaryStatement::Global(get_global_decl(token_stream, true)?);
+        }
+        TokenType::VAR => {
+            result = PrimaryStatement::Global(get_global_decl(token_stream, false)?);
+        }
+        TokenType::STRUCT => {
+            result = PrimaryStatement::Struct(get_struct_decl(token_stream)?);
+        }
         _ => {
             unreachable!()
         }
@@ -217,20 +725,39 @@ fn primary<I>(token_stream: &mut TokenStream<I>) -> Result<PrimaryStatement, Une
 }
 
 
-pub fn parse_stream(token_stream: &Vec<LexerToken>) -> Result<String, UnexpectedTokenError> {
-    let mut stream = TokenStream(token_stream.iter().cloned().peekable());
+pub fn parse_stream(token_stream: &[LexerToken]) -> Result<Box<Program>, UnexpectedTokenError> {
+    let mut stream = TokenStream::new(token_stream.iter().cloned().peekable());
+
+    program(&mut stream)
+}
 
-    let state = program(&mut stream)?;
-    let result = Graphviz::from(state.as_ref() as &CreatesGraphviz);
 
-    result.write_file(String::from("./a.out"));
-    return Ok(String::from("Ok"));
+pub struct Program {
+    pub primary: PrimaryStatement,
+    pub next: Option<Box<Program>>
 }
 
+impl Program {
+    // Flattens the program list into its top level statements, in source order
+    pub fn primaries(&self) -> Vec<&PrimaryStatement> {
+        let mut result: Vec<&PrimaryStatement> = vec![&self.primary];
+        let mut current = &self.next;
+        while let Some(node) = current {
+            result.push(&node.primary);
+            current = &node.next;
+        }
+        result
+    }
 
-struct Program {
-    primary: PrimaryStatement,
-    next: Option<Box<Program>>
+    pub fn primaries_mut(&mut self) -> Vec<&mut PrimaryStatement> {
+        let mut result: Vec<&mut PrimaryStatement> = Vec::new();
+        let mut current = Some(self);
+        while let Some(Program { primary, next }) = current {
+            result.push(primary);
+            current = next.as_deref_mut();
+        }
+        result
+    }
 }
 
 impl CreatesGraphviz for Program {
@@ -270,4 +797,81 @@ fn program<I>(token_stream: &mut TokenStream<I>) -> Result<Box<Program>, Unexpec
     };
 
     return Ok(Box::new(result));
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub fn parse_source(source: &str) -> Box<Program> {
+    parse_stream(&crate::lexer::lex_string(String::from(source))).unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::MulOp;
+    use crate::parser::bin_op::BinOp;
+    use crate::parser::bin_op::Factor;
+
+    fn statements(program: &Program) -> &Vec<Statement> {
+        match program.primaries().last() {
+            Some(PrimaryStatement::Definition { decl: _, inner_statement }) => match inner_statement.as_ref() {
+                Statement::Block(statements) => statements,
+                _ => panic!("body is not a block")
+            },
+            _ => panic!("no definition")
+        }
+    }
+
+    fn is_deref(expr: &AstExprNode) -> bool {
+        matches!(expr, AstExprNode::Deref { pointer: _, location: _ })
+    }
+
+    #[test]
+    fn star_starting_a_line_begins_a_statement() {
+        let program = parse_source("
+def memcpy(d: *mut u8, s: *u8, n: u16): u16 {
+    if (n == 0) return 0
+    d[0] = s[0]
+    *d = *s
+    return memcpy(d + 1, s + 1, n - 1)
+}
+");
+        let statements = statements(&program);
+        assert_eq!(statements.len(), 4);
+        match &statements[1] {
+            Statement::Assign { target, value, location: _ } => {
+                assert!(matches!(target.as_ref(), AstExprNode::Terminal(Factor::Index { id: _, index: _, location: _ })));
+                assert!(matches!(value.as_ref(), AstExprNode::Terminal(Factor::Index { id: _, index: _, location: _ })));
+            }
+            _ => panic!("expected an assignment")
+        }
+        match &statements[2] {
+            Statement::Assign { target, value, location: _ } => {
+                assert!(is_deref(target));
+                assert!(is_deref(value));
+            }
+            _ => panic!("expected an assignment")
+        }
+    }
+
+    #[test]
+    fn star_within_a_line_multiplies() {
+        let program = parse_source("
+def f(a: u8, b: u8): u8 {
+    var c = a *
+        b
+    return a * b
+}
+");
+        let statements = statements(&program);
+        assert_eq!(statements.len(), 2);
+        for statement in statements {
+            let value = match statement {
+                Statement::Local { name: _, ty: _, value, location: _ } => value,
+                Statement::ReturnExpr(value) => value,
+                _ => panic!("unexpected statement")
+            };
+            assert!(matches!(value.as_ref(), AstExprNode::Node { left: _, op_type: BinOp::Mult(MulOp::MULTIPLY), next: _, location: _ }));
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::lexer::LexerToken;
+use crate::lexer::MulOp;
+use crate::lexer::SourceLocation;
+use crate::lexer::TokenType;
+
+use crate::token_stream::TokenStream;
+use crate::token_stream::UnexpectedTokenError;
+
+// Type given to unannotated parameters, returns and literals
+pub const DEFAULT_INT_TYPE: IntType = IntType::U16;
+
+// Type given to literals with a fractional part
+pub const DEFAULT_FIXED_TYPE: FixedType = FixedType::F8x8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IntType {
+    U8,
+    I8,
+    U16,
+    I16,
+}
+
+impl IntType {
+    pub fn from_name(name: &str) -> Option<IntType> {
+        match name {
+            "u8" => Some(IntType::U8),
+            "i8" => Some(IntType::I8),
+            "u16" => Some(IntType::U16),
+            "i16" => Some(IntType::I16),
+            _ => None,
+        }
+    }
+
+    // Size of a value in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            IntType::U8 | IntType::I8 => 1,
+            IntType::U16 | IntType::I16 => 2,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        match self {
+            IntType::I8 | IntType::I16 => true,
+            IntType::U8 | IntType::U16 => false,
+        }
+    }
+
+    pub fn max_value(&self) -> i64 {
+        match self {
+            IntType::U8 => 255,
+            IntType::I8 => 127,
+            IntType::U16 => 65535,
+            IntType::I16 => 32767,
+        }
+    }
+
+    pub fn min_value(&self) -> i64 {
+        match self {
+            IntType::U8 | IntType::U16 => 0,
+            IntType::I8 => -128,
+            IntType::I16 => -32768,
+        }
+    }
+
+    // Truncates a value to this width, as storing it would
+    pub fn wrap(&self, value: i64) -> i64 {
+        let bits = 8 * self.size() as u32;
+        let truncated = value & ((1 << bits) - 1);
+        if self.is_signed() && truncated > self.max_value() {
+            truncated - (1 << bits)
+        } else {
+            truncated
+        }
+    }
+}
+
+impl fmt::Display for IntType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntType::U8 => write!(f, "u8"),
+            IntType::I8 => write!(f, "i8"),
+            IntType::U16 => write!(f, "u16"),
+            IntType::I16 => write!(f, "i16"),
+        }
+    }
+}
+
+// Signed binary fixed-point numbers, named for their integer and
+// fraction bits. A value is stored as an integer counting steps of
+// 2^-fraction_bits.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FixedType {
+    F8x8,
+    F16x16,
+}
+
+impl FixedType {
+    pub fn from_name(name: &str) -> Option<FixedType> {
+        match name {
+            "f8x8" => Some(FixedType::F8x8),
+            "f16x16" => Some(FixedType::F16x16),
+            _ => None,
+        }
+    }
+
+    // Size of a value in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            FixedType::F8x8 => 2,
+            FixedType::F16x16 => 4,
+        }
+    }
+
+    pub fn fraction_bits(&self) -> usize {
+        match self {
+            FixedType::F8x8 => 8,
+            FixedType::F16x16 => 16,
+        }
+    }
+
+    // The stored integer for the nearest value to `value`
+    pub fn encode(&self, value: f64) -> i64 {
+        (value * (1i64 << self.fraction_bits()) as f64).round() as i64
+    }
+
+    // The value a stored integer stands for
+    pub fn decode(&self, bits: i64) -> f64 {
+        bits as f64 / (1i64 << self.fraction_bits()) as f64
+    }
+}
+
+impl fmt::Display for FixedType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FixedType::F8x8 => write!(f, "f8x8"),
+            FixedType::F16x16 => write!(f, "f16x16"),
+        }
+    }
+}
+
+// Packed binary-coded decimal, two digits to a byte, as the 6502's
+// decimal mode adds and subtracts them
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BcdType {
+    Bcd8,
+    Bcd16,
+}
+
+impl BcdType {
+    pub fn from_name(name: &str) -> Option<BcdType> {
+        match name {
+            "bcd8" => Some(BcdType::Bcd8),
+            "bcd16" => Some(BcdType::Bcd16),
+            _ => None,
+        }
+    }
+
+    // Size of a value in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            BcdType::Bcd8 => 1,
+            BcdType::Bcd16 => 2,
+        }
+    }
+
+    pub fn max_value(&self) -> i64 {
+        match self {
+            BcdType::Bcd8 => 99,
+            BcdType::Bcd16 => 9999,
+        }
+    }
+
+    // The digits of a value, dropping any fraction and wrapping past
+    // the largest one like the decimal adds do
+    pub fn encode(&self, value: f64) -> i64 {
+        let mut remaining = (value.floor() as i64).rem_euclid(self.max_value() + 1);
+        let mut bits = 0;
+        for digit in 0..2 * self.size() {
+            bits |= (remaining % 10) << (4 * digit);
+            remaining /= 10;
+        }
+        bits
+    }
+
+    // The value stored digits stand for
+    pub fn decode(&self, bits: i64) -> i64 {
+        let mut value = 0;
+        for digit in (0..2 * self.size()).rev() {
+            value = value * 10 + ((bits >> (4 * digit)) & 0xF);
+        }
+        value
+    }
+}
+
+impl fmt::Display for BcdType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BcdType::Bcd8 => write!(f, "bcd8"),
+            BcdType::Bcd16 => write!(f, "bcd16"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int(IntType),
+    Fixed(FixedType),
+    // IEEE single precision, computed in software by the runtime
+    Float,
+    Bcd(BcdType),
+    Array { element: Box<Type>, length: usize },
+    // Only `*mut` pointers may be written through
+    Pointer { pointee: Box<Type>, mutable: bool },
+    Struct(Rc<StructType>),
+}
+
+impl Type {
+    // Size of a value in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Int(int_type) => int_type.size(),
+            Type::Fixed(fixed_type) => fixed_type.size(),
+            Type::Float => 4,
+            Type::Bcd(bcd_type) => bcd_type.size(),
+            Type::Array { element, length } => element.size() * length,
+            Type::Pointer {
+                pointee: _,
+                mutable: _,
+            } => 2,
+            Type::Struct(structure) => structure.size(),
+        }
+    }
+}
+
+impl Type {
+    // The bits a literal of this type is stored as. Integers drop any
+    // fraction, fixed-point numbers round to the nearest step, floats
+    // to the nearest single precision value and BCD keeps the digits
+    // that fit.
+    pub fn encode(&self, value: f64) -> i64 {
+        match self {
+            Type::Fixed(fixed_type) => fixed_type.encode(value),
+            Type::Float => (value as f32).to_bits() as i64,
+            Type::Bcd(bcd_type) => bcd_type.encode(value),
+            _ => value as i64,
+        }
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        matches!(
+            self,
+            Type::Pointer {
+                pointee: _,
+                mutable: _
+            }
+        )
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        matches!(self, Type::Fixed(_))
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::Float)
+    }
+
+    pub fn is_bcd(&self) -> bool {
+        matches!(self, Type::Bcd(_))
+    }
+
+    // Whether values may have a fraction, which rules them out as
+    // indices, pointer offsets and addresses
+    pub fn has_fraction(&self) -> bool {
+        self.is_fixed() || self.is_float()
+    }
+
+    pub fn is_struct(&self) -> bool {
+        matches!(self, Type::Struct(_))
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(
+            self,
+            Type::Array {
+                element: _,
+                length: _
+            }
+        )
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int(int_type) => write!(f, "{}", int_type),
+            Type::Fixed(fixed_type) => write!(f, "{}", fixed_type),
+            Type::Float => write!(f, "f32"),
+            Type::Bcd(bcd_type) => write!(f, "{}", bcd_type),
+            Type::Array { element, length } => write!(f, "[{}; {}]", element, length),
+            Type::Pointer {
+                pointee,
+                mutable: true,
+            } => write!(f, "*mut {}", pointee),
+            Type::Pointer {
+                pointee,
+                mutable: false,
+            } => write!(f, "*{}", pointee),
+            Type::Struct(structure) => write!(f, "{}", structure.name),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StructLayout {
+    // The fields of each element are stored together
+    ArrayOfStructs,
+    // Each field of an array of structs is stored as its own array,
+    // so a field is indexed by the element number alone
+    StructOfArrays,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct StructField {
+    pub name: String,
+    pub ty: Type,
+    pub location: SourceLocation,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<StructField>,
+    pub layout: StructLayout,
+    pub location: SourceLocation,
+}
+
+impl StructType {
+    pub fn size(&self) -> usize {
+        self.fields.iter().map(|field| field.ty.size()).sum()
+    }
+
+    // Offset of a field within one element, and its type
+    pub fn field(&self, name: &str) -> Option<(usize, &Type)> {
+        let mut offset = 0;
+        for field in &self.fields {
+            if field.name == name {
+                return Some((offset, &field.ty));
+            }
+            offset += field.ty.size();
+        }
+        None
+    }
+}
+
+// Parses a type annotation, either an integer type name like `u8`,
+// a fixed-point type name like `f8x8`, `f32`, a BCD type name like
+// `bcd8`, an array type like `[u8; 16]`,
+// a pointer type like `*mut u8` or the name of a struct declared earlier
+pub fn parse_type<I>(token_stream: &mut TokenStream<I>) -> Result<Type, UnexpectedTokenError>
+where
+    I: Iterator<Item = LexerToken>,
+{
+    if let Some(star_token) = token_stream.accept(TokenType::MUL_OP) {
+        if star_token.mul_op != Some(MulOp::MULTIPLY) {
+            return Err(UnexpectedTokenError::unexpected(
+                TokenType::MUL_OP,
+                star_token.location,
+            ));
+        }
+        let mutable = token_stream.accept(TokenType::MUT).is_some();
+        let result = Type::Pointer {
+            pointee: Box::new(parse_type(token_stream)?),
+            mutable,
+        };
+        return Ok(result);
+    }
+
+    if token_stream.accept(TokenType::L_BRACKET).is_some() {
+        let element = parse_type(token_stream)?;
+        let _ = token_stream.expect(TokenType::SEMICOLON)?;
+        let length_token = token_stream.expect(TokenType::NUMBER)?;
+        let _ = token_stream.expect(TokenType::R_BRACKET)?;
+
+        let result = Type::Array {
+            element: Box::new(element),
+            length: length_token.number.unwrap() as usize,
+        };
+        return Ok(result);
+    }
+
+    let name_token = token_stream.expect(TokenType::IDENTIFIER)?;
+    let name = name_token.label.unwrap();
+    if let Some(int_type) = IntType::from_name(&name) {
+        return Ok(Type::Int(int_type));
+    }
+    if let Some(fixed_type) = FixedType::from_name(&name) {
+        return Ok(Type::Fixed(fixed_type));
+    }
+    if name == "f32" {
+        return Ok(Type::Float);
+    }
+    if let Some(bcd_type) = BcdType::from_name(&name) {
+        return Ok(Type::Bcd(bcd_type));
+    }
+    match token_stream.structs.get(&name) {
+        Some(structure) => Ok(Type::Struct(structure.clone())),
+        None => Err(UnexpectedTokenError::unknown_type(
+            name,
+            name_token.location,
+        )),
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::Initializer;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::types::StructLayout;
+use crate::parser::types::Type;
+
+use crate::semantic::constant;
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::typeck::TypeTable;
+use crate::semantic::walk_statement;
+use crate::semantic::SemanticError;
+
+// Tables are indexed with abs,Y addressing, so every byte offset
+// into them must fit in the Y register
+const MAX_INDEXED_SIZE: usize = 256;
+
+// Whether the initializer has the shape of the global. Its values are
+// computed later, see `interpret`.
+fn check_initializer(global: &GlobalDecl, errors: &mut Vec<SemanticError>) {
+    match (&global.ty, &global.initializer) {
+        (Type::Array { element: _, length }, Some(Initializer::List(elements)))
+            if elements.len() != *length =>
+        {
+            let message = format!(
+                "`{}` is declared with {} elements but initialized with {}",
+                global.name,
+                length,
+                elements.len()
+            );
+            errors.push(SemanticError::new(message, global.location));
+        }
+        (
+            Type::Array {
+                element: _,
+                length: _,
+            },
+            Some(Initializer::List(_)),
+        ) => {}
+        (
+            Type::Array {
+                element: _,
+                length: _,
+            },
+            Some(Initializer::Scalar(_)),
+        ) => {
+            let message = format!("Array `{}` must be initialized with a list", global.name);
+            errors.push(SemanticError::new(message, global.location));
+        }
+        (_, Some(Initializer::List(_))) => {
+            let message = format!(
+                "`{}` is not an array but is initialized with a list",
+                global.name
+            );
+            errors.push(SemanticError::new(message, global.location));
+        }
+        _ => {}
+    }
+}
+
+fn check_index(
+    expr: &AstExprNode,
+    arrays: &HashMap<String, usize>,
+    types: &TypeTable,
+    overflow: Overflow,
+    errors: &mut Vec<SemanticError>,
+) {
+    if let AstExprNode::Terminal(Factor::Index {
+        id,
+        index,
+        location,
+    }) = expr
+    {
+        // Anything else indexed must be a pointer, which the type checker verifies
+        if let Some(length) = arrays.get(id) {
+            if let Some(value) = constant::evaluate_typed(index, types, overflow) {
+                if value.fract() != 0.0 {
+                    let message = format!("Index {} into `{}` is not a whole number", value, id);
+                    errors.push(SemanticError::new(message, *location));
+                } else if value < 0.0 || value >= *length as f64 {
+                    let message = format!(
+                        "Index {} is out of bounds for `{}` of length {}",
+                        value, id, length
+                    );
+                    errors.push(SemanticError::new(message, *location));
+                }
+            }
+        }
+    }
+}
+
+// Checks global array declarations and every indexing expression
+// with a constant index against the length of its array. The index is
+// computed at its type with `overflow`, like the generated code does.
+pub fn check_arrays(
+    program: &Program,
+    types: &TypeTable,
+    overflow: Overflow,
+    errors: &mut Vec<SemanticError>,
+) {
+    let mut arrays: HashMap<String, usize> = HashMap::new();
+
+    for primary in program.primaries() {
+        if let PrimaryStatement::Global(global) = primary {
+            check_initializer(global, errors);
+            if let Type::Array { element, length } = &global.ty {
+                if let Type::Array {
+                    element: _,
+                    length: _,
+                } = element.as_ref()
+                {
+                    let message = format!(
+                        "`{}` is an array of arrays, which is not supported",
+                        global.name
+                    );
+                    errors.push(SemanticError::new(message, global.location));
+                }
+                match element.as_ref() {
+                    // Every field is its own table
+                    Type::Struct(structure) if structure.layout == StructLayout::StructOfArrays => {
+                        for field in &structure.fields {
+                            if field.ty.size() * length > MAX_INDEXED_SIZE {
+                                let message = format!("Field `{}` of `{}` takes {} bytes, more than the {} that can be indexed", field.name, global.name, field.ty.size() * length, MAX_INDEXED_SIZE);
+                                errors.push(SemanticError::new(message, global.location));
+                            }
+                        }
+                    }
+                    _ => {
+                        if global.ty.size() > MAX_INDEXED_SIZE {
+                            let message = format!(
+                                "`{}` takes {} bytes, more than the {} that can be indexed",
+                                global.name,
+                                global.ty.size(),
+                                MAX_INDEXED_SIZE
+                            );
+                            errors.push(SemanticError::new(message, global.location));
+                        }
+                    }
+                }
+                arrays.insert(global.name.clone(), *length);
+            }
+        }
+    }
+
+    for primary in program.primaries() {
+        if let PrimaryStatement::Definition {
+            decl: _,
+            inner_statement,
+        } = primary
+        {
+            walk_statement(inner_statement, &mut |expr| {
+                check_index(expr, &arrays, types, overflow, errors)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+    use crate::semantic::typeck::check_types;
+
+    fn errors(source: &str) -> Vec<String> {
+        let program = parse_source(source);
+        let mut errors = Vec::new();
+        let types = check_types(&program, &mut errors);
+        check_arrays(&program, &types, Overflow::Wrapping, &mut errors);
+        errors.into_iter().map(|error| error.message).collect()
+    }
+
+    const TABLE: &str = "const table: [u8; 4] = [1, 2, 3, 4]\n";
+
+    #[test]
+    fn index_within_bounds_is_accepted() {
+        let source = format!("{}def f(): u8 {{ return table[0] + table[3] }}", TABLE);
+        assert!(errors(&source).is_empty());
+    }
+
+    #[test]
+    fn index_past_the_end_is_out_of_bounds() {
+        let source = format!("{}def f(): u8 {{ return table[4] }}", TABLE);
+        assert_eq!(
+            errors(&source),
+            vec!["Index 4 is out of bounds for `table` of length 4"]
+        );
+    }
+
+    #[test]
+    fn negative_index_is_out_of_bounds() {
+        let source = format!("{}def f(): u8 {{ return table[(0 as i8) - 1] }}", TABLE);
+        assert_eq!(
+            errors(&source),
+            vec!["Index -1 is out of bounds for `table` of length 4"]
+        );
+    }
+
+    #[test]
+    fn index_is_computed_with_integer_division() {
+        let source = format!("{}def f(): u8 {{ return table[3 / 2] }}", TABLE);
+        assert!(errors(&source).is_empty());
+        let source = format!("{}def f(): u8 {{ return table[9 / 2] }}", TABLE);
+        assert_eq!(
+            errors(&source),
+            vec!["Index 4 is out of bounds for `table` of length 4"]
+        );
+    }
+
+    #[test]
+    fn index_divided_by_zero_is_not_checked() {
+        let source = format!("{}def f(): u8 {{ return table[1 / 0] }}", TABLE);
+        assert!(errors(&source).is_empty());
+    }
+
+    #[test]
+    fn index_is_brought_into_range_of_its_type() {
+        let source = format!("{}def f(): u8 {{ return table[(255 as u8) + 2] }}", TABLE);
+        assert!(errors(&source).is_empty());
+    }
+
+    #[test]
+    fn index_cast_from_a_float_is_truncated_toward_zero() {
+        let source = format!(
+            "{}def f(): u8 {{ return table[((0.5 as f32) - 1) as i8] }}",
+            TABLE
+        );
+        assert!(errors(&source).is_empty());
+        let source = format!("{}def f(): u8 {{ return table[(0.5 - 1) as i8] }}", TABLE);
+        assert_eq!(
+            errors(&source),
+            vec!["Index -1 is out of bounds for `table` of length 4"]
+        );
+    }
+
+    #[test]
+    fn list_length_must_match_the_declaration() {
+        assert_eq!(
+            errors("const table: [u8; 3] = [1, 2]"),
+            vec!["`table` is declared with 3 elements but initialized with 2"]
+        );
+    }
+}
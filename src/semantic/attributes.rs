@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+
+use crate::semantic::SemanticError;
+
+// Every attribute a function may carry, with its number of arguments,
+// or None for a list of one or more
+const KNOWN_ATTRIBUTES: [(&str, Option<usize>); 3] =
+    [("interrupt", Some(1)), ("allow", None), ("pure", Some(0))];
+
+fn check_decl(decl: &FuncDecl, errors: &mut Vec<SemanticError>) {
+    let mut seen: HashSet<&str> = HashSet::new();
+    for attribute in &decl.attributes {
+        if !seen.insert(&attribute.name) {
+            let message = format!(
+                "Attribute `{}` is given more than once on `{}`",
+                attribute.name, decl.name
+            );
+            errors.push(SemanticError::new(message, attribute.location));
+        }
+
+        match KNOWN_ATTRIBUTES
+            .iter()
+            .find(|(name, _)| *name == attribute.name)
+        {
+            Some((_, Some(arity))) if *arity != attribute.args.len() => {
+                let message = format!(
+                    "Attribute `{}` takes {} arguments but {} were given",
+                    attribute.name,
+                    arity,
+                    attribute.args.len()
+                );
+                errors.push(SemanticError::new(message, attribute.location));
+            }
+            Some((_, None)) if attribute.args.is_empty() => {
+                let message = format!("Attribute `{}` takes a list of arguments", attribute.name);
+                errors.push(SemanticError::new(message, attribute.location));
+            }
+            Some(_) => {}
+            None => {
+                let message = format!("Unknown attribute `{}` on `{}`", attribute.name, decl.name);
+                errors.push(SemanticError::new(message, attribute.location));
+            }
+        }
+    }
+}
+
+// Checks that function attributes are known and have the right shape.
+// What each one means is checked by the pass that uses it.
+pub fn check_attributes(program: &Program, errors: &mut Vec<SemanticError>) {
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            } => {
+                check_decl(decl, errors);
+            }
+            PrimaryStatement::Extern(decl) => {
+                check_decl(decl, errors);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+
+    fn errors(source: &str) -> Vec<String> {
+        let program = parse_source(source);
+        let mut errors = Vec::new();
+        check_attributes(&program, &mut errors);
+        errors.into_iter().map(|error| error.message).collect()
+    }
+
+    #[test]
+    fn known_attributes_are_accepted() {
+        assert!(errors(
+            "def f pure allow(shadowing, unusedParameter)(x: u8): u8 { return 1 }\n\
+             def h interrupt(nmi)() { return 0 }\n\
+             extern g pure(x: u8): u8"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn unknown_attributes_are_reported() {
+        assert_eq!(
+            errors("def f inline(x: u8): u8 { return x }"),
+            vec!["Unknown attribute `inline` on `f`"]
+        );
+    }
+
+    #[test]
+    fn attributes_take_their_number_of_arguments() {
+        assert_eq!(
+            errors("def f interrupt(nmi, irq)() { return 0 }"),
+            vec!["Attribute `interrupt` takes 1 arguments but 2 were given"]
+        );
+        assert_eq!(
+            errors("def f pure(x)(x: u8): u8 { return x }"),
+            vec!["Attribute `pure` takes 0 arguments but 1 were given"]
+        );
+        assert_eq!(
+            errors("def f allow()(x: u8): u8 { return 1 }"),
+            vec!["Attribute `allow` takes a list of arguments"]
+        );
+    }
+
+    #[test]
+    fn attributes_are_given_once() {
+        assert_eq!(
+            errors("def f pure pure(x: u8): u8 { return x }"),
+            vec!["Attribute `pure` is given more than once on `f`"]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::graphviz::Graphviz;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::parser::Statement;
+
+use crate::semantic::walk_statement;
+
+// How a function reaches itself through calls. A recursive function
+// needs a new frame for each call that is still running, where others
+// could have theirs allocated once.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Recursion {
+    None,
+    // Calls itself directly. A tail call's value is returned as it is.
+    Direct { tail: bool },
+    // Reaches itself through other functions
+    Mutual,
+}
+
+impl fmt::Display for Recursion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Recursion::None => write!(f, "not recursive"),
+            Recursion::Direct { tail: true } => write!(f, "self-recursive through tail calls"),
+            Recursion::Direct { tail: false } => write!(f, "self-recursive"),
+            Recursion::Mutual => write!(f, "mutually recursive"),
+        }
+    }
+}
+
+// A call written in the program
+struct Call {
+    callee: String,
+    is_tail: bool,
+}
+
+// Which functions each definition calls. Externs are leaves, and
+// intrinsics, which are lowered inline, are left out.
+pub struct CallGraph {
+    // Every definition and extern, in program order
+    functions: Vec<(String, bool)>,
+    calls: HashMap<String, Vec<Call>>,
+    recursion: HashMap<String, Recursion>,
+}
+
+// Calls whose value is returned as it is, with nothing left to do after
+fn tail_calls(statement: &Statement, result: &mut Vec<*const AstExprNode>) {
+    match statement {
+        Statement::Select {
+            condition: _,
+            statement,
+            else_clause,
+        } => {
+            tail_calls(statement, result);
+            if let Some(clause) = else_clause {
+                tail_calls(clause, result);
+            }
+        }
+        Statement::ReturnExpr(expr) => {
+            if let AstExprNode::Terminal(Factor::Id {
+                id: _,
+                optional_call: Some(_),
+                location: _,
+            }) = expr.as_ref()
+            {
+                result.push(expr.as_ref());
+            }
+        }
+        Statement::Block(statements) => {
+            for statement in statements {
+                tail_calls(statement, result);
+            }
+        }
+        Statement::Match(matching) => {
+            for statement in matching.statements() {
+                tail_calls(statement, result);
+            }
+        }
+        _ => {}
+    }
+}
+
+// The calls in a statement to any of `functions`
+fn collect_calls(statement: &Statement, functions: &HashSet<&str>, calls: &mut Vec<Call>) {
+    let mut tail: Vec<*const AstExprNode> = Vec::new();
+    tail_calls(statement, &mut tail);
+    walk_statement(statement, &mut |expr| {
+        if let AstExprNode::Terminal(Factor::Id {
+            id,
+            optional_call: Some(_),
+            location: _,
+        }) = expr
+        {
+            if functions.contains(id.as_str()) {
+                calls.push(Call {
+                    callee: id.clone(),
+                    is_tail: tail.contains(&(expr as *const AstExprNode)),
+                });
+            }
+        }
+    });
+}
+
+impl CallGraph {
+    pub fn build(program: &Program) -> CallGraph {
+        let mut functions: Vec<(String, bool)> = Vec::new();
+        for primary in program.primaries() {
+            match primary {
+                PrimaryStatement::Definition {
+                    decl,
+                    inner_statement: _,
+                } => {
+                    functions.retain(|(name, _)| *name != decl.name);
+                    functions.push((decl.name.clone(), false));
+                }
+                PrimaryStatement::Extern(decl)
+                    if !functions.iter().any(|(name, _)| *name == decl.name) =>
+                {
+                    functions.push((decl.name.clone(), true));
+                }
+                _ => {}
+            }
+        }
+
+        let names: HashSet<&str> = functions.iter().map(|(name, _)| name.as_str()).collect();
+        let mut calls: HashMap<String, Vec<Call>> = HashMap::new();
+        for primary in program.primaries() {
+            if let PrimaryStatement::Definition {
+                decl,
+                inner_statement,
+            } = primary
+            {
+                let mut found: Vec<Call> = Vec::new();
+                collect_calls(inner_statement, &names, &mut found);
+                calls.insert(decl.name.clone(), found);
+            }
+        }
+
+        let mut graph = CallGraph {
+            functions,
+            calls,
+            recursion: HashMap::new(),
+        };
+        for (name, _) in &graph.functions {
+            let recursion = graph.classify(name);
+            graph.recursion.insert(name.clone(), recursion);
+        }
+        graph
+    }
+
+    // The functions `name` calls directly, each once, in the order they
+    // are first called
+    pub fn callees(&self, name: &str) -> Vec<&str> {
+        let mut result: Vec<&str> = Vec::new();
+        for call in self.calls.get(name).into_iter().flatten() {
+            if !result.contains(&call.callee.as_str()) {
+                result.push(&call.callee);
+            }
+        }
+        result
+    }
+
+    pub fn recursion(&self, name: &str) -> Recursion {
+        self.recursion.get(name).cloned().unwrap_or(Recursion::None)
+    }
+
+    // Every function reachable from `name` through one or more calls
+    fn reachable(&self, name: &str) -> HashSet<&str> {
+        let mut result: HashSet<&str> = HashSet::new();
+        let mut stack: Vec<&str> = self.callees(name);
+        while let Some(next) = stack.pop() {
+            if result.insert(next) {
+                stack.extend(self.callees(next));
+            }
+        }
+        result
+    }
+
+    fn classify(&self, name: &str) -> Recursion {
+        let through_others = self
+            .callees(name)
+            .iter()
+            .filter(|callee| **callee != name)
+            .any(|callee| self.reachable(callee).contains(name));
+        if through_others {
+            return Recursion::Mutual;
+        }
+        let own: Vec<&Call> = self
+            .calls
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|call| call.callee == name)
+            .collect();
+        if own.is_empty() {
+            return Recursion::None;
+        }
+        Recursion::Direct {
+            tail: own.iter().all(|call| call.is_tail),
+        }
+    }
+
+    // One node per function, labelled with how it recurses, and one
+    // edge per function it calls
+    pub fn to_graphviz(&self) -> Graphviz {
+        let mut graph = Graphviz::new();
+        for (name, is_extern) in &self.functions {
+            let label = if *is_extern {
+                format!("{}\\nextern", name)
+            } else {
+                format!("{}\\n{}", name, self.recursion(name))
+            };
+            graph.node(format!("_{}", name), label);
+            for callee in self.callees(name) {
+                graph.connect(format!("_{}", name), format!("_{}", callee));
+            }
+        }
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+
+    fn graph(source: &str) -> CallGraph {
+        CallGraph::build(&parse_source(source))
+    }
+
+    #[test]
+    fn classifies_recursion() {
+        let graph = graph(
+            "extern putc(c: u8): u8\n\
+             def leaf(x: u8): u8 { return putc(x) }\n\
+             def count(n: u8): u8 { if (n == 0) { return 0 } return count(n - 1) }\n\
+             def sum(n: u8): u8 { if (n == 0) { return 0 } return n + sum(n - 1) }\n\
+             def mixed(n: u8): u8 { if (n == 0) { return mixed(1) + 1 } return mixed(n - 1) }\n\
+             def even(n: u8): u8 { if (n == 0) { return 1 } return odd(n - 1) }\n\
+             def odd(n: u8): u8 { if (n == 0) { return 0 } return even(n - 1) }",
+        );
+        assert_eq!(graph.recursion("putc"), Recursion::None);
+        assert_eq!(graph.recursion("leaf"), Recursion::None);
+        assert_eq!(graph.recursion("count"), Recursion::Direct { tail: true });
+        assert_eq!(graph.recursion("sum"), Recursion::Direct { tail: false });
+        assert_eq!(graph.recursion("mixed"), Recursion::Direct { tail: false });
+        assert_eq!(graph.recursion("even"), Recursion::Mutual);
+        assert_eq!(graph.recursion("odd"), Recursion::Mutual);
+    }
+
+    #[test]
+    fn mutual_recursion_wins_over_direct() {
+        let graph = graph(
+            "def ping(n: u8): u8 { if (n == 0) { return pong(n) } return ping(n - 1) }\n\
+             def pong(n: u8): u8 { return ping(n) }\n\
+             def caller(n: u8): u8 { return ping(n) }",
+        );
+        assert_eq!(graph.recursion("ping"), Recursion::Mutual);
+        assert_eq!(graph.recursion("pong"), Recursion::Mutual);
+        // Reaching a cycle does not make a function part of it
+        assert_eq!(graph.recursion("caller"), Recursion::None);
+    }
+
+    #[test]
+    fn lists_callees_once_in_order() {
+        let graph = graph(
+            "extern putc(c: u8): u8\n\
+             def shout(x: u8): u8 { putc(x)\nputc(x)\nreturn quiet(x) }\n\
+             def quiet(x: u8): u8 { return x }",
+        );
+        assert_eq!(graph.callees("shout"), vec!["putc", "quiet"]);
+        assert!(graph.callees("quiet").is_empty());
+        assert!(graph.callees("putc").is_empty());
+    }
+}
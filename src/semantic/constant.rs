@@ -0,0 +1,130 @@
+use crate::lexer::MulOp;
+use crate::lexer::RelOp;
+use crate::lexer::SumOp;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::BinOp;
+use crate::parser::bin_op::Factor;
+use crate::parser::types::Type;
+
+use crate::semantic::interpret::fit;
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::typeck::TypeTable;
+
+// Evaluates an expression built only from numeric literals.
+// Returns None if any part of it depends on a name.
+pub fn evaluate(expr: &AstExprNode) -> Option<f64> {
+    evaluate_with(expr, &|_| None)
+}
+
+// Evaluates an expression built from numeric literals and names that
+// `named` knows the value of. Returns None if any other part of it
+// depends on a name.
+pub fn evaluate_with<F>(expr: &AstExprNode, named: &F) -> Option<f64>
+where
+    F: Fn(&AstExprNode) -> Option<f64>,
+{
+    let evaluate = |expr: &AstExprNode| evaluate_with(expr, named);
+    match expr {
+        AstExprNode::Terminal(Factor::Numeric { value, location: _ }) => Some(*value),
+        AstExprNode::Terminal(_) => named(expr),
+        AstExprNode::SubNode(sub_node) => evaluate(sub_node),
+        AstExprNode::Node {
+            left,
+            op_type,
+            next,
+            location: _,
+        } => {
+            let left = evaluate(left)?;
+            let right = evaluate(next)?;
+            Some(apply(op_type, left, right))
+        }
+        AstExprNode::Cast {
+            value,
+            ty,
+            location: _,
+        } => cast(evaluate(value)?, ty),
+        AstExprNode::AddressOf {
+            target: _,
+            location: _,
+        }
+        | AstExprNode::Deref {
+            pointer: _,
+            location: _,
+        } => None,
+        AstExprNode::Field {
+            base: _,
+            field: _,
+            location: _,
+        } => None,
+    }
+}
+
+// Evaluates an expression built only from numeric literals the way
+// the generated code would, bringing every result into range of its
+// type with `overflow`. Returns None if any part of it depends on a
+// name, divides an integer by zero, or overflows when that is checked.
+pub fn evaluate_typed(expr: &AstExprNode, types: &TypeTable, overflow: Overflow) -> Option<f64> {
+    let evaluate = |expr: &AstExprNode| evaluate_typed(expr, types, overflow);
+    match expr {
+        AstExprNode::Terminal(Factor::Numeric { value, location: _ }) => Some(*value),
+        AstExprNode::SubNode(sub_node) => evaluate(sub_node),
+        AstExprNode::Node {
+            left,
+            op_type,
+            next,
+            location: _,
+        } => {
+            let left = evaluate(left)?;
+            let right = evaluate(next)?;
+            let ty = types.get(expr)?;
+            if let (BinOp::Mult(MulOp::DIVIDE), true) = (op_type, right == 0.0 && !ty.is_float()) {
+                return None;
+            }
+            let value = apply(op_type, left, right);
+            match op_type {
+                BinOp::Rel(_) => Some(value),
+                _ => fit(value, ty, overflow),
+            }
+        }
+        AstExprNode::Cast {
+            value: inner,
+            ty,
+            location: _,
+        } => {
+            let value = evaluate(inner)?;
+            match types.get(inner)? {
+                // Truncated toward zero by the runtime, keeping low bits
+                Type::Float => fit(value, ty, Overflow::Wrapping),
+                _ => cast(value, ty),
+            }
+        }
+        _ => None,
+    }
+}
+
+// Casting a fixed-point number to an integer rounds down
+fn cast(value: f64, ty: &Type) -> Option<f64> {
+    match ty {
+        Type::Int(int_type) => Some(int_type.wrap(value.floor() as i64) as f64),
+        Type::Fixed(fixed_type) => Some(fixed_type.decode(fixed_type.encode(value))),
+        Type::Float => Some(value as f32 as f64),
+        Type::Bcd(bcd_type) => Some(bcd_type.decode(bcd_type.encode(value)) as f64),
+        _ => None,
+    }
+}
+
+pub fn apply(op_type: &BinOp, left: f64, right: f64) -> f64 {
+    let truth = |value: bool| if value { 1.0 } else { 0.0 };
+    match op_type {
+        BinOp::Sum(SumOp::ADD) => left + right,
+        BinOp::Sum(SumOp::SUBTRACT) => left - right,
+        BinOp::Mult(MulOp::MULTIPLY) => left * right,
+        BinOp::Mult(MulOp::DIVIDE) => left / right,
+        BinOp::Rel(RelOp::LESS_THAN) => truth(left < right),
+        BinOp::Rel(RelOp::LESS_THAN_EQ) => truth(left <= right),
+        BinOp::Rel(RelOp::EQUAL) => truth(left == right),
+        BinOp::Rel(RelOp::GREATER_THAN) => truth(left > right),
+        BinOp::Rel(RelOp::GREATER_THAN_EQ) => truth(left >= right),
+    }
+}
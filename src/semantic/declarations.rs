@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use crate::lexer::SourceLocation;
+
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+
+use crate::semantic::typeck::Signature;
+use crate::semantic::SemanticError;
+
+// Functions, externs and globals share one namespace
+enum Declaration<'a> {
+    Definition(&'a FuncDecl),
+    Extern(&'a FuncDecl),
+    Global(SourceLocation),
+}
+
+impl<'a> Declaration<'a> {
+    fn description(&self) -> &'static str {
+        match self {
+            Declaration::Definition(_) => "defined as a function",
+            Declaration::Extern(_) => "declared as an extern",
+            Declaration::Global(_) => "declared as a global",
+        }
+    }
+
+    fn location(&self) -> SourceLocation {
+        match self {
+            Declaration::Definition(decl) | Declaration::Extern(decl) => decl.location,
+            Declaration::Global(location) => *location,
+        }
+    }
+}
+
+// Whether `name` is defined in the program, not only declared
+pub fn is_defined(program: &Program, name: &str) -> bool {
+    program.primaries().iter().any(|primary| match primary {
+        PrimaryStatement::Definition {
+            decl,
+            inner_statement: _,
+        } => decl.name == name,
+        _ => false,
+    })
+}
+
+fn check_parameters(decl: &FuncDecl, errors: &mut Vec<SemanticError>) {
+    for (index, arg) in decl.args.iter().enumerate() {
+        if let Some(first) = decl.args[..index]
+            .iter()
+            .find(|other| other.name == arg.name)
+        {
+            let message = format!(
+                "Parameter `{}` of `{}` is already declared at {}",
+                arg.name, decl.name, first.location
+            );
+            errors.push(SemanticError::new(message, arg.location));
+        }
+    }
+}
+
+// An extern may declare a function that is defined further on. Foreign
+// routines live elsewhere, and a definition must match its declaration.
+fn check_forward(declared: &FuncDecl, decl: &FuncDecl, errors: &mut Vec<SemanticError>) {
+    if declared.is_foreign() {
+        let message = format!(
+            "`{}` is declared as a foreign routine at {} and cannot also be defined",
+            decl.name, declared.location
+        );
+        errors.push(SemanticError::new(message, decl.location));
+    } else if Signature::from_decl(declared) != Signature::from_decl(decl) {
+        let message = format!(
+            "`{}` does not match its extern declaration at {}",
+            decl.name, declared.location
+        );
+        errors.push(SemanticError::new(message, decl.location));
+    }
+}
+
+// Reports names declared twice at the top level and parameters that
+// share a name. The only name that may be given twice is that of an
+// extern followed by a matching definition.
+pub fn check_declarations(program: &Program, errors: &mut Vec<SemanticError>) {
+    let mut declared: HashMap<&str, Declaration> = HashMap::new();
+
+    for primary in program.primaries() {
+        let (name, declaration) = match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            } => (&decl.name, Declaration::Definition(decl)),
+            PrimaryStatement::Extern(decl) => (&decl.name, Declaration::Extern(decl)),
+            PrimaryStatement::Global(global) => {
+                (&global.name, Declaration::Global(global.location))
+            }
+            PrimaryStatement::Struct(_) => continue,
+        };
+        if let Declaration::Definition(decl) | Declaration::Extern(decl) = &declaration {
+            check_parameters(decl, errors);
+        }
+
+        match (declared.get(name.as_str()), &declaration) {
+            (None, _) => {}
+            (Some(Declaration::Extern(first)), Declaration::Definition(decl)) => {
+                check_forward(first, decl, errors);
+            }
+            (Some(first), _) => {
+                let message = format!(
+                    "`{}` is already {} at {}",
+                    name,
+                    first.description(),
+                    first.location()
+                );
+                errors.push(SemanticError::new(message, declaration.location()));
+                continue;
+            }
+        }
+        declared.insert(name, declaration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+
+    fn messages(source: &str) -> Vec<String> {
+        let mut errors = Vec::new();
+        check_declarations(&parse_source(source), &mut errors);
+        errors.into_iter().map(|error| error.message).collect()
+    }
+
+    #[test]
+    fn extern_may_come_before_its_definition() {
+        let source = "extern twice(x: u8): u8\n\
+                      def caller(): u8 { return twice(2) }\n\
+                      def twice(x: u8): u8 { return x + x }";
+        assert!(messages(source).is_empty());
+        assert!(is_defined(&parse_source(source), "twice"));
+        assert!(!is_defined(
+            &parse_source("extern twice(x: u8): u8"),
+            "twice"
+        ));
+    }
+
+    #[test]
+    fn definition_must_match_its_extern() {
+        assert_eq!(
+            messages("extern twice(x: u8): u8\ndef twice(x: u16): u8 { return 0 }"),
+            vec!["`twice` does not match its extern declaration at 1:8"]
+        );
+        assert_eq!(
+            messages("extern twice(x: u8): u8\ndef twice(x: u8): u16 { return 0 }"),
+            vec!["`twice` does not match its extern declaration at 1:8"]
+        );
+    }
+
+    #[test]
+    fn foreign_routines_cannot_be_defined() {
+        assert_eq!(
+            messages("extern chrout(c: u8): u8 @ $FFD2\ndef chrout(c: u8): u8 { return c }"),
+            vec!["`chrout` is declared as a foreign routine at 1:8 and cannot also be defined"]
+        );
+    }
+
+    #[test]
+    fn reports_names_declared_twice() {
+        assert_eq!(
+            messages("def f(): u8 { return 0 }\ndef f(): u8 { return 1 }"),
+            vec!["`f` is already defined as a function at 1:5"]
+        );
+        assert_eq!(
+            messages("def f(): u8 { return 0 }\nextern f(): u8"),
+            vec!["`f` is already defined as a function at 1:5"]
+        );
+        assert_eq!(
+            messages("extern f(): u8\nextern f(): u8"),
+            vec!["`f` is already declared as an extern at 1:8"]
+        );
+        assert_eq!(
+            messages("var f: u8\ndef f(): u8 { return 0 }"),
+            vec!["`f` is already declared as a global at 1:5"]
+        );
+    }
+
+    #[test]
+    fn reports_repeated_parameters() {
+        assert_eq!(
+            messages("def f(x: u8, y: u8, x: u8): u8 { return x }"),
+            vec!["Parameter `x` of `f` is already declared at 1:7"]
+        );
+    }
+}
@@ -0,0 +1,630 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::lexer::MulOp;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::BinOp;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::parser::Statement;
+
+use crate::semantic::intrinsics::Intrinsic;
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::names::ResolutionTable;
+use crate::semantic::typeck::TypeTable;
+use crate::semantic::SemanticError;
+
+// What calling a function can do besides returning a value, from
+// least to most. Calls to a pure function with the same arguments can
+// be merged or folded, and a call whose value is unused can be dropped
+// unless it is effectful.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Effect {
+    // Its value depends only on its arguments
+    Pure,
+    // Reads globals, but changes nothing
+    ReadOnly,
+    // Writes memory, reads volatile globals or memory behind a pointer,
+    // which may be a hardware register, runs `asm`, calls an extern or
+    // may call the overflow trap
+    Effectful,
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Effect::Pure => write!(f, "pure"),
+            Effect::ReadOnly => write!(f, "read-only"),
+            Effect::Effectful => write!(f, "effectful"),
+        }
+    }
+}
+
+// The strongest effect found so far, with what caused it first
+struct Found {
+    effect: Effect,
+    reason: Option<String>,
+}
+
+impl Found {
+    fn add(&mut self, effect: Effect, reason: String) {
+        if effect > self.effect {
+            self.effect = effect;
+            self.reason = Some(reason);
+        }
+    }
+}
+
+// The effect of every definition and extern. Externs are effectful
+// unless marked `pure`, and definitions are what their bodies do,
+// including the functions they call.
+pub struct Effects {
+    functions: HashMap<String, (Effect, Option<String>)>,
+}
+
+impl Effects {
+    // Intrinsics touch memory as volatile, or apply an operator that
+    // only has an effect when it can trap
+    pub fn of(&self, name: &str) -> Effect {
+        match Intrinsic::from_name(name) {
+            Some(Intrinsic::Peek) | Some(Intrinsic::Poke) => Effect::Effectful,
+            Some(Intrinsic::Arithmetic(_, Overflow::Checked)) => Effect::Effectful,
+            Some(Intrinsic::Arithmetic(_, _)) => Effect::Pure,
+            None => self
+                .functions
+                .get(name)
+                .map_or(Effect::Effectful, |(effect, _)| *effect),
+        }
+    }
+
+    // What makes a function more than pure, if anything
+    pub fn reason(&self, name: &str) -> Option<&str> {
+        self.functions
+            .get(name)
+            .and_then(|(_, reason)| reason.as_ref().map(|reason| reason.as_str()))
+    }
+}
+
+// Where evaluating a single expression reads memory from, not
+// counting its operands
+pub enum Source<'a> {
+    // Nowhere, or only parameters and locals
+    Nothing,
+    Global(&'a GlobalDecl),
+    // Memory behind a pointer, which may be a hardware register
+    Pointer,
+}
+
+// Names that were not resolved, such as those in an expression moved
+// since, could refer to anything and are taken to read through a
+// pointer. A field is part of a global only when its base is a global
+// struct or an element of a global array.
+pub fn source<'a>(
+    expr: &AstExprNode,
+    names: &ResolutionTable,
+    globals: &HashMap<&str, &'a GlobalDecl>,
+) -> Source<'a> {
+    let global = |expr: &AstExprNode| {
+        names
+            .global(expr)
+            .and_then(|name| globals.get(name).cloned())
+    };
+    match expr {
+        AstExprNode::Terminal(Factor::Id {
+            id: _,
+            optional_call: None,
+            location: _,
+        }) => match (names.get(expr), global(expr)) {
+            (None, _) => Source::Pointer,
+            // Arrays evaluate to their address, which never changes
+            (Some(_), Some(global)) if !global.ty.is_array() => Source::Global(global),
+            (Some(_), _) => Source::Nothing,
+        },
+        AstExprNode::Terminal(Factor::Index {
+            id: _,
+            index: _,
+            location: _,
+        }) => match global(expr) {
+            Some(global) if global.ty.is_array() => Source::Global(global),
+            _ => Source::Pointer,
+        },
+        AstExprNode::Deref {
+            pointer: _,
+            location: _,
+        } => Source::Pointer,
+        AstExprNode::Field {
+            base,
+            field: _,
+            location: _,
+        } => match (base.as_ref(), global(base)) {
+            (
+                AstExprNode::Terminal(Factor::Id {
+                    id: _,
+                    optional_call: None,
+                    location: _,
+                }),
+                Some(global),
+            ) if global.ty.is_struct() => Source::Global(global),
+            (
+                AstExprNode::Terminal(Factor::Index {
+                    id: _,
+                    index: _,
+                    location: _,
+                }),
+                Some(global),
+            ) if global.ty.is_array() => Source::Global(global),
+            _ => Source::Pointer,
+        },
+        _ => Source::Nothing,
+    }
+}
+
+// Walks one function body with the effects known so far
+struct Analyzer<'a> {
+    globals: &'a HashMap<&'a str, &'a GlobalDecl>,
+    effects: &'a Effects,
+    names: &'a ResolutionTable,
+    types: &'a TypeTable,
+    overflow: Overflow,
+    found: Found,
+}
+
+impl<'a> Analyzer<'a> {
+    // Records what reading `expr` itself does
+    fn read(&mut self, expr: &AstExprNode) {
+        match source(expr, self.names, self.globals) {
+            Source::Nothing => {}
+            Source::Global(global) => self.read_global(global),
+            Source::Pointer => {
+                let reason = match expr {
+                    AstExprNode::Terminal(Factor::Index {
+                        id,
+                        index: _,
+                        location: _,
+                    }) => format!("reads memory through `{}`", id),
+                    _ => String::from("reads memory through a pointer"),
+                };
+                self.found.add(Effect::Effectful, reason);
+            }
+        }
+    }
+
+    // The global a name or indexing expression refers to
+    fn global(&self, expr: &AstExprNode) -> Option<&'a GlobalDecl> {
+        self.names
+            .global(expr)
+            .and_then(|name| self.globals.get(name).cloned())
+    }
+
+    fn read_global(&mut self, global: &GlobalDecl) {
+        if global.is_volatile {
+            self.found.add(
+                Effect::Effectful,
+                format!("reads volatile `{}`", global.name),
+            );
+        } else if !global.is_const {
+            self.found
+                .add(Effect::ReadOnly, format!("reads global `{}`", global.name));
+        }
+    }
+
+    // Where a place is, without reading it
+    fn place(&mut self, expr: &AstExprNode) {
+        match expr {
+            AstExprNode::Terminal(Factor::Index {
+                id: _,
+                index,
+                location: _,
+            }) => {
+                self.expression(index);
+            }
+            AstExprNode::Field {
+                base,
+                field: _,
+                location: _,
+            } => {
+                self.place(base);
+            }
+            AstExprNode::Deref {
+                pointer,
+                location: _,
+            } => {
+                self.expression(pointer);
+            }
+            AstExprNode::Terminal(Factor::Id {
+                id: _,
+                optional_call: None,
+                location: _,
+            }) => {}
+            _ => {
+                self.expression(expr);
+            }
+        }
+    }
+
+    fn call(&mut self, id: &str, args: &[Box<AstExprNode>]) {
+        for arg in args {
+            self.expression(arg);
+        }
+        let effect = self.effects.of(id);
+        let reason = match Intrinsic::from_name(id) {
+            Some(Intrinsic::Arithmetic(_, _)) => String::from("may call the overflow trap"),
+            Some(_) => format!("uses `{}`", id),
+            None => format!("calls `{}`, which is {}", id, effect),
+        };
+        self.found.add(effect, reason);
+    }
+
+    fn expression(&mut self, expr: &AstExprNode) {
+        match expr {
+            AstExprNode::Terminal(Factor::Numeric {
+                value: _,
+                location: _,
+            }) => {}
+            AstExprNode::Terminal(Factor::Id {
+                id: _,
+                optional_call: None,
+                location: _,
+            }) => {
+                self.read(expr);
+            }
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: Some(args),
+                location: _,
+            }) => {
+                self.call(id, args);
+            }
+            AstExprNode::Terminal(Factor::Index {
+                id: _,
+                index,
+                location: _,
+            }) => {
+                self.expression(index);
+                self.read(expr);
+            }
+            AstExprNode::SubNode(sub_node) => {
+                self.expression(sub_node);
+            }
+            AstExprNode::Cast {
+                value,
+                ty: _,
+                location: _,
+            } => {
+                self.expression(value);
+            }
+            AstExprNode::AddressOf {
+                target,
+                location: _,
+            } => {
+                self.place(target);
+            }
+            AstExprNode::Deref {
+                pointer,
+                location: _,
+            } => {
+                self.expression(pointer);
+                self.read(expr);
+            }
+            AstExprNode::Field {
+                base,
+                field: _,
+                location: _,
+            } => {
+                // Only the field is read from a global, not all of its base
+                match (source(expr, self.names, self.globals), base.as_ref()) {
+                    (
+                        Source::Global(_),
+                        AstExprNode::Terminal(Factor::Index {
+                            id: _,
+                            index,
+                            location: _,
+                        }),
+                    ) => self.expression(index),
+                    (Source::Global(_), _) => {}
+                    _ => self.expression(base),
+                }
+                self.read(expr);
+            }
+            AstExprNode::Node {
+                left,
+                op_type,
+                next,
+                location: _,
+            } => {
+                self.expression(left);
+                self.expression(next);
+                let traps = match op_type {
+                    BinOp::Sum(_) | BinOp::Mult(MulOp::MULTIPLY) => {
+                        !self.types.get(left).is_some_and(|ty| ty.is_pointer())
+                    }
+                    _ => false,
+                };
+                if traps && self.overflow == Overflow::Checked {
+                    self.found.add(
+                        Effect::Effectful,
+                        String::from("may call the overflow trap"),
+                    );
+                }
+            }
+        }
+    }
+
+    fn assignment(&mut self, target: &AstExprNode) {
+        self.place(target);
+        let reason = match target {
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location: _,
+            }) => match self.global(target) {
+                Some(_) => format!("writes global `{}`", id),
+                None => return,
+            },
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index: _,
+                location: _,
+            }) => match self.global(target) {
+                Some(global) if global.ty.is_array() => format!("writes global `{}`", id),
+                _ => format!("writes memory through `{}`", id),
+            },
+            AstExprNode::Field {
+                base,
+                field: _,
+                location: _,
+            } => match base.as_ref() {
+                AstExprNode::Terminal(Factor::Id {
+                    id,
+                    optional_call: None,
+                    location: _,
+                })
+                | AstExprNode::Terminal(Factor::Index {
+                    id,
+                    index: _,
+                    location: _,
+                }) if self.global(base).is_some() => {
+                    format!("writes global `{}`", id)
+                }
+                _ => String::from("writes memory through a pointer"),
+            },
+            _ => String::from("writes memory through a pointer"),
+        };
+        self.found.add(Effect::Effectful, reason);
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Select {
+                condition,
+                statement,
+                else_clause,
+            } => {
+                self.expression(condition);
+                self.statement(statement);
+                if let Some(clause) = else_clause {
+                    self.statement(clause);
+                }
+            }
+            Statement::ReturnExpr(expr) | Statement::Expression(expr) => {
+                self.expression(expr);
+            }
+            Statement::Block(statements) => {
+                for statement in statements {
+                    self.statement(statement);
+                }
+            }
+            Statement::Local {
+                name: _,
+                ty: _,
+                value,
+                location: _,
+            } => {
+                self.expression(value);
+            }
+            Statement::Assign {
+                target,
+                value,
+                location: _,
+            } => {
+                self.expression(value);
+                self.assignment(target);
+            }
+            Statement::Asm(_) => {
+                self.found
+                    .add(Effect::Effectful, String::from("contains `asm`"));
+            }
+            Statement::Match(matching) => {
+                self.expression(&matching.value);
+                for statement in matching.statements() {
+                    self.statement(statement);
+                }
+            }
+        }
+    }
+}
+
+fn extern_effect(decl: &FuncDecl) -> (Effect, Option<String>) {
+    if decl.attribute("pure").is_some() {
+        return (Effect::Pure, None);
+    }
+    (
+        Effect::Effectful,
+        Some(String::from("is an extern not marked `pure`")),
+    )
+}
+
+// Works out every function's effect, from the externs up through the
+// call graph until nothing changes. `overflow` decides whether plain
+// arithmetic can call the overflow trap. Definitions marked `pure`
+// must turn out to be.
+pub fn analyze_effects(
+    program: &Program,
+    names: &ResolutionTable,
+    types: &TypeTable,
+    overflow: Overflow,
+    errors: &mut Vec<SemanticError>,
+) -> Effects {
+    let mut effects = Effects {
+        functions: HashMap::new(),
+    };
+    let mut globals: HashMap<&str, &GlobalDecl> = HashMap::new();
+    let mut definitions: Vec<(&FuncDecl, &Statement)> = Vec::new();
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement,
+            } => {
+                definitions.push((decl, inner_statement));
+                effects
+                    .functions
+                    .insert(decl.name.clone(), (Effect::Pure, None));
+            }
+            PrimaryStatement::Extern(decl) => {
+                effects
+                    .functions
+                    .entry(decl.name.clone())
+                    .or_insert(extern_effect(decl));
+            }
+            PrimaryStatement::Global(global) => {
+                globals.entry(&global.name).or_insert(global);
+            }
+            PrimaryStatement::Struct(_) => {}
+        }
+    }
+
+    // Effects only ever grow, so this settles
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (decl, body) in &definitions {
+            let mut analyzer = Analyzer {
+                globals: &globals,
+                effects: &effects,
+                names,
+                types,
+                overflow,
+                found: Found {
+                    effect: Effect::Pure,
+                    reason: None,
+                },
+            };
+            analyzer.statement(body);
+            let found = analyzer.found;
+            if found.effect > effects.of(&decl.name) {
+                effects
+                    .functions
+                    .insert(decl.name.clone(), (found.effect, found.reason));
+                changed = true;
+            }
+        }
+    }
+
+    for (decl, _) in &definitions {
+        if let (Some(attribute), Some(reason)) =
+            (decl.attribute("pure"), effects.reason(&decl.name))
+        {
+            let message = format!("`{}` is marked `pure` but {}", decl.name, reason);
+            errors.push(SemanticError::new(message, attribute.location));
+        }
+    }
+    effects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+    use crate::semantic::check_source;
+    use crate::semantic::names;
+    use crate::semantic::typeck;
+
+    #[test]
+    fn effects_are_ordered_from_least_to_most() {
+        assert!(Effect::Pure < Effect::ReadOnly);
+        assert!(Effect::ReadOnly < Effect::Effectful);
+    }
+
+    #[test]
+    fn found_keeps_the_first_reason_for_the_strongest_effect() {
+        let mut found = Found {
+            effect: Effect::Pure,
+            reason: None,
+        };
+        found.add(Effect::ReadOnly, String::from("first read"));
+        found.add(Effect::ReadOnly, String::from("second read"));
+        assert_eq!(found.reason.as_deref(), Some("first read"));
+        found.add(Effect::Effectful, String::from("write"));
+        found.add(Effect::ReadOnly, String::from("third read"));
+        assert_eq!(found.effect, Effect::Effectful);
+        assert_eq!(found.reason.as_deref(), Some("write"));
+    }
+
+    const PROGRAM: &str = "extern ping(): u8\n\
+                           extern twice pure(x: u8): u8\n\
+                           var count: u8\n\
+                           var port: volatile u8 @ $4000\n\
+                           const table: [u8; 2] = [1, 2]\n\
+                           def add(a: u8, b: u8): u8 { return a + b }\n\
+                           def lookup(i: u8): u8 { return table[i] + twice(i) }\n\
+                           def current(): u8 { return count }\n\
+                           def status(): u8 { return port }\n\
+                           def deref(p: *u8): u8 { return *p }\n\
+                           def element(p: *u8): u8 { return p[1] }\n\
+                           def outer(): u8 { return middle() }\n\
+                           def middle(): u8 { return inner() }\n\
+                           def inner(): u8 { return ping() }\n\
+                           def even(n: u8): u8 { if (n == 0) { return 1 } return odd(n - 1) }\n\
+                           def odd(n: u8): u8 { if (n == 0) { return current() } return even(n - 1) }";
+
+    #[test]
+    fn functions_get_the_effect_of_what_they_do() {
+        let (_, analysis) = check_source(PROGRAM, Overflow::Wrapping);
+        let effects = &analysis.effects;
+        assert_eq!(effects.of("add"), Effect::Pure);
+        assert_eq!(effects.of("lookup"), Effect::Pure);
+        assert_eq!(effects.of("current"), Effect::ReadOnly);
+        assert_eq!(effects.of("status"), Effect::Effectful);
+        assert_eq!(effects.of("deref"), Effect::Effectful);
+        assert_eq!(effects.of("element"), Effect::Effectful);
+        assert_eq!(effects.reason("element"), Some("reads memory through `p`"));
+    }
+
+    #[test]
+    fn effects_spread_through_calls_until_nothing_changes() {
+        let (_, analysis) = check_source(PROGRAM, Overflow::Wrapping);
+        let effects = &analysis.effects;
+        assert_eq!(effects.of("outer"), Effect::Effectful);
+        assert_eq!(
+            effects.reason("outer"),
+            Some("calls `middle`, which is effectful")
+        );
+        // Neither reads anything until the other is found to
+        assert_eq!(effects.of("even"), Effect::ReadOnly);
+        assert_eq!(effects.of("odd"), Effect::ReadOnly);
+    }
+
+    #[test]
+    fn checked_arithmetic_may_trap() {
+        let (_, analysis) = check_source(PROGRAM, Overflow::Checked);
+        assert_eq!(analysis.effects.of("add"), Effect::Effectful);
+    }
+
+    #[test]
+    fn definitions_marked_pure_must_be() {
+        let program = parse_source("def fetch pure(p: *u8): u8 { return *p }");
+        let mut errors = Vec::new();
+        let names = names::check_names(&program, &mut errors, &mut Vec::new());
+        let types = typeck::check_types(&program, &mut errors);
+        analyze_effects(&program, &names, &types, Overflow::Wrapping, &mut errors);
+        assert_eq!(
+            errors[0].message,
+            "`fetch` is marked `pure` but reads memory through a pointer"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::ArgLocation;
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::types::Type;
+
+use crate::semantic::constant;
+use crate::semantic::typeck::declared_type;
+use crate::semantic::SemanticError;
+
+use crate::lexer::SourceLocation;
+
+// Operand for JSR to a foreign routine: a fixed address, an external
+// symbol used as is, or the extern's own symbol
+pub fn call_target(decl: &FuncDecl) -> String {
+    match decl.address.as_ref().map(|address| address.as_ref()) {
+        Some(AstExprNode::Terminal(Factor::Id {
+            id,
+            optional_call: None,
+            location: _,
+        })) => id.clone(),
+        Some(address) => format!("${:04X}", constant::evaluate(address).unwrap() as u16),
+        None => format!("_{}", decl.name),
+    }
+}
+
+// The symbol an extern needs imported, if it is not at a fixed address
+pub fn imported_symbol(decl: &FuncDecl) -> Option<String> {
+    match decl.address.as_ref().map(|address| address.as_ref()) {
+        Some(AstExprNode::Terminal(Factor::Id {
+            id,
+            optional_call: None,
+            location: _,
+        })) => Some(id.clone()),
+        Some(_) => None,
+        None => Some(format!("_{}", decl.name)),
+    }
+}
+
+// The address of an argument or result passed in memory
+pub fn memory_address(address: &AstExprNode) -> u16 {
+    constant::evaluate(address).unwrap() as u16
+}
+
+fn check_address(what: &str, address: &AstExprNode, size: usize, errors: &mut Vec<SemanticError>) {
+    match constant::evaluate(address) {
+        Some(value) if value.fract() == 0.0 && value >= 0.0 && value + size as f64 <= 65536.0 => {}
+        Some(value) => {
+            let message = format!("Address {} of {} is not in memory", value, what);
+            errors.push(SemanticError::new(message, address.location()));
+        }
+        None => {
+            let message = format!("Address of {} must be a constant", what);
+            errors.push(SemanticError::new(message, address.location()));
+        }
+    }
+}
+
+// Checks that a value of type `ty` fits where it is passed, and that
+// its registers are not already taken
+fn check_location(
+    what: String,
+    ty: &Type,
+    passed_in: &ArgLocation,
+    location: SourceLocation,
+    used: &mut Vec<(&'static str, String)>,
+    errors: &mut Vec<SemanticError>,
+) {
+    let size = match ty {
+        Type::Int(_)
+        | Type::Fixed(_)
+        | Type::Float
+        | Type::Bcd(_)
+        | Type::Pointer {
+            pointee: _,
+            mutable: _,
+        } => ty.size(),
+        _ => {
+            let message = format!("`{}` cannot be passed to or from a foreign routine", ty);
+            errors.push(SemanticError::new(message, location));
+            return;
+        }
+    };
+
+    if let ArgLocation::Memory(address) = passed_in {
+        check_address(&what, address, size, errors);
+        return;
+    }
+    let registers = passed_in.registers();
+    if registers.len() != size {
+        let message = format!("{} cannot hold {}, which is `{}`", passed_in, what, ty);
+        errors.push(SemanticError::new(message, location));
+    }
+    for register in registers {
+        match used.iter().find(|(other, _)| *other == register) {
+            Some((_, other)) => {
+                let message = format!("{} is already used by {}", register, other);
+                errors.push(SemanticError::new(message, location));
+            }
+            None => {
+                used.push((register, what.clone()));
+            }
+        }
+    }
+}
+
+fn check_foreign(decl: &FuncDecl, errors: &mut Vec<SemanticError>) {
+    if let Some(address) = &decl.address {
+        match address.as_ref() {
+            AstExprNode::Terminal(Factor::Id {
+                id: _,
+                optional_call: None,
+                location: _,
+            }) => {}
+            address if constant::evaluate(address).is_none() => {
+                let message = format!("Address of `{}` must be a constant or a symbol", decl.name);
+                errors.push(SemanticError::new(message, address.location()));
+            }
+            address => check_address(&format!("`{}`", decl.name), address, 1, errors),
+        }
+    }
+
+    let mut used: Vec<(&'static str, String)> = Vec::new();
+    for arg in &decl.args {
+        let what = format!("`{}`", arg.name);
+        match &arg.passed_in {
+            Some(passed_in) => {
+                check_location(
+                    what,
+                    &declared_type(&arg.ty),
+                    passed_in,
+                    arg.location,
+                    &mut used,
+                    errors,
+                );
+            }
+            None => {
+                let message = format!("Parameter {} of `{}` needs a location such as `in A`, since `{}` is a foreign routine", what, decl.name, decl.name);
+                errors.push(SemanticError::new(message, arg.location));
+            }
+        }
+    }
+
+    // The result is read after the call, when the arguments are done with
+    let mut used: Vec<(&'static str, String)> = Vec::new();
+    match (&decl.return_type, &decl.returned_in) {
+        (Some(ty), Some(returned_in)) => {
+            check_location(
+                format!("the result of `{}`", decl.name),
+                ty,
+                returned_in,
+                decl.location,
+                &mut used,
+                errors,
+            );
+        }
+        (Some(_), None) => {
+            let message = format!(
+                "`{}` returns a value, so it needs a location such as `in A`",
+                decl.name
+            );
+            errors.push(SemanticError::new(message, decl.location));
+        }
+        (None, _) => {}
+    }
+}
+
+// Checks where externs live and how they take their arguments. Only
+// externs can be given locations, since `def`s use the data stack.
+pub fn check_externs(program: &Program, errors: &mut Vec<SemanticError>) {
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Extern(decl) if decl.is_foreign() => {
+                check_foreign(decl, errors);
+            }
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            } if decl.is_foreign() => {
+                let message = format!(
+                    "`{}` is defined here, so only an extern can say where its arguments go",
+                    decl.name
+                );
+                errors.push(SemanticError::new(message, decl.location));
+            }
+            _ => {}
+        }
+    }
+}
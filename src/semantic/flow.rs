@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use crate::lexer::SourceLocation;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::Initializer;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::parser::Statement;
+
+use crate::semantic::constant;
+use crate::semantic::interrupts;
+use crate::semantic::lints::Lint;
+use crate::semantic::lints::Warning;
+use crate::semantic::matching;
+use crate::semantic::names::ResolutionTable;
+use crate::semantic::typeck::TypeTable;
+use crate::semantic::SemanticError;
+
+// Whether every path through the statement ends in a return
+pub fn always_returns(statement: &Statement, types: &TypeTable) -> bool {
+    match statement {
+        Statement::Select {
+            condition: _,
+            statement,
+            else_clause: Some(clause),
+        } => always_returns(statement, types) && always_returns(clause, types),
+        Statement::ReturnExpr(_) => true,
+        Statement::Block(statements) => statements
+            .iter()
+            .any(|statement| always_returns(statement, types)),
+        Statement::Match(matching) => {
+            matching::is_exhaustive(matching, types)
+                && matching
+                    .statements()
+                    .iter()
+                    .all(|statement| always_returns(statement, types))
+        }
+        _ => false,
+    }
+}
+
+// Where a statement starts, if it has anything in it
+fn location(statement: &Statement) -> Option<SourceLocation> {
+    match statement {
+        Statement::Select {
+            condition,
+            statement: _,
+            else_clause: _,
+        } => Some(condition.location()),
+        Statement::ReturnExpr(expr) | Statement::Expression(expr) => Some(expr.location()),
+        Statement::Block(statements) => statements.iter().find_map(location),
+        Statement::Local {
+            name: _,
+            ty: _,
+            value: _,
+            location,
+        } => Some(*location),
+        Statement::Assign {
+            target,
+            value: _,
+            location: _,
+        } => Some(target.location()),
+        Statement::Asm(block) => Some(block.location),
+        Statement::Match(matching) => Some(matching.location),
+    }
+}
+
+// `named` gives the value of names of constants
+fn check_statement<F>(
+    statement: &Statement,
+    types: &TypeTable,
+    named: &F,
+    warnings: &mut Vec<Warning>,
+) where
+    F: Fn(&AstExprNode) -> Option<f64>,
+{
+    match statement {
+        Statement::Select {
+            condition,
+            statement,
+            else_clause,
+        } => {
+            if let Some(value) = constant::evaluate_with(condition, named) {
+                let message = format!(
+                    "Condition is always {}",
+                    if value != 0.0 { "true" } else { "false" }
+                );
+                warnings.push(Warning::new(
+                    Lint::ConstantCondition,
+                    message,
+                    condition.location(),
+                ));
+            }
+            check_statement(statement, types, named, warnings);
+            if let Some(clause) = else_clause {
+                check_statement(clause, types, named, warnings);
+            }
+        }
+        Statement::Block(statements) => {
+            // Only the first statement after a return is reported
+            if let Some(index) = statements
+                .iter()
+                .position(|statement| always_returns(statement, types))
+            {
+                if let Some(location) = statements[index + 1..].iter().find_map(location) {
+                    let message = String::from(
+                        "Statement is never reached, since every path before it returns",
+                    );
+                    warnings.push(Warning::new(Lint::UnreachableCode, message, location));
+                }
+            }
+            for statement in statements {
+                check_statement(statement, types, named, warnings);
+            }
+        }
+        Statement::Match(matching) => {
+            for statement in matching.statements() {
+                check_statement(statement, types, named, warnings);
+            }
+        }
+        _ => {}
+    }
+}
+
+// A function must return a value on every path, except an interrupt
+// handler, whose result is dropped. Code after a return and conditions
+// that never change are only warned about, including those that only
+// name constants, whose values are in `initializers`.
+pub fn check_flow(
+    program: &Program,
+    names: &ResolutionTable,
+    types: &TypeTable,
+    initializers: &HashMap<String, Vec<f64>>,
+    errors: &mut Vec<SemanticError>,
+    warnings: &mut Vec<Warning>,
+) {
+    let mut constants: HashMap<&str, f64> = HashMap::new();
+    for primary in program.primaries() {
+        if let PrimaryStatement::Global(global) = primary {
+            if let (true, Some(Initializer::Scalar(_)), Some(value)) = (
+                global.is_const,
+                &global.initializer,
+                initializers
+                    .get(&global.name)
+                    .and_then(|values| values.first()),
+            ) {
+                constants.insert(&global.name, *value);
+            }
+        }
+    }
+    let named = |expr: &AstExprNode| match expr {
+        AstExprNode::Terminal(Factor::Id {
+            id: _,
+            optional_call: None,
+            location: _,
+        }) => constants.get(names.global(expr)?).cloned(),
+        _ => None,
+    };
+
+    for primary in program.primaries() {
+        if let PrimaryStatement::Definition {
+            decl,
+            inner_statement,
+        } = primary
+        {
+            if interrupts::interrupt_vector(decl).is_none()
+                && !always_returns(inner_statement, types)
+            {
+                let message = format!("Not every path through `{}` returns a value", decl.name);
+                errors.push(SemanticError::new(message, decl.location));
+            }
+            check_statement(inner_statement, types, &named, warnings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::semantic::check_source;
+    use crate::semantic::intrinsics::Overflow;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let (_, analysis) = check_source(source, Overflow::Wrapping);
+        analysis
+            .warnings
+            .iter()
+            .map(|warning| warning.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn literal_conditions_are_constant() {
+        let source = "def f(x: u8): u8 { if (1 < 2) { return x } return 0 }";
+        assert_eq!(warnings(source), vec!["Condition is always true"]);
+    }
+
+    #[test]
+    fn conditions_naming_constants_are_constant() {
+        let source = "const DEBUG: u8 = 0\n\
+                      def f(x: u8): u8 { if (DEBUG) { return x } return 0 }";
+        assert_eq!(warnings(source), vec!["Condition is always false"]);
+        let source = "const LEVEL: u8 = 2\n\
+                      def f(x: u8): u8 { if (LEVEL > 1) { return x } return 0 }";
+        assert_eq!(warnings(source), vec!["Condition is always true"]);
+    }
+
+    #[test]
+    fn conditions_naming_variables_are_not_constant() {
+        let source = "var DEBUG: u8\n\
+                      def f(x: u8): u8 { if (DEBUG) { return x } return 0 }";
+        assert!(warnings(source).is_empty());
+        // A parameter that hides a constant
+        let source = "const DEBUG: u8 = 0\n\
+                      def f(DEBUG: u8): u8 { if (DEBUG) { return 1 } return 0 }";
+        assert_eq!(
+            warnings(source),
+            vec!["`DEBUG` shadows the global declared at 1:7"]
+        );
+    }
+
+    #[test]
+    fn code_after_a_return_is_unreachable() {
+        let source = "def f(x: u8): u8 { return x\nreturn 0 }";
+        assert_eq!(
+            warnings(source),
+            vec!["Statement is never reached, since every path before it returns"]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use crate::parser::types::Type;
+
+// A type during inference: either already known or a variable that
+// later uses may pin down
+#[derive(Debug, Clone)]
+pub enum InferType {
+    Known(Type),
+    Var(usize),
+}
+
+// Whether `first` should replace `second` as the default of a merged set
+fn wider_default(first: &Type, second: &Type) -> bool {
+    match (first, second) {
+        (Type::Fixed(_), Type::Int(_)) => true,
+        (Type::Int(_), Type::Fixed(_)) => false,
+        _ => first.size() > second.size(),
+    }
+}
+
+// Union-find over type variables. Each set of unified variables is
+// either bound to a type or, if nothing constrains it by the end,
+// falls back to a default.
+pub struct TypeVariables {
+    parents: Vec<usize>,
+    bindings: Vec<Option<Type>>,
+    defaults: Vec<Type>,
+}
+
+impl TypeVariables {
+    pub fn new() -> TypeVariables {
+        TypeVariables {
+            parents: Vec::new(),
+            bindings: Vec::new(),
+            defaults: Vec::new(),
+        }
+    }
+
+    pub fn fresh(&mut self, default: Type) -> InferType {
+        let var = self.parents.len();
+        self.parents.push(var);
+        self.bindings.push(None);
+        self.defaults.push(default);
+        InferType::Var(var)
+    }
+
+    fn find(&mut self, var: usize) -> usize {
+        let parent = self.parents[var];
+        if parent == var {
+            return var;
+        }
+        let root = self.find(parent);
+        self.parents[var] = root;
+        root
+    }
+
+    // Replaces bound variables with their type
+    pub fn shallow_resolve(&mut self, ty: &InferType) -> InferType {
+        match ty {
+            InferType::Known(_) => ty.clone(),
+            InferType::Var(var) => {
+                let root = self.find(*var);
+                match &self.bindings[root] {
+                    Some(bound) => InferType::Known(bound.clone()),
+                    None => InferType::Var(root),
+                }
+            }
+        }
+    }
+
+    // The final type, using the default for unconstrained variables
+    pub fn resolve(&mut self, ty: &InferType) -> Type {
+        match self.shallow_resolve(ty) {
+            InferType::Known(known) => known,
+            InferType::Var(root) => self.defaults[root].clone(),
+        }
+    }
+
+    // Makes both types equal. On a conflict returns the two types that
+    // could not be unified.
+    pub fn unify(&mut self, first: &InferType, second: &InferType) -> Result<(), (Type, Type)> {
+        match (self.shallow_resolve(first), self.shallow_resolve(second)) {
+            (InferType::Known(first), InferType::Known(second)) => {
+                if first == second {
+                    Ok(())
+                } else {
+                    Err((first, second))
+                }
+            }
+            (InferType::Var(root), InferType::Known(known))
+            | (InferType::Known(known), InferType::Var(root)) => {
+                self.bindings[root] = Some(known);
+                Ok(())
+            }
+            (InferType::Var(first), InferType::Var(second)) => {
+                if first != second {
+                    // Keep the wider default so merging a comparison
+                    // result with a literal cannot lose bits, and a
+                    // fractional literal's so it cannot lose its fraction
+                    if wider_default(&self.defaults[first], &self.defaults[second]) {
+                        self.defaults[second] = self.defaults[first].clone();
+                    }
+                    self.parents[first] = second;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::types::FixedType;
+    use crate::parser::types::IntType;
+
+    const U8: Type = Type::Int(IntType::U8);
+    const U16: Type = Type::Int(IntType::U16);
+
+    #[test]
+    fn unconstrained_variables_take_their_default() {
+        let mut variables = TypeVariables::new();
+        let var = variables.fresh(U8);
+        assert_eq!(variables.resolve(&var), U8);
+    }
+
+    #[test]
+    fn unifying_with_a_known_type_binds_the_variable() {
+        let mut variables = TypeVariables::new();
+        let var = variables.fresh(U8);
+        assert_eq!(variables.unify(&var, &InferType::Known(U16)), Ok(()));
+        assert_eq!(variables.resolve(&var), U16);
+    }
+
+    #[test]
+    fn bindings_are_shared_by_every_unified_variable() {
+        let mut variables = TypeVariables::new();
+        let first = variables.fresh(U8);
+        let second = variables.fresh(U8);
+        let third = variables.fresh(U8);
+        variables.unify(&first, &second).unwrap();
+        variables.unify(&second, &third).unwrap();
+        variables.unify(&third, &InferType::Known(U16)).unwrap();
+        assert_eq!(variables.resolve(&first), U16);
+        assert_eq!(
+            variables.unify(&first, &InferType::Known(U8)),
+            Err((U16, U8))
+        );
+    }
+
+    #[test]
+    fn merged_variables_keep_the_wider_default() {
+        let mut variables = TypeVariables::new();
+        let narrow = variables.fresh(U8);
+        let wide = variables.fresh(U16);
+        variables.unify(&wide, &narrow).unwrap();
+        assert_eq!(variables.resolve(&narrow), U16);
+
+        let int = variables.fresh(U16);
+        let fixed = variables.fresh(Type::Fixed(FixedType::F8x8));
+        variables.unify(&int, &fixed).unwrap();
+        assert_eq!(variables.resolve(&int), Type::Fixed(FixedType::F8x8));
+    }
+
+    #[test]
+    fn known_types_must_be_equal() {
+        let mut variables = TypeVariables::new();
+        assert_eq!(
+            variables.unify(&InferType::Known(U8), &InferType::Known(U8)),
+            Ok(())
+        );
+        assert_eq!(
+            variables.unify(&InferType::Known(U8), &InferType::Known(U16)),
+            Err((U8, U16))
+        );
+    }
+}
@@ -0,0 +1,690 @@
+use std::collections::HashMap;
+
+use crate::lexer::MulOp;
+use crate::lexer::SourceLocation;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::BinOp;
+use crate::parser::bin_op::Factor;
+use crate::parser::matching::MatchPattern;
+use crate::parser::matching::MatchStatement;
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::Initializer;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::parser::Statement;
+use crate::parser::types::Type;
+use crate::parser::types::DEFAULT_INT_TYPE;
+
+use crate::semantic::constant;
+use crate::semantic::intrinsics::Intrinsic;
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::typeck::declared_type;
+use crate::semantic::typeck::TypeTable;
+use crate::semantic::SemanticError;
+
+// Expressions and statements one initializer may evaluate before it is
+// assumed to never finish
+const MAX_STEPS: usize = 1000000;
+
+// Calls deeper than this could never fit on the data stack either
+const MAX_DEPTH: usize = 128;
+
+// How a statement finished
+enum Flow {
+    Next,
+    Return(f64),
+}
+
+fn wrap_bits(value: i64, bits: usize, signed: bool) -> i64 {
+    let truncated = value & ((1 << bits) - 1);
+    if signed && truncated >= 1 << (bits - 1) {
+        truncated - (1 << bits)
+    } else {
+        truncated
+    }
+}
+
+// The value a result of type `ty` holds once stored, with `overflow`
+// deciding what happens to one out of range. None when a checked
+// result does not fit.
+pub fn fit(value: f64, ty: &Type, overflow: Overflow) -> Option<f64> {
+    let (scale, low, high, bits, signed) = match ty {
+        Type::Int(int_type) => (
+            1.0,
+            int_type.min_value(),
+            int_type.max_value(),
+            8 * int_type.size(),
+            int_type.is_signed(),
+        ),
+        Type::Fixed(fixed_type) => {
+            let bits = 8 * fixed_type.size();
+            (
+                (1i64 << fixed_type.fraction_bits()) as f64,
+                -(1i64 << (bits - 1)),
+                (1i64 << (bits - 1)) - 1,
+                bits,
+                true,
+            )
+        }
+        Type::Bcd(bcd_type) => (1.0, 0, bcd_type.max_value(), 0, false),
+        Type::Float => return Some(value as f32 as f64),
+        _ => return Some(value),
+    };
+    let raw = (value * scale).trunc() as i64;
+    let fitted = if raw >= low && raw <= high {
+        raw
+    } else {
+        match overflow {
+            Overflow::Wrapping if bits == 0 => raw.rem_euclid(high + 1),
+            Overflow::Wrapping => wrap_bits(raw, bits, signed),
+            Overflow::Saturating => raw.max(low).min(high),
+            Overflow::Checked => return None,
+        }
+    };
+    Some(fitted as f64 / scale)
+}
+
+// Runs defs on constant arguments so that global initializers can call
+// them. Values are numbers like in `constant`, brought back into range
+// of their type after every operator the way the generated code would,
+// except that float results round to nearest.
+struct Interpreter<'a> {
+    functions: HashMap<String, (&'a FuncDecl, &'a Statement)>,
+    externs: HashMap<String, &'a FuncDecl>,
+    globals: HashMap<String, &'a GlobalDecl>,
+    types: &'a TypeTable,
+    overflow: Overflow,
+    // Values of the initializers evaluated so far
+    values: HashMap<String, Vec<f64>>,
+    // Initializers being evaluated, to catch one that needs itself
+    pending: Vec<String>,
+    // Locals and parameters of the calls in progress, innermost last
+    frames: Vec<Vec<HashMap<String, (f64, Type)>>>,
+    steps: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    fn step(&mut self, location: SourceLocation) -> Result<(), SemanticError> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            let message = format!("Compile-time evaluation took more than {} steps", MAX_STEPS);
+            return Err(SemanticError::new(message, location));
+        }
+        Ok(())
+    }
+
+    fn fit(
+        &self,
+        value: f64,
+        ty: &Type,
+        overflow: Overflow,
+        location: SourceLocation,
+    ) -> Result<f64, SemanticError> {
+        match fit(value, ty, overflow) {
+            Some(fitted) => Ok(fitted),
+            None => {
+                let message = format!("`{}` overflows `{}` at compile time", value, ty);
+                Err(SemanticError::new(message, location))
+            }
+        }
+    }
+
+    fn lookup_local(&mut self, name: &str) -> Option<&mut (f64, Type)> {
+        let scopes = self.frames.last_mut()?;
+        for scope in scopes.iter_mut().rev() {
+            if let Some(local) = scope.get_mut(name) {
+                return Some(local);
+            }
+        }
+        None
+    }
+
+    // The values a global starts with, evaluating its initializer the
+    // first time it is needed
+    fn global_values(
+        &mut self,
+        global: &'a GlobalDecl,
+        location: SourceLocation,
+    ) -> Result<Vec<f64>, SemanticError> {
+        if let Some(values) = self.values.get(&global.name) {
+            return Ok(values.clone());
+        }
+        if self.pending.contains(&global.name) {
+            let message = format!("Initializer of `{}` depends on itself", global.name);
+            return Err(SemanticError::new(message, location));
+        }
+        let elements: Vec<&AstExprNode> = match &global.initializer {
+            Some(Initializer::Scalar(value)) => vec![value],
+            Some(Initializer::List(elements)) => elements.iter().collect(),
+            None => {
+                let message = format!(
+                    "`{}` has no initializer to read at compile time",
+                    global.name
+                );
+                return Err(SemanticError::new(message, location));
+            }
+        };
+
+        self.pending.push(global.name.clone());
+        let mut values = Vec::new();
+        for element in elements {
+            values.push(self.evaluate(element)?);
+        }
+        self.pending.pop();
+        self.values.insert(global.name.clone(), values.clone());
+        Ok(values)
+    }
+
+    // Reads a global, which must be a constant so that it cannot change
+    // between compiling and running
+    fn read_global(
+        &mut self,
+        id: &str,
+        index: Option<f64>,
+        location: SourceLocation,
+    ) -> Result<f64, SemanticError> {
+        let global = match self.globals.get(id) {
+            Some(global) => *global,
+            None => {
+                let message = format!("Unknown name `{}` at compile time", id);
+                return Err(SemanticError::new(message, location));
+            }
+        };
+        if !global.is_const {
+            let message = format!("`{}` is a `var`, which cannot be read at compile time", id);
+            return Err(SemanticError::new(message, location));
+        }
+        let values = self.global_values(global, location)?;
+        let (element, position) = match (&global.ty, index) {
+            (Type::Array { element, length }, Some(index)) => {
+                if index < 0.0 || index >= *length as f64 {
+                    let message = format!(
+                        "Index {} is out of bounds for `{}` of length {}",
+                        index, id, length
+                    );
+                    return Err(SemanticError::new(message, location));
+                }
+                (element.as_ref(), index as usize)
+            }
+            (
+                Type::Array {
+                    element: _,
+                    length: _,
+                },
+                None,
+            ) => {
+                let message = format!(
+                    "Array `{}` has no value at compile time, only its elements do",
+                    id
+                );
+                return Err(SemanticError::new(message, location));
+            }
+            (ty, _) => (ty, 0),
+        };
+        match values.get(position) {
+            Some(value) => self.fit(*value, element, Overflow::Wrapping, location),
+            None => {
+                let message = format!(
+                    "`{}` has no element {} to read at compile time",
+                    id, position
+                );
+                Err(SemanticError::new(message, location))
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        id: &str,
+        args: &'a [Box<AstExprNode>],
+        location: SourceLocation,
+    ) -> Result<f64, SemanticError> {
+        if let Some(intrinsic) = Intrinsic::from_name(id) {
+            return self.call_intrinsic(id, intrinsic, args, location);
+        }
+        let (decl, body) = match self.functions.get(id) {
+            Some(function) => *function,
+            None => {
+                let message = match self.externs.get(id) {
+                    Some(_) => format!("Cannot call extern `{}` at compile time", id),
+                    None => format!("Unknown function `{}` at compile time", id),
+                };
+                return Err(SemanticError::new(message, location));
+            }
+        };
+        if args.len() != decl.args.len() {
+            let message = format!(
+                "`{}` takes {} arguments but {} were given",
+                id,
+                decl.args.len(),
+                args.len()
+            );
+            return Err(SemanticError::new(message, location));
+        }
+        if self.frames.len() >= MAX_DEPTH {
+            let message = format!("Compile-time calls nest deeper than {}", MAX_DEPTH);
+            return Err(SemanticError::new(message, location));
+        }
+
+        let mut params = HashMap::new();
+        for (arg, param) in args.iter().zip(&decl.args) {
+            let ty = declared_type(&param.ty);
+            let value = self.evaluate(arg)?;
+            let value = self.fit(value, &ty, Overflow::Wrapping, arg.location())?;
+            params.insert(param.name.clone(), (value, ty));
+        }
+        self.frames.push(vec![params]);
+        let flow = self.execute(body);
+        self.frames.pop();
+        match flow? {
+            Flow::Return(value) => self.fit(
+                value,
+                &declared_type(&decl.return_type),
+                Overflow::Wrapping,
+                location,
+            ),
+            Flow::Next => {
+                let message = format!("`{}` ended without returning a value at compile time", id);
+                Err(SemanticError::new(message, location))
+            }
+        }
+    }
+
+    fn call_intrinsic(
+        &mut self,
+        id: &str,
+        intrinsic: Intrinsic,
+        args: &'a [Box<AstExprNode>],
+        location: SourceLocation,
+    ) -> Result<f64, SemanticError> {
+        match intrinsic {
+            Intrinsic::Arithmetic(op, overflow) if args.len() == 2 => {
+                let left = self.evaluate(&args[0])?;
+                let right = self.evaluate(&args[1])?;
+                let value = constant::apply(&op.bin_op(), left, right);
+                match self.types.get(&args[0]) {
+                    Some(ty) => self.fit(value, &ty.clone(), overflow, location),
+                    None => Ok(value),
+                }
+            }
+            _ => {
+                let message = format!(
+                    "`{}` reads or writes memory, which cannot happen at compile time",
+                    id
+                );
+                Err(SemanticError::new(message, location))
+            }
+        }
+    }
+
+    fn evaluate(&mut self, expr: &'a AstExprNode) -> Result<f64, SemanticError> {
+        self.step(expr.location())?;
+        match expr {
+            AstExprNode::Terminal(Factor::Numeric { value, location: _ }) => Ok(*value),
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location,
+            }) => match self.lookup_local(id) {
+                Some((value, _)) => Ok(*value),
+                None => self.read_global(id, None, *location),
+            },
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: Some(args),
+                location,
+            }) => self.call(id, args, *location),
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location,
+            }) => {
+                let index = self.evaluate(index)?;
+                self.read_global(id, Some(index), *location)
+            }
+            AstExprNode::SubNode(sub_node) => self.evaluate(sub_node),
+            AstExprNode::Node {
+                left,
+                op_type,
+                next,
+                location,
+            } => {
+                let left_value = self.evaluate(left)?;
+                let right_value = self.evaluate(next)?;
+                let ty = match self.types.get(expr) {
+                    Some(ty) => ty.clone(),
+                    None => return Ok(constant::apply(op_type, left_value, right_value)),
+                };
+                if ty.is_pointer() {
+                    let message = String::from("Pointers cannot be used at compile time");
+                    return Err(SemanticError::new(message, *location));
+                }
+                if let (BinOp::Mult(MulOp::DIVIDE), true) =
+                    (op_type, right_value == 0.0 && !ty.is_float())
+                {
+                    let message = String::from("Division by zero at compile time");
+                    return Err(SemanticError::new(message, *location));
+                }
+                let value = constant::apply(op_type, left_value, right_value);
+                match op_type {
+                    BinOp::Rel(_) => Ok(value),
+                    _ => self.fit(value, &ty, self.overflow, *location),
+                }
+            }
+            AstExprNode::Cast {
+                value,
+                ty,
+                location,
+            } => {
+                let mut result = self.evaluate(value)?;
+                let from = self.types.get(value).cloned();
+                if ty.is_pointer() || from.as_ref().is_some_and(|from| from.is_pointer()) {
+                    let message = String::from("Pointers cannot be used at compile time");
+                    return Err(SemanticError::new(message, *location));
+                }
+                // Fixed-point numbers round down to integers, and BCD
+                // takes integers as unsigned
+                if let (Some(Type::Fixed(_)), Type::Int(_)) = (&from, ty) {
+                    result = result.floor();
+                }
+                if ty.is_bcd() && result < 0.0 {
+                    result = result.rem_euclid(65536.0);
+                }
+                self.fit(result, ty, Overflow::Wrapping, *location)
+            }
+            AstExprNode::AddressOf {
+                target: _,
+                location,
+            }
+            | AstExprNode::Deref {
+                pointer: _,
+                location,
+            } => {
+                let message = String::from("Pointers cannot be used at compile time");
+                Err(SemanticError::new(message, *location))
+            }
+            AstExprNode::Field {
+                base: _,
+                field: _,
+                location,
+            } => {
+                let message = String::from("Struct fields cannot be read at compile time");
+                Err(SemanticError::new(message, *location))
+            }
+        }
+    }
+
+    fn execute_scoped(&mut self, statement: &'a Statement) -> Result<Flow, SemanticError> {
+        self.frames.last_mut().unwrap().push(HashMap::new());
+        let flow = self.execute(statement);
+        self.frames.last_mut().unwrap().pop();
+        flow
+    }
+
+    fn execute(&mut self, statement: &'a Statement) -> Result<Flow, SemanticError> {
+        match statement {
+            Statement::Select {
+                condition,
+                statement,
+                else_clause,
+            } => {
+                if self.evaluate(condition)? != 0.0 {
+                    self.execute_scoped(statement)
+                } else if let Some(clause) = else_clause {
+                    self.execute_scoped(clause)
+                } else {
+                    Ok(Flow::Next)
+                }
+            }
+            Statement::ReturnExpr(expr) => Ok(Flow::Return(self.evaluate(expr)?)),
+            Statement::Block(statements) => {
+                self.frames.last_mut().unwrap().push(HashMap::new());
+                let mut flow = Ok(Flow::Next);
+                for statement in statements {
+                    flow = self.execute(statement);
+                    if let Ok(Flow::Next) = flow {
+                        continue;
+                    }
+                    break;
+                }
+                self.frames.last_mut().unwrap().pop();
+                flow
+            }
+            Statement::Local {
+                name,
+                ty,
+                value,
+                location,
+            } => {
+                let result = self.evaluate(value)?;
+                let ty = ty
+                    .clone()
+                    .or(self.types.get(value).cloned())
+                    .unwrap_or(Type::Int(DEFAULT_INT_TYPE));
+                let result = self.fit(result, &ty, Overflow::Wrapping, *location)?;
+                self.frames
+                    .last_mut()
+                    .unwrap()
+                    .last_mut()
+                    .unwrap()
+                    .insert(name.clone(), (result, ty));
+                Ok(Flow::Next)
+            }
+            Statement::Assign {
+                target,
+                value,
+                location,
+            } => {
+                let result = self.evaluate(value)?;
+                if let AstExprNode::Terminal(Factor::Id {
+                    id,
+                    optional_call: None,
+                    location: _,
+                }) = target.as_ref()
+                {
+                    if let Some((_, ty)) = self.lookup_local(id) {
+                        let ty = ty.clone();
+                        let result = self.fit(result, &ty, Overflow::Wrapping, *location)?;
+                        self.lookup_local(id).unwrap().0 = result;
+                        return Ok(Flow::Next);
+                    }
+                }
+                let message =
+                    String::from("Only locals and parameters can be assigned at compile time");
+                Err(SemanticError::new(message, *location))
+            }
+            Statement::Expression(expr) => {
+                self.evaluate(expr)?;
+                Ok(Flow::Next)
+            }
+            Statement::Asm(block) => {
+                let message = String::from("Inline assembly cannot run at compile time");
+                Err(SemanticError::new(message, block.location))
+            }
+            Statement::Match(matching) => self.execute_match(matching),
+        }
+    }
+
+    fn execute_match(&mut self, matching: &'a MatchStatement) -> Result<Flow, SemanticError> {
+        let value = self.evaluate(&matching.value)?;
+        for arm in &matching.arms {
+            let matched = arm.patterns.iter().any(|pattern| match pattern {
+                MatchPattern::Value(pattern) => constant::evaluate(pattern) == Some(value),
+                MatchPattern::Range {
+                    low,
+                    high,
+                    inclusive,
+                } => {
+                    let (low, high) = (
+                        constant::evaluate(low).unwrap(),
+                        constant::evaluate(high).unwrap(),
+                    );
+                    value >= low && (value < high || (*inclusive && value == high))
+                }
+            });
+            if matched {
+                return self.execute_scoped(&arm.statement);
+            }
+        }
+        match &matching.default {
+            Some(default) => self.execute_scoped(&default.statement),
+            None => Ok(Flow::Next),
+        }
+    }
+}
+
+// Evaluates the initializer of every global, running any defs they
+// call. The values are those written, before being stored as the type
+// of the global.
+pub fn evaluate_initializers(
+    program: &Program,
+    types: &TypeTable,
+    overflow: Overflow,
+    errors: &mut Vec<SemanticError>,
+) -> HashMap<String, Vec<f64>> {
+    let mut interpreter = Interpreter {
+        functions: HashMap::new(),
+        externs: HashMap::new(),
+        globals: HashMap::new(),
+        types,
+        overflow,
+        values: HashMap::new(),
+        pending: Vec::new(),
+        frames: Vec::new(),
+        steps: 0,
+    };
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement,
+            } => {
+                interpreter
+                    .functions
+                    .insert(decl.name.clone(), (decl, inner_statement));
+            }
+            PrimaryStatement::Extern(decl) => {
+                interpreter.externs.insert(decl.name.clone(), decl);
+            }
+            PrimaryStatement::Global(global) => {
+                interpreter.globals.insert(global.name.clone(), global);
+            }
+            _ => {}
+        }
+    }
+
+    for primary in program.primaries() {
+        if let PrimaryStatement::Global(global) = primary {
+            if global.initializer.is_none() {
+                continue;
+            }
+            interpreter.steps = 0;
+            interpreter.pending.clear();
+            interpreter.frames.clear();
+            if let Err(error) = interpreter.global_values(global, global.location) {
+                let message = format!(
+                    "Initializer of `{}` is not constant: {}",
+                    global.name, error.message
+                );
+                errors.push(SemanticError::new(message, error.location));
+            }
+        }
+    }
+    interpreter.values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+    use crate::parser::types::BcdType;
+    use crate::parser::types::FixedType;
+    use crate::parser::types::IntType;
+    use crate::semantic::check_program;
+    use crate::semantic::lints::LintLevels;
+
+    const U8: Type = Type::Int(IntType::U8);
+    const I8: Type = Type::Int(IntType::I8);
+
+    #[test]
+    fn values_in_range_are_kept_whole() {
+        for overflow in [Overflow::Wrapping, Overflow::Saturating, Overflow::Checked] {
+            assert_eq!(fit(200.0, &U8, overflow), Some(200.0));
+            assert_eq!(fit(-128.0, &I8, overflow), Some(-128.0));
+            assert_eq!(fit(7.9, &U8, overflow), Some(7.0));
+        }
+    }
+
+    #[test]
+    fn values_out_of_range_follow_the_overflow_mode() {
+        assert_eq!(fit(300.0, &U8, Overflow::Wrapping), Some(44.0));
+        assert_eq!(fit(-1.0, &U8, Overflow::Wrapping), Some(255.0));
+        assert_eq!(fit(128.0, &I8, Overflow::Wrapping), Some(-128.0));
+        assert_eq!(fit(300.0, &U8, Overflow::Saturating), Some(255.0));
+        assert_eq!(fit(-200.0, &I8, Overflow::Saturating), Some(-128.0));
+        assert_eq!(fit(300.0, &U8, Overflow::Checked), None);
+    }
+
+    #[test]
+    fn fixed_point_and_bcd_values_are_fitted_to_their_encoding() {
+        let fixed = Type::Fixed(FixedType::F8x8);
+        assert_eq!(fit(1.3, &fixed, Overflow::Checked), Some(1.296875));
+        assert_eq!(fit(128.0, &fixed, Overflow::Saturating), Some(127.99609375));
+        assert_eq!(fit(128.0, &fixed, Overflow::Checked), None);
+        let bcd = Type::Bcd(BcdType::Bcd8);
+        assert_eq!(fit(123.0, &bcd, Overflow::Wrapping), Some(23.0));
+        assert_eq!(fit(123.0, &bcd, Overflow::Saturating), Some(99.0));
+        assert_eq!(fit(0.75, &Type::Float, Overflow::Checked), Some(0.75));
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let program = parse_source(source);
+        match check_program(&program, Overflow::Wrapping, &LintLevels::new()) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors.into_iter().map(|error| error.message).collect(),
+        }
+    }
+
+    #[test]
+    fn initializers_call_definitions() {
+        let program = parse_source(
+            "def square(n: u16): u16 { return n * n }\n\
+             const area: u16 = square(12) + 1",
+        );
+        let analysis = check_program(&program, Overflow::Wrapping, &LintLevels::new()).unwrap();
+        assert_eq!(analysis.initializers["area"], vec![145.0]);
+    }
+
+    #[test]
+    fn evaluation_that_runs_too_long_is_stopped() {
+        let source = "def fib(n: u16): u16 {\n\
+                      if (n < 2) { return n }\n\
+                      return fib(n - 1) + fib(n - 2)\n\
+                      }\n\
+                      const big: u16 = fib(40)";
+        assert_eq!(
+            errors(source),
+            vec![format!(
+                "Initializer of `big` is not constant: \
+                 Compile-time evaluation took more than {} steps",
+                MAX_STEPS
+            )]
+        );
+    }
+
+    #[test]
+    fn calls_that_nest_too_deep_are_stopped() {
+        let source = "def down(n: u8): u8 { return down(n) }\n\
+                      const bottom: u8 = down(1)";
+        assert_eq!(
+            errors(source),
+            vec![format!(
+                "Initializer of `bottom` is not constant: \
+                 Compile-time calls nest deeper than {}",
+                MAX_DEPTH
+            )]
+        );
+    }
+}
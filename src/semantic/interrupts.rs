@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+
+use crate::semantic::walk_statement;
+use crate::semantic::SemanticError;
+
+// The hardware vectors a handler can claim. RESET always points at
+// the startup code that calls `main`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Vector {
+    Nmi,
+    Irq,
+}
+
+impl Vector {
+    fn from_name(name: &str) -> Option<Vector> {
+        match name {
+            "nmi" => Some(Vector::Nmi),
+            "irq" => Some(Vector::Irq),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Vector::Nmi => {
+                write!(f, "NMI")
+            }
+            Vector::Irq => {
+                write!(f, "IRQ")
+            }
+        }
+    }
+}
+
+// The vector a function handles, given by `interrupt(vector)`
+pub fn interrupt_vector(decl: &FuncDecl) -> Option<Vector> {
+    let attribute = decl.attribute("interrupt")?;
+    Vector::from_name(attribute.args.first()?)
+}
+
+// Checks `interrupt` attributes: handlers take no parameters, are
+// never called directly, and each vector has at most one handler. The
+// vector table is only emitted along with the reset code for `main`.
+pub fn check_interrupts(program: &Program, errors: &mut Vec<SemanticError>) {
+    let mut handlers: HashMap<Vector, &FuncDecl> = HashMap::new();
+
+    for primary in program.primaries() {
+        let (decl, is_extern) = match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            } => (decl, false),
+            PrimaryStatement::Extern(decl) => (decl, true),
+            _ => continue,
+        };
+        let attribute = match decl.attribute("interrupt") {
+            Some(attribute) => attribute,
+            None => continue,
+        };
+
+        if is_extern {
+            let message = format!(
+                "External function `{}` cannot be an interrupt handler",
+                decl.name
+            );
+            errors.push(SemanticError::new(message, attribute.location));
+            continue;
+        }
+        if !decl.args.is_empty() {
+            let message = format!("Interrupt handler `{}` cannot take parameters", decl.name);
+            errors.push(SemanticError::new(message, decl.location));
+        }
+
+        let vector = match attribute
+            .args
+            .first()
+            .map(|name| (name, Vector::from_name(name)))
+        {
+            Some((_, Some(vector))) => vector,
+            Some((name, None)) => {
+                let message = format!(
+                    "Unknown interrupt vector `{}`, expected `nmi` or `irq`",
+                    name
+                );
+                errors.push(SemanticError::new(message, attribute.location));
+                continue;
+            }
+            None => continue,
+        };
+        if let Some(first) = handlers.get(&vector) {
+            let message = format!(
+                "`{}` claims the {} vector, which `{}` at {} already uses",
+                decl.name, vector, first.name, first.location
+            );
+            errors.push(SemanticError::new(message, attribute.location));
+        } else {
+            handlers.insert(vector, decl);
+        }
+    }
+
+    let has_main = program.primaries().iter().any(|primary| match primary {
+        PrimaryStatement::Definition {
+            decl,
+            inner_statement: _,
+        } => decl.name == "main" && decl.args.is_empty(),
+        _ => false,
+    });
+    if !has_main {
+        for handler in [Vector::Nmi, Vector::Irq]
+            .iter()
+            .filter_map(|vector| handlers.get(vector))
+        {
+            let message = format!(
+                "Interrupt handler `{}` is never installed, there is no `main` to emit the vectors with",
+                handler.name
+            );
+            errors.push(SemanticError::new(message, handler.location));
+        }
+    }
+
+    for primary in program.primaries() {
+        if let PrimaryStatement::Definition {
+            decl: _,
+            inner_statement,
+        } = primary
+        {
+            walk_statement(inner_statement, &mut |expr: &AstExprNode| {
+                if let AstExprNode::Terminal(Factor::Id {
+                    id,
+                    optional_call: Some(_),
+                    location,
+                }) = expr
+                {
+                    if handlers.values().any(|handler| &handler.name == id) {
+                        let message = format!(
+                            "Interrupt handler `{}` cannot be called, it returns with RTI",
+                            id
+                        );
+                        errors.push(SemanticError::new(message, *location));
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+
+    fn errors(source: &str) -> Vec<String> {
+        let program = parse_source(source);
+        let mut errors = Vec::new();
+        check_interrupts(&program, &mut errors);
+        errors.into_iter().map(|error| error.message).collect()
+    }
+
+    #[test]
+    fn handlers_are_installed_with_main() {
+        assert!(
+            errors("def tick interrupt(irq)() { return 0 }\ndef main() { return 0 }").is_empty()
+        );
+    }
+
+    #[test]
+    fn handlers_without_main_are_never_installed() {
+        assert_eq!(
+            errors(
+                "def tick interrupt(irq)() { return 0 }\n\
+                 def frame interrupt(nmi)() { return 0 }"
+            ),
+            vec![
+                "Interrupt handler `frame` is never installed, there is no `main` to emit the vectors with",
+                "Interrupt handler `tick` is never installed, there is no `main` to emit the vectors with",
+            ]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::lexer::MulOp;
+use crate::lexer::SumOp;
+
+use crate::parser::bin_op::BinOp;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::types::IntType;
+use crate::parser::types::Type;
+
+use crate::semantic::typeck::Signature;
+use crate::semantic::SemanticError;
+
+// A function the program may define to be called when checked
+// arithmetic overflows. It takes no arguments.
+pub const OVERFLOW_TRAP: &str = "overflowTrap";
+
+// What `+`, `-` and `*` do with a result that does not fit its type
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Overflow {
+    // Keep the low bits
+    Wrapping,
+    // Clamp to the smallest or largest value of the type
+    Saturating,
+    // Call the overflow trap, then keep the low bits if it returns
+    Checked,
+}
+
+impl Overflow {
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        match name {
+            "wrapping" => Some(Overflow::Wrapping),
+            "saturating" => Some(Overflow::Saturating),
+            "checked" => Some(Overflow::Checked),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+}
+
+impl ArithmeticOp {
+    pub fn bin_op(&self) -> BinOp {
+        match self {
+            ArithmeticOp::Add => BinOp::Sum(SumOp::ADD),
+            ArithmeticOp::Subtract => BinOp::Sum(SumOp::SUBTRACT),
+            ArithmeticOp::Multiply => BinOp::Mult(MulOp::MULTIPLY),
+        }
+    }
+}
+
+// The names of arithmetic intrinsics join a mode and an operator
+const ARITHMETIC_MODES: [(&str, Overflow); 3] = [
+    ("wrapping", Overflow::Wrapping),
+    ("saturating", Overflow::Saturating),
+    ("checked", Overflow::Checked),
+];
+const ARITHMETIC_OPS: [(&str, ArithmeticOp); 3] = [
+    ("Add", ArithmeticOp::Add),
+    ("Sub", ArithmeticOp::Subtract),
+    ("Mul", ArithmeticOp::Multiply),
+];
+
+// Functions built into the compiler and lowered inline. Their memory
+// accesses are volatile, like those of globals declared `volatile`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Intrinsic {
+    // `peek(address)` reads the byte at `address`
+    Peek,
+    // `poke(address, value)` writes `value` to `address` and returns it
+    Poke,
+    // `wrappingAdd(a, b)`, `saturatingMul(a, b)` and so on apply an
+    // operator with the given overflow behaviour, whatever the default
+    Arithmetic(ArithmeticOp, Overflow),
+}
+
+impl Intrinsic {
+    pub fn from_name(name: &str) -> Option<Intrinsic> {
+        match name {
+            "peek" => return Some(Intrinsic::Peek),
+            "poke" => return Some(Intrinsic::Poke),
+            _ => {}
+        }
+
+        for (prefix, overflow) in &ARITHMETIC_MODES {
+            for (suffix, op) in &ARITHMETIC_OPS {
+                if name.len() == prefix.len() + suffix.len()
+                    && name.starts_with(prefix)
+                    && name.ends_with(suffix)
+                {
+                    return Some(Intrinsic::Arithmetic(*op, *overflow));
+                }
+            }
+        }
+        None
+    }
+
+    // Every name `from_name` accepts
+    pub fn names() -> Vec<String> {
+        let mut result = vec![String::from("peek"), String::from("poke")];
+        for (prefix, _) in &ARITHMETIC_MODES {
+            for (suffix, _) in &ARITHMETIC_OPS {
+                result.push(format!("{}{}", prefix, suffix));
+            }
+        }
+        result
+    }
+
+    // None for intrinsics that are typed like the operator they apply
+    pub fn signature(&self) -> Option<Signature> {
+        let address = Type::Int(IntType::U16);
+        let byte = Type::Int(IntType::U8);
+        match self {
+            Intrinsic::Peek => Some(Signature {
+                args: vec![address],
+                return_type: byte,
+            }),
+            Intrinsic::Poke => Some(Signature {
+                args: vec![address, byte.clone()],
+                return_type: byte,
+            }),
+            Intrinsic::Arithmetic(_, _) => None,
+        }
+    }
+}
+
+// The overflow trap is called with nothing pushed for it
+pub fn check_overflow_trap(program: &Program, errors: &mut Vec<SemanticError>) {
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            }
+            | PrimaryStatement::Extern(decl)
+                if decl.name == OVERFLOW_TRAP && !decl.args.is_empty() =>
+            {
+                let message = format!(
+                    "`{}` is called on overflow without arguments, so it cannot take any",
+                    OVERFLOW_TRAP
+                );
+                errors.push(SemanticError::new(message, decl.location));
+            }
+            _ => {}
+        }
+    }
+}
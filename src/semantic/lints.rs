@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::lexer::SourceLocation;
+
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+
+use crate::semantic::SemanticError;
+
+// Kinds of problem that only stop compilation when denied
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedParameter,
+    UnusedFunction,
+    Shadowing,
+    ConstantCondition,
+    LossyLiteral,
+    UnreachableCode,
+    IncompleteMatch,
+    // Found when generating code, since the stacks are measured there
+    UnboundedRecursion,
+}
+
+const LINTS: [Lint; 8] = [
+    Lint::UnusedParameter,
+    Lint::UnusedFunction,
+    Lint::Shadowing,
+    Lint::ConstantCondition,
+    Lint::LossyLiteral,
+    Lint::UnreachableCode,
+    Lint::IncompleteMatch,
+    Lint::UnboundedRecursion,
+];
+
+impl Lint {
+    pub fn from_name(name: &str) -> Option<Lint> {
+        LINTS.iter().find(|lint| lint.name() == name).cloned()
+    }
+
+    // Written like an identifier, so that attributes can name it
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedParameter => "unusedParameter",
+            Lint::UnusedFunction => "unusedFunction",
+            Lint::Shadowing => "shadowing",
+            Lint::ConstantCondition => "constantCondition",
+            Lint::LossyLiteral => "lossyLiteral",
+            Lint::UnreachableCode => "unreachableCode",
+            Lint::IncompleteMatch => "incompleteMatch",
+            Lint::UnboundedRecursion => "unboundedRecursion",
+        }
+    }
+}
+
+// What is done with a lint when it is found
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    // `-A`, `-W` or `-D`, as given on the command line
+    pub fn from_flag(flag: &str) -> Option<Level> {
+        match flag {
+            "-A" => Some(Level::Allow),
+            "-W" => Some(Level::Warn),
+            "-D" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+
+    // Splits a flag like `-Dshadowing` into its level and lint name
+    pub fn parse_flag(arg: &str) -> Option<(Level, &str)> {
+        if arg.len() <= 2 {
+            return None;
+        }
+        let level = Level::from_flag(arg.get(..2)?)?;
+        Some((level, &arg[2..]))
+    }
+}
+
+// The level of every lint outside definitions that allow it
+pub struct LintLevels {
+    levels: HashMap<Lint, Level>,
+}
+
+impl LintLevels {
+    // Every lint warns until told otherwise
+    pub fn new() -> LintLevels {
+        LintLevels {
+            levels: LINTS.iter().map(|lint| (*lint, Level::Warn)).collect(),
+        }
+    }
+
+    // Sets a lint, or every lint for `all`. False if there is no such lint.
+    pub fn set(&mut self, name: &str, level: Level) -> bool {
+        if name == "all" {
+            for lint in LINTS.iter() {
+                self.levels.insert(*lint, level);
+            }
+            return true;
+        }
+        match Lint::from_name(name) {
+            Some(lint) => {
+                self.levels.insert(lint, level);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn level(&self, lint: Lint) -> Level {
+        self.levels[&lint]
+    }
+}
+
+#[derive(Debug)]
+pub struct Warning {
+    pub lint: Lint,
+    pub message: String,
+    pub location: SourceLocation,
+}
+
+impl Warning {
+    pub fn new(lint: Lint, message: String, location: SourceLocation) -> Warning {
+        Warning {
+            lint,
+            message,
+            location,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} [{}]",
+            self.location,
+            self.message,
+            self.lint.name()
+        )
+    }
+}
+
+// The lints a definition allows with `allow(lint, ...)`
+fn allowed(decl: &FuncDecl) -> Vec<Lint> {
+    match decl.attribute("allow") {
+        Some(attribute) => attribute
+            .args
+            .iter()
+            .filter_map(|name| Lint::from_name(name))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// Where each top level item starts, with the lints it allows. An item
+// runs until the next one starts.
+fn item_starts(program: &Program) -> Vec<(SourceLocation, Vec<Lint>)> {
+    program
+        .primaries()
+        .iter()
+        .map(|primary| match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            } => (decl.location, allowed(decl)),
+            PrimaryStatement::Extern(decl) => (decl.location, Vec::new()),
+            PrimaryStatement::Global(global) => (global.location, Vec::new()),
+            PrimaryStatement::Struct(structure) => (structure.location, Vec::new()),
+        })
+        .collect()
+}
+
+// Lints named in `allow` must exist
+pub fn check_lint_attributes(program: &Program, errors: &mut Vec<SemanticError>) {
+    for primary in program.primaries() {
+        if let PrimaryStatement::Definition {
+            decl,
+            inner_statement: _,
+        }
+        | PrimaryStatement::Extern(decl) = primary
+        {
+            if let Some(attribute) = decl.attribute("allow") {
+                for name in attribute
+                    .args
+                    .iter()
+                    .filter(|name| Lint::from_name(name).is_none())
+                {
+                    let message = format!("Unknown lint `{}` in `allow` on `{}`", name, decl.name);
+                    errors.push(SemanticError::new(message, attribute.location));
+                }
+            }
+        }
+    }
+}
+
+// Drops the warnings that are allowed, by the command line or by the
+// definition they are found in, and turns denied ones into errors
+pub fn apply_levels(
+    program: &Program,
+    levels: &LintLevels,
+    warnings: Vec<Warning>,
+    errors: &mut Vec<SemanticError>,
+) -> Vec<Warning> {
+    let starts = item_starts(program);
+    let mut result: Vec<Warning> = Vec::new();
+    for warning in warnings {
+        let position = (warning.location.line, warning.location.column);
+        let item = starts
+            .iter()
+            .rev()
+            .find(|(start, _)| (start.line, start.column) <= position);
+        if item.is_some_and(|(_, allowed)| allowed.contains(&warning.lint)) {
+            continue;
+        }
+        match levels.level(warning.lint) {
+            Level::Allow => {}
+            Level::Warn => {
+                result.push(warning);
+            }
+            Level::Deny => {
+                let message = format!("{} [{}]", warning.message, warning.lint.name());
+                errors.push(SemanticError::new(message, warning.location));
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+    use crate::semantic::check_program;
+    use crate::semantic::intrinsics::Overflow;
+
+    // Leaves `x` unused in both definitions, the first of which may
+    // allow that with `attribute`
+    fn unused(attribute: &str) -> String {
+        format!(
+            "def f {}(x: u8) {{ return 1 }}\n\
+             def g(x: u8) {{ return 2 }}\n\
+             def main() {{ return f(1) + g(2) }}",
+            attribute
+        )
+    }
+
+    // Messages of the unused parameter warnings, and of the errors, with `levels`
+    fn report(source: &str, levels: &LintLevels) -> (Vec<String>, Vec<String>) {
+        let program = parse_source(source);
+        match check_program(&program, Overflow::Wrapping, levels) {
+            Ok(analysis) => (
+                analysis
+                    .warnings
+                    .iter()
+                    .filter(|warning| warning.lint == Lint::UnusedParameter)
+                    .map(|warning| warning.message.clone())
+                    .collect(),
+                Vec::new(),
+            ),
+            Err(errors) => (
+                Vec::new(),
+                errors.iter().map(|error| error.message.clone()).collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn flags_give_a_level_and_a_lint() {
+        assert_eq!(
+            Level::parse_flag("-Ashadowing"),
+            Some((Level::Allow, "shadowing"))
+        );
+        assert_eq!(Level::parse_flag("-Wall"), Some((Level::Warn, "all")));
+        assert_eq!(
+            Level::parse_flag("-DunusedParameter"),
+            Some((Level::Deny, "unusedParameter"))
+        );
+        assert_eq!(Level::parse_flag("-D"), None);
+        assert_eq!(Level::parse_flag("-Xshadowing"), None);
+        assert_eq!(Level::parse_flag("file.kal"), None);
+    }
+
+    #[test]
+    fn levels_are_set_per_lint_or_for_all() {
+        let mut levels = LintLevels::new();
+        assert!(LINTS.iter().all(|lint| levels.level(*lint) == Level::Warn));
+        assert!(levels.set("shadowing", Level::Deny));
+        assert_eq!(levels.level(Lint::Shadowing), Level::Deny);
+        assert_eq!(levels.level(Lint::LossyLiteral), Level::Warn);
+        assert!(levels.set("all", Level::Allow));
+        assert!(LINTS.iter().all(|lint| levels.level(*lint) == Level::Allow));
+        assert!(!levels.set("noSuchLint", Level::Deny));
+    }
+
+    #[test]
+    fn each_level_changes_the_output() {
+        let source = unused("");
+        let mut levels = LintLevels::new();
+        let (warnings, errors) = report(&source, &levels);
+        assert_eq!(
+            warnings,
+            vec![
+                "Parameter `x` of `f` is never used",
+                "Parameter `x` of `g` is never used",
+            ]
+        );
+        assert!(errors.is_empty());
+
+        levels.set("unusedParameter", Level::Allow);
+        assert_eq!(report(&source, &levels), (Vec::new(), Vec::new()));
+
+        levels.set("unusedParameter", Level::Deny);
+        let (warnings, errors) = report(&source, &levels);
+        assert!(warnings.is_empty());
+        assert_eq!(
+            errors,
+            vec![
+                "Parameter `x` of `f` is never used [unusedParameter]",
+                "Parameter `x` of `g` is never used [unusedParameter]",
+            ]
+        );
+    }
+
+    #[test]
+    fn allow_only_covers_its_own_item() {
+        let (warnings, _) = report(&unused("allow(unusedParameter)"), &LintLevels::new());
+        assert_eq!(warnings, vec!["Parameter `x` of `g` is never used"]);
+
+        // Even a denied lint is allowed there
+        let mut levels = LintLevels::new();
+        levels.set("unusedParameter", Level::Deny);
+        let (_, errors) = report(&unused("allow(unusedParameter)"), &levels);
+        assert_eq!(
+            errors,
+            vec!["Parameter `x` of `g` is never used [unusedParameter]"]
+        );
+
+        // Allowing another lint changes nothing
+        let (warnings, _) = report(&unused("allow(shadowing)"), &LintLevels::new());
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn allowed_lints_must_exist() {
+        let program = parse_source("def f allow(shadowing, noSuchLint)() { return 0 }");
+        let mut errors = Vec::new();
+        check_lint_attributes(&program, &mut errors);
+        assert_eq!(
+            errors[0].message,
+            "Unknown lint `noSuchLint` in `allow` on `f`"
+        );
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashSet;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::Initializer;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::types::BcdType;
+use crate::parser::types::FixedType;
+use crate::parser::types::IntType;
+use crate::parser::types::Type;
+
+use crate::semantic::constant;
+use crate::semantic::interpret;
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::lints::Lint;
+use crate::semantic::lints::Warning;
+use crate::semantic::typeck::TypeTable;
+use crate::semantic::walk_expression;
+use crate::semantic::walk_statement;
+use crate::semantic::SemanticError;
+
+// The value a literal of type `ty` actually has once stored
+fn stored_value(value: f64, ty: &Type) -> f64 {
+    let bits = ty.encode(value);
+    match ty {
+        Type::Fixed(fixed_type) => fixed_type.decode(bits),
+        Type::Float => f32::from_bits(bits as u32) as f64,
+        Type::Bcd(bcd_type) => bcd_type.decode(bits) as f64,
+        _ => bits as f64,
+    }
+}
+
+// The smallest and largest values of a number type
+fn range(ty: &Type) -> Option<(f64, f64)> {
+    match ty {
+        Type::Int(int_type) => Some((int_type.min_value() as f64, int_type.max_value() as f64)),
+        Type::Fixed(fixed_type) => {
+            let limit = (1i64 << (8 * fixed_type.size() - 1)) as f64;
+            Some((
+                -limit / (1i64 << fixed_type.fraction_bits()) as f64,
+                fixed_type.decode(limit as i64 - 1),
+            ))
+        }
+        Type::Float => Some((-f32::MAX as f64, f32::MAX as f64)),
+        Type::Bcd(bcd_type) => Some((0.0, bcd_type.max_value() as f64)),
+        Type::Pointer {
+            pointee: _,
+            mutable: _,
+        } => Some((0.0, 65535.0)),
+        _ => None,
+    }
+}
+
+fn fits(value: f64, ty: &Type) -> bool {
+    match range(ty) {
+        Some((low, high)) => {
+            value >= low && value <= high && (ty.has_fraction() || value.fract() == 0.0)
+        }
+        None => false,
+    }
+}
+
+// Types of the same kind as `ty`, nearest first: narrowest, then of the
+// same signedness
+fn alternatives(ty: &Type) -> Vec<Type> {
+    match ty {
+        Type::Int(int_type) => {
+            let mut result = vec![IntType::U8, IntType::I8, IntType::U16, IntType::I16];
+            result.sort_by_key(|other| (other.size(), other.is_signed() != int_type.is_signed()));
+            result.into_iter().map(Type::Int).collect()
+        }
+        Type::Fixed(_) => vec![Type::Fixed(FixedType::F8x8), Type::Fixed(FixedType::F16x16)],
+        Type::Bcd(_) => vec![Type::Bcd(BcdType::Bcd8), Type::Bcd(BcdType::Bcd16)],
+        _ => Vec::new(),
+    }
+}
+
+// Where to point someone whose literal does not fit, if anywhere
+fn suggestion(value: f64, candidates: Vec<Type>) -> String {
+    match candidates
+        .into_iter()
+        .find(|candidate| fits(value, candidate))
+    {
+        Some(candidate) => format!("; the nearest type that holds it is `{}`", candidate),
+        None => String::new(),
+    }
+}
+
+fn check_literal(
+    value: f64,
+    ty: &Type,
+    expr: &AstExprNode,
+    errors: &mut Vec<SemanticError>,
+    warnings: &mut Vec<Warning>,
+) {
+    let (low, high) = match range(ty) {
+        Some(range) => range,
+        None => return,
+    };
+    if !ty.has_fraction() && value.fract() != 0.0 {
+        let candidates = vec![
+            Type::Fixed(FixedType::F8x8),
+            Type::Fixed(FixedType::F16x16),
+            Type::Float,
+        ];
+        let message = format!(
+            "`{}` is not a whole number, so it cannot be `{}`{}",
+            value,
+            ty,
+            suggestion(value, candidates)
+        );
+        errors.push(SemanticError::new(message, expr.location()));
+        return;
+    }
+    if value < low || value > high {
+        let candidates = alternatives(ty)
+            .into_iter()
+            .filter(|candidate| candidate != ty)
+            .collect();
+        let message = format!(
+            "`{}` is out of range for `{}`, which holds {} to {}{}",
+            value,
+            ty,
+            low,
+            high,
+            suggestion(value, candidates)
+        );
+        errors.push(SemanticError::new(message, expr.location()));
+        return;
+    }
+    // Nearly every decimal fraction rounds as a float, so only
+    // fixed-point rounding is worth a warning
+    let stored = stored_value(value, ty);
+    if ty.is_fixed() && stored != value {
+        let message = format!(
+            "`{}` cannot be represented exactly as `{}` and becomes {}",
+            value, ty, stored
+        );
+        warnings.push(Warning::new(Lint::LossyLiteral, message, expr.location()));
+    }
+}
+
+// Operators on literals are evaluated at compile time, so a result out
+// of range of its type would be silently brought into it. Division by
+// zero is left for the runtime.
+fn check_constant(value: f64, ty: &Type, expr: &AstExprNode, errors: &mut Vec<SemanticError>) {
+    if !value.is_finite() || interpret::fit(value, ty, Overflow::Checked).is_some() {
+        return;
+    }
+    if let Some((low, high)) = range(ty) {
+        let message = format!(
+            "Constant expression is {}, which is out of range for `{}`, which holds {} to {}",
+            value.trunc(),
+            ty,
+            low,
+            high
+        );
+        errors.push(SemanticError::new(message, expr.location()));
+    }
+}
+
+// Checks a literal, or the value of an operator whose operands are all
+// literals. The literals are checked first, and the value only if they
+// fit. `checked` holds what an enclosing expression has covered.
+fn check_expression(
+    expr: &AstExprNode,
+    types: &TypeTable,
+    checked: &mut HashSet<*const AstExprNode>,
+    errors: &mut Vec<SemanticError>,
+    warnings: &mut Vec<Warning>,
+) {
+    if checked.contains(&(expr as *const AstExprNode)) {
+        return;
+    }
+    match (expr, types.get(expr)) {
+        (AstExprNode::Terminal(Factor::Numeric { value, location: _ }), Some(ty)) => {
+            check_literal(*value, ty, expr, errors, warnings);
+        }
+        (
+            AstExprNode::Node {
+                left: _,
+                op_type: _,
+                next: _,
+                location: _,
+            },
+            Some(ty),
+        ) => {
+            let value = match constant::evaluate(expr) {
+                Some(value) => value,
+                None => return,
+            };
+            let before = errors.len();
+            walk_expression(expr, &mut |inner| {
+                checked.insert(inner as *const AstExprNode);
+                if let (AstExprNode::Terminal(Factor::Numeric { value, location: _ }), Some(ty)) =
+                    (inner, types.get(inner))
+                {
+                    check_literal(*value, ty, inner, errors, warnings);
+                }
+            });
+            if errors.len() == before {
+                check_constant(value, ty, expr, errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_initializer(
+    global: &GlobalDecl,
+    types: &TypeTable,
+    errors: &mut Vec<SemanticError>,
+    warnings: &mut Vec<Warning>,
+) {
+    let (ty, elements): (&Type, Vec<&AstExprNode>) = match (&global.ty, &global.initializer) {
+        (Type::Array { element, length: _ }, Some(Initializer::List(elements))) => {
+            (element, elements.iter().collect())
+        }
+        (ty, Some(Initializer::Scalar(value))) => (ty, vec![value.as_ref()]),
+        _ => return,
+    };
+    let mut checked = HashSet::new();
+    for element in elements {
+        // A literal on its own is stored as the declared type
+        if let AstExprNode::Terminal(Factor::Numeric { value, location: _ }) = element {
+            check_literal(*value, ty, element, errors, warnings);
+            continue;
+        }
+        walk_expression(element, &mut |expr| {
+            check_expression(expr, types, &mut checked, errors, warnings)
+        });
+    }
+}
+
+// Literals are written in decimal but stored as integers, fixed-point
+// numbers, floats or BCD. One that does not fit its type, or has a
+// fraction its type cannot hold, would be silently truncated, and one
+// that only rounds is warned about. So would the value of operators on
+// literals, such as `0 - 1`, which are checked the same way.
+pub fn check_literals(
+    program: &Program,
+    types: &TypeTable,
+    errors: &mut Vec<SemanticError>,
+    warnings: &mut Vec<Warning>,
+) {
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Global(global) => {
+                check_initializer(global, types, errors, warnings);
+            }
+            PrimaryStatement::Definition {
+                decl: _,
+                inner_statement,
+            } => {
+                let mut checked = HashSet::new();
+                walk_statement(inner_statement, &mut |expr| {
+                    check_expression(expr, types, &mut checked, errors, warnings)
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+    use crate::semantic::check_program;
+    use crate::semantic::lints::LintLevels;
+
+    // The errors, or else the warnings, for a program
+    fn report(source: &str) -> Vec<String> {
+        let program = parse_source(source);
+        match check_program(&program, Overflow::Wrapping, &LintLevels::new()) {
+            Ok(analysis) => analysis
+                .warnings
+                .into_iter()
+                .map(|warning| warning.message)
+                .collect(),
+            Err(errors) => errors.into_iter().map(|error| error.message).collect(),
+        }
+    }
+
+    fn local(ty: &str, value: &str) -> Vec<String> {
+        report(&format!(
+            "def f(): {} {{\nvar x: {} = {}\nreturn x\n}}",
+            ty, ty, value
+        ))
+    }
+
+    #[test]
+    fn range_covers_every_number_type() {
+        assert_eq!(range(&Type::Int(IntType::U8)), Some((0.0, 255.0)));
+        assert_eq!(range(&Type::Int(IntType::I16)), Some((-32768.0, 32767.0)));
+        assert_eq!(
+            range(&Type::Fixed(FixedType::F8x8)),
+            Some((-128.0, 127.99609375))
+        );
+        assert_eq!(range(&Type::Bcd(BcdType::Bcd8)), Some((0.0, 99.0)));
+        assert_eq!(
+            range(&Type::Array {
+                element: Box::new(Type::Int(IntType::U8)),
+                length: 2
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn fits_needs_a_fraction_only_types_with_one_hold() {
+        assert!(fits(255.0, &Type::Int(IntType::U8)));
+        assert!(!fits(256.0, &Type::Int(IntType::U8)));
+        assert!(!fits(-1.0, &Type::Int(IntType::U8)));
+        assert!(!fits(1.5, &Type::Int(IntType::I16)));
+        assert!(fits(1.5, &Type::Fixed(FixedType::F8x8)));
+    }
+
+    #[test]
+    fn alternatives_are_narrowest_first_then_same_signedness() {
+        assert_eq!(
+            alternatives(&Type::Int(IntType::I8)),
+            vec![
+                Type::Int(IntType::I8),
+                Type::Int(IntType::U8),
+                Type::Int(IntType::I16),
+                Type::Int(IntType::U16),
+            ]
+        );
+        assert!(alternatives(&Type::Float).is_empty());
+    }
+
+    #[test]
+    fn literal_too_large_for_its_type() {
+        assert_eq!(
+            local("u8", "300"),
+            vec!["`300` is out of range for `u8`, which holds 0 to 255; the nearest type that holds it is `u16`"]
+        );
+        assert_eq!(
+            local("i8", "200"),
+            vec!["`200` is out of range for `i8`, which holds -128 to 127; the nearest type that holds it is `u8`"]
+        );
+    }
+
+    #[test]
+    fn literal_with_a_fraction_its_type_cannot_hold() {
+        assert_eq!(
+            local("u8", "2.5"),
+            vec!["`2.5` is not a whole number, so it cannot be `u8`; the nearest type that holds it is `f8x8`"]
+        );
+    }
+
+    #[test]
+    fn literal_that_rounds_is_warned_about() {
+        assert_eq!(
+            local("f8x8", "0.3"),
+            vec!["`0.3` cannot be represented exactly as `f8x8` and becomes 0.30078125"]
+        );
+        assert!(local("f8x8", "0.25").is_empty());
+    }
+
+    #[test]
+    fn constant_expressions_must_fit_their_type() {
+        assert_eq!(
+            local("u16", "0 - 1"),
+            vec!["Constant expression is -1, which is out of range for `u16`, which holds 0 to 65535"]
+        );
+        assert_eq!(
+            report("const c: i8 = 100 + 100"),
+            vec!["Constant expression is 200, which is out of range for `i8`, which holds -128 to 127"]
+        );
+        assert!(local("u16", "200 * 2").is_empty());
+        assert!(local("u8", "7 / 2").is_empty());
+    }
+
+    #[test]
+    fn literals_out_of_range_are_reported_before_their_expression() {
+        assert_eq!(
+            local("u8", "300 - 100"),
+            vec!["`300` is out of range for `u8`, which holds 0 to 255; the nearest type that holds it is `u16`"]
+        );
+    }
+}
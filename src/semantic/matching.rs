@@ -0,0 +1,396 @@
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::matching::MatchPattern;
+use crate::parser::matching::MatchStatement;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::parser::Statement;
+use crate::parser::types::IntType;
+use crate::parser::types::Type;
+
+use crate::semantic::constant;
+use crate::semantic::lints::Lint;
+use crate::semantic::lints::Warning;
+use crate::semantic::typeck::TypeTable;
+use crate::semantic::SemanticError;
+
+// Gaps listed in a missing arms warning before the rest are elided
+const LISTED_GAPS: usize = 4;
+
+fn bound(expr: &AstExprNode) -> Result<i64, SemanticError> {
+    match constant::evaluate(expr) {
+        Some(value) if value.fract() == 0.0 => Ok(value as i64),
+        Some(value) => Err(SemanticError::new(
+            format!("Pattern {} is not an integer", value),
+            expr.location(),
+        )),
+        None => Err(SemanticError::new(
+            String::from("Patterns must be constant"),
+            expr.location(),
+        )),
+    }
+}
+
+// The first and last value a pattern matches
+pub fn pattern_bounds(pattern: &MatchPattern) -> Result<(i64, i64), SemanticError> {
+    match pattern {
+        MatchPattern::Value(value) => {
+            let value = bound(value)?;
+            Ok((value, value))
+        }
+        MatchPattern::Range {
+            low,
+            high,
+            inclusive,
+        } => {
+            let first = bound(low)?;
+            let last = if *inclusive {
+                bound(high)?
+            } else {
+                bound(high)? - 1
+            };
+            if last < first {
+                let message = format!(
+                    "Range {}..{}{} matches nothing",
+                    first,
+                    if *inclusive { "=" } else { "" },
+                    bound(high)?
+                );
+                return Err(SemanticError::new(message, pattern.location()));
+            }
+            Ok((first, last))
+        }
+    }
+}
+
+fn format_range(first: i64, last: i64) -> String {
+    if first == last {
+        format!("{}", first)
+    } else {
+        format!("{}..={}", first, last)
+    }
+}
+
+// The parts of `first..=last` that no interval in `covered` contains.
+// `covered` is sorted and does not overlap.
+fn uncovered(covered: &Vec<(i64, i64, usize)>, first: i64, last: i64) -> Vec<(i64, i64)> {
+    let mut result: Vec<(i64, i64)> = Vec::new();
+    let mut next = first;
+    for (low, high, _) in covered {
+        if *high < next || *low > last {
+            continue;
+        }
+        if *low > next {
+            result.push((next, *low - 1));
+        }
+        next = *high + 1;
+        if next > last {
+            return result;
+        }
+    }
+    result.push((next, last));
+    result
+}
+
+// Which arm handles each value, as sorted intervals of values with the
+// index of their arm. Values in no interval go to the `else` arm. An
+// earlier arm wins where patterns overlap, and patterns that cannot be
+// evaluated are left out, since they have been reported already.
+pub fn arm_intervals(matching: &MatchStatement, int_type: IntType) -> Vec<(i64, i64, usize)> {
+    let mut covered: Vec<(i64, i64, usize)> = Vec::new();
+    for (index, arm) in matching.arms.iter().enumerate() {
+        for pattern in &arm.patterns {
+            let (first, last) = match pattern_bounds(pattern) {
+                Ok(bounds) => bounds,
+                Err(_) => continue,
+            };
+            let first = first.max(int_type.min_value());
+            let last = last.min(int_type.max_value());
+            for (low, high) in uncovered(&covered, first, last) {
+                covered.push((low, high, index));
+            }
+            covered.sort();
+        }
+    }
+
+    // Neighbours that go to the same arm become one interval
+    let mut result: Vec<(i64, i64, usize)> = Vec::new();
+    for (low, high, index) in covered {
+        match result.last_mut() {
+            Some(previous) if previous.1 + 1 == low && previous.2 == index => {
+                previous.1 = high;
+            }
+            _ => {
+                result.push((low, high, index));
+            }
+        }
+    }
+    result
+}
+
+// Whether every value goes to some arm, by its patterns or by `else`
+pub fn is_exhaustive(matching: &MatchStatement, types: &TypeTable) -> bool {
+    if matching.default.is_some() {
+        return true;
+    }
+    let int_type = match types.get(&matching.value) {
+        Some(Type::Int(int_type)) => *int_type,
+        Some(Type::Pointer {
+            pointee: _,
+            mutable: _,
+        }) => IntType::U16,
+        _ => return false,
+    };
+    uncovered(
+        &arm_intervals(matching, int_type),
+        int_type.min_value(),
+        int_type.max_value(),
+    )
+    .is_empty()
+}
+
+fn check_match(
+    matching: &MatchStatement,
+    types: &TypeTable,
+    errors: &mut Vec<SemanticError>,
+    warnings: &mut Vec<Warning>,
+) {
+    let int_type = match types.get(&matching.value) {
+        Some(Type::Int(int_type)) => *int_type,
+        Some(Type::Pointer {
+            pointee: _,
+            mutable: _,
+        }) => IntType::U16,
+        Some(ty) => {
+            let message = format!("Cannot match on `{}`, only on integers", ty);
+            errors.push(SemanticError::new(message, matching.value.location()));
+            return;
+        }
+        None => return,
+    };
+
+    let mut covered: Vec<(i64, i64, usize)> = Vec::new();
+    for (index, arm) in matching.arms.iter().enumerate() {
+        for pattern in &arm.patterns {
+            let (first, last) = match pattern_bounds(pattern) {
+                Ok(bounds) => bounds,
+                Err(error) => {
+                    errors.push(error);
+                    continue;
+                }
+            };
+            if first < int_type.min_value() || last > int_type.max_value() {
+                let message = format!(
+                    "Pattern {} is out of range for `{}`",
+                    format_range(first, last),
+                    int_type
+                );
+                errors.push(SemanticError::new(message, pattern.location()));
+                continue;
+            }
+
+            let gaps = uncovered(&covered, first, last);
+            if gaps.is_empty() {
+                let message = format!(
+                    "Pattern {} is already matched by an earlier arm",
+                    format_range(first, last)
+                );
+                warnings.push(Warning::new(
+                    Lint::UnreachableCode,
+                    message,
+                    pattern.location(),
+                ));
+            }
+            for (low, high) in gaps {
+                covered.push((low, high, index));
+            }
+            covered.sort();
+        }
+    }
+
+    let missing = uncovered(&covered, int_type.min_value(), int_type.max_value());
+    match &matching.default {
+        Some(default) if missing.is_empty() => {
+            let message = format!(
+                "`else` arm is never reached, since every `{}` is matched",
+                int_type
+            );
+            warnings.push(Warning::new(
+                Lint::UnreachableCode,
+                message,
+                default.location,
+            ));
+        }
+        None if !missing.is_empty() => {
+            let mut listed: Vec<String> = missing
+                .iter()
+                .take(LISTED_GAPS)
+                .map(|(low, high)| format_range(*low, *high))
+                .collect();
+            if missing.len() > LISTED_GAPS {
+                listed.push(String::from("..."));
+            }
+            let message = format!(
+                "`match` on `{}` does not cover {}; add an `else` arm",
+                int_type,
+                listed.join(", ")
+            );
+            warnings.push(Warning::new(
+                Lint::IncompleteMatch,
+                message,
+                matching.location,
+            ));
+        }
+        _ => {}
+    }
+}
+
+fn check_statement(
+    statement: &Statement,
+    types: &TypeTable,
+    errors: &mut Vec<SemanticError>,
+    warnings: &mut Vec<Warning>,
+) {
+    match statement {
+        Statement::Select {
+            condition: _,
+            statement,
+            else_clause,
+        } => {
+            check_statement(statement, types, errors, warnings);
+            if let Some(clause) = else_clause {
+                check_statement(clause, types, errors, warnings);
+            }
+        }
+        Statement::Block(statements) => {
+            for statement in statements {
+                check_statement(statement, types, errors, warnings);
+            }
+        }
+        Statement::Match(matching) => {
+            check_match(matching, types, errors, warnings);
+            for statement in matching.statements() {
+                check_statement(statement, types, errors, warnings);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Patterns must be integer constants of the matched type. Arms that can
+// never run and values no arm handles are only warned about.
+pub fn check_matches(
+    program: &Program,
+    types: &TypeTable,
+    errors: &mut Vec<SemanticError>,
+    warnings: &mut Vec<Warning>,
+) {
+    for primary in program.primaries() {
+        if let PrimaryStatement::Definition {
+            decl: _,
+            inner_statement,
+        } = primary
+        {
+            check_statement(inner_statement, types, errors, warnings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+    use crate::semantic::check_source;
+    use crate::semantic::intrinsics::Overflow;
+
+    #[test]
+    fn uncovered_finds_the_gaps_between_intervals() {
+        let covered = vec![(2, 3, 0), (6, 6, 1)];
+        assert_eq!(uncovered(&covered, 0, 9), vec![(0, 1), (4, 5), (7, 9)]);
+        assert_eq!(uncovered(&covered, 2, 3), vec![]);
+        assert_eq!(uncovered(&covered, 3, 6), vec![(4, 5)]);
+        assert_eq!(uncovered(&Vec::new(), 0, 255), vec![(0, 255)]);
+    }
+
+    fn first_match(program: &Program) -> &MatchStatement {
+        for primary in program.primaries() {
+            if let PrimaryStatement::Definition {
+                decl: _,
+                inner_statement,
+            } = primary
+            {
+                if let Statement::Block(statements) = inner_statement.as_ref() {
+                    for statement in statements {
+                        if let Statement::Match(matching) = statement {
+                            return matching;
+                        }
+                    }
+                }
+            }
+        }
+        panic!("No match in the program")
+    }
+
+    #[test]
+    fn earlier_arms_win_and_neighbours_merge() {
+        let program = parse_source(
+            "def f(x: u8): u8 {\n\
+             match (x) {\n\
+             3, 4: return 1\n\
+             4..=9, 10..12: return 2\n\
+             250..=300: return 3\n\
+             else: return 0\n\
+             }\n\
+             }",
+        );
+        assert_eq!(
+            arm_intervals(first_match(&program), IntType::U8),
+            vec![(3, 4, 0), (5, 11, 1), (250, 255, 2)]
+        );
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        let (_, analysis) = check_source(source, Overflow::Wrapping);
+        analysis
+            .warnings
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect()
+    }
+
+    #[test]
+    fn matches_without_else_must_cover_every_value() {
+        let source = "def f(x: u8): u8 {\n\
+                      match (x) {\n\
+                      0..100: return 1\n\
+                      101..=255: return 2\n\
+                      }\n\
+                      return 0\n\
+                      }";
+        assert_eq!(
+            warnings(source),
+            vec!["`match` on `u8` does not cover 100; add an `else` arm"]
+        );
+        let source = "def f(x: u8): u8 {\n\
+                      match (x) {\n\
+                      0..=127: return 1\n\
+                      128..=255: return 2\n\
+                      }\n\
+                      }";
+        assert!(warnings(source).is_empty());
+    }
+
+    #[test]
+    fn patterns_matched_earlier_are_unreachable() {
+        let source = "def f(x: u8): u8 {\n\
+                      match (x) {\n\
+                      0..=9: return 1\n\
+                      5: return 2\n\
+                      else: return 0\n\
+                      }\n\
+                      }";
+        assert_eq!(
+            warnings(source),
+            vec!["Pattern 5 is already matched by an earlier arm"]
+        );
+    }
+}
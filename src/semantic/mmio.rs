@@ -0,0 +1,69 @@
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+
+use crate::semantic::constant;
+use crate::semantic::SemanticError;
+
+// Size of the 6502 address space
+const MEMORY_SIZE: f64 = 65536.0;
+
+// The fixed address of a global, once `check_addresses` has passed
+pub fn address(global: &GlobalDecl) -> Option<u16> {
+    let address = global.address.as_ref()?;
+    Some(constant::evaluate(address).unwrap() as u16)
+}
+
+fn check_global(global: &GlobalDecl, errors: &mut Vec<SemanticError>) {
+    if global.is_const && global.is_volatile {
+        let message = format!("Constant `{}` cannot be volatile", global.name);
+        errors.push(SemanticError::new(message, global.location));
+    }
+
+    let address = match &global.address {
+        Some(address) => address,
+        None => return,
+    };
+    if global.is_const {
+        let message = format!(
+            "Constant `{}` cannot be placed at an address, only variables can",
+            global.name
+        );
+        errors.push(SemanticError::new(message, global.location));
+        return;
+    }
+
+    match constant::evaluate(address) {
+        None => {
+            let message = format!("Address of `{}` must be a constant", global.name);
+            errors.push(SemanticError::new(message, address.location()));
+        }
+        Some(value) if value.fract() != 0.0 || value < 0.0 => {
+            let message = format!(
+                "Address of `{}` must be a whole number, not {}",
+                global.name, value
+            );
+            errors.push(SemanticError::new(message, address.location()));
+        }
+        Some(value) if value + global.ty.size() as f64 > MEMORY_SIZE => {
+            let message = format!(
+                "`{}` at ${:X} takes {} bytes and runs past the end of memory",
+                global.name,
+                value as u64,
+                global.ty.size()
+            );
+            errors.push(SemanticError::new(message, address.location()));
+        }
+        Some(_) => {}
+    }
+}
+
+// Checks globals declared at fixed addresses, such as memory mapped
+// hardware registers
+pub fn check_addresses(program: &Program, errors: &mut Vec<SemanticError>) {
+    for primary in program.primaries() {
+        if let PrimaryStatement::Global(global) = primary {
+            check_global(global, errors);
+        }
+    }
+}
@@ -0,0 +1,280 @@
+pub mod arrays;
+pub mod attributes;
+pub mod calls;
+pub mod constant;
+pub mod declarations;
+pub mod effects;
+pub mod externs;
+pub mod flow;
+pub mod infer;
+pub mod interpret;
+pub mod interrupts;
+pub mod intrinsics;
+pub mod lints;
+pub mod literals;
+pub mod matching;
+pub mod mmio;
+pub mod names;
+pub mod structs;
+pub mod typeck;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::lexer::SourceLocation;
+
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::Factor;
+use crate::parser::matching::MatchPattern;
+use crate::parser::parser::Program;
+use crate::parser::parser::Statement;
+
+use crate::semantic::calls::CallGraph;
+use crate::semantic::effects::Effects;
+use crate::semantic::intrinsics::Overflow;
+use crate::semantic::lints::LintLevels;
+use crate::semantic::lints::Warning;
+use crate::semantic::names::ResolutionTable;
+use crate::semantic::typeck::TypeTable;
+
+#[derive(Debug)]
+pub struct SemanticError {
+    pub message: String,
+    pub location: SourceLocation,
+}
+
+impl SemanticError {
+    pub fn new(message: String, location: SourceLocation) -> SemanticError {
+        SemanticError { message, location }
+    }
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+impl Error for SemanticError {}
+
+// Everything the semantic passes learned about a program that
+// later stages need
+pub struct Analysis {
+    pub types: TypeTable,
+    pub names: ResolutionTable,
+    pub calls: CallGraph,
+    pub effects: Effects,
+    // Values of each global's initializer, computed at compile time
+    pub initializers: HashMap<String, Vec<f64>>,
+    // Problems that do not stop compilation, by the lint that found them
+    pub warnings: Vec<Warning>,
+}
+
+// Runs every semantic check over the program, collecting all errors
+// rather than stopping at the first one. `overflow` is what operators
+// do when initializers are evaluated, as in the generated code, and
+// `lints` says which warnings to report and which are errors.
+pub fn check_program(
+    program: &Program,
+    overflow: Overflow,
+    lints: &LintLevels,
+) -> Result<Analysis, Vec<SemanticError>> {
+    let mut errors: Vec<SemanticError> = Vec::new();
+    let mut warnings: Vec<Warning> = Vec::new();
+
+    structs::check_structs(program, &mut errors);
+    mmio::check_addresses(program, &mut errors);
+    attributes::check_attributes(program, &mut errors);
+    lints::check_lint_attributes(program, &mut errors);
+    interrupts::check_interrupts(program, &mut errors);
+    externs::check_externs(program, &mut errors);
+    intrinsics::check_overflow_trap(program, &mut errors);
+    declarations::check_declarations(program, &mut errors);
+    let names = names::check_names(program, &mut errors, &mut warnings);
+    let types = typeck::check_types(program, &mut errors);
+    arrays::check_arrays(program, &types, overflow, &mut errors);
+    let effects = effects::analyze_effects(program, &names, &types, overflow, &mut errors);
+    // Evaluating needs every call and operator to be well typed
+    let initializers = if errors.is_empty() {
+        interpret::evaluate_initializers(program, &types, overflow, &mut errors)
+    } else {
+        HashMap::new()
+    };
+    matching::check_matches(program, &types, &mut errors, &mut warnings);
+    flow::check_flow(
+        program,
+        &names,
+        &types,
+        &initializers,
+        &mut errors,
+        &mut warnings,
+    );
+    literals::check_literals(program, &types, &mut errors, &mut warnings);
+    let mut warnings = lints::apply_levels(program, lints, warnings, &mut errors);
+
+    errors.sort_by_key(|error| (error.location.line, error.location.column));
+    warnings.sort_by_key(|warning| (warning.location.line, warning.location.column));
+    if errors.is_empty() {
+        Ok(Analysis {
+            types,
+            names,
+            calls: CallGraph::build(program),
+            effects,
+            initializers,
+            warnings,
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+pub fn check_source(source: &str, overflow: Overflow) -> (Box<Program>, Analysis) {
+    let program = crate::parser::parser::parse_source(source);
+    match check_program(&program, overflow, &LintLevels::new()) {
+        Ok(analysis) => (program, analysis),
+        Err(errors) => panic!("{}", errors[0]),
+    }
+}
+
+// Calls `visitor` on every expression node reachable from `expr`,
+// parents before children
+pub fn walk_expression<'a, F>(expr: &'a AstExprNode, visitor: &mut F)
+where
+    F: FnMut(&'a AstExprNode),
+{
+    visitor(expr);
+    match expr {
+        AstExprNode::Node {
+            left,
+            op_type: _,
+            next,
+            location: _,
+        } => {
+            walk_expression(left, visitor);
+            walk_expression(next, visitor);
+        }
+        AstExprNode::SubNode(sub_node) => {
+            walk_expression(sub_node, visitor);
+        }
+        AstExprNode::Cast {
+            value,
+            ty: _,
+            location: _,
+        } => {
+            walk_expression(value, visitor);
+        }
+        AstExprNode::AddressOf {
+            target,
+            location: _,
+        } => {
+            walk_expression(target, visitor);
+        }
+        AstExprNode::Deref {
+            pointer,
+            location: _,
+        } => {
+            walk_expression(pointer, visitor);
+        }
+        AstExprNode::Field {
+            base,
+            field: _,
+            location: _,
+        } => {
+            walk_expression(base, visitor);
+        }
+        AstExprNode::Terminal(Factor::Id {
+            id: _,
+            optional_call: Some(args),
+            location: _,
+        }) => {
+            for arg in args {
+                walk_expression(arg, visitor);
+            }
+        }
+        AstExprNode::Terminal(Factor::Index {
+            id: _,
+            index,
+            location: _,
+        }) => {
+            walk_expression(index, visitor);
+        }
+        AstExprNode::Terminal(_) => {}
+    }
+}
+
+// Calls `visitor` on every expression node inside `statement`
+pub fn walk_statement<'a, F>(statement: &'a Statement, visitor: &mut F)
+where
+    F: FnMut(&'a AstExprNode),
+{
+    match statement {
+        Statement::Select {
+            condition,
+            statement,
+            else_clause,
+        } => {
+            walk_expression(condition, visitor);
+            walk_statement(statement, visitor);
+            if let Some(clause) = else_clause {
+                walk_statement(clause, visitor);
+            }
+        }
+        Statement::ReturnExpr(expr) => {
+            walk_expression(expr, visitor);
+        }
+        Statement::Block(statements) => {
+            for statement in statements {
+                walk_statement(statement, visitor);
+            }
+        }
+        Statement::Local {
+            name: _,
+            ty: _,
+            value,
+            location: _,
+        } => {
+            walk_expression(value, visitor);
+        }
+        Statement::Assign {
+            target,
+            value,
+            location: _,
+        } => {
+            walk_expression(target, visitor);
+            walk_expression(value, visitor);
+        }
+        Statement::Expression(expr) => {
+            walk_expression(expr, visitor);
+        }
+        Statement::Asm(block) => {
+            for operand in &block.operands {
+                walk_expression(&operand.value, visitor);
+            }
+        }
+        Statement::Match(matching) => {
+            walk_expression(&matching.value, visitor);
+            for arm in &matching.arms {
+                for pattern in &arm.patterns {
+                    match pattern {
+                        MatchPattern::Value(value) => {
+                            walk_expression(value, visitor);
+                        }
+                        MatchPattern::Range {
+                            low,
+                            high,
+                            inclusive: _,
+                        } => {
+                            walk_expression(low, visitor);
+                            walk_expression(high, visitor);
+                        }
+                    }
+                }
+            }
+            for statement in matching.statements() {
+                walk_statement(statement, visitor);
+            }
+        }
+    }
+}
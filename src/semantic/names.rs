@@ -0,0 +1,691 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::lexer::SourceLocation;
+
+use crate::parser::asm::AsmBlock;
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::FuncArg;
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::Initializer;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::parser::Statement;
+
+use crate::semantic::interrupts;
+use crate::semantic::intrinsics::Intrinsic;
+use crate::semantic::intrinsics::OVERFLOW_TRAP;
+use crate::semantic::lints::Lint;
+use crate::semantic::lints::Warning;
+use crate::semantic::SemanticError;
+
+// What a name refers to
+#[derive(Copy, Clone)]
+enum Symbol<'a> {
+    Function(&'a FuncDecl),
+    Global(&'a GlobalDecl),
+    Parameter(&'a FuncArg),
+    Local(SourceLocation),
+}
+
+impl<'a> Symbol<'a> {
+    fn resolution(&self) -> Resolution {
+        match self {
+            Symbol::Function(decl) => Resolution::Function(decl.name.clone()),
+            Symbol::Global(global) => Resolution::Global(global.name.clone()),
+            Symbol::Parameter(arg) => Resolution::Parameter(arg.name.clone()),
+            Symbol::Local(location) => Resolution::Local(*location),
+        }
+    }
+
+    fn is_function(&self) -> bool {
+        matches!(self, Symbol::Function(_))
+    }
+
+    fn is_global(&self) -> bool {
+        matches!(self, Symbol::Global(_))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Symbol::Function(decl) => format!("function declared at {}", decl.location),
+            Symbol::Global(global) => format!("global declared at {}", global.location),
+            Symbol::Parameter(arg) => format!("parameter declared at {}", arg.location),
+            Symbol::Local(location) => format!("local declared at {}", location),
+        }
+    }
+}
+
+// What a name refers to where it is used. Locals are told apart by
+// where they are declared.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    Function(String),
+    Global(String),
+    Parameter(String),
+    Local(SourceLocation),
+}
+
+// The resolution of every name used as a value or called, by the
+// expression it appears in. Intrinsics are not declared, so calls to
+// them are not found here.
+pub struct ResolutionTable {
+    names: HashMap<*const AstExprNode, Resolution>,
+}
+
+impl ResolutionTable {
+    pub fn new() -> ResolutionTable {
+        ResolutionTable {
+            names: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, expr: &AstExprNode) -> Option<&Resolution> {
+        self.names.get(&(expr as *const AstExprNode))
+    }
+
+    // The global a name or indexing expression refers to, if it is
+    // not a parameter, local or function
+    pub fn global(&self, expr: &AstExprNode) -> Option<&str> {
+        match self.get(expr) {
+            Some(Resolution::Global(name)) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+struct Resolver<'a, 'b> {
+    // Everything declared at the top level, collected before any body
+    // is resolved so that functions may be used before their definition
+    symbols: HashMap<&'a str, Symbol<'a>>,
+    scopes: Vec<Vec<(&'a str, Symbol<'a>)>>,
+    // The function being resolved, whose calls to itself do not count
+    // as uses
+    current: Option<&'a str>,
+    used_functions: HashSet<&'a str>,
+    used_parameters: HashSet<&'a str>,
+    table: ResolutionTable,
+    errors: &'b mut Vec<SemanticError>,
+    warnings: &'b mut Vec<Warning>,
+}
+
+impl<'a, 'b> Resolver<'a, 'b> {
+    // Parameters and locals hide top level names of their own
+    fn lookup(&self, name: &str) -> Option<Symbol<'a>> {
+        for scope in self.scopes.iter().rev() {
+            if let Some((_, symbol)) = scope.iter().rev().find(|(other, _)| *other == name) {
+                return Some(*symbol);
+            }
+        }
+        self.symbols.get(name).cloned()
+    }
+
+    fn declare(&mut self, name: &'a str, symbol: Symbol<'a>, location: SourceLocation) {
+        if let Some(shadowed) = self.lookup(name) {
+            let message = format!("`{}` shadows the {}", name, shadowed.describe());
+            self.warnings
+                .push(Warning::new(Lint::Shadowing, message, location));
+        }
+        self.scopes.last_mut().unwrap().push((name, symbol));
+    }
+
+    fn note_use(&mut self, symbol: Symbol<'a>) {
+        match symbol {
+            Symbol::Function(decl) if self.current != Some(decl.name.as_str()) => {
+                self.used_functions.insert(&decl.name);
+            }
+            Symbol::Parameter(arg) => {
+                self.used_parameters.insert(&arg.name);
+            }
+            _ => {}
+        }
+    }
+
+    // Names that a misspelled value could have meant: variables in
+    // scope, then globals
+    fn values(&self) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        for scope in self.scopes.iter().rev() {
+            result.extend(scope.iter().rev().map(|(name, _)| name.to_string()));
+        }
+        let mut globals: Vec<String> = self
+            .symbols
+            .iter()
+            .filter(|(_, symbol)| symbol.is_global())
+            .map(|(name, _)| name.to_string())
+            .collect();
+        globals.sort();
+        result.extend(globals);
+        result
+    }
+
+    fn functions(&self) -> Vec<String> {
+        let mut result: Vec<String> = self
+            .symbols
+            .iter()
+            .filter(|(_, symbol)| symbol.is_function())
+            .map(|(name, _)| name.to_string())
+            .collect();
+        result.sort();
+        result.extend(Intrinsic::names());
+        result
+    }
+
+    fn unknown(
+        &mut self,
+        message: String,
+        name: &str,
+        candidates: Vec<String>,
+        location: SourceLocation,
+    ) {
+        let message = match suggest(name, &candidates) {
+            Some(suggestion) => format!("{}, did you mean `{}`?", message, suggestion),
+            None => message,
+        };
+        self.errors.push(SemanticError::new(message, location));
+    }
+
+    fn record(&mut self, expr: &AstExprNode, symbol: Symbol<'a>) {
+        self.table
+            .names
+            .insert(expr as *const AstExprNode, symbol.resolution());
+    }
+
+    // A name used as a value. Functions are found here so that using
+    // one without calling it is reported by the type checker.
+    fn resolve_value(&mut self, name: &str, location: SourceLocation) -> Option<Symbol<'a>> {
+        if let Some(symbol) = self.lookup(name) {
+            self.note_use(symbol);
+            return Some(symbol);
+        }
+        let candidates = self.values();
+        self.unknown(
+            format!("Cannot find `{}` in this scope", name),
+            name,
+            candidates,
+            location,
+        );
+        None
+    }
+
+    // Calls must name a function and pass one argument for each of its
+    // parameters. The arguments of intrinsics are checked with their types.
+    fn resolve_call(
+        &mut self,
+        name: &str,
+        args: usize,
+        location: SourceLocation,
+    ) -> Option<Symbol<'a>> {
+        let symbol = self.lookup(name);
+        match symbol {
+            Some(symbol @ Symbol::Function(decl)) => {
+                self.note_use(symbol);
+                if args != decl.args.len() {
+                    let message = format!(
+                        "`{}` takes {} arguments but {} were given",
+                        name,
+                        decl.args.len(),
+                        args
+                    );
+                    self.errors.push(SemanticError::new(message, location));
+                }
+            }
+            Some(Symbol::Global(_)) => {
+                let message = format!(
+                    "Cannot call `{}`, which is a global and not a function",
+                    name
+                );
+                self.errors.push(SemanticError::new(message, location));
+            }
+            Some(Symbol::Parameter(_)) => {
+                let message = format!(
+                    "Cannot call `{}`, which is a parameter and not a function",
+                    name
+                );
+                self.errors.push(SemanticError::new(message, location));
+            }
+            Some(Symbol::Local(_)) => {
+                let message = format!(
+                    "Cannot call `{}`, which is a local and not a function",
+                    name
+                );
+                self.errors.push(SemanticError::new(message, location));
+            }
+            None if Intrinsic::from_name(name).is_some() => {}
+            None => {
+                let candidates = self.functions();
+                self.unknown(
+                    format!("Cannot find function `{}`", name),
+                    name,
+                    candidates,
+                    location,
+                );
+            }
+        }
+        symbol
+    }
+
+    fn resolve_expression(&mut self, expr: &'a AstExprNode) {
+        match expr {
+            AstExprNode::Node {
+                left,
+                op_type: _,
+                next,
+                location: _,
+            } => {
+                self.resolve_expression(left);
+                self.resolve_expression(next);
+            }
+            AstExprNode::SubNode(sub_node) => {
+                self.resolve_expression(sub_node);
+            }
+            AstExprNode::Cast {
+                value,
+                ty: _,
+                location: _,
+            } => {
+                self.resolve_expression(value);
+            }
+            AstExprNode::AddressOf {
+                target,
+                location: _,
+            } => {
+                self.resolve_expression(target);
+            }
+            AstExprNode::Deref {
+                pointer,
+                location: _,
+            } => {
+                self.resolve_expression(pointer);
+            }
+            AstExprNode::Field {
+                base,
+                field: _,
+                location: _,
+            } => {
+                self.resolve_expression(base);
+            }
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location,
+            }) => {
+                if let Some(symbol) = self.resolve_value(id, *location) {
+                    self.record(expr, symbol);
+                }
+            }
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: Some(args),
+                location,
+            }) => {
+                if let Some(symbol) = self.resolve_call(id, args.len(), *location) {
+                    self.record(expr, symbol);
+                }
+                for arg in args {
+                    self.resolve_expression(arg);
+                }
+            }
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location,
+            }) => {
+                if let Some(symbol) = self.resolve_value(id, *location) {
+                    self.record(expr, symbol);
+                }
+                self.resolve_expression(index);
+            }
+            AstExprNode::Terminal(Factor::Numeric {
+                value: _,
+                location: _,
+            }) => {}
+        }
+    }
+
+    // Resolves a statement that gets a scope of its own, like the body of an `if`
+    fn resolve_scoped(&mut self, statement: &'a Statement) {
+        self.scopes.push(Vec::new());
+        self.resolve_statement(statement);
+        self.scopes.pop();
+    }
+
+    fn resolve_statement(&mut self, statement: &'a Statement) {
+        match statement {
+            Statement::Select {
+                condition,
+                statement,
+                else_clause,
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_scoped(statement);
+                if let Some(clause) = else_clause {
+                    self.resolve_scoped(clause);
+                }
+            }
+            Statement::ReturnExpr(expr) => {
+                self.resolve_expression(expr);
+            }
+            Statement::Block(statements) => {
+                self.scopes.push(Vec::new());
+                for statement in statements {
+                    self.resolve_statement(statement);
+                }
+                self.scopes.pop();
+            }
+            Statement::Local {
+                name,
+                ty: _,
+                value,
+                location,
+            } => {
+                // The initializer cannot see the local it declares
+                self.resolve_expression(value);
+                self.declare(name, Symbol::Local(*location), *location);
+            }
+            Statement::Assign {
+                target,
+                value,
+                location: _,
+            } => {
+                self.resolve_expression(target);
+                self.resolve_expression(value);
+            }
+            Statement::Expression(expr) => {
+                self.resolve_expression(expr);
+            }
+            Statement::Asm(block) => {
+                self.resolve_asm(block);
+            }
+            Statement::Match(matching) => {
+                // Patterns are constants, which name nothing
+                self.resolve_expression(&matching.value);
+                for statement in matching.statements() {
+                    self.resolve_scoped(statement);
+                }
+            }
+        }
+    }
+
+    // The text may also name globals and functions, by their symbol
+    fn resolve_asm(&mut self, block: &'a AsmBlock) {
+        for operand in &block.operands {
+            self.resolve_expression(&operand.value);
+        }
+        for name in block.placeholders() {
+            if block.operands.iter().any(|operand| operand.name() == name) {
+                continue;
+            }
+            self.resolve_value(name, block.location);
+        }
+    }
+
+    fn resolve_function(&mut self, decl: &'a FuncDecl, statement: &'a Statement) {
+        self.scopes = vec![Vec::new()];
+        for arg in &decl.args {
+            // Parameters given twice are reported by `declarations`
+            if let Some(Symbol::Parameter(_)) = self.lookup(&arg.name) {
+                continue;
+            }
+            self.declare(&arg.name, Symbol::Parameter(arg), arg.location);
+        }
+        self.current = Some(&decl.name);
+        self.used_parameters.clear();
+        self.resolve_statement(statement);
+        self.current = None;
+
+        for arg in &decl.args {
+            if !self.used_parameters.contains(arg.name.as_str()) {
+                let message = format!("Parameter `{}` of `{}` is never used", arg.name, decl.name);
+                self.warnings
+                    .push(Warning::new(Lint::UnusedParameter, message, arg.location));
+            }
+        }
+    }
+
+    fn resolve_initializer(&mut self, initializer: &'a Initializer) {
+        self.scopes = Vec::new();
+        match initializer {
+            Initializer::Scalar(value) => {
+                self.resolve_expression(value);
+            }
+            Initializer::List(elements) => {
+                for element in elements {
+                    self.resolve_expression(element);
+                }
+            }
+        }
+    }
+}
+
+// The number of single character insertions, deletions,
+// substitutions and swaps of neighbours that turn `first` into `second`
+fn edit_distance(first: &str, second: &str) -> usize {
+    let first: Vec<char> = first.chars().collect();
+    let second: Vec<char> = second.chars().collect();
+    let mut distances = vec![vec![0; second.len() + 1]; first.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=first.len() {
+        for j in 1..=second.len() {
+            let cost = if first[i - 1] == second[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j - 1] + cost)
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+            if i > 1 && j > 1 && first[i - 1] == second[j - 2] && first[i - 2] == second[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[first.len()][second.len()]
+}
+
+// The closest of `candidates` to a misspelled `name`, if any is close
+// enough to be a likely typo. Short names are too alike to guess at.
+// Earlier candidates win ties.
+fn suggest(name: &str, candidates: &Vec<String>) -> Option<String> {
+    let limit = name.chars().count() / 3;
+    let mut best: Option<(usize, &String)> = None;
+    for candidate in candidates {
+        let distance = edit_distance(&name.to_lowercase(), &candidate.to_lowercase());
+        if distance <= limit && best.is_none_or(|(best_distance, _)| distance < best_distance) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, candidate)| candidate.clone())
+}
+
+// Functions that run without being called from the program
+fn is_entry_point(decl: &FuncDecl) -> bool {
+    decl.name == "main"
+        || decl.name == OVERFLOW_TRAP
+        || interrupts::interrupt_vector(decl).is_some()
+}
+
+// Checks that every name refers to a parameter, local, global or
+// function, and that calls are to functions with as many arguments as
+// they take. Top level declarations are visible everywhere, whatever
+// their order, and locals from where they are declared to the end of
+// their block. Warns about names that hide others and about parameters
+// and functions that are never used. Returns what every name that
+// could be resolved refers to.
+pub fn check_names(
+    program: &Program,
+    errors: &mut Vec<SemanticError>,
+    warnings: &mut Vec<Warning>,
+) -> ResolutionTable {
+    let mut resolver = Resolver {
+        symbols: HashMap::new(),
+        scopes: Vec::new(),
+        current: None,
+        used_functions: HashSet::new(),
+        used_parameters: HashSet::new(),
+        table: ResolutionTable::new(),
+        errors,
+        warnings,
+    };
+
+    // Names declared twice are reported by `declarations`, and the
+    // first declaration is kept
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            }
+            | PrimaryStatement::Extern(decl) => {
+                resolver
+                    .symbols
+                    .entry(&decl.name)
+                    .or_insert(Symbol::Function(decl));
+            }
+            PrimaryStatement::Global(global) => {
+                resolver
+                    .symbols
+                    .entry(&global.name)
+                    .or_insert(Symbol::Global(global));
+            }
+            PrimaryStatement::Struct(_) => {}
+        }
+    }
+
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement,
+            } => {
+                resolver.resolve_function(decl, inner_statement);
+            }
+            PrimaryStatement::Global(global) => {
+                if let Some(initializer) = &global.initializer {
+                    resolver.resolve_initializer(initializer);
+                }
+            }
+            _ => {}
+        }
+    }
+    // Without `main` the program is a library, whose functions are all
+    // exported for other code to call
+    if resolver
+        .symbols
+        .get("main")
+        .is_none_or(|symbol| !symbol.is_function())
+    {
+        return resolver.table;
+    }
+    for primary in program.primaries() {
+        if let PrimaryStatement::Definition {
+            decl,
+            inner_statement: _,
+        } = primary
+        {
+            if !is_entry_point(decl) && !resolver.used_functions.contains(decl.name.as_str()) {
+                let message = format!("Function `{}` is never used", decl.name);
+                resolver
+                    .warnings
+                    .push(Warning::new(Lint::UnusedFunction, message, decl.location));
+            }
+        }
+    }
+    resolver.table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+
+    fn candidates(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn edit_distance_counts_each_kind_of_edit() {
+        assert_eq!(edit_distance("count", "count"), 0);
+        assert_eq!(edit_distance("count", "coun"), 1);
+        assert_eq!(edit_distance("count", "counts"), 1);
+        assert_eq!(edit_distance("count", "mount"), 1);
+        assert_eq!(edit_distance("count", "cuont"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn suggest_finds_a_close_name() {
+        let names = candidates(&["counter", "total"]);
+        assert_eq!(suggest("conuter", &names), Some(String::from("counter")));
+        assert_eq!(suggest("COUNTER", &names), Some(String::from("counter")));
+    }
+
+    #[test]
+    fn suggest_gives_up_on_distant_and_short_names() {
+        let names = candidates(&["counter", "x"]);
+        assert_eq!(suggest("total", &names), None);
+        assert_eq!(suggest("y", &names), None);
+    }
+
+    #[test]
+    fn suggest_prefers_earlier_candidates_on_ties() {
+        let names = candidates(&["valueb", "valuea"]);
+        assert_eq!(suggest("valuec", &names), Some(String::from("valueb")));
+    }
+
+    // The value returned by each function, in order
+    fn returns(program: &Program) -> Vec<&AstExprNode> {
+        let mut result = Vec::new();
+        for primary in program.primaries() {
+            if let PrimaryStatement::Definition {
+                decl: _,
+                inner_statement,
+            } = primary
+            {
+                let statements = match inner_statement.as_ref() {
+                    Statement::Block(statements) => statements,
+                    _ => continue,
+                };
+                for statement in statements {
+                    if let Statement::ReturnExpr(expr) = statement {
+                        result.push(expr.as_ref());
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn names_resolve_to_what_they_refer_to_where_used() {
+        let program = parse_source(
+            "var limit: u8\n\
+             def f(limit: u8): u8 { return limit }\n\
+             def g(): u8 { return limit }",
+        );
+        let table = check_names(&program, &mut Vec::new(), &mut Vec::new());
+        let returns = returns(&program);
+        assert_eq!(
+            table.get(returns[0]),
+            Some(&Resolution::Parameter(String::from("limit")))
+        );
+        assert_eq!(table.global(returns[1]), Some("limit"));
+    }
+
+    #[test]
+    fn unknown_names_are_reported_with_a_suggestion() {
+        let program = parse_source("var counter: u8\ndef f(): u8 { return countr }");
+        let mut errors = Vec::new();
+        let table = check_names(&program, &mut errors, &mut Vec::new());
+        assert_eq!(
+            errors[0].message,
+            "Cannot find `countr` in this scope, did you mean `counter`?"
+        );
+        assert_eq!(table.get(returns(&program)[0]), None);
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::types::StructType;
+use crate::parser::types::Type;
+
+use crate::semantic::SemanticError;
+
+fn holds_struct(ty: &Type) -> bool {
+    match ty {
+        Type::Struct(_) => true,
+        Type::Array { element, length: _ } => holds_struct(element),
+        _ => false,
+    }
+}
+
+fn check_fields(structure: &StructType, errors: &mut Vec<SemanticError>) {
+    if structure.fields.is_empty() {
+        let message = format!("Struct `{}` has no fields", structure.name);
+        errors.push(SemanticError::new(message, structure.location));
+    }
+
+    let mut seen: HashSet<&str> = HashSet::new();
+    for field in &structure.fields {
+        if !seen.insert(&field.name) {
+            let message = format!(
+                "Field `{}` is declared more than once in `{}`",
+                field.name, structure.name
+            );
+            errors.push(SemanticError::new(message, field.location));
+        }
+        match field.ty {
+            Type::Int(_)
+            | Type::Fixed(_)
+            | Type::Float
+            | Type::Bcd(_)
+            | Type::Pointer {
+                pointee: _,
+                mutable: _,
+            } => {}
+            _ => {
+                let message = format!(
+                    "Field `{}` of `{}` must be a number or a pointer",
+                    field.name, structure.name
+                );
+                errors.push(SemanticError::new(message, field.location));
+            }
+        }
+    }
+}
+
+// Checks struct declarations, and that structs are only stored in
+// RAM since there is no syntax to initialize one
+pub fn check_structs(program: &Program, errors: &mut Vec<SemanticError>) {
+    let mut names: HashSet<&str> = HashSet::new();
+
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Struct(structure) => {
+                if !names.insert(&structure.name) {
+                    let message = format!("Struct `{}` is declared more than once", structure.name);
+                    errors.push(SemanticError::new(message, structure.location));
+                }
+                check_fields(structure, errors);
+            }
+            PrimaryStatement::Global(global) if global.is_const && holds_struct(&global.ty) => {
+                let message = format!("Constant `{}` cannot hold structs", global.name);
+                errors.push(SemanticError::new(message, global.location));
+            }
+            _ => {}
+        }
+    }
+}
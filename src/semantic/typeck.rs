@@ -0,0 +1,1448 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::lexer::SourceLocation;
+
+use crate::parser::asm::AsmBlock;
+use crate::parser::asm::AsmClobber;
+use crate::parser::asm::AsmLocation;
+use crate::parser::bin_op::AstExprNode;
+use crate::parser::bin_op::BinOp;
+use crate::parser::bin_op::Factor;
+use crate::parser::parser::FuncDecl;
+use crate::parser::parser::GlobalDecl;
+use crate::parser::parser::Initializer;
+use crate::parser::parser::PrimaryStatement;
+use crate::parser::parser::Program;
+use crate::parser::parser::Statement;
+use crate::parser::types::IntType;
+use crate::parser::types::StructLayout;
+use crate::parser::types::StructType;
+use crate::parser::types::Type;
+use crate::parser::types::DEFAULT_FIXED_TYPE;
+use crate::parser::types::DEFAULT_INT_TYPE;
+
+use crate::semantic::infer::InferType;
+use crate::semantic::infer::TypeVariables;
+use crate::semantic::intrinsics::ArithmeticOp;
+use crate::semantic::intrinsics::Intrinsic;
+use crate::semantic::walk_statement;
+use crate::semantic::SemanticError;
+
+// The type of every expression node, keyed by node address the same
+// way `Graphviz` tracks visited nodes. The program must not move
+// while the table is in use.
+pub struct TypeTable {
+    types: HashMap<*const AstExprNode, Type>,
+}
+
+impl TypeTable {
+    pub fn new() -> TypeTable {
+        TypeTable {
+            types: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, expr: &AstExprNode) -> Option<&Type> {
+        self.types.get(&(expr as *const AstExprNode))
+    }
+
+    // The integer type of an expression that passed type checking.
+    // Pointers are handled as unsigned 16 bit addresses.
+    pub fn int_type(&self, expr: &AstExprNode) -> IntType {
+        match self.get(expr) {
+            Some(Type::Int(int_type)) => *int_type,
+            Some(Type::Pointer {
+                pointee: _,
+                mutable: _,
+            }) => IntType::U16,
+            other => panic!("Expression at {} has type {:?}", expr.location(), other),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Signature {
+    pub args: Vec<Type>,
+    pub return_type: Type,
+}
+
+impl Signature {
+    pub fn from_decl(decl: &FuncDecl) -> Signature {
+        Signature {
+            args: decl.args.iter().map(|arg| declared_type(&arg.ty)).collect(),
+            return_type: declared_type(&decl.return_type),
+        }
+    }
+}
+
+// Unannotated parameters and returns use the default integer type
+pub fn declared_type(ty: &Option<Type>) -> Type {
+    match ty {
+        Some(ty) => ty.clone(),
+        None => Type::Int(DEFAULT_INT_TYPE),
+    }
+}
+
+// Bytes of `__tmp` that zero page operands of an asm block share
+pub const ASM_ZERO_PAGE_BYTES: usize = 4;
+
+// Instructions that change X, which holds the data stack pointer
+const WRITES_X: [&str; 5] = ["LDX", "TAX", "TSX", "INX", "DEX"];
+
+// Arrays and structs do not fit in a data stack slot
+fn aggregate_kind(ty: &Type) -> Option<&'static str> {
+    match ty {
+        Type::Array {
+            element: _,
+            length: _,
+        } => Some("an array"),
+        Type::Struct(_) => Some("a struct"),
+        _ => None,
+    }
+}
+
+struct TypeChecker<'a, 'b> {
+    functions: HashMap<String, Signature>,
+    globals: HashMap<String, &'a GlobalDecl>,
+    scopes: Vec<HashMap<String, InferType>>,
+    return_type: Type,
+    variables: TypeVariables,
+    // Type of every node seen so far, resolved once all uses are known
+    inferred: Vec<(*const AstExprNode, InferType)>,
+    // Result of every comparison, which must settle on an integer type
+    comparisons: Vec<(InferType, String, SourceLocation)>,
+    errors: &'b mut Vec<SemanticError>,
+}
+
+impl<'a, 'b> TypeChecker<'a, 'b> {
+    fn error(&mut self, message: String, location: SourceLocation) {
+        self.errors.push(SemanticError::new(message, location));
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<InferType> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(ty) = scope.get(name) {
+                return Some(ty.clone());
+            }
+        }
+        None
+    }
+
+    fn declare(&mut self, name: &str, ty: InferType) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), ty);
+    }
+
+    fn unify(
+        &mut self,
+        first: &InferType,
+        second: &InferType,
+        location: SourceLocation,
+        context: &str,
+    ) {
+        if let Err((first, second)) = self.variables.unify(first, second) {
+            self.error(
+                format!("Mismatched types `{}` and `{}` {}", first, second, context),
+                location,
+            );
+        }
+    }
+
+    // Like `unify`, but also lets a `*mut T` be used where a `*T` is expected
+    fn coerce(
+        &mut self,
+        expected: &Type,
+        actual: &InferType,
+        location: SourceLocation,
+        context: &str,
+    ) {
+        if let (
+            Type::Pointer {
+                pointee,
+                mutable: false,
+            },
+            InferType::Known(Type::Pointer {
+                pointee: actual_pointee,
+                mutable: true,
+            }),
+        ) = (expected, self.variables.shallow_resolve(actual))
+        {
+            if *pointee == actual_pointee {
+                return;
+            }
+        }
+        self.unify(
+            &InferType::Known(expected.clone()),
+            actual,
+            location,
+            context,
+        );
+    }
+
+    // Values are passed in data stack slots, which only hold integers
+    fn check_signature(&mut self, decl: &FuncDecl) {
+        if Intrinsic::from_name(&decl.name).is_some() {
+            self.error(
+                format!("`{}` is built in and cannot be declared", decl.name),
+                decl.location,
+            );
+        }
+        for arg in &decl.args {
+            if let Some(kind) = arg.ty.as_ref().and_then(aggregate_kind) {
+                self.error(
+                    format!("Parameter `{}` cannot be {}", arg.name, kind),
+                    arg.location,
+                );
+            }
+        }
+        if let Some(kind) = decl.return_type.as_ref().and_then(aggregate_kind) {
+            self.error(
+                format!("`{}` cannot return {}", decl.name, kind),
+                decl.location,
+            );
+        }
+    }
+
+    // Numbers in an initializer are typed like the value of a local, so
+    // that calls in it are checked and evaluated at the declared width.
+    // Initializers of other shapes are reported by `arrays`.
+    fn check_initializer(&mut self, global: &GlobalDecl) {
+        let (element, values): (&Type, Vec<&AstExprNode>) = match (&global.ty, &global.initializer)
+        {
+            (Type::Array { element, length: _ }, Some(Initializer::List(elements))) => {
+                (element, elements.iter().collect())
+            }
+            (
+                Type::Array {
+                    element: _,
+                    length: _,
+                },
+                _,
+            ) => return,
+            (ty, Some(Initializer::Scalar(value))) => (ty, vec![value.as_ref()]),
+            _ => return,
+        };
+        let is_number = matches!(
+            element,
+            Type::Int(_) | Type::Fixed(_) | Type::Float | Type::Bcd(_)
+        );
+        if !is_number {
+            return;
+        }
+        self.scopes = vec![HashMap::new()];
+        for value in values {
+            if let Some(actual) = self.infer(value) {
+                self.coerce(
+                    element,
+                    &actual,
+                    value.location(),
+                    &format!("in initializer of `{}`", global.name),
+                );
+            }
+        }
+    }
+
+    fn check_function(&mut self, decl: &FuncDecl, statement: &Statement) {
+        let mut params: HashMap<String, InferType> = HashMap::new();
+        for arg in &decl.args {
+            params.insert(arg.name.clone(), InferType::Known(declared_type(&arg.ty)));
+        }
+        self.scopes = vec![params];
+        self.return_type = declared_type(&decl.return_type);
+        self.check_statement(statement);
+    }
+
+    // Checks a statement that gets a scope of its own, like the body of an `if`
+    fn check_scoped(&mut self, statement: &Statement) {
+        self.scopes.push(HashMap::new());
+        self.check_statement(statement);
+        self.scopes.pop();
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Select {
+                condition,
+                statement,
+                else_clause,
+            } => {
+                self.infer(condition);
+                self.check_scoped(statement);
+                if let Some(clause) = else_clause {
+                    self.check_scoped(clause);
+                }
+            }
+            Statement::ReturnExpr(expr) => {
+                if let Some(actual) = self.infer(expr) {
+                    let return_type = self.return_type.clone();
+                    self.coerce(&return_type, &actual, expr.location(), "in return value");
+                }
+            }
+            Statement::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                for statement in statements {
+                    self.check_statement(statement);
+                }
+                self.scopes.pop();
+            }
+            Statement::Local {
+                name,
+                ty,
+                value,
+                location,
+            } => {
+                if let Some(kind) = ty.as_ref().and_then(aggregate_kind) {
+                    self.error(format!("Local `{}` cannot be {}", name, kind), *location);
+                    return;
+                }
+                // Without an annotation the local shares the type of its
+                // initializer, which later uses may still decide
+                let local_type = match self.infer(value) {
+                    Some(actual) => actual,
+                    None => return,
+                };
+                if let Some(ty) = ty {
+                    self.coerce(
+                        ty,
+                        &local_type,
+                        value.location(),
+                        &format!("in initializer of `{}`", name),
+                    );
+                    self.declare(name, InferType::Known(ty.clone()));
+                } else {
+                    self.declare(name, local_type);
+                }
+            }
+            Statement::Assign {
+                target,
+                value,
+                location,
+            } => {
+                if let Some(target_type) = self.check_target(target, *location) {
+                    self.inferred
+                        .push((target.as_ref() as *const AstExprNode, target_type.clone()));
+                    if let Some(actual) = self.infer(value) {
+                        match self.variables.shallow_resolve(&target_type) {
+                            InferType::Known(known) => {
+                                self.coerce(&known, &actual, *location, "in assignment")
+                            }
+                            InferType::Var(_) => {
+                                self.unify(&target_type, &actual, *location, "in assignment")
+                            }
+                        }
+                    }
+                }
+            }
+            Statement::Expression(expr) => {
+                self.infer(expr);
+            }
+            Statement::Asm(block) => {
+                self.check_asm(block);
+            }
+            Statement::Match(matching) => {
+                // Patterns are constants checked against the resolved type
+                self.infer(&matching.value);
+                for statement in matching.statements() {
+                    self.check_scoped(statement);
+                }
+            }
+        }
+    }
+
+    // Register operands must be one byte, and the text may only name
+    // zero page operands, globals and functions
+    fn check_asm(&mut self, block: &AsmBlock) {
+        // A register may be loaded from one variable and stored to another
+        let mut readers: Vec<(AsmLocation, &str)> = Vec::new();
+        let mut writers: Vec<(AsmLocation, &str)> = Vec::new();
+        for operand in &block.operands {
+            let location = operand.value.location();
+            let ty = if operand.direction.writes() {
+                let ty = self.check_target(&operand.value, location);
+                if let Some(ty) = &ty {
+                    self.inferred
+                        .push((operand.value.as_ref() as *const AstExprNode, ty.clone()));
+                }
+                ty
+            } else {
+                self.infer(&operand.value)
+            };
+            let ty = match ty {
+                Some(ty) => ty,
+                None => continue,
+            };
+
+            if operand.location == AsmLocation::ZeroPage {
+                if let InferType::Known(known) = self.variables.shallow_resolve(&ty) {
+                    if let Some(kind) = aggregate_kind(&known) {
+                        self.error(
+                            format!(
+                                "`{}` is {} and cannot be an asm operand",
+                                operand.name(),
+                                kind
+                            ),
+                            location,
+                        );
+                    }
+                }
+                continue;
+            }
+
+            match self.variables.shallow_resolve(&ty) {
+                InferType::Var(_) => {
+                    self.unify(
+                        &ty,
+                        &InferType::Known(Type::Int(IntType::U8)),
+                        location,
+                        "in asm operand",
+                    );
+                }
+                InferType::Known(Type::Int(int_type)) if int_type.size() == 1 => {}
+                InferType::Known(known) => {
+                    self.error(
+                        format!(
+                            "`{}` is `{}`, which does not fit in {}",
+                            operand.name(),
+                            known,
+                            operand.location
+                        ),
+                        location,
+                    );
+                }
+            }
+            for (bound, uses) in [
+                (operand.direction.reads(), &mut readers),
+                (operand.direction.writes(), &mut writers),
+            ] {
+                if !bound {
+                    continue;
+                }
+                match uses
+                    .iter()
+                    .find(|(register, _)| *register == operand.location)
+                {
+                    Some((_, other)) => {
+                        self.error(
+                            format!(
+                                "{} is bound to both `{}` and `{}`",
+                                operand.location,
+                                other,
+                                operand.name()
+                            ),
+                            location,
+                        );
+                    }
+                    None => {
+                        uses.push((operand.location, operand.name()));
+                    }
+                }
+            }
+        }
+
+        for name in block.placeholders() {
+            match block.operands.iter().find(|operand| operand.name() == name) {
+                Some(operand) if operand.location == AsmLocation::ZeroPage => {}
+                Some(operand) => {
+                    self.error(
+                        format!(
+                            "`{}` is bound to {} and has no address to name",
+                            name, operand.location
+                        ),
+                        block.location,
+                    );
+                }
+                None if self.lookup_variable(name).is_some() => {
+                    self.error(
+                        format!("`{}` must be a `zp` operand to be named in asm", name),
+                        block.location,
+                    );
+                }
+                // Globals and functions are named by their symbol, and
+                // anything else is reported by `names`
+                None => {}
+            }
+        }
+
+        let changes_x = block.text.lines().any(|line| {
+            let instruction = line.rsplit(':').next().unwrap().trim();
+            WRITES_X
+                .iter()
+                .any(|mnemonic| instruction.to_uppercase().starts_with(mnemonic))
+        });
+        if changes_x && !block.clobbers(AsmClobber::X) && !block.binds(AsmLocation::X) {
+            self.error(String::from("Inline assembly changes X, which holds the data stack pointer, without `clobbers(X)`"), block.location);
+        }
+    }
+
+    // Finds the type stored by an assignment target
+    fn check_target(
+        &mut self,
+        target: &AstExprNode,
+        location: SourceLocation,
+    ) -> Option<InferType> {
+        match target {
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location: _,
+            }) => {
+                if let Some(ty) = self.lookup_variable(id) {
+                    return Some(ty);
+                }
+                match self
+                    .globals
+                    .get(id)
+                    .map(|global| (global.is_const, &global.ty))
+                {
+                    Some((true, _)) => {
+                        self.error(format!("Cannot assign to constant `{}`", id), location);
+                    }
+                    Some((
+                        false,
+                        Type::Array {
+                            element: _,
+                            length: _,
+                        },
+                    )) => {
+                        self.error(
+                            format!("Cannot assign to the whole array `{}`", id),
+                            location,
+                        );
+                    }
+                    Some((false, ty)) => {
+                        return Some(InferType::Known(ty.clone()));
+                    }
+                    None if self.functions.contains_key(id) => {
+                        self.error(format!("Cannot assign to function `{}`", id), location);
+                    }
+                    // Reported by `names`
+                    None => {}
+                }
+                None
+            }
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location: _,
+            }) => {
+                let (element, writable) = self.indexable(id, location)?;
+                self.infer(index)?;
+                if !writable {
+                    if self.lookup_variable(id).is_none()
+                        && self.globals.get(id).map(|global| global.is_const) == Some(true)
+                    {
+                        self.error(
+                            format!("Cannot assign into constant table `{}`", id),
+                            location,
+                        );
+                    } else {
+                        self.error(
+                            format!(
+                                "Cannot write through `{}`, which is not a `*mut` pointer",
+                                id
+                            ),
+                            location,
+                        );
+                    }
+                    return None;
+                }
+                Some(InferType::Known(element))
+            }
+            AstExprNode::Field {
+                base,
+                field,
+                location: _,
+            } => {
+                let (structure, writable) = self.field_base(base, field, location)?;
+                let ty = self.field_type(&structure, field, location)?;
+                if !writable {
+                    self.error(
+                        format!(
+                            "Cannot write field `{}` through a pointer that is not `*mut`",
+                            field
+                        ),
+                        location,
+                    );
+                    return None;
+                }
+                Some(InferType::Known(ty))
+            }
+            AstExprNode::Deref {
+                pointer,
+                location: _,
+            } => {
+                let pointer_type = self.infer(pointer)?;
+                let (pointee, mutable) = self.pointee(&pointer_type, location)?;
+                if !mutable {
+                    let ty = self.variables.resolve(&pointer_type);
+                    self.error(
+                        format!(
+                            "Cannot write through `{}`, which is not a `*mut` pointer",
+                            ty
+                        ),
+                        location,
+                    );
+                    return None;
+                }
+                Some(InferType::Known(pointee))
+            }
+            _ => {
+                self.error(String::from("Invalid assignment target"), location);
+                None
+            }
+        }
+    }
+
+    // Works out the type of an expression, unifying along the way,
+    // and remembers it for the node
+    fn infer(&mut self, expr: &AstExprNode) -> Option<InferType> {
+        let result = match expr {
+            AstExprNode::Terminal(factor) => self.infer_factor(factor)?,
+            AstExprNode::SubNode(sub_node) => self.infer(sub_node)?,
+            AstExprNode::Cast {
+                value,
+                ty,
+                location,
+            } => {
+                self.infer(value)?;
+                if let Some(kind) = aggregate_kind(ty) {
+                    self.error(format!("Cannot cast to {} type `{}`", kind, ty), *location);
+                    return None;
+                }
+                InferType::Known(ty.clone())
+            }
+            AstExprNode::AddressOf { target, location } => self.infer_address(target, *location)?,
+            AstExprNode::Deref { pointer, location } => {
+                let pointer_type = self.infer(pointer)?;
+                let (pointee, _) = self.pointee(&pointer_type, *location)?;
+                InferType::Known(pointee)
+            }
+            AstExprNode::Field {
+                base,
+                field,
+                location,
+            } => {
+                let (structure, _) = self.field_base(base, field, *location)?;
+                InferType::Known(self.field_type(&structure, field, *location)?)
+            }
+            AstExprNode::Node {
+                left,
+                op_type,
+                next,
+                location,
+            } => {
+                let left_type = self.infer(left)?;
+                let right_type = self.infer(next)?;
+
+                // Adding to or subtracting from a pointer steps it by
+                // whole elements, by any integer amount
+                if let (
+                    BinOp::Sum(_),
+                    InferType::Known(Type::Pointer {
+                        pointee: _,
+                        mutable: _,
+                    }),
+                ) = (op_type, self.variables.shallow_resolve(&left_type))
+                {
+                    if let InferType::Known(Type::Pointer {
+                        pointee: _,
+                        mutable: _,
+                    }) = self.variables.shallow_resolve(&right_type)
+                    {
+                        self.error(
+                            format!("Cannot apply `{}` to two pointers", op_type),
+                            *location,
+                        );
+                        return None;
+                    }
+                    left_type
+                } else {
+                    self.unify(
+                        &left_type,
+                        &right_type,
+                        *location,
+                        &format!("in `{}`", op_type),
+                    );
+                    if let BinOp::Rel(_) = op_type {
+                        // Comparisons give 0 or 1, which fits whatever integer is wanted
+                        let result = self.variables.fresh(Type::Int(IntType::U8));
+                        self.comparisons
+                            .push((result.clone(), op_type.to_string(), *location));
+                        result
+                    } else if let InferType::Known(
+                        ty @ Type::Pointer {
+                            pointee: _,
+                            mutable: _,
+                        },
+                    ) = self.variables.shallow_resolve(&left_type)
+                    {
+                        self.error(
+                            format!("Cannot apply `{}` to pointer type `{}`", op_type, ty),
+                            *location,
+                        );
+                        return None;
+                    } else {
+                        left_type
+                    }
+                }
+            }
+        };
+
+        if let InferType::Known(Type::Struct(structure)) = &result {
+            self.error(
+                format!(
+                    "Struct `{}` cannot be used as a value, only its fields can",
+                    structure.name
+                ),
+                expr.location(),
+            );
+            return None;
+        }
+
+        self.inferred
+            .push((expr as *const AstExprNode, result.clone()));
+        Some(result)
+    }
+
+    // The struct a field is read from and whether it may be written.
+    // Pointers to structs are followed without an explicit `*`.
+    fn field_base(
+        &mut self,
+        base: &AstExprNode,
+        field: &str,
+        location: SourceLocation,
+    ) -> Option<(Rc<StructType>, bool)> {
+        let (ty, writable) = match base {
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location: _,
+            }) if self.lookup_variable(id).is_none()
+                && self
+                    .globals
+                    .get(id)
+                    .is_some_and(|global| global.ty.is_struct()) =>
+            {
+                let global = self.globals[id];
+                self.inferred.push((
+                    base as *const AstExprNode,
+                    InferType::Known(global.ty.clone()),
+                ));
+                (global.ty.clone(), !global.is_const)
+            }
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location: _,
+            }) => {
+                let (element, writable) = self.indexable(id, location)?;
+                self.infer(index)?;
+                self.inferred.push((
+                    base as *const AstExprNode,
+                    InferType::Known(element.clone()),
+                ));
+                (element, writable)
+            }
+            _ => {
+                let base_type = self.infer(base)?;
+                match self.variables.shallow_resolve(&base_type) {
+                    InferType::Known(Type::Pointer { pointee, mutable }) => (*pointee, mutable),
+                    other => (self.variables.resolve(&other), false),
+                }
+            }
+        };
+
+        match ty {
+            Type::Struct(structure) => Some((structure, writable)),
+            other => {
+                self.error(format!("`{}` has no field `{}`", other, field), location);
+                None
+            }
+        }
+    }
+
+    fn field_type(
+        &mut self,
+        structure: &StructType,
+        field: &str,
+        location: SourceLocation,
+    ) -> Option<Type> {
+        match structure.field(field) {
+            Some((_, ty)) => Some(ty.clone()),
+            None => {
+                self.error(
+                    format!("`{}` has no field `{}`", structure.name, field),
+                    location,
+                );
+                None
+            }
+        }
+    }
+
+    fn infer_factor(&mut self, factor: &Factor) -> Option<InferType> {
+        match factor {
+            Factor::Numeric { value, location: _ } => {
+                if value.fract() != 0.0 {
+                    return Some(self.variables.fresh(Type::Fixed(DEFAULT_FIXED_TYPE)));
+                }
+                Some(self.variables.fresh(Type::Int(DEFAULT_INT_TYPE)))
+            }
+            Factor::Id {
+                id,
+                optional_call: None,
+                location,
+            } => {
+                if let Some(ty) = self.lookup_variable(id) {
+                    return Some(ty);
+                }
+                if let Some(global) = self.globals.get(id) {
+                    return match &global.ty {
+                        // Arrays evaluate to their address
+                        Type::Array {
+                            element: _,
+                            length: _,
+                        } => Some(InferType::Known(Type::Int(IntType::U16))),
+                        ty => Some(InferType::Known(ty.clone())),
+                    };
+                }
+                if self.functions.contains_key(id) {
+                    self.error(format!("`{}` is a function, not a value", id), *location);
+                    return None;
+                }
+                // Reported by `names`
+                None
+            }
+            Factor::Id {
+                id,
+                optional_call: Some(args),
+                location,
+            } => {
+                let signature = match self.functions.get(id) {
+                    Some(signature) => signature.clone(),
+                    None => match Intrinsic::from_name(id) {
+                        Some(Intrinsic::Arithmetic(op, _)) => {
+                            return self.infer_arithmetic(id, op, args, *location);
+                        }
+                        Some(intrinsic) => {
+                            // Lowered inline, so every argument must be there
+                            let signature = intrinsic.signature().unwrap();
+                            if args.len() != signature.args.len() {
+                                self.error(
+                                    format!(
+                                        "`{}` takes {} arguments but {} were given",
+                                        id,
+                                        signature.args.len(),
+                                        args.len()
+                                    ),
+                                    *location,
+                                );
+                                return None;
+                            }
+                            signature
+                        }
+                        // Reported by `names`
+                        None => return None,
+                    },
+                };
+                for (index, (arg, arg_type)) in args.iter().zip(signature.args.iter()).enumerate() {
+                    if let Some(actual) = self.infer(arg) {
+                        let context = format!("in argument {} of `{}`", index + 1, id);
+                        self.coerce(arg_type, &actual, arg.location(), &context);
+                    }
+                }
+                Some(InferType::Known(signature.return_type))
+            }
+            Factor::Index {
+                id,
+                index,
+                location,
+            } => {
+                let (element, _) = self.indexable(id, *location)?;
+                self.infer(index)?;
+                Some(InferType::Known(element))
+            }
+        }
+    }
+
+    // Arithmetic intrinsics take two integers of the same type, like
+    // the operator they apply
+    fn infer_arithmetic(
+        &mut self,
+        id: &str,
+        op: ArithmeticOp,
+        args: &[Box<AstExprNode>],
+        location: SourceLocation,
+    ) -> Option<InferType> {
+        if args.len() != 2 {
+            self.error(
+                format!("`{}` takes 2 arguments but {} were given", id, args.len()),
+                location,
+            );
+            return None;
+        }
+        let left_type = self.infer(&args[0])?;
+        let right_type = self.infer(&args[1])?;
+        self.unify(&left_type, &right_type, location, &format!("in `{}`", id));
+        if let InferType::Known(
+            ty @ Type::Pointer {
+                pointee: _,
+                mutable: _,
+            },
+        ) = self.variables.shallow_resolve(&left_type)
+        {
+            self.error(
+                format!("Cannot apply `{}` to pointer type `{}`", op.bin_op(), ty),
+                location,
+            );
+            return None;
+        }
+        if let InferType::Known(Type::Float) = self.variables.shallow_resolve(&left_type) {
+            self.error(
+                format!(
+                    "`{}` does not apply to `f32`, which overflows to infinity",
+                    id
+                ),
+                location,
+            );
+            return None;
+        }
+        if let (ArithmeticOp::Multiply, InferType::Known(ty @ Type::Bcd(_))) =
+            (op, self.variables.shallow_resolve(&left_type))
+        {
+            self.error(
+                format!(
+                    "`{}` does not apply to `{}`, which only adds and subtracts",
+                    id, ty
+                ),
+                location,
+            );
+            return None;
+        }
+        Some(left_type)
+    }
+
+    // The type read through a pointer and whether it may be written
+    fn pointee(
+        &mut self,
+        pointer_type: &InferType,
+        location: SourceLocation,
+    ) -> Option<(Type, bool)> {
+        match self.variables.shallow_resolve(pointer_type) {
+            InferType::Known(Type::Pointer { pointee, mutable }) => {
+                if let Type::Array {
+                    element: _,
+                    length: _,
+                } = pointee.as_ref()
+                {
+                    self.error(
+                        format!(
+                            "Cannot access `{}` through a pointer, point at its elements instead",
+                            pointee
+                        ),
+                        location,
+                    );
+                    return None;
+                }
+                Some((*pointee, mutable))
+            }
+            other => {
+                let ty = self.variables.resolve(&other);
+                self.error(
+                    format!("Cannot dereference `{}`, which is not a pointer", ty),
+                    location,
+                );
+                None
+            }
+        }
+    }
+
+    // The element type of a table or pointer that is indexed, and
+    // whether elements may be written
+    fn indexable(&mut self, id: &str, location: SourceLocation) -> Option<(Type, bool)> {
+        let ty = match self.lookup_variable(id) {
+            Some(ty) => ty,
+            None => match self.globals.get(id) {
+                Some(global) => {
+                    if let Type::Array { element, length: _ } = &global.ty {
+                        return Some((element.as_ref().clone(), !global.is_const));
+                    }
+                    InferType::Known(global.ty.clone())
+                }
+                None if self.functions.contains_key(id) => {
+                    self.error(
+                        format!("`{}` is a function and cannot be indexed", id),
+                        location,
+                    );
+                    return None;
+                }
+                // Reported by `names`
+                None => return None,
+            },
+        };
+        if let InferType::Known(Type::Pointer {
+            pointee: _,
+            mutable: _,
+        }) = self.variables.shallow_resolve(&ty)
+        {
+            return self.pointee(&ty, location);
+        }
+        self.error(
+            format!("`{}` is not an array or pointer and cannot be indexed", id),
+            location,
+        );
+        None
+    }
+
+    // `&global`, `&table[i]` or `&pointer[i]`. Locals live in data
+    // stack slots that move between calls, so they have no address.
+    fn infer_address(
+        &mut self,
+        target: &AstExprNode,
+        location: SourceLocation,
+    ) -> Option<InferType> {
+        let (pointee, mutable) = match target {
+            AstExprNode::Terminal(Factor::Id {
+                id,
+                optional_call: None,
+                location: _,
+            }) => {
+                if self.lookup_variable(id).is_some() {
+                    self.error(
+                        format!("Cannot take the address of local `{}`", id),
+                        location,
+                    );
+                    return None;
+                }
+                match self.globals.get(id) {
+                    Some(global) => {
+                        let pointee = match &global.ty {
+                            Type::Array { element, length: _ } => element.as_ref().clone(),
+                            ty => ty.clone(),
+                        };
+                        (pointee, !global.is_const)
+                    }
+                    None if self.functions.contains_key(id) => {
+                        self.error(
+                            format!("Cannot take the address of function `{}`", id),
+                            location,
+                        );
+                        return None;
+                    }
+                    // Reported by `names`
+                    None => return None,
+                }
+            }
+            AstExprNode::Terminal(Factor::Index {
+                id,
+                index,
+                location: _,
+            }) => {
+                let (element, writable) = self.indexable(id, location)?;
+                self.infer(index)?;
+                let is_table = self.lookup_variable(id).is_none();
+                if let Type::Struct(structure) = &element {
+                    if is_table && structure.layout == StructLayout::StructOfArrays {
+                        self.error(format!("Cannot take the address of an element of `{}`, whose fields are stored apart", id), location);
+                        return None;
+                    }
+                }
+                (element, writable)
+            }
+            _ => {
+                self.error(
+                    String::from("Can only take the address of a global or an element"),
+                    location,
+                );
+                return None;
+            }
+        };
+        self.inferred.push((
+            target as *const AstExprNode,
+            InferType::Known(pointee.clone()),
+        ));
+        let result = Type::Pointer {
+            pointee: Box::new(pointee),
+            mutable,
+        };
+        Some(InferType::Known(result))
+    }
+
+    // Settles every inferred type now that all uses have been seen
+    fn finish(mut self) -> TypeTable {
+        let mut table = TypeTable::new();
+        let inferred = std::mem::take(&mut self.inferred);
+        for (expr, ty) in inferred {
+            table.types.insert(expr, self.variables.resolve(&ty));
+        }
+        let comparisons = std::mem::take(&mut self.comparisons);
+        for (ty, op_type, location) in comparisons {
+            let resolved = self.variables.resolve(&ty);
+            if !matches!(resolved, Type::Int(_)) {
+                self.error(
+                    format!(
+                        "Mismatched types `{}` and `{}` in result of `{}`",
+                        resolved,
+                        Type::Int(IntType::U8),
+                        op_type
+                    ),
+                    location,
+                );
+            }
+        }
+        table
+    }
+}
+
+// Infers the type of every expression from the declared integer types,
+// letting unannotated locals and literals take the width their uses
+// demand, and returns the resolved types for lowering
+pub fn check_types(program: &Program, errors: &mut Vec<SemanticError>) -> TypeTable {
+    let mut checker = TypeChecker {
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+        scopes: Vec::new(),
+        return_type: Type::Int(DEFAULT_INT_TYPE),
+        variables: TypeVariables::new(),
+        inferred: Vec::new(),
+        comparisons: Vec::new(),
+        errors,
+    };
+
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement: _,
+            } => {
+                checker.check_signature(decl);
+                checker
+                    .functions
+                    .insert(decl.name.clone(), Signature::from_decl(decl));
+            }
+            PrimaryStatement::Extern(decl) => {
+                checker.check_signature(decl);
+                checker
+                    .functions
+                    .insert(decl.name.clone(), Signature::from_decl(decl));
+            }
+            PrimaryStatement::Global(global) => {
+                checker.globals.insert(global.name.clone(), global);
+            }
+            PrimaryStatement::Struct(_) => {}
+        }
+    }
+
+    for primary in program.primaries() {
+        match primary {
+            PrimaryStatement::Definition {
+                decl,
+                inner_statement,
+            } => {
+                checker.check_function(decl, inner_statement);
+            }
+            PrimaryStatement::Global(global) => {
+                checker.check_initializer(global);
+            }
+            _ => {}
+        }
+    }
+
+    let table = checker.finish();
+    for primary in program.primaries() {
+        if let PrimaryStatement::Definition {
+            decl: _,
+            inner_statement,
+        } = primary
+        {
+            check_asm_zero_page(inner_statement, &table, errors);
+            walk_statement(inner_statement, &mut |expr| {
+                check_number_use(expr, &table, errors)
+            });
+        }
+    }
+    table
+}
+
+// A literal only becomes fixed-point, float or BCD once its type is
+// resolved, so where one may not go is checked afterwards. Indexes and
+// pointer offsets count elements, and a fraction of an address means
+// nothing. BCD only has decimal adds and subtracts, and converts to and
+// from plain integers.
+fn check_number_use(expr: &AstExprNode, table: &TypeTable, errors: &mut Vec<SemanticError>) {
+    let not_integer = |expr: &AstExprNode| {
+        table
+            .get(expr)
+            .is_some_and(|ty| ty.has_fraction() || ty.is_bcd())
+    };
+    let is_pointer = |expr: &AstExprNode| table.get(expr).is_some_and(|ty| ty.is_pointer());
+    let is_bcd = |expr: &AstExprNode| table.get(expr).is_some_and(|ty| ty.is_bcd());
+    match expr {
+        AstExprNode::Terminal(Factor::Index {
+            id: _,
+            index,
+            location: _,
+        }) if not_integer(index) => {
+            let message = format!(
+                "Cannot index with `{}`, only with an integer",
+                table.get(index).unwrap()
+            );
+            errors.push(SemanticError::new(message, index.location()));
+        }
+        AstExprNode::Node {
+            left,
+            op_type: BinOp::Sum(_),
+            next,
+            location,
+        } if is_pointer(left) && not_integer(next) => {
+            let message = format!(
+                "Cannot offset a pointer by `{}`, only by an integer",
+                table.get(next).unwrap()
+            );
+            errors.push(SemanticError::new(message, *location));
+        }
+        AstExprNode::Node {
+            left,
+            op_type: op_type @ BinOp::Mult(_),
+            next: _,
+            location,
+        } if is_bcd(left) => {
+            let message = format!(
+                "Cannot apply `{}` to `{}`, which only adds and subtracts",
+                op_type,
+                table.get(left).unwrap()
+            );
+            errors.push(SemanticError::new(message, *location));
+        }
+        AstExprNode::Cast {
+            value,
+            ty,
+            location,
+        } if (not_integer(value) && ty.is_pointer())
+            || (is_pointer(value) && (ty.has_fraction() || ty.is_bcd())) =>
+        {
+            let message = format!("Cannot cast `{}` to `{}`", table.get(value).unwrap(), ty);
+            errors.push(SemanticError::new(message, *location));
+        }
+        AstExprNode::Cast {
+            value,
+            ty,
+            location,
+        } if (is_bcd(value) && ty.has_fraction())
+            || (table.get(value).is_some_and(|ty| ty.has_fraction()) && ty.is_bcd()) =>
+        {
+            let message = format!(
+                "Cannot cast `{}` to `{}`, only between BCD and integers",
+                table.get(value).unwrap(),
+                ty
+            );
+            errors.push(SemanticError::new(message, *location));
+        }
+        _ => {}
+    }
+}
+
+// Only resolved types tell how much zero page the operands need
+fn check_asm_zero_page(statement: &Statement, table: &TypeTable, errors: &mut Vec<SemanticError>) {
+    match statement {
+        Statement::Select {
+            condition: _,
+            statement,
+            else_clause,
+        } => {
+            check_asm_zero_page(statement, table, errors);
+            if let Some(clause) = else_clause {
+                check_asm_zero_page(clause, table, errors);
+            }
+        }
+        Statement::Block(statements) => {
+            for statement in statements {
+                check_asm_zero_page(statement, table, errors);
+            }
+        }
+        Statement::Match(matching) => {
+            for statement in matching.statements() {
+                check_asm_zero_page(statement, table, errors);
+            }
+        }
+        Statement::Asm(block) => {
+            let bytes: usize = block
+                .operands
+                .iter()
+                .filter(|operand| operand.location == AsmLocation::ZeroPage)
+                .filter_map(|operand| table.get(&operand.value).map(|ty| ty.size()))
+                .sum();
+            if bytes > ASM_ZERO_PAGE_BYTES {
+                let message = format!(
+                    "Zero page operands of an asm block take {} bytes, more than the {} available",
+                    bytes, ASM_ZERO_PAGE_BYTES
+                );
+                errors.push(SemanticError::new(message, block.location));
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse_source;
+
+    fn errors(source: &str) -> Vec<String> {
+        let program = parse_source(source);
+        let mut errors = Vec::new();
+        check_types(&program, &mut errors);
+        errors.into_iter().map(|error| error.message).collect()
+    }
+
+    // The type the initializer of local `name` in the first def settles on
+    fn local_type(source: &str, name: &str) -> Type {
+        let program = parse_source(source);
+        let mut errors = Vec::new();
+        let table = check_types(&program, &mut errors);
+        assert!(errors.is_empty(), "{}", errors[0]);
+        let statements = match program.primaries()[0] {
+            PrimaryStatement::Definition {
+                decl: _,
+                inner_statement,
+            } => match inner_statement.as_ref() {
+                Statement::Block(statements) => statements,
+                _ => panic!("Expected a def with a block"),
+            },
+            _ => panic!("Expected a def"),
+        };
+        statements
+            .iter()
+            .find_map(|statement| match statement {
+                Statement::Local {
+                    name: local,
+                    ty: _,
+                    value,
+                    location: _,
+                } if local == name => table.get(value).cloned(),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn operands_must_have_the_same_type() {
+        assert_eq!(
+            errors("def f(a: u8, b: u16): u8 { return a + b }"),
+            vec!["Mismatched types `u8` and `u16` in `+`"]
+        );
+        assert_eq!(
+            errors("def f(a: u8): u16 { return a }"),
+            vec!["Mismatched types `u16` and `u8` in return value"]
+        );
+        assert_eq!(
+            errors("def g(x: u8): u8 { return x }\ndef f(a: u16): u8 { return g(a) }"),
+            vec!["Mismatched types `u8` and `u16` in argument 1 of `g`"]
+        );
+        assert_eq!(
+            errors("def f(a: i8) { var b: u8 = a\n return 0 }"),
+            vec!["Mismatched types `u8` and `i8` in initializer of `b`"]
+        );
+    }
+
+    #[test]
+    fn casts_convert_between_numbers() {
+        assert!(errors("def f(a: u16): u8 { return a as u8 }").is_empty());
+        assert!(errors("def f(a: f8x8): i16 { return a as i16 }").is_empty());
+        assert!(errors("def f(a: u16): *u8 { return a as *u8 }").is_empty());
+        assert_eq!(
+            errors("def f(a: f8x8): *u8 { return a as *u8 }"),
+            vec!["Cannot cast `f8x8` to `*u8`"]
+        );
+        assert_eq!(
+            errors("def f(p: *u8): u16 { return (p as f32) as u16 }"),
+            vec!["Cannot cast `*u8` to `f32`"]
+        );
+    }
+
+    #[test]
+    fn pointers_only_step_by_integers() {
+        assert!(errors("def f(p: *u8, n: u8): *u8 { return p + n - 1 }").is_empty());
+        assert_eq!(
+            errors("def f(p: *u8, q: *u8): *u8 { return p + q }"),
+            vec!["Cannot apply `+` to two pointers"]
+        );
+        assert_eq!(
+            errors("def f(p: *u8): *u8 { return p * 2 }"),
+            vec!["Cannot apply `*` to pointer type `*u8`"]
+        );
+        assert_eq!(
+            errors("def f(p: *u8, n: u8): u8 { return n - p }"),
+            vec!["Mismatched types `u8` and `*u8` in `-`"]
+        );
+        assert_eq!(
+            errors("def f(p: *u8, x: f8x8): *u8 { return p + x }"),
+            vec!["Cannot offset a pointer by `f8x8`, only by an integer"]
+        );
+    }
+
+    #[test]
+    fn literals_take_their_default_type_unless_used_otherwise() {
+        let body = |lines: &str| format!("def f(a: u8) {{ {}\n return 0 }}", lines);
+        assert_eq!(
+            local_type(&body("var x = 1"), "x"),
+            Type::Int(DEFAULT_INT_TYPE)
+        );
+        assert_eq!(
+            local_type(&body("var x = 1.5"), "x"),
+            Type::Fixed(DEFAULT_FIXED_TYPE)
+        );
+        assert_eq!(
+            local_type(&body("var x = 1\n var y = x + a"), "x"),
+            Type::Int(IntType::U8)
+        );
+        assert_eq!(
+            local_type(&body("var x = 1 < 2"), "x"),
+            Type::Int(IntType::U8)
+        );
+        assert_eq!(
+            local_type(&body("var x = 1 + 0.5"), "x"),
+            Type::Fixed(DEFAULT_FIXED_TYPE)
+        );
+    }
+
+    #[test]
+    fn comparison_results_fit_any_integer() {
+        assert!(errors("def g(a: u8, b: u8): u16 { return a < b }").is_empty());
+        assert!(errors("def g(a: i16, b: i16): i8 { var c = a == b\n return c }").is_empty());
+    }
+
+    #[test]
+    fn comparison_results_are_not_floats() {
+        assert_eq!(
+            errors("def g(a: u8, b: u8): f32 { return a < b }"),
+            vec!["Mismatched types `f32` and `u8` in result of `<`"]
+        );
+    }
+
+    #[test]
+    fn comparison_results_are_not_pointers() {
+        assert_eq!(
+            errors("def g(a: u8, b: u8): *u8 { return a < b }"),
+            vec!["Mismatched types `*u8` and `u8` in result of `<`"]
+        );
+    }
+
+    const WRITES_X_ERROR: &str =
+        "Inline assembly changes X, which holds the data stack pointer, without `clobbers(X)`";
+
+    #[test]
+    fn asm_changing_x_must_say_so() {
+        assert_eq!(
+            errors("def f() { asm { LDX #0 }\n return 0 }"),
+            vec![WRITES_X_ERROR]
+        );
+        assert_eq!(
+            errors("def f() { asm { @loop: dex\n BNE @loop }\n return 0 }"),
+            vec![WRITES_X_ERROR]
+        );
+        assert!(errors("def f() { asm clobbers(X) { LDX #0 }\n return 0 }").is_empty());
+        assert!(errors("def f(n: u8) { asm(n: X) { DEX }\n return 0 }").is_empty());
+        assert!(errors("def f() { asm { LDY #0\n STX __tmp }\n return 0 }").is_empty());
+    }
+}
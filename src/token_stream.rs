@@ -1,20 +1,100 @@
+use std::collections::HashMap;
 use std::iter::Peekable;
+use std::rc::Rc;
 use std::error::Error;
 use std::fmt;
 
 use crate::lexer::TokenType;
 use crate::lexer::LexerToken;
+use crate::lexer::SourceLocation;
+
+use crate::parser::types::StructType;
 
 #[derive(Debug)]
 pub struct UnexpectedTokenError {
     expected: Vec<TokenType>,
-    actual: Option<TokenType>
+    actual: Option<(TokenType, SourceLocation)>,
+    message: Option<String>
+}
+
+impl UnexpectedTokenError {
+    pub fn unknown_type(name: String, location: SourceLocation) -> UnexpectedTokenError {
+        UnexpectedTokenError {
+            expected: vec![TokenType::IDENTIFIER],
+            actual: Some((TokenType::IDENTIFIER, location)),
+            message: Some(format!("Unknown type `{}`", name))
+        }
+    }
+
+    pub fn unknown_layout(name: String, location: SourceLocation) -> UnexpectedTokenError {
+        UnexpectedTokenError {
+            expected: vec![TokenType::IDENTIFIER],
+            actual: Some((TokenType::IDENTIFIER, location)),
+            message: Some(format!("Unknown struct layout `{}`, expected `aos` or `soa`", name))
+        }
+    }
+
+    pub fn unknown_asm_location(name: String, location: SourceLocation) -> UnexpectedTokenError {
+        UnexpectedTokenError {
+            expected: vec![TokenType::IDENTIFIER],
+            actual: Some((TokenType::IDENTIFIER, location)),
+            message: Some(format!("Unknown operand location `{}`, expected `A`, `X`, `Y` or `zp`", name))
+        }
+    }
+
+    pub fn unknown_asm_direction(name: String, location: SourceLocation) -> UnexpectedTokenError {
+        UnexpectedTokenError {
+            expected: vec![TokenType::IDENTIFIER],
+            actual: Some((TokenType::IDENTIFIER, location)),
+            message: Some(format!("Unknown operand direction `{}`, expected `in`, `out` or `inout`", name))
+        }
+    }
+
+    pub fn unknown_asm_clobber(name: String, location: SourceLocation) -> UnexpectedTokenError {
+        UnexpectedTokenError {
+            expected: vec![TokenType::IDENTIFIER],
+            actual: Some((TokenType::IDENTIFIER, location)),
+            message: Some(format!("Unknown clobber `{}`, expected `A`, `X`, `Y` or `flags`", name))
+        }
+    }
+
+    pub fn unknown_arg_location(name: String, location: SourceLocation) -> UnexpectedTokenError {
+        UnexpectedTokenError {
+            expected: vec![TokenType::IDENTIFIER],
+            actual: Some((TokenType::IDENTIFIER, location)),
+            message: Some(format!("Unknown register `{}`, expected `A`, `X`, `Y`, `AX`, `AY`, `XY` or an address", name))
+        }
+    }
+
+    pub fn duplicate_default(location: SourceLocation) -> UnexpectedTokenError {
+        UnexpectedTokenError {
+            expected: vec![],
+            actual: Some((TokenType::ELSE, location)),
+            message: Some(String::from("`match` already has an `else` arm"))
+        }
+    }
+
+    pub fn unexpected(token_type: TokenType, location: SourceLocation) -> UnexpectedTokenError {
+        UnexpectedTokenError {
+            expected: vec![],
+            actual: Some((token_type, location)),
+            message: Some(format!("Unexpected {:?}", token_type))
+        }
+    }
+
+    // Where the offending token is, or None if the tokens ran out
+    pub fn location(&self) -> Option<SourceLocation> {
+        self.actual.map(|(_, location)| location)
+    }
 }
 
 impl fmt::Display for UnexpectedTokenError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if let Some(actual_type) = &self.actual {
-            write!(f, "Expected token {:?} but found {:?}", self.expected, actual_type)
+        if let (Some(message), Some((_, location))) = (&self.message, &self.actual) {
+            write!(f, "{}: {}", location, message)
+        }
+        else if let Some((actual_type, location)) = &self.actual {
+            write!(f, "{}: Expected token {:?} but found {:?}", location, self.expected, actual_type)
         }
         else {
             write!(f, "Expected token {:?} but found EOF", self.expected)
@@ -26,13 +106,45 @@ impl Error for UnexpectedTokenError {
 
 }
 
-pub struct TokenStream<I: Iterator<Item = LexerToken>> (pub Peekable<I>);
+pub struct TokenStream<I: Iterator<Item = LexerToken>> {
+    tokens: Peekable<I>,
+    // Structs declared so far, which later type annotations may name
+    pub structs: HashMap<String, Rc<StructType>>,
+    // Line of the last token taken
+    line: u32
+}
 
 impl<I: Iterator<Item = LexerToken>> TokenStream<I> {
+    pub fn new(tokens: Peekable<I>) -> TokenStream<I> {
+        TokenStream {
+            tokens,
+            structs: HashMap::new(),
+            line: 0
+        }
+    }
+
+    fn take(&mut self) -> Option<LexerToken> {
+        let token = self.tokens.next();
+        if let Some(token) = &token {
+            self.line = token.location.line;
+        }
+        token
+    }
+
+    // Whether the next token is the first on its line
+    pub fn next_starts_line(&mut self) -> bool {
+        let line = self.line;
+        self.tokens.peek().is_some_and(|top| top.location.line > line)
+    }
+
+    pub fn peek(&mut self) -> Option<&LexerToken> {
+        self.tokens.peek()
+    }
+
     pub fn accept(&mut self, expected: TokenType) -> Option<LexerToken> {
-        if let Some(top) = self.0.peek() {
+        if let Some(top) = self.tokens.peek() {
             if top.token_type == expected {
-                Some(self.0.next().unwrap().clone())
+                self.take()
             }
             else {
                 None
@@ -43,15 +155,29 @@ impl<I: Iterator<Item = LexerToken>> TokenStream<I> {
         }
     }
 
+    // Accepts an identifier used as a contextual keyword, such as `in`
+    pub fn accept_label(&mut self, label: &str) -> Option<LexerToken> {
+        let matches = match self.tokens.peek() {
+            Some(top) => top.token_type == TokenType::IDENTIFIER && top.label.as_deref() == Some(label),
+            None => false
+        };
+        if matches {
+            self.take()
+        }
+        else {
+            None
+        }
+    }
+
     pub fn multi(&mut self, types: &Vec<TokenType>) -> Option<LexerToken> {
         if self.is_eof() {
             return None;
         }
 
         let mut itt = types.iter();
-        let top_token: &LexerToken = self.0.peek().unwrap();
+        let top_token: &LexerToken = self.tokens.peek().unwrap();
         if types.contains(&top_token.token_type) {
-            return Some(self.0.next().unwrap().clone());
+            self.take()
         }
         else {
             return None;
@@ -59,18 +185,20 @@ impl<I: Iterator<Item = LexerToken>> TokenStream<I> {
     }
 
     pub fn is_eof(&mut self) -> bool {
-        self.0.peek().is_none()
+        self.tokens.peek().is_none()
     }
 
-    fn get_actual(&mut self) -> Option<TokenType> {
-        if let Some(top) = self.0.peek() {
-            return Some(top.token_type);
-        }
-        else {
-            return None;
+    pub fn next_is(&mut self, expected: TokenType) -> bool {
+        match self.tokens.peek() {
+            Some(top) => top.token_type == expected,
+            None => false
         }
     }
 
+    fn get_actual(&mut self) -> Option<(TokenType, SourceLocation)> {
+        self.tokens.peek().map(|top| (top.token_type, top.location))
+    }
+
     pub fn expect_multi(&mut self, types: &Vec<TokenType>) -> Result<LexerToken, UnexpectedTokenError> {
         if let Some(result) = self.multi(types) {
             return Ok(result);
@@ -79,6 +207,7 @@ impl<I: Iterator<Item = LexerToken>> TokenStream<I> {
             let error = UnexpectedTokenError {
                 actual: self.get_actual(),
                 expected: types.clone(),
+                message: None
             };
             return Err(error)
         }
@@ -91,7 +220,8 @@ impl<I: Iterator<Item = LexerToken>> TokenStream<I> {
         else {
             let error = UnexpectedTokenError {
                 actual: self.get_actual(),
-                expected: vec![expected]
+                expected: vec![expected],
+                message: None
             };
             return Err(error)
         }